@@ -34,8 +34,12 @@ public:
     return tmp;
   }
 
-  // Convenience: string view of a cell (uses Value::toString())
-  std::string toString(size_t idx) const { return values_.at(idx)->toString(); }
+  // Convenience: string view of a cell (uses Value::toString()); null cells
+  // (stored as nullptr) render like NullValue
+  std::string toString(size_t idx) const {
+    const auto &v = values_.at(idx);
+    return v ? v->toString() : "null";
+  }
 
 private:
   std::vector<std::unique_ptr<Value>> values_;
@@ -0,0 +1,151 @@
+use axum::{extract::State, Json};
+use kadedb_services_ffi::{BlockingTasks, BreakerState, StoragePool};
+use serde::Serialize;
+
+use crate::AppState;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PoolStatusResponse {
+    ok: bool,
+    /// `ok`, or `degraded` when a pool is saturated past the threshold or
+    /// its circuit breaker isn't closed.
+    status: &'static str,
+    blocking: BlockingStatus,
+    pools: Vec<PoolStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct BlockingStatus {
+    queued: usize,
+    running: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    size: usize,
+    in_use: usize,
+    waiting: usize,
+    /// `(in_use + waiting) / size`: past 1.0, callers are queueing.
+    saturation: f64,
+    /// The circuit breaker: `closed`, `open` or `half_open`.
+    circuit: &'static str,
+}
+
+fn saturation(pool: &StoragePool) -> f64 {
+    (pool.in_use() + pool.queued()) as f64 / pool.size() as f64
+}
+
+/// Whether any pool's saturation is past the configured threshold, or any
+/// circuit breaker isn't closed.
+pub(crate) fn degraded(state: &AppState) -> bool {
+    if circuit(state) != BreakerState::Closed {
+        return true;
+    }
+    let Some(threshold) = state.live.pool_degraded_saturation() else {
+        return false;
+    };
+    state
+        .tenancy
+        .pools()
+        .into_iter()
+        .any(|(_, pool)| saturation(pool) > threshold)
+}
+
+/// The worst circuit breaker state over all pools: `Open` if any is open,
+/// else `HalfOpen` if any is trying to recover.
+pub(crate) fn circuit(state: &AppState) -> BreakerState {
+    let states: Vec<BreakerState> = state
+        .tenancy
+        .pools()
+        .into_iter()
+        .map(|(_, pool)| pool.circuit())
+        .collect();
+    [BreakerState::Open, BreakerState::HalfOpen]
+        .into_iter()
+        .find(|worst| states.contains(worst))
+        .unwrap_or(BreakerState::Closed)
+}
+
+/// `GET /admin/pool`
+///
+/// Blocking FFI tasks waiting for or holding a thread, and each storage
+/// pool's slots. Tokio doesn't report idle blocking threads on stable
+/// builds, so those aren't included.
+pub(crate) async fn pool_status(State(state): State<AppState>) -> Json<PoolStatusResponse> {
+    let blocking = BlockingTasks::current();
+    let pools = state
+        .tenancy
+        .pools()
+        .into_iter()
+        .map(|(tenant, pool)| PoolStatus {
+            tenant: tenant.map(str::to_string),
+            size: pool.size(),
+            in_use: pool.in_use(),
+            waiting: pool.queued(),
+            saturation: saturation(pool),
+            circuit: pool.circuit().as_str(),
+        })
+        .collect();
+    Json(PoolStatusResponse {
+        ok: true,
+        status: if degraded(&state) { "degraded" } else { "ok" },
+        blocking: BlockingStatus {
+            queued: blocking.queued,
+            running: blocking.running,
+        },
+        pools,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StatsResponse {
+    ok: bool,
+    pools: Vec<PoolStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    statement_cache: StatementCacheStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct StatementCacheStatus {
+    capacity: usize,
+    len: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    /// `hits / (hits + misses)`; `null` before the first lookup.
+    hit_rate: Option<f64>,
+}
+
+/// `GET /admin/stats`
+///
+/// Each storage pool's statement cache: its size, and hits, misses and
+/// evictions since startup.
+pub(crate) async fn stats(State(state): State<AppState>) -> Json<StatsResponse> {
+    let pools = state
+        .tenancy
+        .pools()
+        .into_iter()
+        .map(|(tenant, pool)| {
+            let cache = pool.statement_cache_stats();
+            PoolStats {
+                tenant: tenant.map(str::to_string),
+                statement_cache: StatementCacheStatus {
+                    capacity: cache.capacity,
+                    len: cache.len,
+                    hits: cache.hits,
+                    misses: cache.misses,
+                    evictions: cache.evictions,
+                    hit_rate: cache.hit_rate(),
+                },
+            }
+        })
+        .collect();
+    Json(StatsResponse { ok: true, pools })
+}
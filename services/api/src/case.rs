@@ -0,0 +1,123 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{Map, Value};
+
+/// Naming convention for JSON response fields.
+///
+/// Responses are defined in snake_case; camelCase is produced by renaming
+/// object keys on the way out, either server-wide (`KADEDB_JSON_CASE=camel`)
+/// or per request (`?case=camel`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl JsonCase {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "snake" => Some(Self::Snake),
+            "camel" => Some(Self::Camel),
+            _ => None,
+        }
+    }
+
+    /// The case `req` asks for with `?case=`, else `default`.
+    pub(crate) fn of_request<B>(req: &Request<B>, default: Self) -> Self {
+        req.uri()
+            .query()
+            .and_then(|q| {
+                q.split('&')
+                    .find_map(|pair| pair.strip_prefix("case="))
+                    .and_then(Self::parse)
+            })
+            .unwrap_or(default)
+    }
+
+    /// A snake_case field name in this case.
+    pub(crate) fn key(self, key: &str) -> String {
+        match self {
+            Self::Snake => key.to_string(),
+            Self::Camel => to_camel(key),
+        }
+    }
+}
+
+/// Fields whose values are user data (column names as keys) and must not be
+/// renamed.
+const DATA_FIELDS: &[&str] = &["rows"];
+
+pub(crate) async fn json_case(
+    State(default): State<JsonCase>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let case = JsonCase::of_request(&req, default);
+
+    let res = next.run(req).await;
+    if case == JsonCase::Snake || !is_json(&res) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(mut value) => {
+            camelize(&mut value);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(serde_json::to_vec(&value).expect("serialize json"))
+        }
+        Err(_) => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
+
+pub(crate) fn is_json(res: &Response) -> bool {
+    res.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+fn camelize(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let renamed: Map<String, Value> = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    if !DATA_FIELDS.contains(&key.as_str()) {
+                        camelize(&mut value);
+                    }
+                    (to_camel(&key), value)
+                })
+                .collect();
+            *map = renamed;
+        }
+        Value::Array(items) => items.iter_mut().for_each(camelize),
+        _ => {}
+    }
+}
+
+fn to_camel(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' && !out.is_empty() {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,50 @@
+use axum::http::{HeaderName, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::ApiError;
+
+/// On a CSV export, the checksum of the response body.
+pub const CHECKSUM_HEADER: HeaderName = HeaderName::from_static("x-checksum");
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChecksumParams {
+    checksum: Option<String>,
+}
+
+impl ChecksumParams {
+    /// A digest to fill if the request asked for `checksum=sha256`, the only
+    /// algorithm offered.
+    pub(crate) fn digest(&self) -> Result<Option<RowDigest>, ApiError> {
+        match self.checksum.as_deref() {
+            None => Ok(None),
+            Some("sha256") => Ok(Some(RowDigest::default())),
+            Some(other) => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("unsupported checksum `{other}`; expected `sha256`"),
+            )),
+        }
+    }
+}
+
+/// SHA-256 over the bytes of a result, reported as `sha256:<hex>`.
+#[derive(Default, Clone)]
+pub(crate) struct RowDigest(Sha256);
+
+impl RowDigest {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    /// Hashes `row` the way the JSON shapes report it: a compact JSON array
+    /// of its cells in column order, then `\n`.
+    pub(crate) fn update_row(&mut self, row: &[String]) {
+        let line = serde_json::to_vec(row).expect("serialize row");
+        self.0.update(&line);
+        self.0.update(b"\n");
+    }
+
+    pub(crate) fn finish(self) -> String {
+        format!("sha256:{:x}", self.0.finalize())
+    }
+}
@@ -0,0 +1,295 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use kadedb_services_auth::RoleTimeouts;
+use kadedb_services_ffi::{ColumnTypeAliases, RestrictedTables};
+use kadedb_services_grpc::DEFAULT_PROGRESS_INTERVAL;
+use kadedb_services_telemetry::{
+    AccessLog, ListenerConfig, QueryTags, SlowQueryLog, CONFIG_FILE_ENV,
+};
+
+use crate::{
+    case::JsonCase, cookie::AuthCookie, error::ErrorVerbosity, export::validate_null_as,
+    scope::RouteScopes, templates::QueryTemplates,
+};
+
+/// Default `pool_degraded_saturation`: as many callers waiting as the pool
+/// has slots.
+pub const DEFAULT_POOL_DEGRADED_SATURATION: f64 = 2.0;
+
+/// Default `ready_deep_interval`.
+pub const DEFAULT_READY_DEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default `max_columns_per_table`.
+pub const DEFAULT_MAX_COLUMNS_PER_TABLE: usize = 1024;
+
+/// Default `sort_max_rows`.
+pub const DEFAULT_SORT_MAX_ROWS: usize = 10_000;
+
+/// Default `HttpConfig::idle_timeout`.
+pub const DEFAULT_HTTP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long client connections stay open.
+///
+/// - `keepalive` serves more than one request per HTTP/1.1 connection. Off,
+///   every response closes its connection.
+/// - `idle_timeout` closes a connection that has gone this long without
+///   sending the next request (or finishing its headers), so clients and
+///   proxies that hold connections open indefinitely can't exhaust file
+///   descriptors. `None` waits forever.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub keepalive: bool,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            keepalive: true,
+            idle_timeout: Some(DEFAULT_HTTP_IDLE_TIMEOUT),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Reads `KADEDB_HTTP_KEEPALIVE` (`true`/`false`) and
+    /// `KADEDB_HTTP_IDLE_TIMEOUT_MS` (0 disables); unset or invalid values
+    /// keep the defaults.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            keepalive: std::env::var("KADEDB_HTTP_KEEPALIVE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.keepalive),
+            idle_timeout: match std::env::var("KADEDB_HTTP_IDLE_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+            {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => default.idle_timeout,
+            },
+        }
+    }
+}
+
+/// Certificate and private key (PEM) for in-process TLS.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Reads `KADEDB_TLS_CERT` and `KADEDB_TLS_KEY`; `None` unless both are
+    /// set.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            cert_path: std::env::var_os("KADEDB_TLS_CERT")?.into(),
+            key_path: std::env::var_os("KADEDB_TLS_KEY")?.into(),
+        })
+    }
+}
+
+/// Service-level settings that aren't tied to auth or storage.
+#[derive(Debug, Clone, Default)]
+pub struct ApiConfig {
+    /// Field naming for JSON responses when the request doesn't pick one.
+    pub json_case: JsonCase,
+    /// How CSV export renders NULL cells unless the request sets `null_as`.
+    pub csv_null_as: String,
+    /// `X-Query-Tag` values recorded on spans and metrics.
+    pub query_tags: QueryTags,
+    /// TCP tuning for the listener and accepted connections.
+    pub listener: ListenerConfig,
+    /// Keep-alive and idle timeout of client connections.
+    pub http: HttpConfig,
+    /// Queries slower than this are logged at WARN.
+    pub slow_queries: SlowQueryLog,
+    /// Serve HTTPS with this certificate instead of plaintext. Requires the
+    /// `tls` feature.
+    pub tls: Option<TlsConfig>,
+    /// Token scopes required per route, on top of the role.
+    pub route_scopes: RouteScopes,
+    /// Deadline for producing a response; past it the request fails with 504.
+    pub request_timeout: Option<Duration>,
+    /// Longest deadline a client may ask for with `X-Query-Timeout-Ms`.
+    /// Longer requests are clamped to it; `None` accepts any.
+    pub max_query_timeout: Option<Duration>,
+    /// Default and longest deadline per caller role, in place of
+    /// `request_timeout` and on top of `max_query_timeout`.
+    pub role_timeouts: RoleTimeouts,
+    /// One line per request at INFO, for ingestion.
+    pub access_log: AccessLog,
+    /// Accept the token from a cookie when there's no `Authorization` header.
+    pub auth_cookie: Option<AuthCookie>,
+    /// Rows returned at most by a `/query` SELECT that has no LIMIT.
+    pub auto_limit: Option<usize>,
+    /// Named queries runnable through `/templates/:name/run`.
+    pub templates: QueryTemplates,
+    /// Realm named in the `WWW-Authenticate` challenge of 401 responses.
+    pub auth_realm: Option<String>,
+    /// `/health` reports `degraded` while a storage pool's `(in use +
+    /// waiting) / size` is above this.
+    pub pool_degraded_saturation: Option<f64>,
+    /// Whether error responses reveal storage error messages.
+    pub error_verbosity: ErrorVerbosity,
+    /// `/ready?deep=true` probes within this long of the last deep check get
+    /// its report instead of writing again.
+    pub ready_deep_interval: Duration,
+    /// Settings file re-read by `POST /admin/reload`.
+    pub config_file: Option<PathBuf>,
+    /// How often an NDJSON `/query` stream that asked for progress gets a
+    /// progress line; `None` sends none.
+    pub progress_interval: Option<Duration>,
+    /// Tables only admins may query.
+    pub restricted_tables: RestrictedTables,
+    /// Answer `/query` with just the rows, columns in a header, unless the
+    /// request sets `envelope=true`.
+    pub bare_results: bool,
+    /// Most columns a `POST /tables` definition may have; `None` allows any.
+    pub max_columns_per_table: Option<usize>,
+    /// Most rows `?sort=` sorts; larger results are returned unsorted.
+    /// `None` sorts any result.
+    pub sort_max_rows: Option<usize>,
+    /// Type names `POST /tables` accepts besides the canonical ones.
+    pub column_type_aliases: ColumnTypeAliases,
+}
+
+impl ApiConfig {
+    /// Reads `KADEDB_JSON_CASE` (`snake` or `camel`), `KADEDB_CSV_NULL_AS`
+    /// `KADEDB_QUERY_TAGS`, the `KADEDB_TCP_*` and `KADEDB_PROXY_PROTOCOL`
+    /// listener settings, the `KADEDB_HTTP_*` connection settings, the
+    /// `KADEDB_SLOW_QUERY_*` log settings, `KADEDB_TLS_CERT`/`KADEDB_TLS_KEY`
+    /// `KADEDB_ROUTE_SCOPES`, `KADEDB_REQUEST_TIMEOUT_MS`,
+    /// `KADEDB_MAX_QUERY_TIMEOUT_MS`, `KADEDB_ROLE_TIMEOUTS`, the
+    /// `KADEDB_ACCESS_LOG*` settings, the `KADEDB_AUTH_*COOKIE` settings,
+    /// `KADEDB_AUTO_LIMIT` (0 disables), `KADEDB_QUERY_TEMPLATES`,
+    /// `KADEDB_AUTH_REALM`, `KADEDB_POOL_DEGRADED_SATURATION` (default 2, 0
+    /// disables), `KADEDB_ERROR_VERBOSITY` (`detailed` or `minimal`),
+    /// `KADEDB_READY_DEEP_INTERVAL_MS` (default 30000), `KADEDB_CONFIG_FILE`,
+    /// `KADEDB_PROGRESS_INTERVAL_MS` (default 5000, 0 disables),
+    /// `KADEDB_RESTRICTED_TABLES`, `KADEDB_BARE_RESULTS` (`true`/`false`) and
+    /// `KADEDB_MAX_COLUMNS_PER_TABLE` (default 1024, 0 disables),
+    /// `KADEDB_SORT_MAX_ROWS` (default 10000, 0 disables) and
+    /// `KADEDB_COLUMN_TYPE_ALIASES` (`alias=type,...`, added to the shipped
+    /// aliases); unset or invalid values keep the defaults.
+    pub fn from_env() -> Self {
+        let json_case = std::env::var("KADEDB_JSON_CASE")
+            .ok()
+            .and_then(|v| JsonCase::parse(&v))
+            .unwrap_or_default();
+        let csv_null_as = std::env::var("KADEDB_CSV_NULL_AS")
+            .ok()
+            .filter(|v| validate_null_as(v).is_ok())
+            .unwrap_or_default();
+        let var = |name: &str| std::env::var(name).ok();
+        Self {
+            json_case,
+            csv_null_as,
+            query_tags: QueryTags::from_env(),
+            listener: ListenerConfig::from_env(),
+            http: HttpConfig::from_env(),
+            slow_queries: SlowQueryLog::from_env(),
+            tls: TlsConfig::from_env(),
+            route_scopes: RouteScopes::from_env(),
+            request_timeout: request_timeout_from_env(),
+            max_query_timeout: std::env::var("KADEDB_MAX_QUERY_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            role_timeouts: std::env::var("KADEDB_ROLE_TIMEOUTS")
+                .map(|v| RoleTimeouts::parse(&v))
+                .unwrap_or_default(),
+            access_log: AccessLog::from_env(),
+            auth_cookie: AuthCookie::from_env(),
+            auto_limit: auto_limit(var),
+            templates: QueryTemplates::from_env(),
+            auth_realm: std::env::var("KADEDB_AUTH_REALM")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            pool_degraded_saturation: pool_degraded_saturation(var),
+            error_verbosity: std::env::var("KADEDB_ERROR_VERBOSITY")
+                .ok()
+                .and_then(|v| ErrorVerbosity::parse(&v))
+                .unwrap_or_default(),
+            ready_deep_interval: std::env::var("KADEDB_READY_DEEP_INTERVAL_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(DEFAULT_READY_DEEP_INTERVAL, Duration::from_millis),
+            config_file: std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from),
+            progress_interval: std::env::var("KADEDB_PROGRESS_INTERVAL_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(Some(DEFAULT_PROGRESS_INTERVAL), |ms: u64| {
+                    (ms > 0).then(|| Duration::from_millis(ms))
+                }),
+            restricted_tables: std::env::var("KADEDB_RESTRICTED_TABLES")
+                .map(|v| RestrictedTables::parse(&v))
+                .unwrap_or_default(),
+            bare_results: std::env::var("KADEDB_BARE_RESULTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            max_columns_per_table: std::env::var("KADEDB_MAX_COLUMNS_PER_TABLE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(Some(DEFAULT_MAX_COLUMNS_PER_TABLE), |n: usize| {
+                    (n > 0).then_some(n)
+                }),
+            sort_max_rows: std::env::var("KADEDB_SORT_MAX_ROWS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(Some(DEFAULT_SORT_MAX_ROWS), |n: usize| (n > 0).then_some(n)),
+            column_type_aliases: std::env::var("KADEDB_COLUMN_TYPE_ALIASES")
+                .map(|v| ColumnTypeAliases::parse(&v))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Checks what can be checked before serving: route scopes must name
+    /// known routes (see [`RouteScopes::validate`]), and TLS needs the `tls`
+    /// feature and readable certificate and key files.
+    pub fn validate(&self) -> Result<(), String> {
+        self.route_scopes.validate()?;
+        let Some(tls) = &self.tls else {
+            return Ok(());
+        };
+        if !cfg!(feature = "tls") {
+            return Err("KADEDB_TLS_CERT is set, but built without the `tls` feature".to_string());
+        }
+        for path in [&tls.cert_path, &tls.key_path] {
+            std::fs::File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// `KADEDB_AUTO_LIMIT`; 0 disables.
+pub(crate) fn auto_limit(var: impl Fn(&str) -> Option<String>) -> Option<usize> {
+    var("KADEDB_AUTO_LIMIT")
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+}
+
+/// `KADEDB_POOL_DEGRADED_SATURATION`; 0 disables, unset is the default.
+pub(crate) fn pool_degraded_saturation(var: impl Fn(&str) -> Option<String>) -> Option<f64> {
+    match var("KADEDB_POOL_DEGRADED_SATURATION").and_then(|v| v.parse::<f64>().ok()) {
+        Some(t) if t > 0.0 => Some(t),
+        Some(_) => None,
+        None => Some(DEFAULT_POOL_DEGRADED_SATURATION),
+    }
+}
+
+/// `KADEDB_REQUEST_TIMEOUT_MS`, shared with the gRPC server. Unset, invalid
+/// or zero means no deadline.
+pub(crate) fn request_timeout_from_env() -> Option<Duration> {
+    std::env::var("KADEDB_REQUEST_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&ms| ms > 0)
+        .map(Duration::from_millis)
+}
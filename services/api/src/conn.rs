@@ -0,0 +1,133 @@
+//! The plaintext HTTP server: what `axum::serve` does, plus the connection
+//! lifetimes in [`HttpConfig`] and PROXY protocol headers.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use kadedb_services_telemetry::ListenerConfig;
+use tokio::net::TcpListener;
+use tower_service::Service;
+
+use crate::config::HttpConfig;
+
+/// A connection builder applying `http`. The idle timeout is hyper's
+/// header read timeout, which runs whenever an HTTP/1 connection is waiting
+/// for the next request.
+pub(crate) fn builder(http: &HttpConfig) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(http.keepalive)
+        .header_read_timeout(http.idle_timeout);
+    builder
+}
+
+/// Serves `app` until `shutdown` resolves, then stops accepting and waits for
+/// open connections to finish their in-flight requests. Requests carry their
+/// client as [`ConnectInfo<SocketAddr>`].
+pub(crate) async fn serve(
+    listener: TcpListener,
+    app: Router,
+    http: &HttpConfig,
+    listener_cfg: &ListenerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let builder = builder(http);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let mut stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    accept_failed(err).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        if let Err(err) = stream.set_nodelay(listener_cfg.nodelay) {
+            tracing::trace!(error = %err, "failed to set TCP_NODELAY");
+        }
+        // The PROXY protocol header is read on the connection's own task,
+        // so a slow client doesn't hold up accepting the next.
+        let (app, builder, listener_cfg) = (app.clone(), builder.clone(), listener_cfg.clone());
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let client = match listener_cfg.client_addr(&mut stream).await {
+                Ok(client) => client,
+                Err(err) => {
+                    tracing::debug!(error = %err, "dropped connection");
+                    return;
+                }
+            };
+            let service = TowerToHyperService::new(WithClient::new(app, client));
+            let conn = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .into_owned();
+            if let Err(err) = watcher.watch(conn).await {
+                tracing::trace!(error = %err, "connection closed with an error");
+            }
+        });
+    }
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Attaches the connection's client address to each of its requests.
+#[derive(Clone)]
+pub(crate) struct WithClient<S> {
+    inner: S,
+    client: SocketAddr,
+}
+
+impl<S> WithClient<S> {
+    pub(crate) fn new(inner: S, client: SocketAddr) -> Self {
+        Self { inner, client }
+    }
+}
+
+impl<S, B> Service<axum::http::Request<B>> for WithClient<S>
+where
+    S: Service<axum::http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: axum::http::Request<B>) -> Self::Future {
+        req.extensions_mut().insert(ConnectInfo(self.client));
+        self.inner.call(req)
+    }
+}
+
+/// Per-connection failures (the peer gave up before we accepted) are
+/// ignored. Anything else, such as running out of file descriptors, is
+/// logged and accepting pauses for a second rather than spinning.
+async fn accept_failed(err: io::Error) {
+    if matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    ) {
+        return;
+    }
+    tracing::error!(error = %err, "accept failed");
+    tokio::time::sleep(Duration::from_secs(1)).await;
+}
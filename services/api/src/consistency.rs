@@ -0,0 +1,37 @@
+use kadedb_services_ffi::StoragePool;
+use serde::Deserialize;
+
+/// Whether a read may be served by a read replica.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Consistency {
+    /// Reads go to the replica, which may lag behind recent writes.
+    #[default]
+    Eventual,
+    /// Reads go to the primary, so they see the caller's own writes.
+    Strong,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ConsistencyParams {
+    #[serde(default)]
+    consistency: Consistency,
+}
+
+impl ConsistencyParams {
+    /// The pool `sql` runs on: see [`StoragePool::route`]. Counted in
+    /// `query_routes_total` by `pool` when there is a replica to choose.
+    pub(crate) fn route(&self, pool: &StoragePool, sql: &str) -> StoragePool {
+        let Some(replica) = pool.read_replica() else {
+            return pool.clone();
+        };
+        let routed = pool.route(sql, self.consistency == Consistency::Strong);
+        let target = if std::ptr::eq(routed, replica) {
+            "replica"
+        } else {
+            "primary"
+        };
+        metrics::counter!("query_routes_total", "pool" => target).increment(1);
+        routed.clone()
+    }
+}
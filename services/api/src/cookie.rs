@@ -0,0 +1,76 @@
+use axum::http::{header, HeaderMap, Method};
+use kadedb_services_auth::AuthError;
+
+/// Request header echoing the CSRF cookie on mutating cookie-authenticated
+/// requests (double-submit).
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Accepts the JWT from a cookie when a request has no `Authorization`
+/// header, for browser clients that can't set one (navigations, SSE).
+///
+/// Safe methods (GET, HEAD) may always use the cookie. Other methods may only
+/// when `csrf_cookie` is set and the request repeats that cookie's value in
+/// [`CSRF_HEADER`]; otherwise they still need the header.
+#[derive(Debug, Clone)]
+pub struct AuthCookie {
+    pub name: String,
+    pub csrf_cookie: Option<String>,
+}
+
+impl AuthCookie {
+    /// Reads `KADEDB_AUTH_COOKIE` (the token cookie; unset disables cookie
+    /// auth) and `KADEDB_AUTH_CSRF_COOKIE`.
+    pub fn from_env() -> Option<Self> {
+        let name = std::env::var("KADEDB_AUTH_COOKIE")
+            .ok()
+            .filter(|v| !v.is_empty())?;
+        let csrf_cookie = std::env::var("KADEDB_AUTH_CSRF_COOKIE")
+            .ok()
+            .filter(|v| !v.is_empty());
+        Some(Self { name, csrf_cookie })
+    }
+
+    /// The token as an `Authorization` value, if the request carries the
+    /// cookie and may authenticate with it.
+    pub(crate) fn bearer(
+        &self,
+        method: &Method,
+        headers: &HeaderMap,
+    ) -> Result<Option<String>, AuthError> {
+        let Some(token) = cookie(headers, &self.name) else {
+            return Ok(None);
+        };
+        if !(method == Method::GET || method == Method::HEAD) {
+            let csrf = self.csrf_cookie.as_deref().and_then(|n| cookie(headers, n));
+            let echoed = headers.get(CSRF_HEADER).and_then(|v| v.to_str().ok());
+            match (csrf, echoed) {
+                (Some(csrf), Some(echoed)) if constant_time_eq(csrf, echoed) => {}
+                // No double-submit configured: the cookie doesn't count.
+                _ if self.csrf_cookie.is_none() => return Ok(None),
+                _ => return Err(AuthError::CsrfMismatch),
+            }
+        }
+        Ok(Some(format!("Bearer {token}")))
+    }
+}
+
+/// The value of cookie `name` across all `Cookie` headers.
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_empty())
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
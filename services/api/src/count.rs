@@ -0,0 +1,103 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, HeaderName, StatusCode},
+    Extension, Json,
+};
+use kadedb_services_ffi::{spawn_query, FfiError, StatementKind, Value};
+use kadedb_services_telemetry::record_query;
+use serde::Serialize;
+
+use crate::{
+    consistency::ConsistencyParams,
+    error::{ApiError, ApiJson},
+    queries::{QueryTag, Subject, QUERY_ID_HEADER},
+    restricted::TableAccess,
+    tenant::TenantPool,
+    AppState, Deadline, QueryRequest,
+};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CountResponse {
+    ok: bool,
+    count: u64,
+}
+
+/// `POST /query/count`
+///
+/// The number of rows a `SELECT` returns, without the rows. Any other
+/// statement is 400. The engine can't run `SELECT COUNT(*)` over a
+/// subquery yet, so the rows are counted here as they are read; they still
+/// never leave the server. The auto limit doesn't apply. Like `/query`, the
+/// count is registered for `DELETE /queries/:id` and stops at the deadline.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn count_query(
+    State(state): State<AppState>,
+    TenantPool(pool): TenantPool,
+    QueryTag(tag): QueryTag,
+    Subject(subject): Subject,
+    access: TableAccess,
+    deadline: Option<Extension<Deadline>>,
+    Query(consistency): Query<ConsistencyParams>,
+    headers: HeaderMap,
+    ApiJson(req): ApiJson<QueryRequest>,
+) -> Result<([(HeaderName, String); 1], Json<CountResponse>), ApiError> {
+    if StatementKind::of(&req.query) != StatementKind::Select {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`/query/count` takes a SELECT statement",
+        ));
+    }
+    access.check(&req.query)?;
+    let params: Vec<Value> = req.params.into_iter().map(Value::from).collect();
+    let pool = consistency.route(&pool, &req.query);
+    let active = state.queries.register(&headers, subject.clone())?;
+    let cancel = active.token();
+    let started = Instant::now();
+    let guard = pool.acquire().await?;
+    let storage = guard.storage();
+    let sql = req.query;
+    let slow = state.live.slow_queries();
+    let span = tracing::info_span!("query.count", tag = %tag);
+    // The closure owns the pool slot, so it stays taken until the engine is
+    // done even if the request goes away first.
+    let result = spawn_query(sql.clone(), move || {
+        let _entered = span.enter();
+        let run = || {
+            let statement = storage.prepare(&sql);
+            let mut rs = storage.execute_prepared(&statement, &params)?;
+            let mut reader = rs.row_reader().with_cancel(cancel);
+            if let Some(Extension(Deadline(deadline))) = deadline {
+                reader = reader.with_deadline(deadline);
+            }
+            let mut count = 0u64;
+            while reader.next_row()?.is_some() {
+                count += 1;
+            }
+            slow.record(&sql, started.elapsed(), count as usize, subject.as_deref());
+            Ok::<_, FfiError>(count)
+        };
+        let result = run();
+        if result.as_ref().is_err_and(FfiError::is_storage_failure) {
+            guard.record_failure();
+        }
+        result
+    })
+    .await
+    .expect("spawn_blocking");
+    record_query(
+        &tag,
+        StatementKind::Select.as_str(),
+        result.is_ok(),
+        started.elapsed(),
+    );
+
+    Ok((
+        [(QUERY_ID_HEADER, active.id().to_string())],
+        Json(CountResponse {
+            ok: true,
+            count: result?,
+        }),
+    ))
+}
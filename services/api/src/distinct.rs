@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+use std::hash::{BuildHasher, RandomState};
+
+use axum::http::HeaderName;
+use serde::Deserialize;
+
+/// Set on a `?distinct=true` response when the dedup set hit its cap and the
+/// remaining rows were passed through unfiltered.
+pub const DISTINCT_PARTIAL_HEADER: HeaderName = HeaderName::from_static("x-distinct-partial");
+
+/// Cap on the distinct rows remembered per request (~1.6 MB of hashes).
+pub(crate) const DISTINCT_MAX_ROWS: usize = 100_000;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DistinctParams {
+    #[serde(default, deserialize_with = "crate::from_query_str")]
+    pub distinct: bool,
+}
+
+/// Drops rows equal to an earlier row, keeping first occurrences in order.
+///
+/// Only a 64-bit hash of each row is kept, so at most `max_rows` hashes are
+/// held. Once that many distinct rows have been seen, later rows pass through
+/// unfiltered and the second value is `true`.
+pub(crate) fn dedup(rows: Vec<Vec<String>>, max_rows: usize) -> (Vec<Vec<String>>, bool) {
+    let hasher = RandomState::new();
+    let mut seen = HashSet::with_capacity(rows.len().min(max_rows));
+    let mut partial = false;
+    let rows = rows
+        .into_iter()
+        .filter(|row| {
+            if partial {
+                return true;
+            }
+            let hash = hasher.hash_one(row);
+            if seen.contains(&hash) {
+                return false;
+            }
+            if seen.len() == max_rows {
+                partial = true;
+            } else {
+                seen.insert(hash);
+            }
+            true
+        })
+        .collect();
+    (rows, partial)
+}
@@ -0,0 +1,288 @@
+use axum::{
+    async_trait,
+    body::{to_bytes, Body},
+    extract::{rejection::JsonRejection, FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use kadedb_services_ffi::{ErrorCode, FfiError};
+use kadedb_services_telemetry::RequestId;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error_response, AppState, ErrorResponse};
+
+pub use kadedb_services_ffi::ErrorVerbosity;
+
+/// One field-level problem in a well-formed request. `field` is a JSON
+/// Pointer into the request body, e.g. `/columns/1/name`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub(crate) fn new(field: impl Into<String>, message: impl ToString) -> Self {
+        Self {
+            field: field.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A failed request, rendered as `{"ok": false, "error": ...}` with its
+/// status. Storage errors convert with `?`, so handlers don't match on
+/// [`FfiError`] variants themselves.
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    code: ErrorCode,
+    message: String,
+    details: Vec<FieldError>,
+    /// The message describes storage internals rather than the request; see
+    /// [`ErrorVerbosity::Minimal`].
+    internal: bool,
+    /// Storage itself failed; see [`FfiError::is_storage_failure`].
+    storage_failure: bool,
+}
+
+impl ApiError {
+    pub(crate) fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            code: ErrorCode::from_http_status(status.as_u16()),
+            message: message.to_string(),
+            details: Vec::new(),
+            internal: false,
+            storage_failure: false,
+        }
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Overrides the code [`ApiError::new`] derived from the status.
+    pub(crate) fn with_code(self, code: ErrorCode) -> Self {
+        Self { code, ..self }
+    }
+
+    /// Whether the error counts towards the pool's circuit breaker.
+    pub(crate) fn is_storage_failure(&self) -> bool {
+        self.storage_failure
+    }
+
+    /// 422 for a request that parsed but failed validation, listing every
+    /// problem found.
+    pub(crate) fn invalid(details: Vec<FieldError>) -> Self {
+        let message = match details.as_slice() {
+            [only] => format!("{}: {}", only.field, only.message),
+            _ => format!("{} invalid fields", details.len()),
+        };
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            code: ErrorCode::InvalidRequest,
+            message,
+            details,
+            internal: false,
+            storage_failure: false,
+        }
+    }
+}
+
+/// The HTTP status for a storage error: caller mistakes are 4xx, an
+/// unavailable backend is 503 and a timeout 504. Mirrors the gRPC mapping in
+/// the ffi crate.
+pub(crate) fn ffi_status(err: &FfiError) -> StatusCode {
+    match err {
+        FfiError::ExecuteQueryFailed
+        | FfiError::ParamCountMismatch { .. }
+        | FfiError::InvalidParam { .. }
+        | FfiError::ParamTypeMismatch { .. }
+        | FfiError::ArityMismatch { .. }
+        | FfiError::TypeMismatch { .. }
+        | FfiError::QueryTooLong { .. } => StatusCode::BAD_REQUEST,
+        FfiError::UnknownTable(_) => StatusCode::NOT_FOUND,
+        FfiError::StatementNotAllowed(_) => StatusCode::FORBIDDEN,
+        FfiError::Cancelled => StatusCode::CONFLICT,
+        FfiError::CreateStorageFailed
+        | FfiError::NativeUnavailable
+        | FfiError::PoolBusy
+        | FfiError::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+        FfiError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        FfiError::CreateTableFailed(_)
+        | FfiError::ListTablesFailed
+        | FfiError::InsertFailed { .. }
+        | FfiError::DeleteFailed { .. }
+        | FfiError::UpdateFailed { .. }
+        | FfiError::Utf8(_)
+        | FfiError::Nul(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl From<FfiError> for ApiError {
+    fn from(err: FfiError) -> Self {
+        Self {
+            internal: err.exposes_internals(),
+            storage_failure: err.is_storage_failure(),
+            code: ErrorCode::from(&err),
+            ..Self::new(ffi_status(&err), err)
+        }
+    }
+}
+
+impl From<(StatusCode, Json<ErrorResponse>)> for ApiError {
+    fn from((status, Json(body)): (StatusCode, Json<ErrorResponse>)) -> Self {
+        Self {
+            status,
+            code: ErrorCode::parse(body.code)
+                .unwrap_or_else(|| ErrorCode::from_http_status(status.as_u16())),
+            message: body.error,
+            details: body.details,
+            internal: false,
+            storage_failure: false,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, Json(mut body)) = error_response(self.status, self.message);
+        body.code = self.code.as_str();
+        body.details = self.details;
+        let mut res = (status, Json(body)).into_response();
+        if self.internal {
+            res.extensions_mut().insert(InternalError);
+        }
+        res
+    }
+}
+
+/// Marks an error response whose message [`ErrorVerbosity::Minimal`] hides.
+#[derive(Debug, Clone, Copy)]
+struct InternalError;
+
+/// Applies [`ErrorVerbosity::Minimal`] to outgoing responses.
+pub(crate) async fn minimal_errors(req: Request, next: Next) -> Response {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let mut res = next.run(req).await;
+    if let Some(challenge) = res.headers().get(header::WWW_AUTHENTICATE) {
+        // `auth_rejection` writes `error_description` last.
+        let kept = challenge
+            .to_str()
+            .ok()
+            .and_then(|v| v.split_once(", error_description="))
+            .and_then(|(kept, _)| HeaderValue::from_str(kept).ok());
+        if let Some(kept) = kept {
+            res.headers_mut().insert(header::WWW_AUTHENTICATE, kept);
+        }
+    }
+    if res.extensions().get::<InternalError>().is_none() {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let original = to_bytes(body, usize::MAX)
+        .await
+        .ok()
+        .and_then(|b| serde_json::from_slice::<serde_json::Value>(&b).ok());
+    let field = |name: &str| {
+        original
+            .as_ref()
+            .and_then(|v| v.get(name).and_then(|e| e.as_str()).map(str::to_string))
+    };
+    let error = field("error").unwrap_or_default();
+    let code = field("code").and_then(|code| ErrorCode::parse(&code));
+    let correlation_id = request_id.unwrap_or_else(RequestId::generate).to_string();
+    tracing::warn!(%correlation_id, status = %parts.status, %error, "request failed");
+
+    let (_, Json(mut body)) = error_response(parts.status, generic_message(parts.status));
+    // The code stays: it says what kind of failure, not what failed.
+    if let Some(code) = code {
+        body.code = code.as_str();
+    }
+    body.correlation_id = Some(correlation_id);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let bytes = serde_json::to_vec(&body).expect("serialize error");
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// What [`ErrorVerbosity::Minimal`] says in place of a hidden message.
+fn generic_message(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .unwrap_or("request failed")
+        .to_lowercase()
+}
+
+/// Words storage errors that a handler reports outside an [`ApiError`] (an
+/// NDJSON error line, an import summary), where [`minimal_errors`] can't
+/// see them.
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorReport {
+    verbosity: ErrorVerbosity,
+    request_id: Option<RequestId>,
+}
+
+impl ErrorReport {
+    /// `err`'s message for the client and, when [`ErrorVerbosity::Minimal`]
+    /// hides it, the correlation id the full error was logged under; the
+    /// message is then a generic one.
+    pub(crate) fn message(&self, err: &FfiError) -> (String, Option<String>) {
+        if !self.verbosity.hides(err) {
+            return (err.to_string(), None);
+        }
+        let status = ffi_status(err);
+        let correlation_id = self
+            .request_id
+            .clone()
+            .unwrap_or_else(RequestId::generate)
+            .to_string();
+        tracing::warn!(%correlation_id, %status, error = %err, "request failed");
+        (generic_message(status), Some(correlation_id))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ErrorReport {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            verbosity: state.config.error_verbosity,
+            request_id: parts.extensions.get::<RequestId>().cloned(),
+        })
+    }
+}
+
+/// A JSON body whose parse failures are 400 in the usual error envelope.
+///
+/// axum's `Json` answers a body of the wrong shape with a plain-text 422;
+/// here 422 is kept for bodies that parse but fail validation.
+pub(crate) struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for ApiJson<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(req, state).await {
+            Ok(Json(value)) => Ok(Self(value)),
+            Err(err @ JsonRejection::MissingJsonContentType(_)) => {
+                Err(ApiError::new(err.status(), err.body_text()))
+            }
+            Err(err) => Err(ApiError::new(StatusCode::BAD_REQUEST, err.body_text())),
+        }
+    }
+}
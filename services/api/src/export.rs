@@ -0,0 +1,308 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use kadedb_services_ffi::{ColumnType, FfiError, StatementKind};
+use kadedb_services_telemetry::record_query;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checksum::{ChecksumParams, CHECKSUM_HEADER},
+    consistency::ConsistencyParams,
+    error::ApiError,
+    error_response, keyset,
+    queries::{QueryTag, Subject, QUERY_ID_HEADER},
+    restricted::TableAccess,
+    tenant::TenantPool,
+    AppState, Deadline,
+};
+
+/// Response header carrying the cursor for the next chunk; absent on the last one.
+pub const NEXT_CURSOR_HEADER: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// Rows per chunk when the caller doesn't ask for a size.
+const DEFAULT_CHUNK_ROWS: usize = 10_000;
+
+/// How long a cursor stays valid after it's issued.
+const CURSOR_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Signs and verifies export cursors.
+///
+/// Cursors are signed with the JWT secret when one is configured, so they
+/// survive restarts and work across replicas sharing the secret. Otherwise a
+/// per-process key is generated and cursors die with the process.
+#[derive(Clone)]
+pub(crate) struct CursorKeys(Arc<(EncodingKey, DecodingKey)>);
+
+impl CursorKeys {
+    pub(crate) fn new(secret: Option<&str>) -> Self {
+        let secret = match secret {
+            Some(s) => s.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0u8; 32];
+                getrandom::getrandom(&mut key).expect("generate export cursor key");
+                key
+            }
+        };
+        Self(Arc::new((
+            EncodingKey::from_secret(&secret),
+            DecodingKey::from_secret(&secret),
+        )))
+    }
+
+    fn sign(&self, cursor: &Cursor) -> Option<String> {
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), cursor, &self.0 .0).ok()
+    }
+
+    fn verify(&self, token: &str) -> Option<Cursor> {
+        jsonwebtoken::decode(token, &self.0 .1, &Validation::new(Algorithm::HS256))
+            .ok()
+            .map(|data| data.claims)
+    }
+}
+
+/// Where an export stopped: the query to re-run and how many rows of its
+/// result have already been delivered.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    query: String,
+    offset: usize,
+    limit: usize,
+    exp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ExportParams {
+    query: Option<String>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+    null_as: Option<String>,
+}
+
+/// Longest accepted NULL sentinel.
+const MAX_NULL_AS_LEN: usize = 16;
+
+/// Checks a NULL sentinel for CSV export. Anything that would need quoting is
+/// rejected, since a quoted sentinel reads back as a string. A sentinel that
+/// also occurs as real data (say `NULL` in a name column) is not detected;
+/// callers pick one that can't.
+pub(crate) fn validate_null_as(value: &str) -> Result<(), String> {
+    if value.len() > MAX_NULL_AS_LEN {
+        return Err(format!(
+            "`null_as` is longer than {MAX_NULL_AS_LEN} characters"
+        ));
+    }
+    if needs_quoting(value) || value.trim() != value {
+        return Err(format!(
+            "`null_as` must not contain delimiters, quotes, line breaks or surrounding spaces: {value:?}"
+        ));
+    }
+    Ok(())
+}
+
+/// `GET /export?query=...&limit=N` / `GET /export?cursor=...`
+///
+/// Returns the result as CSV (header record first), at most `limit` rows per
+/// response. NULL cells are written as `null_as` (default: the configured
+/// sentinel, normally an empty unquoted field). If rows remain, the response carries an `X-Next-Cursor` header;
+/// passing it back as `?cursor=` resumes where the previous chunk ended.
+/// With `checksum=sha256`, `X-Checksum` carries the digest of the body.
+/// SELECTs run on the pool's read replica, if any, unless
+/// `consistency=strong`. Reading stops with 504 once the request's deadline
+/// passes, and the pool slot is held until the engine is done.
+///
+/// A cursor records the query and a row offset, and the server re-runs the
+/// query and skips to that offset on every resume. Chunks are therefore only
+/// consistent with each other if the query has a stable ordering (an
+/// `ORDER BY` on a unique key) and the underlying data isn't modified
+/// mid-export.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn export(
+    State(state): State<AppState>,
+    TenantPool(pool): TenantPool,
+    QueryTag(tag): QueryTag,
+    Subject(subject): Subject,
+    access: TableAccess,
+    Query(params): Query<ExportParams>,
+    Query(checksum): Query<ChecksumParams>,
+    Query(consistency): Query<ConsistencyParams>,
+    deadline: Option<Extension<Deadline>>,
+    headers: HeaderMap,
+) -> Response {
+    let digest = match checksum.digest() {
+        Ok(digest) => digest,
+        Err(err) => return err.into_response(),
+    };
+    let (query, offset, limit) = match (params.cursor, params.query) {
+        (Some(token), None) => match state.cursors.verify(&token) {
+            Some(c) => (c.query, c.offset, params.limit.unwrap_or(c.limit)),
+            None => {
+                return error_response(StatusCode::BAD_REQUEST, "invalid or expired cursor")
+                    .into_response()
+            }
+        },
+        (None, Some(query)) => (query, 0, params.limit.unwrap_or(DEFAULT_CHUNK_ROWS)),
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "exactly one of `query` or `cursor` is required",
+            )
+            .into_response()
+        }
+    };
+    let null_as = params
+        .null_as
+        .unwrap_or_else(|| state.config.csv_null_as.clone());
+    if let Err(err) = validate_null_as(&null_as) {
+        return error_response(StatusCode::BAD_REQUEST, err).into_response();
+    }
+    if limit == 0 {
+        return error_response(StatusCode::BAD_REQUEST, "`limit` must be positive").into_response();
+    }
+    if let Err(err) = access.check(&query) {
+        return err.into_response();
+    }
+
+    let pool = consistency.route(&pool, &query);
+    let active = match state.queries.register(&headers, subject.clone()) {
+        Ok(active) => active,
+        Err(err) => return err.into_response(),
+    };
+    let cancel = active.token();
+    let started = Instant::now();
+    let guard = match pool.acquire().await {
+        Ok(guard) => guard,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let storage = guard.storage();
+    let sql = query.clone();
+    let kind = StatementKind::of(&sql).as_str();
+    let slow = state.live.slow_queries();
+    let span = tracing::info_span!("query.execute", tag = %tag, offset, limit);
+    // The closure owns the pool slot, so it stays taken until the engine is
+    // done even if the request goes away first.
+    let result = kadedb_services_ffi::spawn_query(query.clone(), move || {
+        let _entered = span.enter();
+        let run = || {
+            let executing = Instant::now();
+            let statement = storage.prepare(&sql);
+            let mut rs = storage.execute_prepared(&statement, &[])?;
+            let columns = rs.columns();
+
+            let mut out = String::new();
+            push_record(&mut out, columns.iter().map(|c| c.name.as_str()));
+            let mut reader = rs.row_reader().with_cancel(cancel);
+            if let Some(Extension(Deadline(deadline))) = deadline {
+                reader = reader.with_deadline(deadline);
+            }
+            let mut skipped = 0;
+            while skipped < offset && reader.next_row()?.is_some() {
+                skipped += 1;
+            }
+            let mut written = 0;
+            let mut more = false;
+            while let Some(row) = reader.next_row()? {
+                if written == limit {
+                    more = true;
+                    break;
+                }
+                push_record(
+                    &mut out,
+                    (0..row.len()).map(|i| {
+                        if row.is_null(i) {
+                            null_as.as_str()
+                        } else if columns[i].column_type == ColumnType::String {
+                            keyset::unquote(row.get(i))
+                        } else {
+                            row.get(i)
+                        }
+                    }),
+                );
+                written += 1;
+            }
+            slow.record(&sql, executing.elapsed(), written, subject.as_deref());
+            Ok::<_, FfiError>((out, more))
+        };
+        let result = run();
+        if result.as_ref().is_err_and(FfiError::is_storage_failure) {
+            guard.record_failure();
+        }
+        result
+    })
+    .await
+    .expect("spawn_blocking");
+    record_query(&tag, kind, result.is_ok(), started.elapsed());
+
+    let (body, more) = match result {
+        Ok(r) => r,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+
+    let checksum = digest.map(|mut digest| {
+        digest.update(body.as_bytes());
+        digest.finish()
+    });
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (QUERY_ID_HEADER, active.id().to_string()),
+        ],
+        body,
+    )
+        .into_response();
+    if let Some(checksum) = checksum {
+        let value = checksum
+            .parse()
+            .expect("hex digest is a valid header value");
+        response.headers_mut().insert(CHECKSUM_HEADER, value);
+    }
+    if more {
+        let exp = (SystemTime::now() + CURSOR_TTL)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let next = Cursor {
+            query,
+            offset: offset + limit,
+            limit,
+            exp,
+        };
+        match state.cursors.sign(&next).map(|t| t.parse()) {
+            Some(Ok(value)) => {
+                response.headers_mut().insert(NEXT_CURSOR_HEADER, value);
+            }
+            _ => {
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to issue cursor")
+                    .into_response()
+            }
+        }
+    }
+    response
+}
+
+/// Appends one CSV record, quoting fields that need it.
+fn push_record<'a>(out: &mut String, fields: impl Iterator<Item = &'a str>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if needs_quoting(field) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+fn needs_quoting(field: &str) -> bool {
+    field.contains([',', '"', '\n', '\r'])
+}
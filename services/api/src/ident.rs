@@ -0,0 +1,68 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+
+use crate::{error_response, ErrorResponse};
+
+/// Longest identifier accepted, matching common SQL engine limits.
+const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// Fragments that never belong in an identifier. The character check below
+/// already excludes them; this is a second line of defense should that check
+/// ever be loosened (e.g. to allow quoted identifiers).
+const DENYLIST: &[&str] = &["--", ";", "/*", "*/", "'", "\"", "\\", "\0"];
+
+/// Keywords refused as bare identifiers.
+const RESERVED: &[&str] = &[
+    "alter", "create", "delete", "drop", "from", "insert", "select", "table", "truncate", "union",
+    "update", "where",
+];
+
+/// Checks that `name` is a plain SQL identifier: an ASCII letter or
+/// underscore followed by letters, digits or underscores.
+pub(crate) fn validate_identifier(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("identifier must not be empty".to_string());
+    }
+    if name.len() > MAX_IDENTIFIER_LEN {
+        return Err(format!(
+            "identifier longer than {MAX_IDENTIFIER_LEN} characters"
+        ));
+    }
+    if DENYLIST.iter().any(|bad| name.contains(bad)) {
+        return Err(format!("invalid identifier `{name}`"));
+    }
+
+    let mut chars = name.chars();
+    let starts_ok = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !starts_ok || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid identifier `{name}`"));
+    }
+
+    if RESERVED.iter().any(|kw| name.eq_ignore_ascii_case(kw)) {
+        return Err(format!("`{name}` is a reserved word"));
+    }
+    Ok(())
+}
+
+/// The `:name` path segment of `/tables/:name/...` routes, validated as an
+/// identifier before any handler logic runs.
+pub(crate) struct TableName(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TableName {
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(name) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| error_response(StatusCode::BAD_REQUEST, err.body_text()))?;
+        validate_identifier(&name).map_err(|err| error_response(StatusCode::BAD_REQUEST, err))?;
+        Ok(Self(name))
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{body::Body, extract::Query, http::StatusCode, Json};
+use csv_core::ReadRecordResult;
+use futures_util::StreamExt;
+use kadedb_services_ffi::{
+    spawn_query, ColumnInfo, ColumnType, FfiError, PoolGuard, PreparedInsert, StatementKind, Value,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ffi_status, ErrorReport},
+    ident::TableName,
+    tenant::TenantPool,
+};
+
+/// Rows are handed to storage in batches of this size.
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// Upper bound on per-row errors reported back when `continue_on_error` is set.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// Longest CSV record accepted, in bytes of field data; a record also has at
+/// most this many fields.
+const MAX_RECORD_BYTES: usize = 1 << 20;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ImportParams {
+    format: Option<String>,
+    #[serde(default)]
+    continue_on_error: bool,
+    /// How empty fields are read; see [`EmptyFields::parse`].
+    empty: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ImportResponse {
+    imported: usize,
+    errors: Vec<ImportError>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportError {
+    /// 1-based data row number (the header is not counted); absent for
+    /// request-level errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row: Option<usize>,
+    /// The column whose field couldn't be coerced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<String>,
+    message: String,
+    /// Set when the message is withheld under minimal error verbosity: the
+    /// id the full error was logged under.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+}
+
+impl ImportError {
+    fn request(message: impl Into<String>) -> Self {
+        Self {
+            row: None,
+            column: None,
+            message: message.into(),
+            correlation_id: None,
+        }
+    }
+
+    fn row(row: usize, message: impl Into<String>) -> Self {
+        Self {
+            row: Some(row),
+            column: None,
+            message: message.into(),
+            correlation_id: None,
+        }
+    }
+
+    /// A storage failure, worded as `report` allows.
+    fn storage(row: Option<usize>, err: &FfiError, report: &ErrorReport) -> Self {
+        let (message, correlation_id) = report.message(err);
+        Self {
+            row,
+            column: None,
+            message,
+            correlation_id,
+        }
+    }
+}
+
+/// What an empty CSV field becomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum EmptyField {
+    #[default]
+    Null,
+    /// The type's zero value: `0`, `0.0`, `""` or `false`.
+    Default,
+    /// The row fails.
+    Error,
+}
+
+impl EmptyField {
+    fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "null" => Some(Self::Null),
+            "default" => Some(Self::Default),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Empty-field handling for each column of an import.
+#[derive(Debug, Default)]
+struct EmptyFields {
+    fallback: EmptyField,
+    columns: HashMap<String, EmptyField>,
+}
+
+impl EmptyFields {
+    /// Parses the `empty` parameter: comma-separated `column:mode` pairs,
+    /// plus optionally a bare mode for every other column, e.g.
+    /// `error,notes:null`. Modes are `null` (the default), `default` and
+    /// `error`.
+    fn parse(spec: &str, columns: &[ColumnInfo]) -> Result<Self, String> {
+        let mut fields = Self::default();
+        for part in spec.split(',').map(str::trim) {
+            let (column, mode) = match part.split_once(':') {
+                Some((column, mode)) => (Some(column.trim()), mode.trim()),
+                None => (None, part),
+            };
+            let mode = EmptyField::parse(mode).ok_or_else(|| {
+                format!("invalid empty-field mode `{mode}`: expected null, default or error")
+            })?;
+            match column {
+                Some(column) if columns.iter().any(|c| c.name == column) => {
+                    fields.columns.insert(column.to_string(), mode);
+                }
+                Some(column) => return Err(format!("unknown column `{column}` in `empty`")),
+                None => fields.fallback = mode,
+            }
+        }
+        Ok(fields)
+    }
+
+    /// The mode of each table column, in schema order.
+    fn resolve(&self, columns: &[ColumnInfo]) -> Vec<EmptyField> {
+        columns
+            .iter()
+            .map(|c| *self.columns.get(&c.name).unwrap_or(&self.fallback))
+            .collect()
+    }
+}
+
+fn reject(status: StatusCode, err: ImportError) -> (StatusCode, Json<ImportResponse>) {
+    (
+        status,
+        Json(ImportResponse {
+            imported: 0,
+            errors: vec![err],
+        }),
+    )
+}
+
+/// `POST /tables/:name/import?format=csv`
+///
+/// The body is parsed incrementally; the first record is a header naming the
+/// target columns (columns absent from the header are inserted as NULL).
+/// Malformed CSV fails the import with 400; a header naming unknown columns
+/// or fields that don't fit their column fail it with 422, naming the `row`
+/// and `column`.
+///
+/// Fields are parsed as their column's type. Empty fields are NULL unless
+/// `empty` says otherwise for the column (see [`EmptyFields::parse`]).
+///
+/// Validated rows are flushed to storage batch by batch, keeping memory
+/// bounded by the batch size. The native storage layer has no transactions,
+/// so by default the import stops at the first failing row: batches already
+/// flushed stay applied and are counted in `imported`, the rest of the body is
+/// discarded. With `continue_on_error=true` failing rows are skipped instead.
+///
+/// Under minimal error verbosity, storage failures are reported with a
+/// generic message and a `correlation_id`, like other error responses.
+pub(crate) async fn import_table(
+    TenantPool(pool): TenantPool,
+    TableName(table): TableName,
+    Query(params): Query<ImportParams>,
+    report: ErrorReport,
+    body: Body,
+) -> (StatusCode, Json<ImportResponse>) {
+    match params.format.as_deref().unwrap_or("csv") {
+        "csv" => {}
+        other => {
+            return reject(
+                StatusCode::BAD_REQUEST,
+                ImportError::request(format!("unsupported import format `{other}`")),
+            )
+        }
+    }
+
+    // Hold one pool slot for the whole import.
+    let guard = match pool.acquire().await {
+        Ok(guard) => guard,
+        Err(err) => return reject(ffi_status(&err), ImportError::storage(None, &err, &report)),
+    };
+    let storage = guard.storage();
+    if let Err(err) = storage
+        .allowed_statements()
+        .check_kind(StatementKind::Insert)
+    {
+        return reject(ffi_status(&err), ImportError::storage(None, &err, &report));
+    }
+    let prepared = {
+        let table = table.clone();
+        let span = tracing::info_span!("query.prepare", table = %table);
+        let context = format!("import into {table}");
+        spawn_query(context, move || {
+            span.in_scope(|| storage.insert_target(&table))
+        })
+        .await
+        .expect("spawn_blocking")
+    };
+    let prepared = match prepared {
+        Ok(p) => p,
+        Err(err) => return reject(ffi_status(&err), ImportError::storage(None, &err, &report)),
+    };
+    let empty = match params.empty.as_deref() {
+        Some(spec) => match EmptyFields::parse(spec, prepared.columns()) {
+            Ok(empty) => empty,
+            Err(msg) => return reject(StatusCode::BAD_REQUEST, ImportError::request(msg)),
+        },
+        None => EmptyFields::default(),
+    }
+    .resolve(prepared.columns());
+
+    let mut import = Import {
+        guard,
+        prepared,
+        continue_on_error: params.continue_on_error,
+        mapping: None,
+        empty,
+        row: 0,
+        pending: Vec::new(),
+        imported: 0,
+        errors: Vec::new(),
+        report,
+    };
+
+    let mut parser = CsvRecords::new();
+    let mut records = Vec::new();
+    let mut stream = body.into_data_stream();
+    loop {
+        let chunk = stream.next().await;
+        let done = chunk.is_none();
+        let read = match chunk {
+            Some(Ok(bytes)) => parser.push(&bytes, &mut records),
+            Some(Err(err)) => {
+                return reject(
+                    StatusCode::BAD_REQUEST,
+                    ImportError::request(format!("failed to read body: {err}")),
+                )
+            }
+            None => parser.finish(&mut records),
+        };
+
+        for record in records.drain(..) {
+            if let Err(status) = import.record(record).await {
+                return import.into_response(status);
+            }
+        }
+        if let Err(msg) = read {
+            let err = match import.mapping {
+                Some(_) => ImportError::row(import.row + 1, msg),
+                None => ImportError::request(msg),
+            };
+            let status = import.fail(StatusCode::BAD_REQUEST, err);
+            return import.into_response(status);
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    if import.mapping.is_none() {
+        return reject(
+            StatusCode::BAD_REQUEST,
+            ImportError::request("missing header row"),
+        );
+    }
+
+    if let Err(status) = import.flush().await {
+        return import.into_response(status);
+    }
+    import.into_response(StatusCode::OK)
+}
+
+struct Import {
+    guard: PoolGuard,
+    prepared: Arc<PreparedInsert>,
+    continue_on_error: bool,
+    /// For each header field, the index of the table column it fills.
+    mapping: Option<Vec<usize>>,
+    /// Empty-field handling per table column.
+    empty: Vec<EmptyField>,
+    row: usize,
+    pending: Vec<(usize, Vec<Value>)>,
+    imported: usize,
+    errors: Vec<ImportError>,
+    report: ErrorReport,
+}
+
+impl Import {
+    async fn record(&mut self, record: Result<Vec<String>, String>) -> Result<(), StatusCode> {
+        if self.mapping.is_none() {
+            let header = record
+                .map_err(|msg| self.fail(StatusCode::BAD_REQUEST, ImportError::request(msg)))?;
+            let mapping = header_mapping(self.prepared.columns(), &header).map_err(|msg| {
+                self.fail(StatusCode::UNPROCESSABLE_ENTITY, ImportError::request(msg))
+            })?;
+            self.mapping = Some(mapping);
+            return Ok(());
+        }
+
+        self.row += 1;
+        let row = self.row;
+        let mapping = self.mapping.as_deref().expect("header mapped");
+        // Malformed CSV is a 400; well-formed fields that don't fit the
+        // table are a 422.
+        let values = record
+            .map_err(|msg| (StatusCode::BAD_REQUEST, ImportError::row(row, msg)))
+            .and_then(|fields| {
+                coerce_row(self.prepared.columns(), mapping, &self.empty, &fields)
+                    .and_then(|values| {
+                        self.prepared.check_row(&values).map_err(|err| {
+                            let column = match &err {
+                                FfiError::TypeMismatch { column, .. } => Some(column.clone()),
+                                _ => None,
+                            };
+                            (column, err.to_string())
+                        })?;
+                        Ok(values)
+                    })
+                    .map_err(|(column, msg)| {
+                        let mut err = ImportError::row(row, msg);
+                        err.column = column;
+                        (StatusCode::UNPROCESSABLE_ENTITY, err)
+                    })
+            });
+
+        match values {
+            Ok(values) => self.pending.push((row, values)),
+            Err((status, err)) => self.row_error(status, err)?,
+        }
+
+        if self.pending.len() >= IMPORT_BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), StatusCode> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let storage = self.guard.storage();
+        let prepared = self.prepared.clone();
+        let batch = std::mem::take(&mut self.pending);
+        let continue_on_error = self.continue_on_error;
+        let span = tracing::info_span!("query.execute", rows = batch.len());
+        let context = format!("import into {}", prepared.table());
+        let (imported, failures) = spawn_query(context, move || {
+            let _entered = span.enter();
+            let mut imported = 0usize;
+            let mut failures = Vec::new();
+            for (row, values) in batch {
+                match prepared.execute(&storage, &values) {
+                    Ok(()) => imported += 1,
+                    Err(err) => {
+                        failures.push((row, err));
+                        if !continue_on_error {
+                            break;
+                        }
+                    }
+                }
+            }
+            (imported, failures)
+        })
+        .await
+        .expect("spawn_blocking");
+
+        self.imported += imported;
+        for (row, err) in failures {
+            let failure = ImportError::storage(Some(row), &err, &self.report);
+            self.row_error(StatusCode::UNPROCESSABLE_ENTITY, failure)?;
+        }
+        Ok(())
+    }
+
+    fn row_error(&mut self, status: StatusCode, err: ImportError) -> Result<(), StatusCode> {
+        if !self.continue_on_error {
+            return Err(self.fail(status, err));
+        }
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(err);
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, status: StatusCode, err: ImportError) -> StatusCode {
+        self.pending.clear();
+        self.errors.push(err);
+        status
+    }
+
+    fn into_response(self, status: StatusCode) -> (StatusCode, Json<ImportResponse>) {
+        (
+            status,
+            Json(ImportResponse {
+                imported: self.imported,
+                errors: self.errors,
+            }),
+        )
+    }
+}
+
+fn header_mapping(columns: &[ColumnInfo], header: &[String]) -> Result<Vec<usize>, String> {
+    let mut mapping = Vec::with_capacity(header.len());
+    for name in header {
+        let name = name.trim();
+        let idx = columns
+            .iter()
+            .position(|c| c.name == name)
+            .ok_or_else(|| format!("unknown column `{name}` in header"))?;
+        if mapping.contains(&idx) {
+            return Err(format!("duplicate column `{name}` in header"));
+        }
+        mapping.push(idx);
+    }
+    Ok(mapping)
+}
+
+/// Parses a record's fields into a row in schema order. Errors name the
+/// column when one field is to blame.
+fn coerce_row(
+    columns: &[ColumnInfo],
+    mapping: &[usize],
+    empty: &[EmptyField],
+    fields: &[String],
+) -> Result<Vec<Value>, (Option<String>, String)> {
+    if fields.len() != mapping.len() {
+        return Err((
+            None,
+            format!("expected {} fields, found {}", mapping.len(), fields.len()),
+        ));
+    }
+
+    let mut values = vec![Value::Null; columns.len()];
+    for (field, &idx) in fields.iter().zip(mapping) {
+        let col = &columns[idx];
+        let value = if field.is_empty() {
+            empty_field(col.column_type, empty[idx])
+                .ok_or_else(|| format!("column `{}`: empty field", col.name))
+        } else {
+            coerce_field(col.column_type, field).ok_or_else(|| {
+                format!(
+                    "column `{}`: invalid {:?} `{field}`",
+                    col.name, col.column_type
+                )
+            })
+        };
+        values[idx] = value.map_err(|msg| (Some(col.name.clone()), msg))?;
+    }
+    Ok(values)
+}
+
+/// An empty field under `mode`; `None` when it is an error.
+fn empty_field(ty: ColumnType, mode: EmptyField) -> Option<Value> {
+    match mode {
+        EmptyField::Null => Some(Value::Null),
+        EmptyField::Error => None,
+        EmptyField::Default => Some(match ty {
+            ColumnType::Null => Value::Null,
+            ColumnType::Integer => Value::Integer(0),
+            ColumnType::Float => Value::Float(0.0),
+            ColumnType::String => Value::String(String::new()),
+            ColumnType::Boolean => Value::Boolean(false),
+        }),
+    }
+}
+
+fn coerce_field(ty: ColumnType, field: &str) -> Option<Value> {
+    let trimmed = field.trim();
+    match ty {
+        ColumnType::Null => None,
+        ColumnType::Integer => trimmed.parse().ok().map(Value::Integer),
+        ColumnType::Float => trimmed.parse().ok().map(Value::Float),
+        ColumnType::String => Some(Value::String(field.to_string())),
+        ColumnType::Boolean => match trimmed.to_ascii_lowercase().as_str() {
+            "true" | "1" => Some(Value::Boolean(true)),
+            "false" | "0" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+    }
+}
+
+/// Incremental CSV record splitter over arbitrarily chunked input. A record
+/// past [`MAX_RECORD_BYTES`] fails the read, so one that never ends can't
+/// grow the buffers without bound.
+struct CsvRecords {
+    reader: csv_core::Reader,
+    out: Vec<u8>,
+    out_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+}
+
+impl CsvRecords {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            out: vec![0; 1024],
+            out_len: 0,
+            ends: vec![0; 32],
+            ends_len: 0,
+        }
+    }
+
+    fn push(
+        &mut self,
+        input: &[u8],
+        records: &mut Vec<Result<Vec<String>, String>>,
+    ) -> Result<(), String> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        self.read(input, records)
+    }
+
+    fn finish(&mut self, records: &mut Vec<Result<Vec<String>, String>>) -> Result<(), String> {
+        self.read(&[], records)
+    }
+
+    fn read(
+        &mut self,
+        mut input: &[u8],
+        records: &mut Vec<Result<Vec<String>, String>>,
+    ) -> Result<(), String> {
+        loop {
+            let (res, nin, nout, nend) = self.reader.read_record(
+                input,
+                &mut self.out[self.out_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[nin..];
+            self.out_len += nout;
+            self.ends_len += nend;
+
+            match res {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return Ok(()),
+                ReadRecordResult::OutputFull => grow(&mut self.out)?,
+                ReadRecordResult::OutputEndsFull => grow(&mut self.ends)?,
+                ReadRecordResult::Record => records.push(self.take_record()),
+            }
+        }
+    }
+
+    fn take_record(&mut self) -> Result<Vec<String>, String> {
+        let mut start = 0;
+        let mut fields = Vec::with_capacity(self.ends_len);
+        let mut result = Ok(());
+        for &end in &self.ends[..self.ends_len] {
+            match std::str::from_utf8(&self.out[start..end]) {
+                Ok(s) => fields.push(s.to_string()),
+                Err(_) => result = Err("field is not valid UTF-8".to_string()),
+            }
+            start = end;
+        }
+        self.out_len = 0;
+        self.ends_len = 0;
+        result.map(|_| fields)
+    }
+}
+
+/// Doubles a record buffer, up to [`MAX_RECORD_BYTES`] entries.
+fn grow<T: Default + Clone>(buf: &mut Vec<T>) -> Result<(), String> {
+    if buf.len() >= MAX_RECORD_BYTES {
+        return Err(format!("record longer than {MAX_RECORD_BYTES} bytes"));
+    }
+    buf.resize((buf.len() * 2).min(MAX_RECORD_BYTES), T::default());
+    Ok(())
+}
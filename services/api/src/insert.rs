@@ -0,0 +1,556 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{FromRequest, Query, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::StreamExt;
+use kadedb_services_ffi::{
+    spawn_query, ColumnType, FfiError, PoolGuard, Predicate, PreparedInsert, StatementKind,
+    StoragePool, Value,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    error::{ApiError, ApiJson, FieldError},
+    ident::TableName,
+    tenant::TenantPool,
+};
+
+/// Upper bound on field errors reported for one request.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// NDJSON rows are handed to storage in batches of this size.
+const NDJSON_BATCH_ROWS: usize = 500;
+
+/// Longest NDJSON line accepted.
+const MAX_LINE_BYTES: usize = 1 << 20;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct InsertParams {
+    /// Comma-separated key columns whose values are reported per inserted
+    /// row.
+    key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UpsertParams {
+    /// The column identifying the row.
+    key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UpsertResponse {
+    ok: bool,
+    /// `"inserted"` or `"updated"`.
+    result: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct InsertResponse {
+    ok: bool,
+    inserted: usize,
+    /// The key of each inserted row, in order, when `key` was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    affected_keys: Option<Vec<JsonValue>>,
+    /// The NDJSON line that stopped the insert.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Maps a JSON object onto `target`'s columns as a parameterized INSERT.
+///
+/// The statement names every column of the table in schema order with one
+/// `?` each, and the parameters follow that order; columns missing from the
+/// object are NULL. Object keys must be columns of the table, so nothing the
+/// client sends is ever spliced into the SQL.
+///
+/// The native layer inserts through its row API rather than parsing INSERT
+/// text, so the SQL is what traces record, while the parameters are the row
+/// handed to [`PreparedInsert::execute`].
+///
+/// Errors name the offending column in `field`.
+pub(crate) fn build_insert(
+    target: &PreparedInsert,
+    row: &Map<String, JsonValue>,
+) -> Result<(String, Vec<Value>), FieldError> {
+    let columns = target.columns();
+    let mut params = vec![Value::Null; columns.len()];
+    for (name, value) in row {
+        let idx = columns
+            .iter()
+            .position(|c| c.name == *name)
+            .ok_or_else(|| FieldError::new(name, format!("unknown column `{name}`")))?;
+        params[idx] = json_value(columns[idx].column_type, value)
+            .ok_or_else(|| FieldError::new(name, format!("unsupported value {value}")))?;
+    }
+    target.check_row(&params).map_err(|err| match &err {
+        FfiError::TypeMismatch { column, .. } => FieldError::new(column, &err),
+        _ => FieldError::new("", &err),
+    })?;
+
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        target.table(),
+        names.join(", "),
+        vec!["?"; names.len()].join(", ")
+    );
+    Ok((sql, params))
+}
+
+/// A JSON scalar as a storage value. Integers widen to float for FLOAT
+/// columns; arrays, objects and integers outside `i64` have no equivalent.
+fn json_value(ty: ColumnType, value: &JsonValue) -> Option<Value> {
+    Some(match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(b) => Value::Boolean(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) if ty == ColumnType::Float => Value::Float(i as f64),
+            Some(i) => Value::Integer(i),
+            None if n.is_f64() => Value::Float(n.as_f64()?),
+            None => return None,
+        },
+        JsonValue::String(s) => Value::String(s.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => return None,
+    })
+}
+
+/// `POST /tables/:name/rows`
+///
+/// The body is a JSON array of objects keyed by column name, or with
+/// `Content-Type: application/x-ndjson` one object per line (see
+/// [`insert_ndjson`]).
+///
+/// Every row of an array is checked with [`build_insert`] before any is
+/// applied, so a bad row rejects the whole request with 422 (each problem in
+/// `details`, as `/<row>/<column>`) and nothing is written. A row storage
+/// then fails on stops the insert with the failure's status; as there are
+/// no transactions, the rows before it stay applied and the response
+/// reports them in `inserted` (and `affected_keys`), as NDJSON does.
+///
+/// `key=<col>[,<col>...]` adds `affected_keys`: the key of each row applied,
+/// a value for one key column or an array for several. A table schema can
+/// declare a primary key (`KadeDB_TableSchema_SetPrimaryKey`), but the C
+/// API has no call reading a stored table's schema back, and no RETURNING,
+/// so the key columns are named by the caller and read from the rows as
+/// stored.
+pub(crate) async fn insert_rows(
+    TenantPool(pool): TenantPool,
+    TableName(table): TableName,
+    Query(params): Query<InsertParams>,
+    request: Request,
+) -> Response {
+    let ndjson = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with(NDJSON_CONTENT_TYPE));
+    if ndjson {
+        return insert_ndjson(pool, table, params.key, request.into_body()).await;
+    }
+    let rows = match ApiJson::<Vec<Map<String, JsonValue>>>::from_request(request, &()).await {
+        Ok(ApiJson(rows)) => rows,
+        Err(err) => return err.into_response(),
+    };
+    insert_array(pool, table, params.key, rows)
+        .await
+        .into_response()
+}
+
+/// `PUT /tables/:name/rows?key=<col>`
+///
+/// The body is one JSON object, checked as for `POST`. Rows whose `key`
+/// column equals the object's get the object's columns set, and when there
+/// are none the object is inserted, so the answer is
+/// `{"ok":true,"result":"updated"|"inserted"}`.
+///
+/// The engine has no `ON CONFLICT` or `MERGE`, so this is an update filtered
+/// on the key and then, if it matched nothing, an insert (see
+/// [`Storage::upsert`](kadedb_services_ffi::Storage::upsert)); upserts into
+/// one table run one at a time, so two with a new key don't both insert. Its
+/// updates filter on one column, so `key` names exactly one, and the
+/// object's value for it must not be NULL.
+pub(crate) async fn upsert_row(
+    TenantPool(pool): TenantPool,
+    TableName(table): TableName,
+    Query(params): Query<UpsertParams>,
+    ApiJson(row): ApiJson<Map<String, JsonValue>>,
+) -> Result<Json<UpsertResponse>, ApiError> {
+    let guard = pool.acquire().await?;
+    guard
+        .storage()
+        .allowed_statements()
+        .check_kind(StatementKind::Update)?;
+    let target = insert_target(&guard, &table).await?;
+    let key = match key_columns(&target, Some(&params.key))?.as_deref() {
+        Some(&[key]) => key,
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "`key` must name exactly one column",
+            ))
+        }
+    };
+    let (_, values) = build_insert(&target, &row).map_err(|err| ApiError::invalid(vec![err]))?;
+    let key_name = target.columns()[key].name.clone();
+    if values[key] == Value::Null {
+        return Err(ApiError::invalid(vec![FieldError::new(
+            &key_name,
+            "the key must not be null",
+        )]));
+    }
+
+    let assignments: Vec<(String, Value)> = target
+        .columns()
+        .iter()
+        .zip(&values)
+        .filter(|(column, _)| row.contains_key(&column.name))
+        .map(|(column, value)| (column.name.clone(), value.clone()))
+        .collect();
+    let sql = format!(
+        "UPDATE {table} SET {} WHERE {key_name} = ?",
+        assignments
+            .iter()
+            .map(|(name, _)| format!("{name} = ?"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let predicate = Predicate::eq(key_name, values[key].clone());
+    let storage = guard.storage();
+    let span = tracing::info_span!("query.execute", sql = sql.as_str(), rows = 1);
+    let result = spawn_query(format!("upsert into {table}"), move || {
+        let _guard = guard;
+        let _entered = span.enter();
+        let inserted = storage.upsert(&target, &values, &assignments, &predicate)?;
+        Ok::<_, FfiError>(if inserted { "inserted" } else { "updated" })
+    })
+    .await
+    .expect("spawn_blocking")?;
+
+    Ok(Json(UpsertResponse { ok: true, result }))
+}
+
+/// The positions of the `key` columns in `target`'s layout.
+fn key_columns(target: &PreparedInsert, key: Option<&str>) -> Result<Option<Vec<usize>>, ApiError> {
+    let Some(key) = key else {
+        return Ok(None);
+    };
+    let columns = target.columns();
+    key.split(',')
+        .map(|name| {
+            let name = name.trim();
+            columns.iter().position(|c| c.name == name).ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("`key` names unknown column `{name}`"),
+                )
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// The key of a row of `params`: the value of its single key column, or an
+/// array of them.
+fn row_key(key: &[usize], params: &[Value]) -> JsonValue {
+    match key {
+        [idx] => key_json(&params[*idx]),
+        _ => key.iter().map(|&idx| key_json(&params[idx])).collect(),
+    }
+}
+
+fn key_json(value: &Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Integer(i) => (*i).into(),
+        Value::Float(f) => (*f).into(),
+        Value::String(s) => s.clone().into(),
+        Value::Boolean(b) => (*b).into(),
+        Value::Array(items) => items.iter().map(key_json).collect(),
+    }
+}
+
+/// The keys of the first `applied` of `statements`.
+fn applied_keys(
+    key: &[usize],
+    statements: &[(String, Vec<Value>)],
+    applied: usize,
+) -> Vec<JsonValue> {
+    statements[..applied]
+        .iter()
+        .map(|(_, params)| row_key(key, params))
+        .collect()
+}
+
+async fn insert_array(
+    pool: StoragePool,
+    table: String,
+    key: Option<String>,
+    rows: Vec<Map<String, JsonValue>>,
+) -> Result<Response, ApiError> {
+    let guard = pool.acquire().await?;
+    let target = insert_target(&guard, &table).await?;
+    let key = key_columns(&target, key.as_deref())?;
+
+    let mut statements = Vec::with_capacity(rows.len());
+    let mut details = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        match build_insert(&target, row) {
+            Ok(statement) => statements.push(statement),
+            Err(mut err) if details.len() < MAX_REPORTED_ERRORS => {
+                err.field = format!("/{i}/{}", err.field);
+                details.push(err);
+            }
+            Err(_) => {}
+        }
+    }
+    if !details.is_empty() {
+        return Err(ApiError::invalid(details));
+    }
+
+    let mut affected_keys = key.map(|key| applied_keys(&key, &statements, statements.len()));
+    let (inserted, error) = match execute(&guard, target, statements).await {
+        Ok(inserted) => (inserted, None),
+        Err((applied, err)) => (applied, Some(err)),
+    };
+    if let Some(keys) = &mut affected_keys {
+        keys.truncate(inserted);
+    }
+    let response = Json(InsertResponse {
+        ok: error.is_none(),
+        inserted,
+        affected_keys,
+        line: None,
+        error: error.as_ref().map(ApiError::to_string),
+    });
+    Ok(match error {
+        Some(err) => (err.status(), response).into_response(),
+        None => response.into_response(),
+    })
+}
+
+/// An NDJSON insert: each line is checked and staged as it arrives, and
+/// staged rows are applied every [`NDJSON_BATCH_ROWS`], so memory stays
+/// bounded by the batch and the longest line.
+///
+/// Blank lines are skipped. At the first bad line (malformed JSON is 400, a
+/// row that doesn't fit the table 422) the rows before it are applied and the
+/// insert stops; the response gives that `line` (1-based) and the rows
+/// `inserted`, with their keys if asked for.
+async fn insert_ndjson(
+    pool: StoragePool,
+    table: String,
+    key: Option<String>,
+    body: Body,
+) -> Response {
+    let guard = match pool.acquire().await {
+        Ok(guard) => guard,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let target = match insert_target(&guard, &table).await {
+        Ok(target) => target,
+        Err(err) => return err.into_response(),
+    };
+    let key = match key_columns(&target, key.as_deref()) {
+        Ok(key) => key,
+        Err(err) => return err.into_response(),
+    };
+
+    let mut insert = NdjsonInsert {
+        guard,
+        target,
+        pending: Vec::new(),
+        inserted: 0,
+        affected_keys: key.as_ref().map(|_| Vec::new()),
+        key,
+        line: 0,
+    };
+    let mut buf = Vec::new();
+    let mut stream = body.into_data_stream();
+    loop {
+        let chunk = stream.next().await;
+        let done = chunk.is_none();
+        match chunk {
+            Some(Ok(bytes)) => buf.extend_from_slice(&bytes),
+            Some(Err(err)) => {
+                return insert
+                    .fail(
+                        StatusCode::BAD_REQUEST,
+                        format!("failed to read body: {err}"),
+                    )
+                    .await
+            }
+            // A final line without a trailing newline.
+            None if !buf.is_empty() => buf.push(b'\n'),
+            None => {}
+        }
+
+        let mut start = 0;
+        while let Some(end) = buf[start..].iter().position(|&b| b == b'\n') {
+            let line = &buf[start..start + end];
+            start += end + 1;
+            if let Err((status, msg)) = insert.line(line).await {
+                return insert.fail(status, msg).await;
+            }
+        }
+        buf.drain(..start);
+        if buf.len() > MAX_LINE_BYTES {
+            insert.line += 1;
+            return insert
+                .fail(
+                    StatusCode::BAD_REQUEST,
+                    format!("line longer than {MAX_LINE_BYTES} bytes"),
+                )
+                .await;
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    if let Err(err) = insert.flush().await {
+        return err.into_response();
+    }
+    Json(InsertResponse {
+        ok: true,
+        inserted: insert.inserted,
+        affected_keys: insert.affected_keys,
+        line: None,
+        error: None,
+    })
+    .into_response()
+}
+
+struct NdjsonInsert {
+    guard: PoolGuard,
+    target: Arc<PreparedInsert>,
+    pending: Vec<(String, Vec<Value>)>,
+    inserted: usize,
+    key: Option<Vec<usize>>,
+    /// Keys of the rows inserted so far; one entry per row, so this grows
+    /// with the body.
+    affected_keys: Option<Vec<JsonValue>>,
+    /// The last line read, 1-based.
+    line: usize,
+}
+
+impl NdjsonInsert {
+    async fn line(&mut self, line: &[u8]) -> Result<(), (StatusCode, String)> {
+        self.line += 1;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let row: Map<String, JsonValue> = serde_json::from_slice(line).map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid JSON object: {err}"),
+            )
+        })?;
+        let statement = build_insert(&self.target, &row).map_err(|err| {
+            let msg = match err.field.as_str() {
+                "" => err.message,
+                field => format!("{field}: {}", err.message),
+            };
+            (StatusCode::UNPROCESSABLE_ENTITY, msg)
+        })?;
+        self.pending.push(statement);
+        if self.pending.len() >= NDJSON_BATCH_ROWS {
+            self.flush()
+                .await
+                .map_err(|err| (err.status(), err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), ApiError> {
+        let batch = std::mem::take(&mut self.pending);
+        let keys = self
+            .key
+            .as_ref()
+            .map(|key| applied_keys(key, &batch, batch.len()));
+        let (applied, result) = match execute(&self.guard, self.target.clone(), batch).await {
+            Ok(n) => (n, Ok(())),
+            Err((n, err)) => (n, Err(err)),
+        };
+        self.inserted += applied;
+        if let (Some(affected), Some(keys)) = (&mut self.affected_keys, keys) {
+            affected.extend(keys.into_iter().take(applied));
+        }
+        result
+    }
+
+    /// Applies the rows before the failing line and reports the failure.
+    async fn fail(mut self, status: StatusCode, message: String) -> Response {
+        let (status, message) = match self.flush().await {
+            Ok(()) => (status, message),
+            Err(err) => (err.status(), err.to_string()),
+        };
+        (
+            status,
+            Json(InsertResponse {
+                ok: false,
+                inserted: self.inserted,
+                affected_keys: self.affected_keys,
+                line: Some(self.line),
+                error: Some(message),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// The cached insert layout of `table`, once inserts are known to be in the
+/// storage's allowed statements.
+async fn insert_target(guard: &PoolGuard, table: &str) -> Result<Arc<PreparedInsert>, ApiError> {
+    let storage = guard.storage();
+    storage
+        .allowed_statements()
+        .check_kind(StatementKind::Insert)?;
+    let table = table.to_string();
+    let context = format!("insert into {table}");
+    Ok(spawn_query(context, move || storage.insert_target(&table))
+        .await
+        .expect("spawn_blocking")?)
+}
+
+/// Applies `statements` in order, returning how many were applied; on
+/// failure, also how many were applied before it.
+async fn execute(
+    guard: &PoolGuard,
+    target: Arc<PreparedInsert>,
+    statements: Vec<(String, Vec<Value>)>,
+) -> Result<usize, (usize, ApiError)> {
+    if statements.is_empty() {
+        return Ok(0);
+    }
+    let storage = guard.storage();
+    let span = tracing::info_span!(
+        "query.execute",
+        sql = statements.first().map(|(sql, _)| sql.as_str()),
+        rows = statements.len()
+    );
+    let context = format!("insert into {}", target.table());
+    spawn_query(context, move || {
+        let _entered = span.enter();
+        let mut applied = 0;
+        for (_, params) in &statements {
+            target
+                .execute(&storage, params)
+                .map_err(|err| (applied, ApiError::from(err)))?;
+            applied += 1;
+        }
+        Ok(applied)
+    })
+    .await
+    .expect("spawn_blocking")
+}
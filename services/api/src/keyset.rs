@@ -0,0 +1,215 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use axum::http::StatusCode;
+use kadedb_services_ffi::{ColumnInfo, ColumnType, RowRef};
+use serde::Deserialize;
+
+use crate::{error::ApiError, ident};
+
+/// Page size when the caller doesn't ask for one.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Largest page a caller may ask for.
+const MAX_PAGE_SIZE: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct KeysetParams {
+    order_by: Option<String>,
+    after: Option<String>,
+    #[serde(default, deserialize_with = "crate::from_optional_query_str")]
+    limit: Option<usize>,
+}
+
+impl KeysetParams {
+    /// The requested seek, if `order_by` is set.
+    pub(crate) fn seek(self) -> Result<Option<Seek>, ApiError> {
+        let Some(order_by) = self.order_by else {
+            if self.after.is_some() {
+                return Err(bad_request("`after` requires `order_by`"));
+            }
+            return Ok(None);
+        };
+        ident::validate_identifier(&order_by).map_err(bad_request)?;
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(bad_request(format!(
+                "`limit` must be between 1 and {MAX_PAGE_SIZE}"
+            )));
+        }
+        Ok(Some(Seek {
+            order_by,
+            after: self.after,
+            limit,
+        }))
+    }
+}
+
+/// `WHERE order_by > after ORDER BY order_by LIMIT limit`, applied to a
+/// query's result as it is read.
+#[derive(Debug)]
+pub(crate) struct Seek {
+    order_by: String,
+    after: Option<String>,
+    limit: usize,
+}
+
+impl Seek {
+    /// Starts a page over a result with `columns`, checking that `order_by`
+    /// is one of them and that `after` parses as its type.
+    pub(crate) fn page(&self, columns: &[ColumnInfo]) -> Result<Page, ApiError> {
+        let column = columns
+            .iter()
+            .position(|c| c.name == self.order_by)
+            .ok_or_else(|| {
+                bad_request(format!("unknown column `{}` in `order_by`", self.order_by))
+            })?;
+        let ty = columns[column].column_type;
+        let after = self
+            .after
+            .as_deref()
+            .map(|v| {
+                Key::parse(ty, v).ok_or_else(|| {
+                    bad_request(format!("`after` is not a valid {ty:?} value: {v:?}"))
+                })
+            })
+            .transpose()?;
+        Ok(Page {
+            column,
+            ty,
+            after,
+            limit: self.limit,
+            rows: BinaryHeap::with_capacity(self.limit + 1),
+        })
+    }
+}
+
+/// The smallest `limit` rows past the seek key seen so far.
+///
+/// Only `limit` rows are held at a time, whatever the size of the result.
+pub(crate) struct Page {
+    column: usize,
+    ty: ColumnType,
+    after: Option<Key>,
+    limit: usize,
+    /// Max-heap, so the largest kept row is the one to evict.
+    rows: BinaryHeap<(Key, Vec<String>)>,
+}
+
+impl Page {
+    pub(crate) fn push(&mut self, row: RowRef<'_>) {
+        // As in SQL, a NULL key never compares greater than anything.
+        if row.is_null(self.column) {
+            return;
+        }
+        let Some(key) = Key::parse_cell(self.ty, row.get(self.column)) else {
+            return;
+        };
+        if self.after.as_ref().is_some_and(|after| key <= *after) {
+            return;
+        }
+        if self.rows.len() == self.limit && self.rows.peek().is_some_and(|(max, _)| key >= *max) {
+            return;
+        }
+        self.rows.push((key, row.to_vec()));
+        if self.rows.len() > self.limit {
+            self.rows.pop();
+        }
+    }
+
+    /// The page in key order, and the `after` value for the next page when
+    /// this one is full.
+    pub(crate) fn finish(self) -> (Vec<Vec<String>>, Option<String>) {
+        let full = self.rows.len() == self.limit;
+        let sorted = self.rows.into_sorted_vec();
+        let next = sorted
+            .last()
+            .filter(|_| full)
+            .map(|(key, _)| key.to_string());
+        (sorted.into_iter().map(|(_, row)| row).collect(), next)
+    }
+}
+
+/// A sort key read from the `order_by` column, or a `sort` column.
+#[derive(Debug, Clone)]
+pub(crate) enum Key {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+}
+
+impl Key {
+    /// Parses a caller-supplied `after` value.
+    fn parse(ty: ColumnType, value: &str) -> Option<Self> {
+        match ty {
+            ColumnType::Integer => value.trim().parse().ok().map(Key::Integer),
+            ColumnType::Float => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|f: &f64| !f.is_nan())
+                .map(Key::Float),
+            ColumnType::String => Some(Key::String(value.to_string())),
+            ColumnType::Boolean => value.trim().parse().ok().map(Key::Boolean),
+            ColumnType::Null => None,
+        }
+    }
+
+    /// Parses a cell as rendered by the native layer (strings quoted).
+    pub(crate) fn parse_cell(ty: ColumnType, cell: &str) -> Option<Self> {
+        match ty {
+            ColumnType::String => Some(Key::String(unquote(cell).to_string())),
+            _ => Self::parse(ty, cell),
+        }
+    }
+}
+
+/// A string cell without the quotes the native layer renders it with.
+pub(crate) fn unquote(cell: &str) -> &str {
+    cell.strip_prefix('"')
+        .and_then(|c| c.strip_suffix('"'))
+        .unwrap_or(cell)
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Integer(i) => write!(f, "{i}"),
+            Key::Float(x) => write!(f, "{x}"),
+            Key::String(s) => f.write_str(s),
+            Key::Boolean(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Key::Integer(a), Key::Integer(b)) => a.cmp(b),
+            (Key::Float(a), Key::Float(b)) => a.total_cmp(b),
+            (Key::String(a), Key::String(b)) => a.cmp(b),
+            (Key::Boolean(a), Key::Boolean(b)) => a.cmp(b),
+            // All keys of a page come from one column, so share a type.
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+fn bad_request(message: impl ToString) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, message)
+}
@@ -1,61 +1,612 @@
+use std::time::{Duration, Instant};
+
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{HeaderName, StatusCode},
     middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use kadedb_services_auth::{authorize_bearer_header, AuthConfig, AuthError, Permission};
+use kadedb_services_auth::{
+    authenticate_bearer_header, check_single_credential, AuthConfig, AuthError, Permission, Role,
+    RoleTimeouts, API_KEY_HEADER,
+};
+use kadedb_services_ffi::{ColumnSpec, ColumnTypeAliases, ErrorCode, StatementKind, Value};
+use kadedb_services_telemetry::{record_query, AccessEntry, AccessLog, Draining};
 use serde::{Deserialize, Serialize};
 
-pub fn router(auth_cfg: AuthConfig) -> Router {
-    let protected_read = Router::new().route(
-        "/query",
-        post(query).route_layer(middleware::from_fn_with_state(
-            (auth_cfg.clone(), Permission::Read),
+mod admin;
+mod case;
+mod checksum;
+mod config;
+mod conn;
+mod consistency;
+mod cookie;
+mod count;
+mod distinct;
+mod error;
+mod export;
+mod ident;
+mod import;
+mod insert;
+mod keyset;
+mod maintenance;
+mod projection;
+mod proto;
+mod queries;
+mod ready;
+mod reload;
+mod request_id;
+mod restricted;
+mod scope;
+mod script;
+mod shape;
+mod sort;
+mod tables;
+mod templates;
+mod tenant;
+#[cfg(feature = "tls")]
+mod tls;
+
+pub use case::JsonCase;
+pub use checksum::CHECKSUM_HEADER;
+pub use config::{
+    ApiConfig, HttpConfig, TlsConfig, DEFAULT_HTTP_IDLE_TIMEOUT, DEFAULT_MAX_COLUMNS_PER_TABLE,
+    DEFAULT_POOL_DEGRADED_SATURATION, DEFAULT_READY_DEEP_INTERVAL, DEFAULT_SORT_MAX_ROWS,
+};
+pub use cookie::{AuthCookie, CSRF_HEADER};
+pub use distinct::DISTINCT_PARTIAL_HEADER;
+pub use error::ErrorVerbosity;
+use error::{ApiError, ApiJson, FieldError};
+pub use export::NEXT_CURSOR_HEADER;
+pub use queries::QUERY_ID_HEADER;
+use queries::{QueryTag, Subject};
+pub use scope::RouteScopes;
+pub use shape::{AUTO_LIMIT_HEADER, COLUMNS_HEADER};
+pub use sort::SORT_SKIPPED_HEADER;
+pub use templates::QueryTemplates;
+use tenant::TenantPool;
+pub use tenant::{Tenancy, TenantId, TENANT_HEADER};
+
+#[derive(Clone)]
+struct AppState {
+    tenancy: Tenancy,
+    cursors: export::CursorKeys,
+    queries: queries::QueryRegistry,
+    ready: ready::DeepCheck,
+    live: reload::LiveConfig,
+    maintenance: maintenance::Maintenance,
+    config: ApiConfig,
+}
+
+pub fn router(auth_cfg: AuthConfig, tenancy: Tenancy) -> Router {
+    router_with_config(auth_cfg, tenancy, ApiConfig::default())
+}
+
+pub fn router_with_config(auth_cfg: AuthConfig, tenancy: Tenancy, config: ApiConfig) -> Router {
+    app(auth_cfg, tenancy, config, Draining::default())
+}
+
+/// The router, turning requests away with 503 once `draining` is set.
+fn app(auth_cfg: AuthConfig, tenancy: Tenancy, config: ApiConfig, draining: Draining) -> Router {
+    let cursors = export::CursorKeys::new(auth_cfg.jwt_secret.as_deref());
+    let maintenance = maintenance::Maintenance::default();
+    let v1 = v1_routes(&auth_cfg, &config, &maintenance);
+
+    let mut router = Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready::ready))
+        .route("/metrics", get(metrics))
+        .nest("/v1", v1.clone())
+        // Unprefixed aliases of the v1 routes, kept for one release.
+        .merge(v1.route_layer(middleware::from_fn(deprecated_alias)))
+        .layer(middleware::from_fn_with_state(
+            config.json_case,
+            case::json_case,
+        ))
+        .layer(middleware::from_fn_with_state(
+            Timeouts {
+                default: config.request_timeout,
+                max: config.max_query_timeout,
+                roles: config.role_timeouts,
+            },
+            request_timeout,
+        ));
+    if config.error_verbosity == ErrorVerbosity::Minimal {
+        router = router.layer(middleware::from_fn(error::minimal_errors));
+    }
+    router = router.layer(middleware::from_fn_with_state(
+        draining,
+        maintenance::reject_while_draining,
+    ));
+    if config.access_log.is_enabled() {
+        router = router.layer(middleware::from_fn_with_state(
+            config.access_log.clone(),
+            access_log,
+        ));
+    }
+    router = router.layer(middleware::from_fn_with_state(
+        config.json_case,
+        request_id::request_id,
+    ));
+    router.with_state(AppState {
+        tenancy,
+        cursors,
+        queries: queries::QueryRegistry::default(),
+        ready: ready::DeepCheck::default(),
+        live: reload::LiveConfig::new(&config),
+        maintenance,
+        config,
+    })
+}
+
+/// The v1 API. Response shapes under a version prefix don't change
+/// incompatibly; breaking changes land under the next version's router.
+///
+/// Routes are declared with their handler below; `config.route_scopes` adds
+/// a required token scope to any of them on top of the role check. The read
+/// and write routes are refused while `maintenance` is paused; admin routes
+/// stay open so the pause can be lifted.
+fn v1_routes(
+    auth_cfg: &AuthConfig,
+    config: &ApiConfig,
+    maintenance: &maintenance::Maintenance,
+) -> Router<AppState> {
+    let route = |path, handler| config.route_scopes.apply(path, handler, auth_cfg);
+
+    let protected_read = Router::new()
+        .route("/query", route("/query", post(query)))
+        .route(
+            "/query/count",
+            route("/query/count", post(count::count_query)),
+        )
+        .route("/export", route("/export", get(export::export)))
+        .route("/tables", route("/tables", get(tables::list_tables)))
+        .route(
+            "/tables/:name/schema",
+            route("/tables/:name/schema", get(tables::table_schema)),
+        )
+        .route(
+            "/templates/:name/run",
+            route("/templates/:name/run", post(templates::run_template)),
+        )
+        .route(
+            "/queries/:id",
+            route("/queries/:id", delete(queries::cancel_query)),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            maintenance.clone(),
+            maintenance::reject_while_paused,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            (
+                auth_cfg.clone(),
+                Permission::Read,
+                config.auth_cookie.clone(),
+                config.auth_realm.clone(),
+            ),
             auth_middleware,
-        )),
-    );
+        ));
 
-    let protected_write = Router::new().route(
-        "/tables",
-        post(create_table).route_layer(middleware::from_fn_with_state(
-            (auth_cfg.clone(), Permission::Write),
+    let protected_write = Router::new()
+        .route("/tables", route("/tables", post(create_table)))
+        .route(
+            "/tables/:name/import",
+            route("/tables/:name/import", post(import::import_table)),
+        )
+        .route(
+            "/tables/:name/rows",
+            route(
+                "/tables/:name/rows",
+                post(insert::insert_rows).put(insert::upsert_row),
+            ),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            maintenance.clone(),
+            maintenance::reject_while_paused,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            (
+                auth_cfg.clone(),
+                Permission::Write,
+                config.auth_cookie.clone(),
+                config.auth_realm.clone(),
+            ),
             auth_middleware,
-        )),
-    );
+        ));
 
-    Router::new()
-        .route("/health", get(health))
-        .merge(protected_read)
-        .merge(protected_write)
+    let protected_admin = Router::new()
+        .route("/admin/pool", route("/admin/pool", get(admin::pool_status)))
+        .route("/admin/stats", route("/admin/stats", get(admin::stats)))
+        .route(
+            "/admin/reload",
+            route("/admin/reload", post(reload::reload)),
+        )
+        .route(
+            "/admin/pause",
+            route("/admin/pause", post(maintenance::pause)),
+        )
+        .route(
+            "/admin/resume",
+            route("/admin/resume", post(maintenance::resume)),
+        )
+        .route("/script", route("/script", post(script::run_script)))
+        .route(
+            "/templates/:name",
+            route(
+                "/templates/:name",
+                put(templates::register_template).delete(templates::remove_template),
+            ),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            (
+                auth_cfg.clone(),
+                Permission::Admin,
+                config.auth_cookie.clone(),
+                config.auth_realm.clone(),
+            ),
+            auth_middleware,
+        ));
+
+    protected_read.merge(protected_write).merge(protected_admin)
+}
+
+/// Sets a request's deadline in milliseconds, overriding `request_timeout`
+/// (or the caller's role default) up to `max_query_timeout` and the role's
+/// max.
+pub const QUERY_TIMEOUT_HEADER: HeaderName = HeaderName::from_static("x-query-timeout-ms");
+
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    default: Option<Duration>,
+    max: Option<Duration>,
+    roles: RoleTimeouts,
 }
 
-pub async fn serve(listener: tokio::net::TcpListener, auth_cfg: AuthConfig) {
-    let app = router(auth_cfg);
-    axum::serve(listener, app).await.expect("serve");
+/// When a request's deadline passes, so handlers can stop the executor too.
+#[derive(Debug, Clone, Copy)]
+struct Deadline(Instant);
+
+/// Lets `auth_middleware` move a request's deadline once the caller's role
+/// is known, since `request_timeout` runs before authentication.
+#[derive(Debug, Clone)]
+struct DeadlineControl {
+    started: Instant,
+    requested: Option<Duration>,
+    timeouts: Timeouts,
+    deadline: std::sync::Arc<tokio::sync::watch::Sender<Option<Instant>>>,
 }
 
-async fn auth_middleware(
-    State((cfg, required)): State<(AuthConfig, Permission)>,
+impl DeadlineControl {
+    /// Applies `role`'s timeouts to `req`, if any are configured for it.
+    fn for_role(&self, req: &mut axum::http::Request<axum::body::Body>, role: Role) {
+        let roles = &self.timeouts.roles;
+        if roles.get(role) == Default::default() {
+            return;
+        }
+        let limit = roles.limit(
+            Some(role),
+            self.requested,
+            self.timeouts.default,
+            self.timeouts.max,
+        );
+        let at = limit.map(|limit| self.started + limit);
+        match at {
+            Some(at) => req.extensions_mut().insert(Deadline(at)),
+            None => req.extensions_mut().remove::<Deadline>(),
+        };
+        self.deadline.send_replace(at);
+    }
+}
+
+/// Resolves once the deadline `deadline` holds has passed, following
+/// changes to it; never while it is `None`.
+async fn deadline_passed(mut deadline: tokio::sync::watch::Receiver<Option<Instant>>) {
+    loop {
+        let at = *deadline.borrow_and_update();
+        let expired = async {
+            match at {
+                Some(at) => tokio::time::sleep_until(at.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            () = expired => return,
+            changed = deadline.changed() => {
+                if changed.is_err() {
+                    // The request is in the handler's hands; the deadline
+                    // can't move any more.
+                    match at {
+                        Some(at) => tokio::time::sleep_until(at.into()).await,
+                        None => std::future::pending().await,
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Fails a request with 504 when its handler hasn't produced a response
+/// within `request_timeout`, the client's [`QUERY_TIMEOUT_HEADER`] or the
+/// caller's role timeout, whatever it is waiting on. Only the time to the
+/// response head counts; a streamed body may take longer.
+async fn request_timeout(
+    State(timeouts): State<Timeouts>,
+    mut req: axum::http::Request<axum::body::Body>,
+    next: middleware::Next,
+) -> axum::response::Response {
+    let requested = match req.headers().get(&QUERY_TIMEOUT_HEADER) {
+        None => None,
+        Some(value) => match value
+            .to_str()
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+        {
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("{QUERY_TIMEOUT_HEADER} must be a positive number of milliseconds"),
+                )
+                .into_response()
+            }
+        },
+    };
+    let limit = timeouts
+        .roles
+        .limit(None, requested, timeouts.default, timeouts.max);
+    if limit.is_none() && timeouts.roles.is_empty() {
+        return next.run(req).await;
+    }
+    let started = Instant::now();
+    let at = limit.map(|limit| started + limit);
+    if let Some(at) = at {
+        req.extensions_mut().insert(Deadline(at));
+    }
+    let (deadline, expired) = tokio::sync::watch::channel(at);
+    req.extensions_mut().insert(DeadlineControl {
+        started,
+        requested,
+        timeouts,
+        deadline: std::sync::Arc::new(deadline),
+    });
+    tokio::select! {
+        biased;
+        res = next.run(req) => res,
+        () = deadline_passed(expired) => {
+            error_response(StatusCode::GATEWAY_TIMEOUT, "request timed out").into_response()
+        }
+    }
+}
+
+/// Writes the access log line for a request once its response head is ready.
+/// The subject comes from the [`kadedb_services_auth::Principal`] that
+/// `auth_middleware` attaches to the response; `bytes` is omitted for
+/// streamed bodies, `client` for requests served without a connection.
+async fn access_log(
+    State(log): State<AccessLog>,
+    req: axum::http::Request<axum::body::Body>,
+    next: middleware::Next,
+) -> axum::response::Response {
+    use axum::body::HttpBody;
+
+    let started = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let client = req
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|info| info.0);
+    let res = next.run(req).await;
+    let subject = res
+        .extensions()
+        .get::<kadedb_services_auth::Principal>()
+        .and_then(|p| p.subject.as_deref());
+    log.record(&AccessEntry {
+        method: method.as_str(),
+        path: &path,
+        status: res.status().as_u16(),
+        latency: started.elapsed(),
+        subject,
+        bytes: res.body().size_hint().exact(),
+        client,
+    });
+    res
+}
+
+/// Marks responses from unprefixed routes as deprecated (RFC 9745) and points
+/// at the `/v1` successor. The aliases are removed one release after `/v1`
+/// shipped; clients should switch as soon as they see the header.
+async fn deprecated_alias(
     req: axum::http::Request<axum::body::Body>,
     next: middleware::Next,
+) -> axum::response::Response {
+    let successor = format!("</v1{}>; rel=\"successor-version\"", req.uri().path());
+    let mut res = next.run(req).await;
+    let headers = res.headers_mut();
+    headers.insert("deprecation", axum::http::HeaderValue::from_static("true"));
+    if let Ok(link) = successor.parse() {
+        headers.insert(axum::http::header::LINK, link);
+    }
+    res
+}
+
+pub async fn serve(listener: tokio::net::TcpListener, auth_cfg: AuthConfig, tenancy: Tenancy) {
+    serve_with_config(listener, auth_cfg, tenancy, ApiConfig::default()).await;
+}
+
+pub async fn serve_with_config(
+    listener: tokio::net::TcpListener,
+    auth_cfg: AuthConfig,
+    tenancy: Tenancy,
+    config: ApiConfig,
+) {
+    serve_with_shutdown(listener, auth_cfg, tenancy, config, std::future::pending())
+        .await
+        .expect("serve");
+}
+
+/// Serves until `shutdown` resolves, then stops accepting connections and
+/// waits for in-flight requests to finish. From the signal on, new requests
+/// (on connections still open, or accepted during
+/// `config.listener.shutdown_grace`) get 503 `shutting_down`. Serves HTTPS
+/// when `config.tls` is set, which fails without the `tls` feature. Handlers can extract the
+/// client as `ConnectInfo<SocketAddr>`: the peer, or with
+/// `config.listener.proxy_protocol` the address its PROXY protocol header
+/// names.
+pub async fn serve_with_shutdown(
+    listener: tokio::net::TcpListener,
+    auth_cfg: AuthConfig,
+    tenancy: Tenancy,
+    config: ApiConfig,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let listener_cfg = config.listener.clone();
+    let tls = config.tls.clone();
+    let http = config.http.clone();
+    let draining = Draining::default();
+    let shutdown = draining.after(shutdown, listener_cfg.shutdown_grace);
+    let app = app(auth_cfg, tenancy, config, draining);
+    if let Some(tls) = tls {
+        #[cfg(feature = "tls")]
+        return tls::serve(listener, app, &tls, &http, &listener_cfg, shutdown).await;
+        #[cfg(not(feature = "tls"))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "TLS certificate {} configured, but built without the `tls` feature",
+                tls.cert_path.display()
+            ),
+        ));
+    }
+    conn::serve(listener, app, &http, &listener_cfg, shutdown).await
+}
+
+/// What a route's `auth_middleware` checks against, and the realm named in
+/// its challenges.
+type AuthState = (AuthConfig, Permission, Option<AuthCookie>, Option<String>);
+
+/// Authenticates the `Authorization` bearer token, or the cookie configured
+/// by [`AuthCookie`] when there is no header.
+async fn auth_middleware(
+    State((cfg, required, cookie, realm)): State<AuthState>,
+    mut req: axum::http::Request<axum::body::Body>,
+    next: middleware::Next,
 ) -> impl IntoResponse {
     let header = req
         .headers()
         .get(axum::http::header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok());
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    let span = tracing::info_span!("auth", permission = ?required, reason = tracing::field::Empty);
+    let auth = span.in_scope(|| {
+        let from_cookie = match (&cookie, header) {
+            (Some(cookie), None) if cfg.enabled => cookie.bearer(req.method(), req.headers())?,
+            _ => None,
+        };
+        let header = header.or(from_cookie.as_deref());
+        check_single_credential(&cfg, header, api_key)?;
+        authenticate_bearer_header(&cfg, header, required)
+    });
+    if let Err(err) = &auth {
+        span.record("reason", err.reason());
+    }
+
+    match auth {
+        Ok(principal) => {
+            let Some(principal) = principal else {
+                return next.run(req).await;
+            };
+            req.extensions_mut().insert(principal.clone());
+            if let Some(control) = req.extensions().get::<DeadlineControl>().cloned() {
+                control.for_role(&mut req, principal.role);
+            }
+            let mut res = next.run(req).await;
+            // For the access log, which sits outside this middleware.
+            res.extensions_mut().insert(principal);
+            res
+        }
+        Err(err @ AuthError::AmbiguousCredentials) => {
+            let reason = err.reason();
+            map_auth_error(err);
+            error_response(StatusCode::BAD_REQUEST, reason).into_response()
+        }
+        Err(err) => auth_rejection(err, realm.as_deref()),
+    }
+}
+
+/// The response for an auth failure. A 401 carries a `WWW-Authenticate:
+/// Bearer` challenge (RFC 6750) naming `realm`, plus `error` and
+/// `error_description` when a token was sent but rejected, so OAuth-aware
+/// clients know to fetch a new one.
+pub(crate) fn auth_rejection(err: AuthError, realm: Option<&str>) -> axum::response::Response {
+    let mut params = Vec::new();
+    if let Some(realm) = realm {
+        params.push(format!("realm=\"{}\"", quoted(realm)));
+    }
+    let error = match &err {
+        // No credentials at all: the challenge alone, per RFC 6750 3.1.
+        AuthError::MissingAuthorization => None,
+        AuthError::InvalidAuthorizationScheme => Some("invalid_request"),
+        _ => Some("invalid_token"),
+    };
+    let description = match &err {
+        AuthError::Jwt(jwt) => match jwt.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => "the token has expired".into(),
+            jsonwebtoken::errors::ErrorKind::InvalidSignature => {
+                "the token signature is invalid".into()
+            }
+            _ => "the token is malformed".into(),
+        },
+        err => err.to_string(),
+    };
+    if let Some(error) = error {
+        params.push(format!("error=\"{error}\""));
+        params.push(format!("error_description=\"{}\"", quoted(&description)));
+    }
 
-    match authorize_bearer_header(&cfg, header, required) {
-        Ok(_) => Ok(next.run(req).await),
-        Err(err) => Err(map_auth_error(err)),
+    let code = ErrorCode::from(&err);
+    let message = err.to_string();
+    let status = map_auth_error(err);
+    let mut res = ApiError::new(status, message)
+        .with_code(code)
+        .into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        let challenge = if params.is_empty() {
+            "Bearer".to_string()
+        } else {
+            format!("Bearer {}", params.join(", "))
+        };
+        if let Ok(value) = axum::http::HeaderValue::from_str(&challenge) {
+            res.headers_mut()
+                .insert(axum::http::header::WWW_AUTHENTICATE, value);
+        }
     }
+    res
 }
 
-fn map_auth_error(err: AuthError) -> StatusCode {
+/// Escapes `value` for a quoted-string.
+fn quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Maps an auth failure to its HTTP status. Every failure is counted in
+/// `auth_failures_total{reason}`, since the status alone doesn't tell a
+/// missing token from a forged or expired one.
+pub(crate) fn map_auth_error(err: AuthError) -> StatusCode {
+    metrics::counter!("auth_failures_total", "reason" => err.reason()).increment(1);
     match err {
-        AuthError::Forbidden => StatusCode::FORBIDDEN,
+        AuthError::Forbidden | AuthError::MissingScope | AuthError::CsrfMismatch => {
+            StatusCode::FORBIDDEN
+        }
+        AuthError::AmbiguousCredentials => StatusCode::BAD_REQUEST,
         _ => StatusCode::UNAUTHORIZED,
     }
 }
@@ -63,31 +614,560 @@ fn map_auth_error(err: AuthError) -> StatusCode {
 #[derive(Debug, Serialize)]
 struct HealthResponse {
     status: &'static str,
+    /// The worst storage circuit breaker state: `closed`, `open` or
+    /// `half_open`.
+    circuit: &'static str,
 }
 
-async fn health() -> Json<HealthResponse> {
-    Json(HealthResponse { status: "ok" })
+/// `ok`, `maintenance` while paused through `/admin/pause`, or `degraded`
+/// while a storage pool is saturated past `pool_degraded_saturation` or its
+/// circuit breaker isn't closed. All are 200: the service is up, only
+/// paused, slow or failing fast.
+async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
+    let status = if state.maintenance.is_paused() {
+        "maintenance"
+    } else if admin::degraded(&state) {
+        "degraded"
+    } else {
+        "ok"
+    };
+    Json(HealthResponse {
+        status,
+        circuit: admin::circuit(&state).as_str(),
+    })
+}
+
+/// Prometheus scrape endpoint; 404 when no recorder is installed.
+async fn metrics() -> impl IntoResponse {
+    match kadedb_services_telemetry::render_metrics() {
+        Some(body) => Ok((
+            [(
+                axum::http::header::CONTENT_TYPE,
+                "text/plain; version=0.0.4",
+            )],
+            body,
+        )),
+        None => Err(StatusCode::NOT_FOUND),
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct QueryRequest {
     query: String,
+    /// Values for `?` placeholders, bound in order.
+    #[serde(default)]
+    params: Vec<Param>,
+}
+
+/// A query parameter: a plain JSON scalar, typed by inference, or the
+/// tagged form `{"type": "float", "value": 42}` when the inferred type isn't
+/// the wanted one.
+///
+/// Inference: `null` is NULL, `true`/`false` BOOLEAN, a number without a
+/// fraction or exponent INTEGER (it must fit in 64 bits), any other number
+/// FLOAT, and a string STRING. An array of such values binds as an `IN`
+/// list (see [`kadedb_services_ffi::Statement`]); arrays don't nest. Objects
+/// other than the tagged form are rejected.
+#[derive(Debug)]
+pub(crate) enum Param {
+    Null,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Array(Vec<Param>),
+}
+
+/// The tagged form of [`Param`].
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+enum TaggedParam {
+    Null,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+}
+
+impl<'de> Deserialize<'de> for Param {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        use serde_json::Value as Json;
+
+        Ok(match Json::deserialize(deserializer)? {
+            Json::Null => Param::Null,
+            Json::Bool(b) => Param::Boolean(b),
+            Json::Number(n) if n.is_f64() => Param::Float(n.as_f64().unwrap_or_default()),
+            Json::Number(n) => Param::Integer(n.as_i64().ok_or_else(|| {
+                D::Error::custom(format!("integer parameter {n} is out of range"))
+            })?),
+            Json::String(s) => Param::String(s),
+            tagged @ Json::Object(_) => match TaggedParam::deserialize(tagged) {
+                Ok(TaggedParam::Null) => Param::Null,
+                Ok(TaggedParam::Integer(i)) => Param::Integer(i),
+                Ok(TaggedParam::Float(f)) => Param::Float(f),
+                Ok(TaggedParam::String(s)) => Param::String(s),
+                Ok(TaggedParam::Boolean(b)) => Param::Boolean(b),
+                Err(err) => return Err(D::Error::custom(err)),
+            },
+            Json::Array(items) => Param::Array(
+                items
+                    .into_iter()
+                    .map(|item| match item {
+                        Json::Array(_) => Err(D::Error::custom("array parameters can't nest")),
+                        item => Param::deserialize(item).map_err(D::Error::custom),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+}
+
+impl From<Param> for Value {
+    fn from(param: Param) -> Self {
+        match param {
+            Param::Null => Value::Null,
+            Param::Integer(i) => Value::Integer(i),
+            Param::Float(f) => Value::Float(f),
+            Param::String(s) => Value::String(s),
+            Param::Boolean(b) => Value::Boolean(b),
+            Param::Array(items) => Value::Array(items.into_iter().map(Value::from).collect()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct QueryResponse {
     ok: bool,
     echoed_query: String,
+    columns: Vec<String>,
+    rows: shape::Rows,
+    /// Rows were cut off at the configured auto-limit.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    auto_limited: bool,
+    /// With `order_by`, the `after` value for the next page; absent on the
+    /// last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_after: Option<String>,
+    /// With `checksum=sha256`, the digest of `rows`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
 }
 
-async fn query(Json(req): Json<QueryRequest>) -> (StatusCode, Json<QueryResponse>) {
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    ok: bool,
+    error: String,
+    /// Why the request failed, as an [`ErrorCode`] string.
+    code: &'static str,
+    /// Field-level problems of a `422` response.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<error::FieldError>,
+    /// Under minimal error verbosity, the id the full error was logged
+    /// under.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+}
+
+fn error_response(status: StatusCode, error: impl ToString) -> (StatusCode, Json<ErrorResponse>) {
     (
-        StatusCode::OK,
+        status,
+        Json(ErrorResponse {
+            ok: false,
+            error: error.to_string(),
+            code: ErrorCode::from_http_status(status.as_u16()).as_str(),
+            details: Vec::new(),
+            correlation_id: None,
+        }),
+    )
+}
+
+/// `/query`'s query string; see [`query`].
+#[derive(Debug, Deserialize)]
+struct QueryParams {
+    #[serde(flatten)]
+    shape: shape::ShapeParams,
+    #[serde(flatten)]
+    distinct: distinct::DistinctParams,
+    #[serde(flatten)]
+    keyset: keyset::KeysetParams,
+    #[serde(flatten)]
+    checksum: checksum::ChecksumParams,
+    #[serde(flatten)]
+    consistency: consistency::ConsistencyParams,
+    #[serde(flatten)]
+    projection: projection::ProjectionParams,
+    #[serde(flatten)]
+    sort: sort::SortParams,
+}
+
+/// Parses a query-string value through `FromStr`. Fields of a struct
+/// flattened into [`QueryParams`] reach it as strings, so non-string ones
+/// deserialize with this.
+fn from_query_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+/// [`from_query_str`] for an optional field.
+fn from_optional_query_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    from_query_str(deserializer).map(Some)
+}
+
+/// What a `/query` run is logged under, when it must stop and how its
+/// errors are worded.
+struct QueryContext {
+    tag: String,
+    subject: Option<String>,
+    deadline: Option<Instant>,
+    report: error::ErrorReport,
+}
+
+#[axum::async_trait]
+impl axum::extract::FromRequestParts<AppState> for QueryContext {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let QueryTag(tag) = QueryTag::from_request_parts(parts, state).await?;
+        let Subject(subject) = Subject::from_request_parts(parts, state).await?;
+        let report = error::ErrorReport::from_request_parts(parts, state).await?;
+        Ok(Self {
+            tag,
+            subject,
+            deadline: parts.extensions.get::<Deadline>().map(|&Deadline(at)| at),
+            report,
+        })
+    }
+}
+
+/// `POST /query?shape=arrays|objects|ndjson&distinct=true`
+///
+/// `arrays` (the default) returns each row as an array in column order;
+/// `objects` keys each row by column name, suffixing repeated names (`id`,
+/// `id_2`); `ndjson` streams one such object per line with no envelope.
+///
+/// `distinct` drops repeated rows after the query runs, so the response may
+/// hold fewer rows than the query produced. It isn't available with `ndjson`.
+///
+/// With an auto-limit configured, a SELECT without LIMIT returns at most that
+/// many rows; `auto_limited` is set when more were available. `ndjson`
+/// responses carry the limit in [`AUTO_LIMIT_HEADER`] instead.
+///
+/// `order_by=<col>&after=<value>&limit=N` pages by key: the rows whose `col`
+/// is greater than `after`, in `col` order, at most `N` of them, with
+/// `next_after` set when more may follow. The engine has no WHERE or ORDER
+/// BY yet, so this is applied as the result is read, holding only one page
+/// of rows; the query still scans the whole table. Not available with
+/// `ndjson` or without the envelope.
+///
+/// `envelope=false` (or `bare_results` configured, unless the request sets
+/// `envelope=true`) answers with just the `rows` array; the columns are in
+/// [`COLUMNS_HEADER`], the auto-limit in [`AUTO_LIMIT_HEADER`] and the
+/// checksum in [`CHECKSUM_HEADER`]. `ndjson` and protobuf responses have no
+/// envelope either way.
+///
+/// `checksum=sha256` adds `checksum`, the SHA-256 of the rows each written as
+/// a compact JSON array in column order plus `\n`, whatever the shape.
+/// `ndjson` streams instead end with a `{"$kadedb":{"checksum":...}}` line
+/// hashing the bytes of every line before it.
+///
+/// When the tenant's pool has a read replica, SELECTs run on the replica
+/// unless `consistency=strong` asks for the primary; everything else runs on
+/// the primary.
+///
+/// `sort=col[:asc|:desc],...` sorts the result by those columns, comparing
+/// values by column type, for queries that lack an ORDER BY. It needs the
+/// whole result in memory, so a result over `sort_max_rows` is returned
+/// unsorted with [`SORT_SKIPPED_HEADER`] set. Not available with `ndjson` or
+/// `order_by`.
+///
+/// `columns=a,b` keeps only those result columns, in that order, before the
+/// rows are deduplicated, hashed and serialized; naming a column the result
+/// doesn't have is a 400. Not available with `ndjson`.
+///
+/// `progress=true` on an `ndjson` stream interleaves
+/// `{"$kadedb":{"progress":{"rows_sent":N,"elapsed_ms":M}}}` lines at the configured
+/// progress interval, so clients can tell a slow query from a stalled one.
+async fn query(
+    State(state): State<AppState>,
+    TenantPool(pool): TenantPool,
+    context: QueryContext,
+    access: restricted::TableAccess,
+    Query(params): Query<QueryParams>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<QueryRequest>,
+) -> Result<axum::response::Response, ApiError> {
+    let QueryParams {
+        shape,
+        distinct,
+        keyset,
+        checksum,
+        consistency,
+        projection,
+        sort,
+    } = params;
+    let QueryContext {
+        tag,
+        subject,
+        deadline,
+        report,
+    } = context;
+    let digest = checksum.digest()?;
+    let protobuf = proto::accepted(&headers);
+    if protobuf && shape.shape == shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`shape=ndjson` is not supported with protobuf responses",
+        ));
+    }
+    if distinct.distinct && shape.shape == shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`distinct` is not supported with `shape=ndjson`",
+        ));
+    }
+    if projection.is_set() && shape.shape == shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`columns` is not supported with `shape=ndjson`",
+        ));
+    }
+    if sort.is_set() && shape.shape == shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`sort` is not supported with `shape=ndjson`",
+        ));
+    }
+    let seek = keyset.seek()?;
+    if seek.is_some() && sort.is_set() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`sort` can't be combined with `order_by`",
+        ));
+    }
+    if seek.is_some() && shape.shape == shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`order_by` is not supported with `shape=ndjson`",
+        ));
+    }
+    if shape.progress && shape.shape != shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`progress` requires `shape=ndjson`",
+        ));
+    }
+    if seek.is_some() && protobuf {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`order_by` is not supported with protobuf responses",
+        ));
+    }
+    let bare = shape.envelope.map_or(state.config.bare_results, |e| !e);
+    if seek.is_some() && bare && !protobuf && shape.shape != shape::Shape::Ndjson {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`order_by` requires `envelope=true`",
+        ));
+    }
+    access.check(&req.query)?;
+    let params: Vec<Value> = req.params.into_iter().map(Value::from).collect();
+    let pool = consistency.route(&pool, &req.query);
+    let active = state.queries.register(&headers, subject.clone())?;
+    let guard = pool.acquire().await?;
+    // Parsing is cheap and cached, so it's fine to do it here.
+    // A keyset page has its own limit.
+    let limit = state
+        .live
+        .auto_limit()
+        .filter(|_| seek.is_none())
+        .filter(|_| guard.storage().prepare(&req.query).is_unbounded_select());
+    if shape.shape == shape::Shape::Ndjson {
+        let slow = state.live.slow_queries();
+        let progress = state.config.progress_interval.filter(|_| shape.progress);
+        return shape::ndjson(
+            guard, active, req.query, params, limit, tag, slow, subject, digest, progress,
+            deadline, report,
+        )
+        .await;
+    }
+
+    let cancel = active.token();
+    let started = Instant::now();
+    let storage = guard.storage();
+    let sql = req.query.clone();
+    let kind = StatementKind::of(&sql).as_str();
+    let slow = state.live.slow_queries();
+    let span = tracing::info_span!("query.execute", tag = %tag);
+    // The closure owns the pool slot, so it stays taken until the engine is
+    // done even if the request goes away first.
+    let result = kadedb_services_ffi::spawn_query(req.query.clone(), move || {
+        let _entered = span.enter();
+        let run = || {
+            let executing = Instant::now();
+            let statement = storage.prepare(&sql);
+            let mut rs = storage.execute_prepared(&statement, &params)?;
+            // Read the schema before iterating so empty results still carry it.
+            let columns = rs.columns();
+            let mut page = seek.map(|seek| seek.page(&columns)).transpose()?;
+            let mut reader = rs.row_reader().with_cancel(cancel);
+            if let Some(deadline) = deadline {
+                reader = reader.with_deadline(deadline);
+            }
+            let mut rows = Vec::new();
+            let mut limited = false;
+            while let Some(row) = reader.next_row()? {
+                if let Some(page) = &mut page {
+                    page.push(row);
+                    continue;
+                }
+                if limit == Some(rows.len()) {
+                    limited = true;
+                    break;
+                }
+                rows.push(row.to_vec());
+            }
+            let mut next_after = None;
+            if let Some(page) = page {
+                (rows, next_after) = page.finish();
+            }
+            slow.record(&sql, executing.elapsed(), rows.len(), subject.as_deref());
+            Ok::<_, ApiError>((columns, rows, limited, next_after))
+        };
+        let result = run();
+        if result.as_ref().is_err_and(ApiError::is_storage_failure) {
+            guard.record_failure();
+        }
+        result
+    })
+    .await
+    .expect("spawn_blocking");
+    record_query(&tag, kind, result.is_ok(), started.elapsed());
+
+    let (mut columns, mut rows, auto_limited, next_after) = result?;
+    let mut sort_skipped = false;
+    if let Some(sort) = sort.resolve(&columns)? {
+        sort_skipped = !sort.apply(&mut rows, state.config.sort_max_rows);
+        if sort_skipped {
+            tracing::warn!(
+                rows = rows.len(),
+                limit = state.config.sort_max_rows,
+                "result too large to sort; returned unsorted"
+            );
+        }
+    }
+    if let Some(projection) = projection.resolve(columns.iter().map(|c| c.name.as_str()))? {
+        columns = projection.apply(&columns);
+        rows = projection.rows(rows);
+    }
+    let mut partial = false;
+    if distinct.distinct {
+        (rows, partial) = distinct::dedup(rows, distinct::DISTINCT_MAX_ROWS);
+        if partial {
+            tracing::warn!(
+                limit = distinct::DISTINCT_MAX_ROWS,
+                "distinct row limit reached; remaining rows not deduplicated"
+            );
+        }
+    }
+    let checksum = digest.map(|mut digest| {
+        rows.iter().for_each(|row| digest.update_row(row));
+        digest.finish()
+    });
+    if protobuf {
+        let mut response = (
+            [
+                (
+                    axum::http::header::CONTENT_TYPE,
+                    proto::PROTOBUF.to_string(),
+                ),
+                (QUERY_ID_HEADER, active.id().to_string()),
+            ],
+            proto::encode(&columns, &rows),
+        )
+            .into_response();
+        result_headers(response.headers_mut(), limit, checksum, partial);
+        sort_header(response.headers_mut(), sort_skipped);
+        return Ok(response);
+    }
+    let columns: Vec<String> = columns.into_iter().map(|c| c.name).collect();
+    let rows = shape::Rows::new(shape.shape, &columns, rows);
+    if bare {
+        let mut response =
+            ([(QUERY_ID_HEADER, active.id().to_string())], Json(rows)).into_response();
+        let headers = response.headers_mut();
+        headers.insert(COLUMNS_HEADER, shape::columns_header(&columns));
+        result_headers(headers, limit, checksum, partial);
+        sort_header(headers, sort_skipped);
+        return Ok(response);
+    }
+    let mut response = (
+        [(QUERY_ID_HEADER, active.id().to_string())],
         Json(QueryResponse {
             ok: true,
             echoed_query: req.query,
+            columns,
+            rows,
+            auto_limited,
+            next_after,
+            checksum,
         }),
     )
+        .into_response();
+    if partial {
+        response.headers_mut().insert(
+            DISTINCT_PARTIAL_HEADER,
+            axum::http::HeaderValue::from_static("true"),
+        );
+    }
+    sort_header(response.headers_mut(), sort_skipped);
+    Ok(response)
+}
+
+/// Flags a result `sort` left unsorted, whatever the response's form.
+fn sort_header(headers: &mut axum::http::HeaderMap, skipped: bool) {
+    if skipped {
+        headers.insert(
+            SORT_SKIPPED_HEADER,
+            axum::http::HeaderValue::from_static("true"),
+        );
+    }
+}
+
+/// What a `/query` response without the JSON envelope carries in headers
+/// instead: the auto-limit applied, the checksum and whether `distinct`
+/// gave up.
+fn result_headers(
+    headers: &mut axum::http::HeaderMap,
+    limit: Option<usize>,
+    checksum: Option<String>,
+    partial: bool,
+) {
+    if let Some(limit) = limit {
+        headers.insert(AUTO_LIMIT_HEADER, limit.into());
+    }
+    if let Some(value) = checksum.and_then(|c| axum::http::HeaderValue::from_str(&c).ok()) {
+        headers.insert(CHECKSUM_HEADER, value);
+    }
+    if partial {
+        headers.insert(
+            DISTINCT_PARTIAL_HEADER,
+            axum::http::HeaderValue::from_static("true"),
+        );
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -96,6 +1176,55 @@ struct CreateTableRequest {
     columns: Vec<ColumnDef>,
 }
 
+impl CreateTableRequest {
+    /// Checks the table and column names, the column types (by name or
+    /// one of `aliases`) and the column count against `max_columns`,
+    /// returning every problem found rather than stopping at the first.
+    fn validate(
+        &self,
+        max_columns: Option<usize>,
+        aliases: &ColumnTypeAliases,
+    ) -> Result<(), Vec<FieldError>> {
+        let mut details = Vec::new();
+        if let Err(err) = ident::validate_identifier(&self.name) {
+            details.push(FieldError::new("/name", err));
+        }
+        if self.columns.is_empty() {
+            details.push(FieldError::new(
+                "/columns",
+                "at least one column is required",
+            ));
+        }
+        if let Some(max) = max_columns.filter(|&max| self.columns.len() > max) {
+            details.push(FieldError::new(
+                "/columns",
+                format!("{} columns exceed the limit of {max}", self.columns.len()),
+            ));
+        }
+        for (i, col) in self.columns.iter().enumerate() {
+            if let Err(err) = ident::validate_identifier(&col.name) {
+                details.push(FieldError::new(format!("/columns/{i}/name"), err));
+            } else if self.columns[..i].iter().any(|c| c.name == col.name) {
+                details.push(FieldError::new(
+                    format!("/columns/{i}/name"),
+                    format!("duplicate column `{}`", col.name),
+                ));
+            }
+            if aliases.resolve(&col.column_type).is_none() {
+                details.push(FieldError::new(
+                    format!("/columns/{i}/column_type"),
+                    format!("unknown column type `{}`", col.column_type),
+                ));
+            }
+        }
+        if details.is_empty() {
+            Ok(())
+        } else {
+            Err(details)
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ColumnDef {
     name: String,
@@ -118,28 +1247,69 @@ struct ColumnSummary {
     nullable: bool,
 }
 
+/// `POST /tables`
+///
+/// Creates the table in the tenant's storage. A body that doesn't parse is
+/// 400. One that parses but names an invalid table or column, an unknown
+/// column type or a column twice, or has more columns than
+/// `max_columns_per_table`, is 422, with every such problem in `details`.
+/// A table that already exists is 409. Column types may be given by an
+/// alias from `column_type_aliases`, and are reported by their canonical
+/// name.
 async fn create_table(
-    Json(req): Json<CreateTableRequest>,
-) -> (StatusCode, Json<CreateTableResponse>) {
+    State(state): State<AppState>,
+    TenantPool(pool): TenantPool,
+    ApiJson(req): ApiJson<CreateTableRequest>,
+) -> Result<Json<CreateTableResponse>, ApiError> {
+    let aliases = &state.config.column_type_aliases;
+    req.validate(state.config.max_columns_per_table, aliases)
+        .map_err(ApiError::invalid)?;
+
     let table = req.name;
-    let columns: Vec<ColumnSummary> = req
+    let specs: Vec<ColumnSpec> = req
         .columns
         .into_iter()
-        .map(|c| ColumnSummary {
+        .map(|c| ColumnSpec {
+            column_type: aliases
+                .resolve(&c.column_type)
+                .expect("validated column type"),
             name: c.name,
-            column_type: c.column_type,
             nullable: c.nullable.unwrap_or(true),
         })
         .collect();
+    let columns: Vec<ColumnSummary> = specs
+        .iter()
+        .map(|c| ColumnSummary {
+            name: c.name.clone(),
+            column_type: c.column_type.name().to_string(),
+            nullable: c.nullable,
+        })
+        .collect();
     let column_count = columns.len();
 
-    (
-        StatusCode::OK,
-        Json(CreateTableResponse {
-            ok: true,
-            table,
-            column_count,
-            columns,
-        }),
-    )
+    let guard = pool.acquire().await?;
+    let storage = guard.storage();
+    storage
+        .allowed_statements()
+        .check_kind(StatementKind::Ddl)?;
+    let name = table.clone();
+    kadedb_services_ffi::spawn_query(format!("create table {table}"), move || {
+        let _guard = guard;
+        if storage.list_tables()?.contains(&name) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("table `{name}` already exists"),
+            ));
+        }
+        Ok(storage.create_table(&name, &specs)?)
+    })
+    .await
+    .expect("spawn_blocking")?;
+
+    Ok(Json(CreateTableResponse {
+        ok: true,
+        table,
+        column_count,
+        columns,
+    }))
 }
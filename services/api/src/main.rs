@@ -1,19 +1,57 @@
+use std::process::ExitCode;
+
+use kadedb_services_api::{ApiConfig, Tenancy};
 use kadedb_services_auth::AuthConfig;
+use kadedb_services_ffi::AllowedStatements;
+use kadedb_services_telemetry::{ConfigFile, StartupError};
+
+fn main() -> ExitCode {
+    match start() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => err.report("kadedb-api"),
+    }
+}
+
+fn start() -> Result<(), StartupError> {
+    ConfigFile::load_into_env().map_err(|err| StartupError::Config(err.to_string()))?;
+    kadedb_services_ffi::install_panic_hook(kadedb_services_telemetry::redact_sql);
+    if let Ok(prefix) = std::env::var("KADEDB_FFI_THREAD_PREFIX") {
+        kadedb_services_ffi::set_thread_prefix(prefix);
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(StartupError::Runtime)?
+        .block_on(run())
+}
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
-        )
-        .init();
+async fn run() -> Result<(), StartupError> {
+    let _telemetry = kadedb_services_telemetry::init("kadedb-api");
 
     let auth_cfg = AuthConfig::from_env();
+    auth_cfg
+        .validate()
+        .map_err(|err| StartupError::Auth(err.to_string()))?;
+    let config = ApiConfig::from_env();
+    config.validate().map_err(StartupError::Config)?;
+    AllowedStatements::from_env().map_err(StartupError::Config)?;
+    let tenancy = Tenancy::from_env().map_err(|err| StartupError::Storage(err.to_string()))?;
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080")
-        .await
-        .expect("bind 0.0.0.0:8080");
+    let addr = "0.0.0.0:8080".parse().expect("valid addr");
+    let listener = config
+        .listener
+        .bind(addr)
+        .map_err(|source| StartupError::Bind { addr, source })?;
 
-    tracing::info!("listening on {}", listener.local_addr().unwrap());
-    kadedb_services_api::serve(listener, auth_cfg).await;
+    tracing::info!("listening on {addr}");
+    kadedb_services_api::serve_with_shutdown(
+        listener,
+        auth_cfg,
+        tenancy,
+        config,
+        std::future::pending(),
+    )
+    .await
+    .map_err(|err| StartupError::Serve(err.to_string()))
 }
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use kadedb_services_telemetry::{Draining, SHUTTING_DOWN};
+use serde::Serialize;
+
+use crate::{error_response, AppState};
+
+/// Seconds paused requests are told to wait before retrying.
+pub(crate) const MAINTENANCE_RETRY_AFTER: u64 = 30;
+
+/// Seconds requests turned away during shutdown are told to wait; another
+/// instance should take them by then.
+pub(crate) const SHUTDOWN_RETRY_AFTER: u64 = 1;
+
+/// Whether `POST /admin/pause` has taken the service out of rotation. Kept
+/// only in memory, so a restart resumes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sets the flag, returning whether it changed.
+    fn set(&self, paused: bool) -> bool {
+        self.0.swap(paused, Ordering::Relaxed) != paused
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MaintenanceResponse {
+    ok: bool,
+    paused: bool,
+}
+
+/// `POST /admin/pause`
+///
+/// New requests to the read and write routes get 503 until
+/// `/admin/resume`; ones already running finish. `/health` and `/ready`
+/// report `maintenance` meanwhile.
+pub(crate) async fn pause(State(state): State<AppState>) -> Json<MaintenanceResponse> {
+    if state.maintenance.set(true) {
+        tracing::warn!("paused for maintenance");
+    }
+    Json(MaintenanceResponse {
+        ok: true,
+        paused: true,
+    })
+}
+
+/// `POST /admin/resume`
+pub(crate) async fn resume(State(state): State<AppState>) -> Json<MaintenanceResponse> {
+    if state.maintenance.set(false) {
+        tracing::info!("resumed after maintenance");
+    }
+    Json(MaintenanceResponse {
+        ok: true,
+        paused: false,
+    })
+}
+
+/// Rejects requests with 503 and `Retry-After` while paused. Cancelling a
+/// running query (`DELETE`) still goes through, so in-flight work can be
+/// stopped during a pause.
+pub(crate) async fn reject_while_paused(
+    State(maintenance): State<Maintenance>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !maintenance.is_paused() || req.method() == Method::DELETE {
+        return next.run(req).await;
+    }
+    let mut response = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "maintenance: the service is paused",
+    )
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, MAINTENANCE_RETRY_AFTER.into());
+    response
+}
+
+/// Rejects every request with 503 `shutting_down` once the server is
+/// draining. HTTP/1 responses also close their connection, so the client
+/// reconnects, most likely to another instance.
+pub(crate) async fn reject_while_draining(
+    State(draining): State<Draining>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !draining.is_draining() {
+        return next.run(req).await;
+    }
+    let mut response =
+        error_response(StatusCode::SERVICE_UNAVAILABLE, SHUTTING_DOWN).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::RETRY_AFTER, SHUTDOWN_RETRY_AFTER.into());
+    if req.version() <= axum::http::Version::HTTP_11 {
+        headers.insert(
+            header::CONNECTION,
+            header::HeaderValue::from_static("close"),
+        );
+    }
+    response
+}
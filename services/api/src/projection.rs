@@ -0,0 +1,71 @@
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ProjectionParams {
+    /// Comma-separated names of the result columns to keep, in the order
+    /// wanted.
+    columns: Option<String>,
+}
+
+impl ProjectionParams {
+    pub(crate) fn is_set(&self) -> bool {
+        self.columns.is_some()
+    }
+
+    /// Where the requested columns sit among `names`, the result's columns;
+    /// `None` when the request doesn't project. A name not in the result,
+    /// or an empty list, is a 400.
+    pub(crate) fn resolve<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str> + Clone,
+    ) -> Result<Option<Projection>, ApiError> {
+        let Some(columns) = &self.columns else {
+            return Ok(None);
+        };
+        let wanted: Vec<&str> = columns
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .collect();
+        if wanted.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "`columns` must name at least one column",
+            ));
+        }
+        wanted
+            .into_iter()
+            .map(|column| {
+                names
+                    .clone()
+                    .into_iter()
+                    .position(|name| name == column)
+                    .ok_or_else(|| {
+                        ApiError::new(
+                            StatusCode::BAD_REQUEST,
+                            format!("`columns` names unknown column `{column}`"),
+                        )
+                    })
+            })
+            .collect::<Result<_, _>>()
+            .map(|indices| Some(Projection(indices)))
+    }
+}
+
+/// The positions of the columns a request kept.
+#[derive(Debug)]
+pub(crate) struct Projection(Vec<usize>);
+
+impl Projection {
+    /// The kept items of `row` (or of the column list), in request order.
+    pub(crate) fn apply<T: Clone>(&self, row: &[T]) -> Vec<T> {
+        self.0.iter().map(|&i| row[i].clone()).collect()
+    }
+
+    pub(crate) fn rows<T: Clone>(&self, rows: Vec<Vec<T>>) -> Vec<Vec<T>> {
+        rows.iter().map(|row| self.apply(row)).collect()
+    }
+}
@@ -0,0 +1,54 @@
+use axum::http::{header, HeaderMap};
+use kadedb_services_ffi::ColumnInfo;
+use kadedb_services_grpc::kadedb::{ColumnSchema, ColumnType, QueryRow, QuerySchema};
+use prost::Message;
+
+use crate::shape::object_keys;
+
+/// Media type of a `/query` result in the gRPC encoding.
+pub(crate) const PROTOBUF: &str = "application/x-protobuf";
+
+/// Whether the `Accept` header lists [`PROTOBUF`].
+pub(crate) fn accepted(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| range.split(';').next().unwrap_or("").trim() == PROTOBUF)
+}
+
+/// Encodes a result as length-delimited messages: a `QuerySchema`, then one
+/// `QueryRow` per row holding the row as a JSON object, as `Query` streams
+/// them.
+pub(crate) fn encode(columns: &[ColumnInfo], rows: &[Vec<String>]) -> Vec<u8> {
+    let schema = QuerySchema {
+        columns: columns
+            .iter()
+            .map(|c| ColumnSchema {
+                name: c.name.clone(),
+                r#type: ColumnType::from(c.column_type).into(),
+            })
+            .collect(),
+    };
+    let names: Vec<String> = columns.iter().map(|c| c.name.clone()).collect();
+    let keys = object_keys(&names);
+    let mut buf = Vec::new();
+    schema
+        .encode_length_delimited(&mut buf)
+        .expect("Vec grows as needed");
+    for row in rows {
+        let object: serde_json::Map<_, _> = keys
+            .iter()
+            .cloned()
+            .zip(row.iter().cloned().map(serde_json::Value::String))
+            .collect();
+        QueryRow {
+            json: serde_json::Value::Object(object).to_string(),
+            progress: None,
+        }
+        .encode_length_delimited(&mut buf)
+        .expect("Vec grows as needed");
+    }
+    buf
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{request::Parts, HeaderMap, HeaderName, StatusCode},
+    Extension,
+};
+use kadedb_services_auth::{Principal, Role};
+use kadedb_services_ffi::CancelToken;
+use kadedb_services_telemetry::QUERY_TAG_HEADER;
+
+use crate::{error::ApiError, AppState};
+
+/// Response header carrying the id of the query that produced the response.
+/// A request may send one to pick the id itself.
+pub const QUERY_ID_HEADER: HeaderName = HeaderName::from_static("x-query-id");
+
+/// Longest id a caller may pick.
+const MAX_QUERY_ID_LEN: usize = 64;
+
+struct Entry {
+    token: CancelToken,
+    /// The `sub` claim of the caller that started the query.
+    owner: Option<String>,
+}
+
+/// In-flight queries by id, for `DELETE /queries/:id`.
+#[derive(Clone, Default)]
+pub(crate) struct QueryRegistry(Arc<Mutex<HashMap<String, Entry>>>);
+
+impl QueryRegistry {
+    /// Registers a new query started by `owner`; it stays cancellable until
+    /// the returned guard is dropped. The id is the request's
+    /// `X-Query-Id` when it sent one, so a caller can cancel a buffered
+    /// query before its response (and the id header) arrives; otherwise a
+    /// random one. A malformed id is a 400, one already in flight a 409.
+    pub(crate) fn register(
+        &self,
+        headers: &HeaderMap,
+        owner: Option<String>,
+    ) -> Result<ActiveQuery, ApiError> {
+        let id = match headers.get(QUERY_ID_HEADER) {
+            Some(id) => id
+                .to_str()
+                .ok()
+                .filter(|id| valid_id(id))
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "`{QUERY_ID_HEADER}` must be 1 to {MAX_QUERY_ID_LEN} letters, digits, `-` or `_`"
+                        ),
+                    )
+                })?,
+            None => {
+                let mut bytes = [0u8; 16];
+                getrandom::getrandom(&mut bytes).expect("generate query id");
+                bytes.iter().map(|b| format!("{b:02x}")).collect()
+            }
+        };
+
+        let token = CancelToken::new();
+        let mut queries = self.0.lock().expect("query registry lock");
+        if queries.contains_key(&id) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("query `{id}` is already running"),
+            ));
+        }
+        queries.insert(
+            id.clone(),
+            Entry {
+                token: token.clone(),
+                owner,
+            },
+        );
+        Ok(ActiveQuery {
+            id,
+            token,
+            registry: self.clone(),
+        })
+    }
+
+    /// Cancels query `id` if `caller` may: anyone when auth is disabled,
+    /// admins, and otherwise only the subject that started it.
+    fn cancel(&self, id: &str, caller: Option<&Principal>) -> bool {
+        let queries = self.0.lock().expect("query registry lock");
+        let Some(entry) = queries.get(id) else {
+            return false;
+        };
+        let allowed = caller.is_none_or(|caller| {
+            caller.role == Role::Admin || (entry.owner.is_some() && entry.owner == caller.subject)
+        });
+        if allowed {
+            entry.token.cancel();
+        }
+        allowed
+    }
+}
+
+fn valid_id(id: &str) -> bool {
+    (1..=MAX_QUERY_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// A registered query; cancels and deregisters itself on drop, so a query
+/// whose request goes away (a client disconnecting from a buffered
+/// response) stops at its next row boundary.
+pub(crate) struct ActiveQuery {
+    id: String,
+    token: CancelToken,
+    registry: QueryRegistry,
+}
+
+impl ActiveQuery {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for ActiveQuery {
+    fn drop(&mut self) {
+        self.token.cancel();
+        self.registry
+            .0
+            .lock()
+            .expect("query registry lock")
+            .remove(&self.id);
+    }
+}
+
+/// `DELETE /queries/:id`
+///
+/// Cancels an in-flight query. With auth enabled only its owner (the
+/// subject that started it) or an admin may. The query stops at its next
+/// row boundary and its request fails with 409; 404 means the id is
+/// unknown, the query already finished, or the caller may not cancel it.
+pub(crate) async fn cancel_query(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let caller = principal.as_ref().map(|Extension(p)| p);
+    if state.queries.cancel(&id, caller) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// The caller's `X-Query-Tag`, reduced to the configured allowlist.
+pub(crate) struct QueryTag(pub String);
+
+#[async_trait]
+impl FromRequestParts<AppState> for QueryTag {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let tag = parts
+            .headers
+            .get(QUERY_TAG_HEADER)
+            .and_then(|v| v.to_str().ok());
+        Ok(Self(state.config.query_tags.resolve(tag)))
+    }
+}
+
+/// The authenticated caller's `sub` claim, if any, for logging.
+pub(crate) struct Subject(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Subject {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .extensions
+                .get::<Principal>()
+                .and_then(|p| p.subject.clone()),
+        ))
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use kadedb_services_ffi::{
+    spawn_query, ColumnSpec, ColumnType, CompareOp, FfiError, Predicate, Storage, StoragePool,
+    Value,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{error::ErrorVerbosity, AppState};
+
+/// Table the deep check writes to.
+const SCRATCH_TABLE: &str = "_kadedb_ready";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReadyParams {
+    #[serde(default)]
+    deep: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ReadyResponse {
+    ok: bool,
+    /// `ready`, `unready` when any check failed, or `maintenance` while
+    /// paused.
+    status: &'static str,
+    deep: bool,
+    checks: Vec<Check>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Check {
+    /// `pool` (a free slot), `read` (the catalog) or `write` (an insert).
+    subsystem: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The last deep check and when it ran. Probes within
+/// `ready_deep_interval` of it get the same report, and concurrent probes
+/// wait for one run instead of each writing.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeepCheck(Arc<Mutex<Option<(Instant, ReadyResponse)>>>);
+
+/// `GET /ready`
+///
+/// Takes a slot from every storage pool and lists its tables. With
+/// `deep=true` it also inserts a row into [`SCRATCH_TABLE`] and deletes it,
+/// so storage that reads but can no longer write shows up as unready. Any failed check
+/// answers 503, naming the subsystem (and tenant) that failed. While paused
+/// through `/admin/pause` it answers 503 `maintenance` without checking, so
+/// load balancers take the instance out of rotation.
+pub(crate) async fn ready(
+    State(state): State<AppState>,
+    Query(params): Query<ReadyParams>,
+) -> (StatusCode, Json<ReadyResponse>) {
+    if state.maintenance.is_paused() {
+        let report = ReadyResponse {
+            ok: false,
+            status: "maintenance",
+            deep: params.deep,
+            checks: Vec::new(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(report));
+    }
+    let report = if params.deep {
+        let mut last = state.ready.0.lock().await;
+        match &*last {
+            Some((at, report)) if at.elapsed() < state.config.ready_deep_interval => report.clone(),
+            _ => {
+                let report = run_checks(&state, true).await;
+                *last = Some((Instant::now(), report.clone()));
+                report
+            }
+        }
+    } else {
+        run_checks(&state, false).await
+    };
+    let status = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn run_checks(state: &AppState, deep: bool) -> ReadyResponse {
+    let minimal = state.config.error_verbosity == ErrorVerbosity::Minimal;
+    let mut checks = Vec::new();
+    for (tenant, pool) in state.tenancy.pools() {
+        for (subsystem, result) in check_pool(pool, deep).await {
+            if let Err(err) = &result {
+                tracing::warn!(subsystem, tenant, %err, "readiness check failed");
+            }
+            checks.push(Check {
+                subsystem,
+                tenant: tenant.map(str::to_string),
+                ok: result.is_ok(),
+                error: result.err().filter(|_| !minimal).map(|err| err.to_string()),
+            });
+        }
+    }
+    let ok = checks.iter().all(|c| c.ok);
+    ReadyResponse {
+        ok,
+        status: if ok { "ready" } else { "unready" },
+        deep,
+        checks,
+    }
+}
+
+/// Runs one pool's checks in order, stopping at the first failure.
+async fn check_pool(pool: &StoragePool, deep: bool) -> Vec<(&'static str, Result<(), FfiError>)> {
+    let guard = match pool.acquire().await {
+        Ok(guard) => guard,
+        Err(err) => return vec![("pool", Err(err))],
+    };
+    let mut checks = vec![("pool", Ok(()))];
+
+    let storage = guard.storage();
+    let read = spawn_query("readiness read", move || storage.list_tables().map(drop))
+        .await
+        .expect("spawn_blocking");
+    let failed = read.is_err();
+    checks.push(("read", read));
+    if failed || !deep {
+        return checks;
+    }
+
+    let storage = guard.storage();
+    let write = spawn_query("readiness write", move || write_probe(&storage))
+        .await
+        .expect("spawn_blocking");
+    checks.push(("write", write));
+    checks
+}
+
+/// Inserts the current time into [`SCRATCH_TABLE`], creating it first if
+/// needed, then deletes it again, so the table stays empty. Rows left by an
+/// earlier probe that failed between the two steps go with it.
+fn write_probe(storage: &Storage) -> Result<(), FfiError> {
+    if !storage
+        .list_tables()?
+        .iter()
+        .any(|table| table == SCRATCH_TABLE)
+    {
+        storage.create_table(
+            SCRATCH_TABLE,
+            &[ColumnSpec {
+                name: "probed_at".to_string(),
+                column_type: ColumnType::Integer,
+                nullable: false,
+            }],
+        )?;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    storage
+        .insert_target(SCRATCH_TABLE)?
+        .execute(storage, &[Value::Integer(now)])?;
+    let probed = Predicate {
+        column: "probed_at".to_string(),
+        op: CompareOp::Le,
+        value: Value::Integer(now),
+    };
+    storage.delete_rows(SCRATCH_TABLE, Some(&probed)).map(drop)
+}
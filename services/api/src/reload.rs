@@ -0,0 +1,152 @@
+use std::sync::{Arc, RwLock};
+
+use axum::{extract::State, http::StatusCode, Json};
+use kadedb_services_telemetry::{ConfigFile, SlowQueryLog, DEFAULT_LOG_FILTER};
+use serde::Serialize;
+
+use crate::config::{auto_limit, pool_degraded_saturation};
+use crate::{error::ApiError, ApiConfig, AppState};
+
+/// Settings `POST /admin/reload` applies while serving. Everything else in
+/// the file only takes effect on restart.
+const RELOADABLE: &[&str] = &[
+    "RUST_LOG",
+    "KADEDB_SLOW_QUERY_THRESHOLD_MS",
+    "KADEDB_SLOW_QUERY_REDACT",
+    "KADEDB_AUTO_LIMIT",
+    "KADEDB_POOL_DEGRADED_SATURATION",
+];
+
+/// The part of [`ApiConfig`] that can change without a restart. Handlers
+/// read these from here rather than from `AppState::config`.
+#[derive(Debug, Clone)]
+struct Live {
+    slow_queries: SlowQueryLog,
+    auto_limit: Option<usize>,
+    pool_degraded_saturation: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LiveConfig(Arc<RwLock<Live>>);
+
+impl LiveConfig {
+    pub(crate) fn new(config: &ApiConfig) -> Self {
+        Self(Arc::new(RwLock::new(Live {
+            slow_queries: config.slow_queries.clone(),
+            auto_limit: config.auto_limit,
+            pool_degraded_saturation: config.pool_degraded_saturation,
+        })))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Live> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn slow_queries(&self) -> SlowQueryLog {
+        self.read().slow_queries.clone()
+    }
+
+    pub(crate) fn auto_limit(&self) -> Option<usize> {
+        self.read().auto_limit
+    }
+
+    pub(crate) fn pool_degraded_saturation(&self) -> Option<f64> {
+        self.read().pool_degraded_saturation
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ReloadResponse {
+    ok: bool,
+    /// Settings whose value changed and now apply.
+    changed: Vec<&'static str>,
+    /// Settings in the file that differ from the running ones but need a
+    /// restart.
+    ignored: Vec<String>,
+}
+
+/// `POST /admin/reload`
+///
+/// Re-reads `config_file` and applies the log filter, slow-query log, auto
+/// limit and degraded-pool threshold. A setting missing from the file falls
+/// back to the environment. Nothing is applied if any value is invalid.
+pub(crate) async fn reload(
+    State(state): State<AppState>,
+) -> Result<Json<ReloadResponse>, ApiError> {
+    let Some(path) = &state.config.config_file else {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "no config file to reload; set KADEDB_CONFIG_FILE",
+        ));
+    };
+    let file = ConfigFile::read(path).map_err(|err| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("{}: {err}", path.display()),
+        )
+    })?;
+    let var = |name: &str| file.var(name);
+    let log_filter = kadedb_services_telemetry::parse_log_filter(
+        &var("RUST_LOG").unwrap_or_else(|| DEFAULT_LOG_FILTER.to_string()),
+    )
+    .map_err(|err| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("RUST_LOG: {err}"),
+        )
+    })?;
+    let next = Live {
+        slow_queries: SlowQueryLog::from_vars(var),
+        auto_limit: auto_limit(var),
+        pool_degraded_saturation: pool_degraded_saturation(var),
+    };
+
+    let mut changed = Vec::new();
+    let mut ignored: Vec<String> = file
+        .iter()
+        .filter(|(name, value)| {
+            !RELOADABLE.contains(name) && std::env::var(name).ok().as_deref() != Some(*value)
+        })
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+    let mut live = state.live.0.write().unwrap_or_else(|e| e.into_inner());
+    match kadedb_services_telemetry::log_filter() {
+        Some(current) if current != log_filter => {
+            kadedb_services_telemetry::set_log_filter(&log_filter).map_err(|err| {
+                ApiError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("RUST_LOG: {err}"),
+                )
+            })?;
+            changed.push("RUST_LOG");
+        }
+        Some(_) => {}
+        // Embedded without the service's subscriber: not ours to change.
+        None if file.iter().any(|(name, _)| name == "RUST_LOG") => {
+            ignored.push("RUST_LOG".to_string())
+        }
+        None => {}
+    }
+    if next.slow_queries.threshold() != live.slow_queries.threshold() {
+        changed.push("KADEDB_SLOW_QUERY_THRESHOLD_MS");
+    }
+    if next.slow_queries.redacts() != live.slow_queries.redacts() {
+        changed.push("KADEDB_SLOW_QUERY_REDACT");
+    }
+    if next.auto_limit != live.auto_limit {
+        changed.push("KADEDB_AUTO_LIMIT");
+    }
+    if next.pool_degraded_saturation != live.pool_degraded_saturation {
+        changed.push("KADEDB_POOL_DEGRADED_SATURATION");
+    }
+    *live = next;
+    drop(live);
+
+    tracing::info!(?changed, ?ignored, "reloaded {}", path.display());
+    Ok(Json(ReloadResponse {
+        ok: true,
+        changed,
+        ignored,
+    }))
+}
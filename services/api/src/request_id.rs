@@ -0,0 +1,58 @@
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use kadedb_services_telemetry::{request_span, RequestId, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+use tracing::Instrument;
+
+use crate::case::{is_json, JsonCase};
+
+/// Gives every request a [`RequestId`], taken from an incoming
+/// `X-Request-Id` when it is usable. Log lines emitted while handling the
+/// request carry it as `request_id`; the response carries it in
+/// `X-Request-Id` and, for JSON object bodies, a `request_id` field (renamed
+/// like the rest of the body under camelCase).
+///
+/// Streamed bodies only get the header.
+pub(crate) async fn request_id(
+    State(default_case): State<JsonCase>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let id = RequestId::from_header(
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let case = JsonCase::of_request(&req, default_case);
+    req.extensions_mut().insert(id.clone());
+    let traceparent = req
+        .headers()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let span = request_span(&id, traceparent);
+    let mut res = next.run(req).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    if !is_json(&res) || res.body().size_hint().exact().is_none() {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut map)) => {
+            map.insert(case.key("request_id"), id.as_str().into());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(serde_json::to_vec(&map).expect("serialize json"))
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,44 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use kadedb_services_auth::{Principal, Role};
+use kadedb_services_ffi::RestrictedTables;
+
+use crate::{error::ApiError, AppState};
+
+/// The tables the caller may not query: `restricted_tables` unless the
+/// caller is an admin. Without auth there is no caller to restrict.
+pub(crate) struct TableAccess(Option<RestrictedTables>);
+
+impl TableAccess {
+    /// 403 if `sql` references a table the caller may not query.
+    pub(crate) fn check(&self, sql: &str) -> Result<(), ApiError> {
+        match self.0.as_ref().and_then(|tables| tables.find_in(sql)) {
+            Some(table) => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("table `{table}` is restricted to admins"),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for TableAccess {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let restricted = &state.config.restricted_tables;
+        let applies = !restricted.is_empty()
+            && parts
+                .extensions
+                .get::<Principal>()
+                .is_some_and(|p| p.role != Role::Admin);
+        Ok(Self(applies.then(|| restricted.clone())))
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{extract::State, middleware, routing::MethodRouter};
+use kadedb_services_auth::{AuthConfig, Principal};
+
+use crate::{auth_rejection, AppState};
+
+/// The routes of the v1 router that can require a scope, as declared there.
+const ROUTES: &[&str] = &[
+    "/query",
+    "/query/count",
+    "/export",
+    "/tables",
+    "/tables/:name/schema",
+    "/tables/:name/import",
+    "/tables/:name/rows",
+    "/templates/:name",
+    "/templates/:name/run",
+    "/queries/:id",
+    "/script",
+    "/admin/pool",
+    "/admin/stats",
+    "/admin/reload",
+    "/admin/pause",
+    "/admin/resume",
+];
+
+/// Scopes a token must carry, by route (`/query`, `/tables/:name/import`,
+/// ... as declared in the v1 router). Routes without an entry only need
+/// their role.
+#[derive(Debug, Clone, Default)]
+pub struct RouteScopes {
+    scopes: HashMap<String, String>,
+    /// Pairs [`RouteScopes::parse`] couldn't read, for
+    /// [`RouteScopes::validate`].
+    malformed: Vec<String>,
+}
+
+impl RouteScopes {
+    /// Requires `scope` on `route`, replacing any scope set before.
+    pub fn with(mut self, route: impl Into<String>, scope: impl Into<String>) -> Self {
+        self.scopes.insert(route.into(), scope.into());
+        self
+    }
+
+    /// Parses `route=scope` pairs separated by commas, e.g.
+    /// `/query=query:run,/tables=tables:create`. Malformed pairs are kept
+    /// aside and fail [`RouteScopes::validate`].
+    pub fn parse(value: &str) -> Self {
+        let mut scopes = Self::default();
+        for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match pair
+                .split_once('=')
+                .map(|(route, scope)| (route.trim(), scope.trim()))
+            {
+                Some((route, scope)) if route.starts_with('/') && !scope.is_empty() => {
+                    scopes.scopes.insert(route.to_string(), scope.to_string());
+                }
+                _ => scopes.malformed.push(pair.to_string()),
+            }
+        }
+        scopes
+    }
+
+    /// Fails on a malformed pair or a route the v1 router doesn't have,
+    /// either of which would otherwise leave a route unprotected.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(pair) = self.malformed.first() {
+            return Err(format!(
+                "KADEDB_ROUTE_SCOPES: malformed pair `{pair}`, expected `route=scope`"
+            ));
+        }
+        let mut unknown: Vec<&str> = self
+            .scopes
+            .keys()
+            .map(String::as_str)
+            .filter(|route| !ROUTES.contains(route))
+            .collect();
+        unknown.sort_unstable();
+        match unknown.first() {
+            Some(route) => Err(format!("KADEDB_ROUTE_SCOPES: unknown route `{route}`")),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads `KADEDB_ROUTE_SCOPES`; unset means no route needs a scope.
+    pub fn from_env() -> Self {
+        std::env::var("KADEDB_ROUTE_SCOPES")
+            .map(|v| Self::parse(&v))
+            .unwrap_or_default()
+    }
+
+    /// `handler` for `route`, additionally requiring the route's scope if
+    /// one is configured.
+    pub(crate) fn apply(
+        &self,
+        route: &str,
+        handler: MethodRouter<AppState>,
+        auth_cfg: &AuthConfig,
+    ) -> MethodRouter<AppState> {
+        match self.scopes.get(route) {
+            Some(scope) => handler.route_layer(middleware::from_fn_with_state(
+                (auth_cfg.clone(), Arc::<str>::from(scope.as_str())),
+                require_scope,
+            )),
+            None => handler,
+        }
+    }
+}
+
+/// Rejects with 403 unless the authenticated caller's token grants the
+/// scope. Runs after `auth_middleware`, so the role has already been checked.
+async fn require_scope(
+    State((cfg, scope)): State<(AuthConfig, Arc<str>)>,
+    req: axum::http::Request<axum::body::Body>,
+    next: middleware::Next,
+) -> axum::response::Response {
+    if !cfg.enabled {
+        return next.run(req).await;
+    }
+    let granted = req
+        .extensions()
+        .get::<Principal>()
+        .map(|p| p.require_scope(&scope));
+    match granted {
+        Some(Ok(())) => next.run(req).await,
+        Some(Err(err)) => {
+            tracing::info!(scope = %scope, "missing required scope");
+            auth_rejection(err, None)
+        }
+        None => auth_rejection(kadedb_services_auth::AuthError::MissingScope, None),
+    }
+}
@@ -0,0 +1,123 @@
+use axum::{http::StatusCode, Json};
+use kadedb_services_ffi::spawn_query;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ApiError, tenant::TenantPool};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ScriptRequest {
+    statements: Vec<String>,
+    #[serde(default)]
+    transactional: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ScriptResponse {
+    ok: bool,
+    /// One entry per statement that ran, in order.
+    results: Vec<StatementResult>,
+    /// The failure that stopped the script, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct StatementResult {
+    /// 1-based position in `statements`.
+    statement: usize,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// `POST /script`
+///
+/// Runs `statements` in order on one pool slot, each in autocommit, and stops
+/// at the first failure. Statements that ran before it stay applied; the
+/// response lists each one's outcome so the caller can tell where it stopped.
+///
+/// A statement of a kind the storage doesn't allow refuses the whole script
+/// with 403 before any of it runs.
+///
+/// The storage engine has no transactions yet, so `transactional: true` is
+/// refused with 501 rather than run without rollback.
+pub(crate) async fn run_script(
+    TenantPool(pool): TenantPool,
+    Json(req): Json<ScriptRequest>,
+) -> Result<(StatusCode, Json<ScriptResponse>), ApiError> {
+    if req.transactional {
+        return Err(ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "transactional scripts are not supported: the storage engine has no transactions",
+        ));
+    }
+    if req.statements.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "`statements` must not be empty",
+        ));
+    }
+
+    let guard = pool.acquire().await?;
+    let storage = guard.storage();
+    for (i, sql) in req.statements.iter().enumerate() {
+        if let Err(err) = storage.allowed_statements().check(sql) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("statement {}: {err}", i + 1),
+            ));
+        }
+    }
+    let context = format!("script of {} statements", req.statements.len());
+    let results = spawn_query(context, move || {
+        let mut results = Vec::with_capacity(req.statements.len());
+        for (i, sql) in req.statements.iter().enumerate() {
+            let _span = tracing::info_span!("query.execute", statement = i + 1).entered();
+            let outcome = storage
+                .execute_prepared(&storage.prepare(sql), &[])
+                .and_then(|mut rs| rs.all_rows_as_strings());
+            let failed = outcome.is_err();
+            results.push(match outcome {
+                Ok(rows) => StatementResult {
+                    statement: i + 1,
+                    ok: true,
+                    rows: Some(rows.len()),
+                    error: None,
+                },
+                Err(err) => StatementResult {
+                    statement: i + 1,
+                    ok: false,
+                    rows: None,
+                    error: Some(err.to_string()),
+                },
+            });
+            if failed {
+                break;
+            }
+        }
+        results
+    })
+    .await
+    .expect("spawn_blocking");
+    drop(guard);
+
+    let error = results
+        .iter()
+        .find_map(|r| r.error.as_ref())
+        .map(|err| format!("statement {}: {err}", results.len()));
+    let status = if error.is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+    Ok((
+        status,
+        Json(ScriptResponse {
+            ok: error.is_none(),
+            results,
+            error,
+        }),
+    ))
+}
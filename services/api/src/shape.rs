@@ -0,0 +1,315 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+use kadedb_services_ffi::{
+    spawn_query, CancelToken, ErrorCode, FfiError, PoolGuard, ResultSet, Value, NDJSON_CONTROL_KEY,
+};
+use kadedb_services_telemetry::{record_query, SlowQueryLog};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    checksum::RowDigest,
+    error::{ApiError, ErrorReport},
+    queries::ActiveQuery,
+    QUERY_ID_HEADER,
+};
+
+/// On an NDJSON response, the auto-limit applied to the stream: at most this
+/// many rows follow.
+pub const AUTO_LIMIT_HEADER: HeaderName = HeaderName::from_static("x-auto-limit");
+
+/// On a `/query` response without the envelope, the result's column names
+/// as a JSON array. Names outside ASCII are `\u` escaped.
+pub const COLUMNS_HEADER: HeaderName = HeaderName::from_static("x-columns");
+
+/// Rows buffered between the blocking reader and the response body.
+const NDJSON_BUFFER_ROWS: usize = 64;
+
+/// How `/query` lays out result rows.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Shape {
+    /// `rows: [[v, ...], ...]` in column order.
+    #[default]
+    Arrays,
+    /// `rows: [{"col": v, ...}, ...]`.
+    Objects,
+    /// One JSON object per line (`application/x-ndjson`), streamed as rows
+    /// are read, with no envelope.
+    Ndjson,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ShapeParams {
+    #[serde(default)]
+    pub shape: Shape,
+    /// Interleave progress lines with an NDJSON stream's rows.
+    #[serde(default, deserialize_with = "crate::from_query_str")]
+    pub progress: bool,
+    /// `false` answers with just the rows; unset keeps the configured
+    /// default.
+    #[serde(default, deserialize_with = "crate::from_optional_query_str")]
+    pub envelope: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Rows {
+    Arrays(Vec<Vec<String>>),
+    Objects(Vec<Map<String, serde_json::Value>>),
+}
+
+impl Rows {
+    pub(crate) fn new(shape: Shape, columns: &[String], rows: Vec<Vec<String>>) -> Self {
+        match shape {
+            Shape::Objects | Shape::Ndjson => {
+                let keys = object_keys(columns);
+                Self::Objects(rows.into_iter().map(|r| to_object(&keys, r)).collect())
+            }
+            Shape::Arrays => Self::Arrays(rows),
+        }
+    }
+}
+
+/// Object keys for `columns`: duplicates get a numeric suffix (`col`,
+/// `col_2`, ...) that doesn't collide with any other column.
+pub(crate) fn object_keys(columns: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::with_capacity(columns.len());
+    for name in columns {
+        let mut key = name.clone();
+        let mut n = 1;
+        while keys.contains(&key) || (n > 1 && columns.contains(&key)) {
+            n += 1;
+            key = format!("{name}_{n}");
+        }
+        keys.push(key);
+    }
+    keys
+}
+
+/// [`COLUMNS_HEADER`] for `columns`.
+pub(crate) fn columns_header(columns: &[String]) -> HeaderValue {
+    let json = serde_json::to_string(columns).unwrap_or_default();
+    let mut ascii = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() && c != '\x7f' {
+            ascii.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                ascii.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    HeaderValue::from_str(&ascii).expect("escaped to visible ASCII")
+}
+
+fn to_object(keys: &[String], row: Vec<String>) -> Map<String, serde_json::Value> {
+    keys.iter()
+        .cloned()
+        .zip(row.into_iter().map(serde_json::Value::String))
+        .collect()
+}
+
+/// Runs `sql` and streams its rows as NDJSON. Errors raised before the first
+/// row (bad statement, unknown table) get a normal error response; once
+/// streaming has started, a failure ends the stream with a final
+/// `{"ok":false,"error":...,"code":...,"rows_sent":N}` line and is logged;
+/// the line's message is worded by `report`, with a `correlation_id` when
+/// it is withheld.
+/// Reading stops at `deadline`, ending the stream with a `timeout` error
+/// line. With a `limit`, at most that many rows are sent. With a `digest`, a stream
+/// that completes ends with a `{"checksum":...}` line hashing the lines
+/// before it. With a `progress` interval, a `{"progress":{...}}` line is sent
+/// that often while the stream is open (see [`with_progress`]). These
+/// control lines are wrapped in a [`NDJSON_CONTROL_KEY`] envelope (see
+/// [`control_line`]) so they can't be mistaken for rows.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn ndjson(
+    guard: PoolGuard,
+    active: ActiveQuery,
+    sql: String,
+    params: Vec<Value>,
+    limit: Option<usize>,
+    tag: String,
+    slow: SlowQueryLog,
+    subject: Option<String>,
+    digest: Option<RowDigest>,
+    progress: Option<Duration>,
+    deadline: Option<Instant>,
+    report: ErrorReport,
+) -> Result<Response, ApiError> {
+    let (ready_tx, ready_rx) = oneshot::channel::<Result<(), FfiError>>();
+    let (tx, rx) = mpsc::channel::<String>(NDJSON_BUFFER_ROWS);
+    let id = active.id().to_string();
+    let cancel = active.token();
+    let storage = guard.storage();
+    let started = Instant::now();
+    let rows_sent = Arc::new(AtomicUsize::new(0));
+    let counter = rows_sent.clone();
+    let span = tracing::info_span!("query.execute", tag = %tag, shape = "ndjson");
+
+    spawn_query(sql.clone(), move || {
+        let _entered = span.enter();
+        // Hold the pool slot and the cancellation registration until the
+        // last row has been handed off.
+        let _guard = guard;
+        let _active = active;
+        let executing = Instant::now();
+        let statement = storage.prepare(&sql);
+        let kind = statement.kind().as_str();
+        let result = match storage.execute_prepared(&statement, &params) {
+            Ok(mut rs) => {
+                let _ = ready_tx.send(Ok(()));
+                send_rows(
+                    &mut rs, cancel, deadline, limit, digest, &report, &counter, &tx,
+                )
+            }
+            Err(err) => {
+                let _ = ready_tx.send(Err(err));
+                record_query(&tag, kind, false, started.elapsed());
+                return;
+            }
+        };
+        record_query(&tag, kind, result.is_ok(), started.elapsed());
+        match result {
+            Ok(rows) => slow.record(&sql, executing.elapsed(), rows, subject.as_deref()),
+            Err((rows_sent, err)) => {
+                tracing::warn!(error = %err, rows_sent, "ndjson stream ended early")
+            }
+        }
+    });
+
+    match ready_rx.await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => return Err(err.into()),
+        Err(_) => return Err(FfiError::ExecuteQueryFailed.into()),
+    }
+
+    let body = match progress {
+        Some(every) => Body::from_stream(with_progress(rx, every, rows_sent, started)),
+        None => Body::from_stream(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|line| (Ok::<_, Infallible>(line), rx))
+        })),
+    };
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (QUERY_ID_HEADER, id),
+        ],
+        body,
+    )
+        .into_response();
+    if let Some(limit) = limit {
+        response
+            .headers_mut()
+            .insert(AUTO_LIMIT_HEADER, limit.into());
+    }
+    Ok(response)
+}
+
+/// The lines of `rx`, with a `{"progress":{"rows_sent":N,"elapsed_ms":M}}`
+/// line every `every` until it ends. The engine can't report how far it has
+/// scanned, so this is only a heartbeat: rows handed to the stream so far
+/// and time since `started`. Progress lines are not part of the checksum.
+fn with_progress(
+    rx: mpsc::Receiver<String>,
+    every: Duration,
+    rows_sent: Arc<AtomicUsize>,
+    started: Instant,
+) -> impl futures_util::Stream<Item = Result<String, Infallible>> {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    stream::unfold((rx, ticks), move |(mut rx, mut ticks)| {
+        let rows_sent = rows_sent.clone();
+        async move {
+            let line = tokio::select! {
+                biased;
+                _ = ticks.tick() => control_line(serde_json::json!({"progress": {
+                    "rows_sent": rows_sent.load(Ordering::Relaxed),
+                    "elapsed_ms": started.elapsed().as_millis() as u64,
+                }})),
+                line = rx.recv() => line?,
+            };
+            Some((Ok(line), (rx, ticks)))
+        }
+    })
+}
+
+/// Sends one NDJSON line per row of `rs` until the result or the receiver
+/// is done, or `limit` rows are sent, returning the number of rows sent and
+/// keeping `rows_sent` up to date. A read error is sent as a final error
+/// line and returned with the count of rows that preceded it.
+#[allow(clippy::too_many_arguments)]
+fn send_rows(
+    rs: &mut ResultSet,
+    cancel: CancelToken,
+    deadline: Option<Instant>,
+    limit: Option<usize>,
+    mut digest: Option<RowDigest>,
+    report: &ErrorReport,
+    rows_sent: &AtomicUsize,
+    tx: &mpsc::Sender<String>,
+) -> Result<usize, (usize, FfiError)> {
+    let keys = object_keys(&rs.column_names());
+    let mut reader = rs.row_reader().with_cancel(cancel);
+    if let Some(deadline) = deadline {
+        reader = reader.with_deadline(deadline);
+    }
+    let mut sent = 0;
+    while limit != Some(sent) {
+        let row = match reader.next_row() {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            Err(err) => {
+                let (message, correlation_id) = report.message(&err);
+                let mut body = serde_json::json!({
+                    "ok": false,
+                    "error": message,
+                    "code": ErrorCode::from(&err).as_str(),
+                    "rows_sent": sent,
+                });
+                if let Some(id) = correlation_id {
+                    body["correlation_id"] = id.into();
+                }
+                let line = control_line(body);
+                let _ = tx.blocking_send(line);
+                return Err((sent, err));
+            }
+        };
+        let mut line =
+            serde_json::to_string(&to_object(&keys, row.to_vec())).expect("serialize row");
+        line.push('\n');
+        if let Some(digest) = &mut digest {
+            digest.update(line.as_bytes());
+        }
+        if tx.blocking_send(line).is_err() {
+            // Client went away.
+            return Ok(sent);
+        }
+        sent += 1;
+        rows_sent.store(sent, Ordering::Relaxed);
+    }
+    if let Some(digest) = digest {
+        let _ = tx.blocking_send(control_line(
+            serde_json::json!({ "checksum": digest.finish() }),
+        ));
+    }
+    Ok(sent)
+}
+
+/// `body` as an NDJSON control line: `{"$kadedb":body}` and a newline.
+fn control_line(body: serde_json::Value) -> String {
+    let mut line = serde_json::json!({ NDJSON_CONTROL_KEY: body }).to_string();
+    line.push('\n');
+    line
+}
@@ -0,0 +1,121 @@
+use std::cmp::Ordering;
+
+use axum::http::{HeaderName, StatusCode};
+use kadedb_services_ffi::{ColumnInfo, ColumnType};
+use serde::Deserialize;
+
+use crate::{error::ApiError, keyset::Key};
+
+/// Set on a `?sort=` response whose result had more rows than
+/// `sort_max_rows`, so it was returned in the order the query produced.
+pub const SORT_SKIPPED_HEADER: HeaderName = HeaderName::from_static("x-sort-skipped");
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SortParams {
+    /// `col[:asc|:desc],...`, most significant first.
+    sort: Option<String>,
+}
+
+impl SortParams {
+    pub(crate) fn is_set(&self) -> bool {
+        self.sort.is_some()
+    }
+
+    /// The requested order over a result with `columns`; `None` when the
+    /// request doesn't sort. Unknown columns and directions are a 400.
+    pub(crate) fn resolve(&self, columns: &[ColumnInfo]) -> Result<Option<Sort>, ApiError> {
+        let Some(sort) = &self.sort else {
+            return Ok(None);
+        };
+        let keys =
+            sort.split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+                .map(|key| {
+                    let (name, direction) = key.split_once(':').unwrap_or((key, "asc"));
+                    let descending = match direction.trim().to_ascii_lowercase().as_str() {
+                        "asc" => false,
+                        "desc" => true,
+                        _ => {
+                            return Err(bad_request(format!(
+                                "`sort` direction must be `asc` or `desc`, not `{direction}`"
+                            )))
+                        }
+                    };
+                    let name = name.trim();
+                    let column = columns.iter().position(|c| c.name == name).ok_or_else(|| {
+                        bad_request(format!("`sort` names unknown column `{name}`"))
+                    })?;
+                    Ok(SortKey {
+                        column,
+                        ty: columns[column].column_type,
+                        descending,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(bad_request("`sort` must name at least one column"));
+        }
+        Ok(Some(Sort(keys)))
+    }
+}
+
+#[derive(Debug)]
+struct SortKey {
+    column: usize,
+    ty: ColumnType,
+    descending: bool,
+}
+
+/// A result order: values compare by their column's type. NULLs, and cells
+/// that don't parse as the type, compare greater than every value, so they
+/// come last ascending and first descending.
+#[derive(Debug)]
+pub(crate) struct Sort(Vec<SortKey>);
+
+impl Sort {
+    /// Sorts `rows` in place, keeping rows with equal keys in their order.
+    /// More than `max_rows` rows are left as they are; returns whether they
+    /// were sorted.
+    pub(crate) fn apply(&self, rows: &mut Vec<Vec<String>>, max_rows: Option<usize>) -> bool {
+        if max_rows.is_some_and(|max| rows.len() > max) {
+            return false;
+        }
+        let mut keyed: Vec<(Vec<Option<Key>>, Vec<String>)> = std::mem::take(rows)
+            .into_iter()
+            .map(|row| {
+                let keys = self
+                    .0
+                    .iter()
+                    .map(|k| match row[k.column].as_str() {
+                        // Strings are quoted, so a bare `null` is NULL.
+                        "null" => None,
+                        cell => Key::parse_cell(k.ty, cell),
+                    })
+                    .collect();
+                (keys, row)
+            })
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| {
+            self.0
+                .iter()
+                .zip(a.iter().zip(b))
+                .map(|(key, (a, b))| {
+                    let order = a.is_none().cmp(&b.is_none()).then_with(|| a.cmp(b));
+                    if key.descending {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                })
+                .find(|&order| order != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+        *rows = keyed.into_iter().map(|(_, row)| row).collect();
+        true
+    }
+}
+
+fn bad_request(message: impl ToString) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, message)
+}
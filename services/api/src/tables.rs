@@ -0,0 +1,118 @@
+use axum::{extract::Query, http::StatusCode, Json};
+use kadedb_services_ffi::{spawn_query, FfiError};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ApiError, ident::TableName, tenant::TenantPool};
+
+/// Page size when the caller doesn't ask for one.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Largest page a caller may ask for.
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ListTablesParams {
+    prefix: Option<String>,
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ListTablesResponse {
+    ok: bool,
+    tables: Vec<String>,
+    /// Tables matching the prefix, across all pages.
+    total: usize,
+    /// Offset of the next page; absent on the last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_offset: Option<usize>,
+}
+
+/// `GET /tables?prefix=...&limit=N&offset=M`
+///
+/// Table names in ascending order, filtered by a case-sensitive name prefix
+/// and paged. The listing is read afresh per request, so tables created or
+/// dropped between pages shift later pages.
+pub(crate) async fn list_tables(
+    TenantPool(pool): TenantPool,
+    Query(params): Query<ListTablesParams>,
+) -> Result<Json<ListTablesResponse>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("`limit` must be between 1 and {MAX_PAGE_SIZE}"),
+        ));
+    }
+
+    let guard = pool.acquire().await?;
+    let storage = guard.storage();
+    let result = spawn_query("list tables".to_string(), move || storage.list_tables())
+        .await
+        .expect("spawn_blocking");
+    if result.as_ref().is_err_and(FfiError::is_storage_failure) {
+        guard.record_failure();
+    }
+    drop(guard);
+    let mut tables = result?;
+
+    if let Some(prefix) = &params.prefix {
+        tables.retain(|name| name.starts_with(prefix.as_str()));
+    }
+    let total = tables.len();
+    let page: Vec<String> = tables.into_iter().skip(params.offset).take(limit).collect();
+    let end = params.offset.saturating_add(page.len());
+    Ok(Json(ListTablesResponse {
+        ok: true,
+        tables: page,
+        total,
+        next_offset: (end < total).then_some(end),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TableSchemaResponse {
+    ok: bool,
+    table: String,
+    columns: Vec<ColumnSchemaResponse>,
+}
+
+#[derive(Debug, Serialize)]
+struct ColumnSchemaResponse {
+    name: String,
+    column_type: &'static str,
+    /// `null` when unknown.
+    nullable: Option<bool>,
+}
+
+/// `GET /tables/:name/schema`
+///
+/// The table's columns in order, with their types and nullability. The
+/// engine has no secondary indexes, so there are none to report.
+pub(crate) async fn table_schema(
+    TenantPool(pool): TenantPool,
+    TableName(table): TableName,
+) -> Result<Json<TableSchemaResponse>, ApiError> {
+    let guard = pool.acquire().await?;
+    let storage = guard.storage();
+    let context = format!("describe {table}");
+    let schema = spawn_query(context, move || storage.describe_table(&table))
+        .await
+        .expect("spawn_blocking")?;
+    drop(guard);
+
+    Ok(Json(TableSchemaResponse {
+        ok: true,
+        table: schema.name.clone(),
+        columns: schema
+            .columns
+            .iter()
+            .map(|c| ColumnSchemaResponse {
+                name: c.name.clone(),
+                column_type: c.column_type.name(),
+                nullable: c.nullable,
+            })
+            .collect(),
+    }))
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use kadedb_services_ffi::{spawn_query, FfiError, StatementKind, Value};
+use kadedb_services_telemetry::record_query;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    consistency::ConsistencyParams, error::ApiError, ident, projection::ProjectionParams,
+    queries::QueryTag, restricted::TableAccess, tenant::TenantPool, AppState, Param,
+};
+
+/// Named, parameterized queries that clients run by name instead of sending
+/// SQL.
+///
+/// Clones share one registry, so templates registered at runtime (through
+/// `PUT /templates/:name`) are visible to every request.
+#[derive(Debug, Clone, Default)]
+pub struct QueryTemplates(Arc<RwLock<HashMap<String, Arc<str>>>>);
+
+impl QueryTemplates {
+    /// Adds or replaces the template `name`. Names follow the identifier
+    /// rules of table names.
+    pub fn register_template(&self, name: &str, sql: &str) -> Result<(), String> {
+        ident::validate_identifier(name)?;
+        if sql.trim().is_empty() {
+            return Err("template SQL must not be empty".to_string());
+        }
+        self.0
+            .write()
+            .expect("templates lock")
+            .insert(name.to_string(), sql.into());
+        Ok(())
+    }
+
+    /// Builder form of [`QueryTemplates::register_template`], panicking on an
+    /// invalid name.
+    pub fn with(self, name: &str, sql: &str) -> Self {
+        self.register_template(name, sql)
+            .expect("valid template name");
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<str>> {
+        self.0.read().expect("templates lock").get(name).cloned()
+    }
+
+    fn remove(&self, name: &str) -> bool {
+        self.0
+            .write()
+            .expect("templates lock")
+            .remove(name)
+            .is_some()
+    }
+
+    /// Loads templates from the JSON object (`{"name": "SQL", ...}`) in the
+    /// file named by `KADEDB_QUERY_TEMPLATES`. Unset means none; an unreadable
+    /// file or invalid entry is logged and skipped.
+    pub fn from_env() -> Self {
+        let templates = Self::default();
+        let Some(path) = std::env::var_os("KADEDB_QUERY_TEMPLATES") else {
+            return templates;
+        };
+        let entries: HashMap<String, String> = match std::fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|err| err.to_string()))
+        {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!(path = ?path, %err, "failed to load query templates");
+                return templates;
+            }
+        };
+        for (name, sql) in entries {
+            if let Err(err) = templates.register_template(&name, &sql) {
+                tracing::warn!(template = %name, %err, "skipping query template");
+            }
+        }
+        templates
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RegisterTemplate {
+    sql: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RunTemplate {
+    /// Values for the template's `?` placeholders, bound in order.
+    #[serde(default)]
+    params: Vec<Param>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TemplateResponse {
+    ok: bool,
+    template: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RunTemplateResponse {
+    ok: bool,
+    template: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+fn unknown_template(name: &str) -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, format!("unknown template `{name}`"))
+}
+
+/// `PUT /templates/:name` (admin): `{"sql": "..."}` adds or replaces a
+/// template. Registered templates live in memory until the server restarts.
+pub(crate) async fn register_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<RegisterTemplate>,
+) -> Result<Json<TemplateResponse>, ApiError> {
+    state
+        .config
+        .templates
+        .register_template(&name, &req.sql)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+    Ok(Json(TemplateResponse {
+        ok: true,
+        template: name,
+    }))
+}
+
+/// `DELETE /templates/:name` (admin)
+pub(crate) async fn remove_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<TemplateResponse>, ApiError> {
+    if !state.config.templates.remove(&name) {
+        return Err(unknown_template(&name));
+    }
+    Ok(Json(TemplateResponse {
+        ok: true,
+        template: name,
+    }))
+}
+
+/// `POST /templates/:name/run`
+///
+/// Binds `params` into the stored SQL and runs it. The caller never supplies
+/// SQL, so read access can be granted without granting arbitrary queries.
+/// A SELECT template runs on the pool's read replica, if any, unless
+/// `consistency=strong`. `columns=a,b` keeps only those result columns, as
+/// on `/query`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn run_template(
+    State(state): State<AppState>,
+    TenantPool(pool): TenantPool,
+    QueryTag(tag): QueryTag,
+    access: TableAccess,
+    Path(name): Path<String>,
+    Query(consistency): Query<ConsistencyParams>,
+    Query(projection): Query<ProjectionParams>,
+    Json(req): Json<RunTemplate>,
+) -> Result<Json<RunTemplateResponse>, ApiError> {
+    let sql = state
+        .config
+        .templates
+        .get(&name)
+        .ok_or_else(|| unknown_template(&name))?;
+    access.check(&sql)?;
+    let params: Vec<Value> = req.params.into_iter().map(Value::from).collect();
+    let kind = StatementKind::of(&sql).as_str();
+
+    let pool = consistency.route(&pool, &sql);
+    let started = Instant::now();
+    let guard = pool.acquire().await?;
+    let storage = guard.storage();
+    let span = tracing::info_span!("query.execute", tag = %tag, template = %name);
+    let result = spawn_query(format!("template {name}"), move || {
+        let _entered = span.enter();
+        let statement = storage.prepare(&sql);
+        let mut rs = storage.execute_prepared(&statement, &params)?;
+        let columns = rs.column_names();
+        let rows = rs.all_rows_as_strings()?;
+        Ok::<_, FfiError>((columns, rows))
+    })
+    .await
+    .expect("spawn_blocking");
+    if result.as_ref().is_err_and(FfiError::is_storage_failure) {
+        guard.record_failure();
+    }
+    drop(guard);
+    record_query(&tag, kind, result.is_ok(), started.elapsed());
+
+    let (mut columns, mut rows) = result?;
+    if let Some(projection) = projection.resolve(columns.iter().map(String::as_str))? {
+        columns = projection.apply(&columns);
+        rows = projection.rows(rows);
+    }
+    Ok(Json(RunTemplateResponse {
+        ok: true,
+        template: name,
+        columns,
+        rows,
+    }))
+}
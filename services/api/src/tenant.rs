@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, response::Response};
+use kadedb_services_auth::{AuthError, Principal};
+use kadedb_services_ffi::{ConfiguredPools, FfiError, StoragePool};
+
+use crate::{auth_rejection, AppState};
+
+/// Header naming the tenant when auth is disabled (local/dev deployments).
+/// With auth enabled only the token's `tenant` claim is trusted.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+pub type TenantId = String;
+
+/// Maps callers to storage. In single-tenant mode every request shares one
+/// pool; in multi-tenant mode each tenant has its own, and requests that
+/// don't identify a known tenant are rejected.
+#[derive(Clone)]
+pub enum Tenancy {
+    Single(StoragePool),
+    Multi(Arc<HashMap<TenantId, StoragePool>>),
+}
+
+impl Tenancy {
+    pub fn single(pool: StoragePool) -> Self {
+        Self::Single(pool)
+    }
+
+    pub fn multi(tenants: HashMap<TenantId, StoragePool>) -> Self {
+        Self::Multi(Arc::new(tenants))
+    }
+
+    /// The pools of [`ConfiguredPools::from_env`]: one per tenant listed in
+    /// `KADEDB_TENANTS`, or a single pool when it is unset.
+    pub fn from_env() -> Result<Self, FfiError> {
+        Ok(match ConfiguredPools::from_env()? {
+            ConfiguredPools::Single(pool) => Self::single(pool),
+            ConfiguredPools::Tenants(pools) => Self::multi(pools),
+        })
+    }
+
+    /// Every pool, with its tenant in multi-tenant mode.
+    pub(crate) fn pools(&self) -> Vec<(Option<&str>, &StoragePool)> {
+        match self {
+            Self::Single(pool) => vec![(None, pool)],
+            Self::Multi(pools) => {
+                let mut pools: Vec<_> = pools.iter().map(|(t, p)| (Some(t.as_str()), p)).collect();
+                pools.sort_by_key(|(tenant, _)| *tenant);
+                pools
+            }
+        }
+    }
+
+    fn resolve(&self, tenant: Option<&str>) -> Result<&StoragePool, AuthError> {
+        match self {
+            Self::Single(pool) => Ok(pool),
+            Self::Multi(pools) => {
+                let tenant = tenant.ok_or(AuthError::MissingTenant)?;
+                pools.get(tenant).ok_or(AuthError::Forbidden)
+            }
+        }
+    }
+}
+
+/// Extracts the storage pool for the calling tenant.
+pub(crate) struct TenantPool(pub StoragePool);
+
+#[async_trait]
+impl FromRequestParts<AppState> for TenantPool {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Response> {
+        let tenant = match parts.extensions.get::<Principal>() {
+            Some(principal) => principal.tenant.as_deref(),
+            None => parts
+                .headers
+                .get(TENANT_HEADER)
+                .and_then(|v| v.to_str().ok()),
+        };
+        match state.tenancy.resolve(tenant) {
+            Ok(pool) => Ok(TenantPool(pool.clone())),
+            Err(err) => Err(auth_rejection(err, state.config.auth_realm.as_deref())),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! In-process TLS for the REST server, enabled by the `tls` feature.
+
+use std::future::Future;
+use std::io;
+
+use axum::Router;
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+    Handle,
+};
+use futures_util::future::BoxFuture;
+use kadedb_services_telemetry::ListenerConfig;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::{HttpConfig, TlsConfig};
+use crate::conn::WithClient;
+
+/// Applies `TCP_NODELAY` to accepted connections and reads their PROXY
+/// protocol header, if configured, before the TLS handshake.
+#[derive(Clone)]
+struct TcpAcceptor {
+    listener: ListenerConfig,
+}
+
+impl<S: Send + 'static> Accept<TcpStream, S> for TcpAcceptor {
+    type Stream = TcpStream;
+    type Service = WithClient<S>;
+    type Future = BoxFuture<'static, io::Result<(TcpStream, WithClient<S>)>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let listener = self.listener.clone();
+        Box::pin(async move {
+            stream.set_nodelay(listener.nodelay)?;
+            let client = listener.client_addr(&mut stream).await?;
+            Ok((stream, WithClient::new(service, client)))
+        })
+    }
+}
+
+/// Serves `app` over TLS until `shutdown` resolves. On Unix, SIGHUP reloads
+/// the certificate and key from disk; connections already open keep the old
+/// certificate, new ones get the reloaded one. A failed reload is logged and
+/// the previous certificate stays in use.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: &TlsConfig,
+    http: &HttpConfig,
+    listener_cfg: &ListenerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let rustls = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await?;
+
+    #[cfg(unix)]
+    let reload = tokio::spawn(reload_on_sighup(rustls.clone(), tls.clone()));
+
+    let handle = Handle::new();
+    let graceful = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        graceful.graceful_shutdown(None);
+    });
+
+    let acceptor = RustlsAcceptor::new(rustls).acceptor(TcpAcceptor {
+        listener: listener_cfg.clone(),
+    });
+    let mut server = axum_server::from_tcp(listener.into_std()?);
+    *server.http_builder() = crate::conn::builder(http);
+    let result = server
+        .acceptor(acceptor)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await;
+
+    #[cfg(unix)]
+    reload.abort();
+    result
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(rustls: RustlsConfig, tls: TlsConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(err) => {
+            tracing::warn!(error = %err, "can't listen for SIGHUP; TLS reload disabled");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match rustls
+            .reload_from_pem_file(&tls.cert_path, &tls.key_path)
+            .await
+        {
+            Ok(()) => tracing::info!("reloaded TLS certificate"),
+            Err(err) => tracing::error!(error = %err, "TLS certificate reload failed"),
+        }
+    }
+}
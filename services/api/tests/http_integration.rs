@@ -1,5 +1,12 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use kadedb_services_api as api;
-use kadedb_services_auth::AuthConfig;
+use kadedb_services_auth::{AuthConfig, RoleTimeouts};
+use kadedb_services_ffi::{
+    AllowedStatements, ColumnCase, ColumnSpec, ColumnType, ColumnTypeAliases, RestrictedTables,
+    Storage, StoragePool, Value,
+};
 
 #[tokio::test]
 async fn health_endpoint_works_over_http() {
@@ -15,6 +22,7 @@ async fn health_endpoint_works_over_http() {
                 enabled: false,
                 jwt_secret: None,
             },
+            api::Tenancy::single(StoragePool::new(4).expect("storage")),
         )
         .await;
     });
@@ -40,6 +48,7 @@ async fn query_endpoint_requires_auth_when_enabled() {
                 enabled: true,
                 jwt_secret: Some("secret".to_string()),
             },
+            api::Tenancy::single(StoragePool::new(4).expect("storage")),
         )
         .await;
     });
@@ -57,3 +66,3616 @@ async fn query_endpoint_requires_auth_when_enabled() {
 
     server.abort();
 }
+
+#[tokio::test]
+async fn unauthorized_responses_carry_a_bearer_challenge() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        auth_realm: Some("kadedb".to_string()),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let challenge = |res: &reqwest::Response| {
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        res.headers()[reqwest::header::WWW_AUTHENTICATE]
+            .to_str()
+            .expect("ascii")
+            .to_string()
+    };
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(challenge(&res), r#"Bearer realm="kadedb""#);
+
+    let forged = token("other", serde_json::json!({"sub": "u", "role": "read"}));
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .bearer_auth(forged)
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(
+        challenge(&res),
+        r#"Bearer realm="kadedb", error="invalid_token", error_description="the token signature is invalid""#
+    );
+
+    server.abort();
+}
+
+fn patients_storage() -> Arc<Storage> {
+    let storage = Storage::new().expect("storage");
+    storage
+        .create_table(
+            "patients",
+            &[
+                ColumnSpec {
+                    name: "id".to_string(),
+                    column_type: ColumnType::Integer,
+                    nullable: false,
+                },
+                ColumnSpec {
+                    name: "name".to_string(),
+                    column_type: ColumnType::String,
+                    nullable: true,
+                },
+            ],
+        )
+        .expect("create table");
+    Arc::new(storage)
+}
+
+async fn spawn_with_storage(
+    storage: Arc<Storage>,
+) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(storage, 4)),
+    )
+    .await
+}
+
+async fn spawn_with_tenancy(
+    auth_cfg: AuthConfig,
+    tenancy: api::Tenancy,
+) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        api::serve(listener, auth_cfg, tenancy).await;
+    });
+
+    (addr, server)
+}
+
+#[tokio::test]
+async fn idle_connections_close_after_the_idle_timeout() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        http: api::HttpConfig {
+            keepalive: true,
+            idle_timeout: Some(std::time::Duration::from_millis(200)),
+        },
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 1)),
+        config,
+    ));
+
+    let mut conn = tokio::net::TcpStream::connect(addr).await.expect("connect");
+    let request = b"GET /health HTTP/1.1\r\nhost: localhost\r\n\r\n";
+    let mut buf = vec![0; 4096];
+    for _ in 0..2 {
+        // The connection is kept alive between requests.
+        conn.write_all(request).await.expect("write");
+        let n = conn.read(&mut buf).await.expect("read");
+        assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+    }
+
+    // Past the idle timeout the server hangs up.
+    let n = tokio::time::timeout(std::time::Duration::from_secs(5), conn.read(&mut buf))
+        .await
+        .expect("closed before the test timeout")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn requests_during_shutdown_get_shutting_down() {
+    use kadedb_services_telemetry::ListenerConfig;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        listener: ListenerConfig {
+            shutdown_grace: Some(std::time::Duration::from_secs(2)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(api::serve_with_shutdown(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 1)),
+        config,
+        async {
+            let _ = rx.await;
+        },
+    ));
+    let health = format!("http://{addr}/health");
+    let res = reqwest::get(&health).await.expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    tx.send(()).expect("shutdown");
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    // Still accepting during the grace period, but only to turn requests away.
+    let res = reqwest::get(&health).await.expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()[reqwest::header::RETRY_AFTER], "1");
+    assert_eq!(res.headers()[reqwest::header::CONNECTION], "close");
+    let body: serde_json::Value = res.json().await.expect("json body");
+    assert!(body["error"]
+        .as_str()
+        .expect("error")
+        .starts_with("shutting_down"));
+
+    server.await.expect("join").expect("serve");
+}
+
+#[tokio::test]
+async fn proxy_protocol_headers_name_the_client() {
+    use kadedb_services_telemetry::ListenerConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener_cfg = ListenerConfig {
+        proxy_protocol: true,
+        ..Default::default()
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    // v1 and v2 headers name the client; bytes after them are untouched.
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    v2.extend([198, 51, 100, 9, 10, 0, 0, 1, 0x1f, 0x90, 0x01, 0xbb]);
+    let cases = [
+        (
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\n".to_vec(),
+            "203.0.113.7:51234",
+        ),
+        (v2, "198.51.100.9:8080"),
+    ];
+    for (header, client) in cases {
+        let mut conn = tokio::net::TcpStream::connect(addr).await.expect("connect");
+        conn.write_all(&header).await.expect("write");
+        conn.write_all(b"after").await.expect("write");
+        let (mut accepted, _) = listener.accept().await.expect("accept");
+        let got = listener_cfg
+            .client_addr(&mut accepted)
+            .await
+            .expect("client");
+        assert_eq!(got.to_string(), client);
+        let mut rest = [0; 5];
+        accepted.read_exact(&mut rest).await.expect("read");
+        assert_eq!(&rest, b"after");
+    }
+
+    // The server answers connections that open with a header and drops
+    // those that don't.
+    let config = api::ApiConfig {
+        listener: listener_cfg,
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 1)),
+        config,
+    ));
+    let request = "GET /health HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+    for (prefix, answered) in [
+        ("PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\n", true),
+        ("", false),
+    ] {
+        let mut conn = tokio::net::TcpStream::connect(addr).await.expect("connect");
+        conn.write_all(format!("{prefix}{request}").as_bytes())
+            .await
+            .expect("write");
+        let mut response = Vec::new();
+        let _ = conn.read_to_end(&mut response).await;
+        assert_eq!(response.starts_with(b"HTTP/1.1 200"), answered);
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn csv_import_inserts_rows() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/tables/patients/import?format=csv"))
+        .body("name,id\nalice,1\n\"bob, jr\",2\n")
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["imported"], 2);
+    assert_eq!(body["errors"].as_array().map(Vec::len), Some(0));
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1][0], "2");
+    assert!(rows[1][1].contains("bob, jr"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn csv_import_rolls_back_on_first_error() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("http://{addr}/tables/patients/import?format=csv"))
+        .body("id,name\n1,alice\nnot-a-number,bob\n3,carol\n")
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["imported"], 0);
+    assert_eq!(body["errors"][0]["row"], 2);
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert!(rows.is_empty());
+
+    let res = client
+        .post(format!(
+            "http://{addr}/tables/patients/import?format=csv&continue_on_error=true"
+        ))
+        .body("id,name\n1,alice\nnot-a-number,bob\n3,carol\n")
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["imported"], 2);
+    assert_eq!(body["errors"].as_array().map(Vec::len), Some(1));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn csv_import_reports_batches_applied_before_a_failure() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+
+    let mut body = String::from("id,name\n");
+    for id in 1..=600 {
+        body.push_str(&format!("{id},p{id}\n"));
+    }
+    body.push_str("not-a-number,bob\n");
+    for id in 602..=700 {
+        body.push_str(&format!("{id},p{id}\n"));
+    }
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/tables/patients/import?format=csv"))
+        .body(body)
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["imported"], 500);
+    assert_eq!(body["errors"][0]["row"], 601);
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.len(), 500);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn csv_import_fails_on_a_record_past_the_size_limit() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+
+    // An unterminated quote makes the rest of the body one record.
+    let mut body = String::from("id,name\n1,alice\n2,\"");
+    body.push_str(&"x".repeat(2 << 20));
+
+    let res = reqwest::Client::new()
+        .post(format!(
+            "http://{addr}/tables/patients/import?format=csv&continue_on_error=true"
+        ))
+        .body(body)
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["errors"][0]["row"], 2);
+    assert_eq!(
+        body["errors"][0]["message"],
+        "record longer than 1048576 bytes"
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn csv_import_applies_empty_field_modes_per_column() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!(
+            "http://{addr}/tables/patients/import?continue_on_error=true&empty=error,name:default"
+        ))
+        .body("id,name\n1,\n,bob\n")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["imported"], 1);
+    assert_eq!(body["errors"][0]["row"], 2);
+    assert_eq!(body["errors"][0]["column"], "id");
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.len(), 1);
+    assert_ne!(rows[0][1], "null");
+
+    let res = client
+        .post(format!(
+            "http://{addr}/tables/patients/import?empty=age:null"
+        ))
+        .body("id,name\n2,carol\n")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn json_rows_insert_by_column_name_and_reject_unknown_columns() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("http://{addr}/tables/patients/rows"))
+        .json(&serde_json::json!([{"id": 1, "name": "alice"}, {"id": 2}]))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["inserted"], 2);
+
+    let res = client
+        .post(format!("http://{addr}/tables/patients/rows"))
+        .json(&serde_json::json!([{"id": 3}, {"id": 4, "name; DROP TABLE patients": "x"}]))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["details"][0]["field"], "/1/name; DROP TABLE patients");
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1], vec!["2".to_string(), "null".to_string()]);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn json_rows_report_the_rows_applied_before_a_storage_failure() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+
+    // A NUL byte passes validation but can't be handed to the engine.
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/tables/patients/rows?key=id"))
+        .json(&serde_json::json!([
+            {"id": 1, "name": "alice"},
+            {"id": 2, "name": "b\u{0}b"},
+            {"id": 3},
+        ]))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["ok"], false);
+    assert_eq!(body["inserted"], 1);
+    assert_eq!(body["affected_keys"], serde_json::json!([1]));
+    assert!(body["error"].is_string());
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.len(), 1);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn ndjson_rows_insert_until_the_first_bad_line() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+
+    let body =
+        "{\"id\": 1, \"name\": \"alice\"}\n\n{\"id\": 2}\n{\"id\": \"three\"}\n{\"id\": 4}\n";
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/tables/patients/rows"))
+        .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+        .body(body)
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["ok"], false);
+    assert_eq!(body["line"], 4);
+    assert_eq!(body["inserted"], 2);
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.len(), 2);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn row_inserts_report_the_keys_they_applied() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows?key=id"))
+        .json(&serde_json::json!([{"id": 1, "name": "alice"}, {"id": 2}]))
+        .send()
+        .await
+        .expect("http post");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["affected_keys"], serde_json::json!([1, 2]));
+
+    // Several key columns give an array per row; an NDJSON insert that
+    // stops early reports the rows it applied.
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows?key=id,name"))
+        .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+        .body("{\"id\": 3, \"name\": \"carol\"}\n{\"id\": \"four\"}\n")
+        .send()
+        .await
+        .expect("http post");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["inserted"], 1);
+    assert_eq!(body["affected_keys"], serde_json::json!([[3, "carol"]]));
+
+    // Without `key` only the count is reported.
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows"))
+        .json(&serde_json::json!([{"id": 5}]))
+        .send()
+        .await
+        .expect("http post");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert!(body.get("affected_keys").is_none());
+
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows?key=uuid"))
+        .json(&serde_json::json!([{"id": 6}]))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "`key` names unknown column `uuid`");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn upserts_insert_new_keys_and_update_existing_ones() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+    let client = reqwest::Client::new();
+    let upsert = |row: serde_json::Value| {
+        client
+            .put(format!("http://{addr}/v1/tables/patients/rows?key=id"))
+            .json(&row)
+            .send()
+    };
+
+    let res = upsert(serde_json::json!({"id": 1, "name": "alice"}))
+        .await
+        .expect("http put");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["ok"], true);
+    assert_eq!(body["result"], "inserted");
+
+    let res = upsert(serde_json::json!({"id": 1, "name": "alicia"}))
+        .await
+        .expect("http put");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["result"], "updated");
+
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows, vec![vec!["1".to_string(), "\"alicia\"".to_string()]]);
+
+    // Concurrent upserts of one new key insert it once.
+    let results = futures_util::future::join_all(
+        (0..16).map(|i| upsert(serde_json::json!({"id": 2, "name": format!("n{i}")}))),
+    )
+    .await;
+    let mut inserted = 0;
+    for res in results {
+        let body: serde_json::Value = res.expect("http put").json().await.expect("json");
+        inserted += (body["result"] == "inserted") as usize;
+    }
+    assert_eq!(inserted, 1);
+    let rows = storage
+        .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+        .await
+        .expect("select");
+    assert_eq!(rows.iter().filter(|row| row[0] == "2").count(), 1);
+
+    // The key must be one column, and the row must carry it.
+    let res = client
+        .put(format!("http://{addr}/v1/tables/patients/rows?key=id,name"))
+        .json(&serde_json::json!({"id": 2}))
+        .send()
+        .await
+        .expect("http put");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let res = upsert(serde_json::json!({"name": "bob"}))
+        .await
+        .expect("http put");
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "id: the key must not be null");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn checksums_cover_the_rows_as_documented() {
+    use sha2::{Digest, Sha256};
+
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    insert
+        .execute(
+            &storage,
+            &[Value::Integer(1), Value::String("a\"b".to_string())],
+        )
+        .expect("insert");
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let sha256 = |bytes: &[u8]| format!("sha256:{:x}", Sha256::digest(bytes));
+
+    let res = client
+        .post(format!(
+            "http://{addr}/v1/query?checksum=sha256&shape=objects"
+        ))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    let body: serde_json::Value = res.json().await.expect("json");
+    let canonical: String = body["rows"]
+        .as_array()
+        .expect("rows")
+        .iter()
+        .map(|row| {
+            let cells: Vec<&serde_json::Value> = body["columns"]
+                .as_array()
+                .expect("columns")
+                .iter()
+                .map(|c| &row[c.as_str().expect("name")])
+                .collect();
+            format!("{}\n", serde_json::to_string(&cells).expect("json"))
+        })
+        .collect();
+    assert_eq!(body["checksum"], sha256(canonical.as_bytes()));
+
+    let res = client
+        .post(format!(
+            "http://{addr}/v1/query?checksum=sha256&shape=ndjson"
+        ))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    let body = res.text().await.expect("body");
+    let (rows, footer) = body.trim_end().rsplit_once('\n').expect("footer line");
+    let footer: serde_json::Value = serde_json::from_str(footer).expect("json");
+    assert_eq!(
+        footer["$kadedb"]["checksum"],
+        sha256(format!("{rows}\n").as_bytes())
+    );
+
+    let res = client
+        .get(format!("http://{addr}/v1/export?checksum=sha256"))
+        .query(&[("query", "SELECT * FROM patients")])
+        .send()
+        .await
+        .expect("http get");
+    let checksum = res.headers()[api::CHECKSUM_HEADER]
+        .to_str()
+        .expect("ascii")
+        .to_string();
+    assert_eq!(checksum, sha256(&res.bytes().await.expect("body")));
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?checksum=md5"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn overlong_queries_are_rejected_with_both_lengths() {
+    let storage = Storage::new().expect("storage").with_max_query_length(32);
+    let (addr, server) = spawn_with_storage(Arc::new(storage)).await;
+
+    let query = format!("SELECT * FROM {}", "t".repeat(30));
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({ "query": query }))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(
+        body["error"],
+        "query is 44 bytes, longer than the 32 allowed"
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn statements_outside_the_allowlist_are_forbidden() {
+    let storage = Storage::new()
+        .expect("storage")
+        .with_allowed_statements(AllowedStatements::parse("select").expect("valid allowlist"));
+    storage
+        .create_table(
+            "patients",
+            &[ColumnSpec {
+                name: "id".to_string(),
+                column_type: ColumnType::Integer,
+                nullable: false,
+            }],
+        )
+        .expect("create table");
+    let (addr, server) = spawn_with_storage(Arc::new(storage)).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "DELETE FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "delete statements are not allowed");
+
+    // A script is refused as a whole, before its allowed statements run.
+    let res = client
+        .post(format!("http://{addr}/v1/script"))
+        .json(&serde_json::json!({"statements": [
+            "SELECT * FROM patients",
+            "DROP TABLE patients",
+        ]}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "statement 2: ddl statements are not allowed");
+
+    // The structured write routes take no SQL but are held to the list too.
+    let res = client
+        .post(format!("http://{addr}/v1/tables"))
+        .json(&serde_json::json!({
+            "name": "visits",
+            "columns": [{"name": "id", "column_type": "integer"}],
+        }))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "ddl statements are not allowed");
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows"))
+        .json(&serde_json::json!([{"id": 1}]))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows"))
+        .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+        .body("{\"id\": 1}\n")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let res = client
+        .put(format!("http://{addr}/v1/tables/patients/rows?key=id"))
+        .json(&serde_json::json!({"id": 1}))
+        .send()
+        .await
+        .expect("http put");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "update statements are not allowed");
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/import"))
+        .body("id\n1\n")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let rows = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post")
+        .json::<serde_json::Value>()
+        .await
+        .expect("json");
+    assert_eq!(rows["rows"], serde_json::json!([]));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn column_case_applies_to_query_results_only() {
+    let storage = Storage::new()
+        .expect("storage")
+        .with_column_case(ColumnCase::Upper);
+    storage
+        .create_table(
+            "patients",
+            &[ColumnSpec {
+                name: "PatientId".to_string(),
+                column_type: ColumnType::Integer,
+                nullable: false,
+            }],
+        )
+        .expect("create table");
+    let (addr, server) = spawn_with_storage(Arc::new(storage)).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["columns"], serde_json::json!(["PATIENTID"]));
+
+    // The table's layout keeps the name it was created with.
+    let res = client
+        .get(format!("http://{addr}/v1/tables/patients/schema"))
+        .send()
+        .await
+        .expect("http get");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["columns"][0]["name"], "PatientId");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn create_table_caps_the_column_count() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        max_columns_per_table: Some(3),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let table = |count: usize| {
+        let columns: Vec<_> = (0..count)
+            .map(|i| serde_json::json!({"name": format!("c{i}"), "column_type": "integer"}))
+            .collect();
+        serde_json::json!({"name": "wide", "columns": columns})
+    };
+
+    let res = client
+        .post(format!("http://{addr}/v1/tables"))
+        .json(&table(3))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // Reported alongside the other schema problems.
+    let mut body = table(4);
+    body["columns"][3]["column_type"] = "blob".into();
+    let res = client
+        .post(format!("http://{addr}/v1/tables"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(
+        body["details"],
+        serde_json::json!([
+            {"field": "/columns", "message": "4 columns exceed the limit of 3"},
+            {"field": "/columns/3/column_type", "message": "unknown column type `blob`"},
+        ])
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn create_table_accepts_column_type_aliases() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let aliases = ColumnTypeAliases::parse("serial=integer, numeric=float, int4=bogus");
+    // An entry naming no canonical type is skipped, keeping the shipped alias.
+    assert_eq!(aliases.resolve("INT4"), Some(ColumnType::Integer));
+    let config = api::ApiConfig {
+        column_type_aliases: aliases,
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/tables"))
+        .json(&serde_json::json!({"name": "vitals", "columns": [
+            {"name": "id", "column_type": "SERIAL"},
+            {"name": "temp", "column_type": "numeric"},
+            {"name": "note", "column_type": "text"},
+            {"name": "seen", "column_type": "Boolean"},
+        ]}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    let types: Vec<&str> = body["columns"]
+        .as_array()
+        .expect("columns")
+        .iter()
+        .map(|c| c["column_type"].as_str().expect("type"))
+        .collect();
+    assert_eq!(types, ["integer", "float", "string", "boolean"]);
+
+    // The table is in storage: it takes rows and can be queried.
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("http://{addr}/v1/tables/vitals/rows"))
+        .json(&serde_json::json!([{"id": 7, "temp": 36.6, "note": "ok", "seen": true}]))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM vitals"}))
+        .send()
+        .await
+        .expect("http post");
+    let body: serde_json::Value = res.json().await.expect("json");
+    let rows = body["rows"].as_array().expect("rows");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][0], "7");
+
+    let res = client
+        .post(format!("http://{addr}/v1/tables"))
+        .json(&serde_json::json!({"name": "vitals", "columns": [
+            {"name": "id", "column_type": "integer"},
+        ]}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "table `vitals` already exists");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn minimal_error_verbosity_hides_storage_messages() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        error_verbosity: api::ErrorVerbosity::Minimal,
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("http://{addr}/v1/tables/secret_table/schema"))
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "not found");
+    assert_eq!(body["correlation_id"].as_str().map(str::len), Some(16));
+    assert_eq!(body["correlation_id"], body["request_id"]);
+
+    // Mistakes in the request itself are still explained.
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients", "params": [{"type": "integer", "value": 1}]}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(
+        body["error"],
+        "statement has 0 placeholders but 1 parameters were bound"
+    );
+    assert!(body.get("correlation_id").is_none());
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn minimal_error_verbosity_covers_imports_and_ndjson_streams() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    // Enough rows that the stream is still running when it gets cancelled.
+    let name = "x".repeat(500);
+    for id in 0..50_000 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::String(name.clone())])
+            .expect("insert");
+    }
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        error_verbosity: api::ErrorVerbosity::Minimal,
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(storage, 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!(
+            "http://{addr}/tables/secret_table/import?format=csv"
+        ))
+        .body("id,name\n1,alice\n")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["errors"][0]["message"], "not found");
+    assert_eq!(body["errors"][0]["correlation_id"], body["request_id"]);
+
+    // The NUL byte passes validation but fails in storage.
+    let res = client
+        .post(format!("http://{addr}/tables/patients/import?format=csv"))
+        .body("id,name\n1,a\0b\n")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["errors"][0]["row"], 1);
+    assert_eq!(body["errors"][0]["message"], "internal server error");
+    assert_eq!(body["errors"][0]["correlation_id"], body["request_id"]);
+
+    // A stream's error line goes through the same wording: cancellation is
+    // the caller's doing, so its message stays.
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let id = res.headers()[api::QUERY_ID_HEADER]
+        .to_str()
+        .expect("query id")
+        .to_string();
+    let cancelled = client
+        .delete(format!("http://{addr}/v1/queries/{id}"))
+        .send()
+        .await
+        .expect("http delete");
+    assert!(cancelled.status().is_success());
+    let text = res.text().await.expect("body");
+    let last: serde_json::Value =
+        serde_json::from_str(text.lines().last().expect("lines")).expect("json line");
+    assert_eq!(last["$kadedb"]["code"], "cancelled");
+    assert_eq!(last["$kadedb"]["error"], "query cancelled");
+    assert!(last["$kadedb"].get("correlation_id").is_none());
+
+    server.abort();
+}
+
+fn token(secret: &str, mut claims: serde_json::Value) -> String {
+    claims["exp"] = serde_json::json!(u32::MAX);
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .expect("encode token")
+}
+
+#[tokio::test]
+async fn import_routes_to_tenant_storage() {
+    let acme = patients_storage();
+    let globex = patients_storage();
+    let tenancy = api::Tenancy::multi(HashMap::from([
+        (
+            "acme".to_string(),
+            StoragePool::with_storage(acme.clone(), 4),
+        ),
+        (
+            "globex".to_string(),
+            StoragePool::with_storage(globex.clone(), 4),
+        ),
+    ]));
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        tenancy,
+    )
+    .await;
+
+    let client = reqwest::Client::new();
+    let url = format!("http://{addr}/tables/patients/import");
+    let import = |token: String| {
+        client
+            .post(&url)
+            .bearer_auth(token)
+            .header(api::TENANT_HEADER, "globex")
+            .body("id,name\n1,alice\n")
+            .send()
+    };
+
+    // The tenant comes from the token; the header is ignored when auth is on.
+    let res = import(token(
+        "secret",
+        serde_json::json!({"role": "write", "tenant": "acme"}),
+    ))
+    .await
+    .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let select = "SELECT * FROM patients".to_string();
+    let acme_rows = acme.execute_query_rows_as_strings(select.clone()).await;
+    let globex_rows = globex.execute_query_rows_as_strings(select).await;
+    assert_eq!(acme_rows.expect("select").len(), 1);
+    assert!(globex_rows.expect("select").is_empty());
+
+    let res = import(token("secret", serde_json::json!({"role": "write"})))
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let res = import(token(
+        "secret",
+        serde_json::json!({"role": "write", "tenant": "initech"}),
+    ))
+    .await
+    .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn query_params_must_match_placeholders() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/query"))
+        .json(&serde_json::json!({
+            "query": "SELECT * FROM patients WHERE id = ? AND name = '?'",
+            "params": [
+                {"type": "integer", "value": 1},
+                {"type": "string", "value": "alice"}
+            ]
+        }))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert!(body["error"]
+        .as_str()
+        .is_some_and(|e| e.contains("1 placeholders but 2 parameters")));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn plain_json_params_bind_by_inferred_type() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+    let query = |params: serde_json::Value| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .post(format!("http://{addr}/v1/query"))
+                .json(&serde_json::json!({"query": "SELECT * FROM patients", "params": params}))
+                .send()
+                .await
+                .expect("http post");
+            (res.status(), res.text().await.expect("body"))
+        }
+    };
+
+    // Every form parses; the statement just has no placeholders for them.
+    let (status, body) = query(serde_json::json!([
+        1,
+        2.5,
+        "x",
+        true,
+        null,
+        {"type": "float", "value": 3}
+    ]))
+    .await;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+    assert!(body.contains("0 placeholders but 6 parameters"), "{body}");
+
+    let (status, body) = query(serde_json::json!([u64::MAX])).await;
+    assert!(status.is_client_error());
+    assert!(body.contains("out of range"), "{body}");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn array_params_bind_as_in_lists() {
+    let storage = patients_storage();
+    let statement = storage.prepare("SELECT * FROM patients WHERE name IN (?) AND id > ?");
+    let bound = statement
+        .bind(&[
+            Value::Array(vec![
+                Value::String("o'hara".to_string()),
+                Value::Null,
+                Value::String("bob".to_string()),
+            ]),
+            Value::Integer(1),
+        ])
+        .expect("bind");
+    assert_eq!(
+        bound,
+        "SELECT * FROM patients WHERE name IN ('o''hara', NULL, 'bob') AND id > 1"
+    );
+
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let query = |params: serde_json::Value| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .post(format!("http://{addr}/v1/query"))
+                .json(&serde_json::json!({
+                    "query": "SELECT * FROM patients WHERE id IN (?)",
+                    "params": params,
+                }))
+                .send()
+                .await
+                .expect("http post");
+            (res.status(), res.text().await.expect("body"))
+        }
+    };
+
+    for (params, error) in [
+        (
+            serde_json::json!([[1, "two"]]),
+            "array mixes integer and string",
+        ),
+        (serde_json::json!([[]]), "array is empty"),
+        (serde_json::json!([[[1]]]), "can't nest"),
+    ] {
+        let (status, body) = query(params).await;
+        assert!(status.is_client_error(), "{body}");
+        assert!(body.contains(error), "{body}");
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn count_returns_only_the_row_count() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 1..=7 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let count = |query: &'static str| {
+        client
+            .post(format!("http://{addr}/v1/query/count"))
+            .json(&serde_json::json!({ "query": query }))
+            .send()
+    };
+
+    let res = count("SELECT * FROM patients").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["count"], 7);
+    assert!(body.get("rows").is_none());
+
+    let res = count("DELETE FROM patients").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "`/query/count` takes a SELECT statement");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn counts_can_be_cancelled_and_free_their_pool_slot() {
+    let pool = StoragePool::with_storage(patients_storage(), 1);
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    // With the only pool slot taken, the count waits registered under the
+    // id it asked for.
+    let slot = pool.acquire().await.expect("acquire");
+    let count = tokio::spawn({
+        let client = client.clone();
+        async move {
+            client
+                .post(format!("http://{addr}/v1/query/count"))
+                .header(api::QUERY_ID_HEADER, "row-count")
+                .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+                .send()
+                .await
+                .expect("http post")
+        }
+    });
+    let mut status = reqwest::StatusCode::NOT_FOUND;
+    for _ in 0..100 {
+        status = client
+            .delete(format!("http://{addr}/v1/queries/row-count"))
+            .send()
+            .await
+            .expect("http delete")
+            .status();
+        if status != reqwest::StatusCode::NOT_FOUND {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(status, reqwest::StatusCode::NO_CONTENT);
+    drop(slot);
+
+    let res = count.await.expect("count task");
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    assert_eq!(pool.in_use(), 0);
+
+    let res = client
+        .post(format!("http://{addr}/v1/query/count"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()[api::QUERY_ID_HEADER].len(), 32);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn export_resumes_from_cursor() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for (id, name) in [(1, "alice"), (2, "bob"), (3, "carol")] {
+        insert
+            .execute(
+                &storage,
+                &[Value::Integer(id), Value::String(name.to_string())],
+            )
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("http://{addr}/export"))
+        .query(&[("query", "SELECT * FROM patients"), ("limit", "2")])
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let cursor = res
+        .headers()
+        .get(api::NEXT_CURSOR_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .expect("next cursor");
+    let first = res.text().await.expect("body");
+    assert_eq!(first, "id,name\r\n1,alice\r\n2,bob\r\n");
+
+    let res = client
+        .get(format!("http://{addr}/export"))
+        .query(&[("cursor", cursor.as_str())])
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.headers().get(api::NEXT_CURSOR_HEADER).is_none());
+    let rest = res.text().await.expect("body");
+    assert_eq!(rest, "id,name\r\n3,carol\r\n");
+
+    let res = client
+        .get(format!("http://{addr}/export"))
+        .query(&[("cursor", "not-a-cursor")])
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn table_routes_reject_non_identifier_names() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+
+    for name in [
+        "foo%3B%20DROP%20TABLE%20bar",
+        "1abc",
+        "pat--ients",
+        "select",
+    ] {
+        let res = client
+            .post(format!("http://{addr}/tables/{name}/import?format=csv"))
+            .body("id\n1\n")
+            .send()
+            .await
+            .expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST, "{name}");
+    }
+
+    let res = client
+        .post(format!("http://{addr}/tables"))
+        .json(&serde_json::json!({
+            "name": "foo; DROP TABLE bar",
+            "columns": [
+                {"name": "id", "column_type": "integer"},
+                {"name": "id", "column_type": "blob"},
+            ],
+        }))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["ok"], false);
+    let fields: Vec<&str> = body["details"]
+        .as_array()
+        .expect("details")
+        .iter()
+        .filter_map(|d| d["field"].as_str())
+        .collect();
+    assert_eq!(
+        fields,
+        ["/name", "/columns/1/name", "/columns/1/column_type"]
+    );
+
+    let res = client
+        .post(format!("http://{addr}/tables"))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body("{\"name\": \"visits\", \"columns\": ")
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn protobuf_accept_encodes_rows_as_grpc_messages() {
+    use kadedb_services_grpc::kadedb::{ColumnType as ProtoType, QueryRow, QuerySchema};
+    use prost::Message;
+
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    insert
+        .execute(&storage, &[Value::Integer(1), Value::String("Ada".into())])
+        .expect("insert");
+    insert
+        .execute(&storage, &[Value::Integer(2), Value::Null])
+        .expect("insert");
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .header(reqwest::header::ACCEPT, "application/x-protobuf")
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        res.headers()[reqwest::header::CONTENT_TYPE],
+        "application/x-protobuf"
+    );
+    let bytes = res.bytes().await.expect("body");
+    let mut buf = &bytes[..];
+    let schema = QuerySchema::decode_length_delimited(&mut buf).expect("schema");
+    let columns: Vec<_> = schema
+        .columns
+        .iter()
+        .map(|c| (c.name.as_str(), c.r#type()))
+        .collect();
+    assert_eq!(
+        columns,
+        [("id", ProtoType::Integer), ("name", ProtoType::String)]
+    );
+    let mut rows = Vec::new();
+    while !buf.is_empty() {
+        let row = QueryRow::decode_length_delimited(&mut buf).expect("row");
+        rows.push(serde_json::from_str::<serde_json::Value>(&row.json).expect("row json"));
+    }
+
+    // The same rows as the JSON objects shape.
+    let json: serde_json::Value = client
+        .post(format!("http://{addr}/v1/query?shape=objects"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(serde_json::Value::Array(rows), json["rows"]);
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .header(reqwest::header::ACCEPT, "application/x-protobuf")
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn ndjson_progress_lines_are_interleaved_with_rows() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 0..5000 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(storage, 4)),
+        api::ApiConfig {
+            progress_interval: Some(std::time::Duration::from_millis(1)),
+            ..Default::default()
+        },
+    ));
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let text = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson&progress=true"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post")
+        .text()
+        .await
+        .expect("body");
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("json line"))
+        .collect();
+    let (progress, rows): (Vec<_>, Vec<_>) = lines.iter().partition(|l| l.get("$kadedb").is_some());
+    assert_eq!(rows.len(), 5000);
+    assert!(!progress.is_empty());
+    let mut last = 0;
+    for line in progress {
+        let progress = &line["$kadedb"]["progress"];
+        let sent = progress["rows_sent"].as_u64().expect("rows_sent");
+        assert!(sent >= last && sent <= 5000);
+        assert!(progress["elapsed_ms"].is_u64());
+        last = sent;
+    }
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?progress=true"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn responses_carry_the_request_id() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    let header = res.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert_eq!(header.len(), 16);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["request_id"], header.as_str());
+
+    // Errors too; a proxy's id is kept, and camelCase renames the field.
+    let res = client
+        .post(format!("http://{addr}/v1/query?case=camel"))
+        .header("x-request-id", "ticket-42")
+        .json(&serde_json::json!({"query": "SELECT * FROM missing"}))
+        .send()
+        .await
+        .expect("http post");
+    assert!(res.status().is_client_error());
+    assert_eq!(res.headers()["x-request-id"], "ticket-42");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["requestId"], "ticket-42");
+
+    // An unusable id is replaced.
+    let res = client
+        .get(format!("http://{addr}/health"))
+        .header("x-request-id", "no spaces allowed")
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.headers()["x-request-id"].len(), 16);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn case_param_renames_response_fields() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({
+        "name": "visits",
+        "columns": [{"name": "patient_id", "column_type": "integer"}],
+    });
+
+    let res = client
+        .post(format!("http://{addr}/tables"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    let snake: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(snake["column_count"], 1);
+
+    let mut body = body;
+    body["name"] = "visits_camel".into();
+    let res = client
+        .post(format!("http://{addr}/tables?case=camel"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    let camel: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(camel["columnCount"], 1);
+    assert_eq!(camel["columns"][0]["columnType"], "integer");
+    assert!(camel.get("column_count").is_none());
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn envelope_false_returns_only_the_rows() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        bare_results: true,
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    client
+        .post(format!("http://{addr}/v1/tables/patients/rows"))
+        .json(&serde_json::json!([{"id": 1, "name": "alice"}]))
+        .send()
+        .await
+        .expect("http post");
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    // Bare by configuration.
+    let res = client
+        .post(format!("http://{addr}/v1/query?checksum=sha256"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["x-columns"], r#"["id","name"]"#);
+    assert!(res.headers().contains_key("x-checksum"));
+    let rows: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(rows[0][0], "1");
+
+    // The request can ask for the envelope back.
+    let res = client
+        .post(format!("http://{addr}/v1/query?envelope=true"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert!(res.headers().get("x-columns").is_none());
+    let enveloped: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(enveloped["ok"], true);
+    assert_eq!(enveloped["rows"], rows);
+
+    // Keyset paging needs the envelope for `next_after`.
+    let res = client
+        .post(format!("http://{addr}/v1/query?order_by=id&after=0"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn reads_go_to_the_replica_unless_strong() {
+    let primary = patients_storage();
+    let replica = patients_storage();
+    let pool = StoragePool::with_storage(primary.clone(), 4)
+        .with_read_replica(StoragePool::with_storage(replica.clone(), 4));
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    // Writes go to the primary, which the replica hasn't caught up with.
+    let res = client
+        .post(format!("http://{addr}/v1/tables/patients/rows"))
+        .json(&serde_json::json!([{"id": 1}]))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        primary
+            .execute_query_rows_as_strings("SELECT * FROM patients".to_string())
+            .await
+            .expect("select")
+            .len(),
+        1
+    );
+
+    let rows = |consistency: &'static str| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .post(format!("http://{addr}/v1/query{consistency}"))
+                .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+                .send()
+                .await
+                .expect("http post");
+            let body: serde_json::Value = res.json().await.expect("json");
+            body["rows"].as_array().map_or(0, Vec::len)
+        }
+    };
+    assert_eq!(rows("").await, 0);
+    assert_eq!(rows("?consistency=strong").await, 1);
+    assert_eq!(rows("?consistency=eventual").await, 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn unprefixed_routes_are_deprecated_aliases_of_v1() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.headers().get("deprecation").is_none());
+
+    let res = client
+        .post(format!("http://{addr}/query"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["deprecation"], "true");
+    assert_eq!(
+        res.headers()["link"],
+        "</v1/query>; rel=\"successor-version\""
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn empty_result_still_reports_columns() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["columns"], serde_json::json!(["id", "name"]));
+    assert_eq!(body["rows"], serde_json::json!([]));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn ddl_through_query_path_returns_without_rows() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+
+    let res = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        reqwest::Client::new()
+            .post(format!("http://{addr}/v1/query"))
+            .json(&serde_json::json!({"query": "CREATE TABLE visits (id INTEGER)"}))
+            .send(),
+    )
+    .await
+    .expect("query path returned")
+    .expect("http post");
+
+    if res.status() == reqwest::StatusCode::OK {
+        let body: serde_json::Value = res.json().await.expect("json");
+        assert_eq!(body["rows"], serde_json::json!([]));
+    } else {
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn query_pages_by_key_after_the_last_seen_value() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in [5, 3, 9, 1, 7] {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+
+    let client = reqwest::Client::new();
+    let page = |after: Option<String>| {
+        let mut url = format!("http://{addr}/v1/query?order_by=id&limit=2");
+        if let Some(after) = after {
+            url.push_str(&format!("&after={after}"));
+        }
+        client
+            .post(url)
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+
+    let mut ids = Vec::new();
+    let mut after = None;
+    loop {
+        let res = page(after).await.expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = res.json().await.expect("json");
+        for row in body["rows"].as_array().expect("rows") {
+            ids.push(row[0].as_str().expect("id").to_string());
+        }
+        after = body["next_after"].as_str().map(str::to_string);
+        if after.is_none() {
+            break;
+        }
+    }
+    assert_eq!(ids, ["1", "3", "5", "7", "9"]);
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?order_by=missing"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn export_renders_nulls_as_requested() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    insert
+        .execute(&storage, &[Value::Integer(1), Value::Null])
+        .expect("insert");
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let url = format!("http://{addr}/v1/export");
+
+    let res = client
+        .get(&url)
+        .query(&[("query", "SELECT * FROM patients")])
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.text().await.expect("body"), "id,name\r\n1,\r\n");
+
+    let res = client
+        .get(&url)
+        .query(&[("query", "SELECT * FROM patients"), ("null_as", "\\N")])
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.text().await.expect("body"), "id,name\r\n1,\\N\r\n");
+
+    let res = client
+        .get(&url)
+        .query(&[("query", "SELECT * FROM patients"), ("null_as", "a,b")])
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn finished_queries_cannot_be_cancelled() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let id = res
+        .headers()
+        .get(api::QUERY_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .expect("query id");
+
+    let res = client
+        .delete(format!("http://{addr}/v1/queries/{id}"))
+        .send()
+        .await
+        .expect("http delete");
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn buffered_queries_can_be_cancelled_by_their_owner() {
+    let pool = StoragePool::with_storage(patients_storage(), 1);
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(pool.clone()),
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let alice = token(
+        "secret",
+        serde_json::json!({"role": "read", "sub": "alice"}),
+    );
+    let bob = token("secret", serde_json::json!({"role": "read", "sub": "bob"}));
+
+    // With the only pool slot taken, the query waits registered under the
+    // id it asked for.
+    let slot = pool.acquire().await.expect("acquire");
+    let query = tokio::spawn({
+        let client = client.clone();
+        let alice = alice.clone();
+        async move {
+            client
+                .post(format!("http://{addr}/v1/query"))
+                .bearer_auth(alice)
+                .header(api::QUERY_ID_HEADER, "nightly-report")
+                .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+                .send()
+                .await
+                .expect("http post")
+        }
+    });
+    let cancel = |token: String| {
+        client
+            .delete(format!("http://{addr}/v1/queries/nightly-report"))
+            .bearer_auth(token)
+            .send()
+    };
+    let mut status = reqwest::StatusCode::NOT_FOUND;
+    for _ in 0..100 {
+        status = cancel(bob.clone()).await.expect("http delete").status();
+        assert_eq!(status, reqwest::StatusCode::NOT_FOUND, "not bob's query");
+        status = cancel(alice.clone()).await.expect("http delete").status();
+        if status != reqwest::StatusCode::NOT_FOUND {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(status, reqwest::StatusCode::NO_CONTENT);
+    drop(slot);
+
+    let res = query.await.expect("query task");
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    assert_eq!(pool.in_use(), 0);
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .bearer_auth(&alice)
+        .header(api::QUERY_ID_HEADER, "not valid!")
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn query_shapes_rows_as_objects_and_ndjson() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 1..=2 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=objects"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let json: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(
+        json["rows"][1],
+        serde_json::json!({"id": "2", "name": "null"})
+    );
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        res.headers()[reqwest::header::CONTENT_TYPE],
+        "application/x-ndjson"
+    );
+    let text = res.text().await.expect("body");
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("json line"))
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["id"], "1");
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .json(&serde_json::json!({"query": "SELECT * FROM missing"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn token_with_api_key_is_rejected_as_ambiguous() {
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+    )
+    .await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/query"))
+        .bearer_auth(token("secret", serde_json::json!({"role": "read"})))
+        .header("x-api-key", "some-key")
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "ambiguous_credentials");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn script_requires_admin_and_stops_at_first_failure() {
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+    )
+    .await;
+
+    let client = reqwest::Client::new();
+    let script = serde_json::json!({"statements": [
+        "SELECT * FROM patients",
+        "SELECT * FROM missing",
+        "SELECT * FROM patients",
+    ]});
+
+    let res = client
+        .post(format!("http://{addr}/v1/script"))
+        .bearer_auth(token("secret", serde_json::json!({"role": "write"})))
+        .json(&script)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let admin = token("secret", serde_json::json!({"role": "admin"}));
+    let res = client
+        .post(format!("http://{addr}/v1/script"))
+        .bearer_auth(&admin)
+        .json(&script)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["results"].as_array().map(Vec::len), Some(2));
+    assert_eq!(body["results"][0]["ok"], true);
+    assert_eq!(body["results"][1]["ok"], false);
+
+    let res = client
+        .post(format!("http://{addr}/v1/script"))
+        .bearer_auth(&admin)
+        .json(&serde_json::json!({"statements": ["SELECT * FROM patients"], "transactional": true}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_IMPLEMENTED);
+
+    server.abort();
+}
+
+#[test]
+fn route_scopes_must_be_well_formed_and_name_known_routes() {
+    let validate = |scopes: &str| {
+        api::ApiConfig {
+            route_scopes: api::RouteScopes::parse(scopes),
+            ..Default::default()
+        }
+        .validate()
+    };
+
+    assert_eq!(
+        validate("/query=tables:read, /tables/:name/import=tables:import"),
+        Ok(())
+    );
+    let err = validate("/query=tables:read,/export").expect_err("malformed pair");
+    assert!(err.contains("`/export`"), "{err}");
+    let err = validate("query=tables:read").expect_err("relative route");
+    assert!(err.contains("`query=tables:read`"), "{err}");
+    let err = validate("/querys=tables:read").expect_err("unknown route");
+    assert!(err.contains("unknown route `/querys`"), "{err}");
+}
+
+#[tokio::test]
+async fn route_scope_is_required_on_top_of_role() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        route_scopes: api::RouteScopes::default().with("/query", "tables:patients:read"),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+
+    let client = reqwest::Client::new();
+    let query = |claims: serde_json::Value| {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .bearer_auth(token("secret", claims))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+
+    let res = query(serde_json::json!({"role": "read"}))
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let res = query(serde_json::json!({"role": "read", "scopes": ["tables:patients:read"]}))
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // The scope doesn't stand in for the role.
+    let res = query(serde_json::json!({"role": "none", "scopes": ["tables:patients:read"]}))
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn restricted_tables_are_admin_only() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        restricted_tables: RestrictedTables::parse("patients"),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+
+    let client = reqwest::Client::new();
+    let query = |role: &str, sql: &str| {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .bearer_auth(token("secret", serde_json::json!({"role": role})))
+            .json(&serde_json::json!({"query": sql}))
+            .send()
+    };
+
+    // Aliases, quoting, case and schema qualifiers don't hide the table.
+    for sql in [
+        "SELECT * FROM patients",
+        "SELECT p.id FROM \"Patients\" AS p",
+        "SELECT * FROM visits v, public.patients",
+        "SELECT * FROM visits JOIN patients ON visits.id = patients.id",
+    ] {
+        let res = query("read", sql).await.expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN, "{sql}");
+        let body: serde_json::Value = res.json().await.expect("json");
+        let error = body["error"].as_str().expect("error").to_lowercase();
+        assert!(
+            error.ends_with("patients` is restricted to admins"),
+            "{error}"
+        );
+    }
+
+    // Other tables reach the engine.
+    let res = query("read", "SELECT * FROM visits")
+        .await
+        .expect("http post");
+    assert_ne!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let res = query("admin", "SELECT * FROM patients")
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn request_past_timeout_is_gateway_timeout() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let pool = StoragePool::with_storage(patients_storage(), 1);
+    let config = api::ApiConfig {
+        request_timeout: Some(std::time::Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+        config,
+    ));
+
+    // Holding the only connection leaves the handler waiting indefinitely.
+    let held = pool.acquire().await.expect("acquire");
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "request timed out");
+    drop(held);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn query_timeout_header_sets_the_deadline_up_to_the_max() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let pool = StoragePool::with_storage(patients_storage(), 1);
+    let config = api::ApiConfig {
+        max_query_timeout: Some(std::time::Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let query = |timeout: &'static str| {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .header(api::QUERY_TIMEOUT_HEADER, timeout)
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+
+    let res = query("soon").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // No server default, but the client's deadline applies; an hour is cut
+    // to the 200ms maximum.
+    let held = pool.acquire().await.expect("acquire");
+    for timeout in ["100", "3600000"] {
+        let started = std::time::Instant::now();
+        let res = query(timeout).await.expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+    drop(held);
+
+    let res = query("5000").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn role_timeouts_replace_the_server_default_per_role() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let pool = StoragePool::with_storage(patients_storage(), 1);
+    let config = api::ApiConfig {
+        request_timeout: Some(std::time::Duration::from_millis(200)),
+        role_timeouts: RoleTimeouts::parse("read:100/300,admin:60000"),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(pool.clone()),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let query = |role: &str, timeout: Option<&str>| {
+        let mut req = client
+            .post(format!("http://{addr}/v1/query"))
+            .bearer_auth(token("secret", serde_json::json!({"role": role})))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}));
+        if let Some(timeout) = timeout {
+            req = req.header(api::QUERY_TIMEOUT_HEADER, timeout);
+        }
+        req.send()
+    };
+
+    // Readers get their short default, and can't ask past their 300ms max.
+    let held = pool.acquire().await.expect("acquire");
+    for timeout in [None, Some("3600000")] {
+        let started = std::time::Instant::now();
+        let res = query("read", timeout).await.expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    // An admin outlasts the 200ms server default: the connection frees up
+    // after 500ms and the query completes.
+    let admin = tokio::spawn(query("admin", None));
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    drop(held);
+    let res = admin.await.expect("join").expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn queued_requests_are_served_in_order_or_refused_after_the_max_wait() {
+    let pool = StoragePool::with_storage(patients_storage(), 1)
+        .with_queue(Some(1), Some(std::time::Duration::from_millis(200)));
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+    )
+    .await;
+    let query = |client: reqwest::Client| async move {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+            .await
+            .expect("http post")
+            .status()
+    };
+    let client = reqwest::Client::new();
+
+    // Released before the wait runs out: the queued request is served.
+    let held = pool.acquire().await.expect("acquire");
+    let queued = tokio::spawn(query(client.clone()));
+    while pool.queued() == 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+    // The queue holds one caller, so the next is refused at once.
+    assert_eq!(
+        query(client.clone()).await,
+        reqwest::StatusCode::SERVICE_UNAVAILABLE
+    );
+    drop(held);
+    assert_eq!(queued.await.expect("join"), reqwest::StatusCode::OK);
+
+    // Held past the wait: refused.
+    let _held = pool.acquire().await.expect("acquire");
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["error"], "storage is busy: no free connection");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn deep_readiness_reports_the_failing_write_path() {
+    let storage = patients_storage();
+    let (addr, server) = spawn_with_storage(storage.clone()).await;
+    let client = reqwest::Client::new();
+    let ready = |query: &'static str| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .get(format!("http://{addr}/ready{query}"))
+                .send()
+                .await
+                .expect("http get");
+            let status = res.status();
+            (status, res.json::<serde_json::Value>().await.expect("json"))
+        }
+    };
+
+    let (status, body) = ready("").await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    let subsystems: Vec<_> = body["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["subsystem"].as_str().unwrap())
+        .collect();
+    assert_eq!(subsystems, ["pool", "read"]);
+
+    let (status, body) = ready("?deep=true").await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(body["checks"][2]["subsystem"], "write");
+    assert_eq!(body["checks"][2]["ok"], true);
+    let mut rs = storage
+        .execute_query("SELECT * FROM _kadedb_ready")
+        .expect("scratch table");
+    // The probe row is deleted once written.
+    assert!(rs.all_rows_as_strings().unwrap().is_empty());
+
+    // A scratch table the probe can't write to: reads still pass.
+    let storage = patients_storage();
+    storage
+        .create_table(
+            "_kadedb_ready",
+            &[ColumnSpec {
+                name: "probed_at".into(),
+                column_type: ColumnType::String,
+                nullable: false,
+            }],
+        )
+        .unwrap();
+    server.abort();
+    let (addr, server) = spawn_with_storage(storage).await;
+    let res = client
+        .get(format!("http://{addr}/ready?deep=true"))
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["status"], "unready");
+    assert_eq!(body["checks"][1]["ok"], true);
+    assert_eq!(body["checks"][2]["subsystem"], "write");
+    assert_eq!(body["checks"][2]["ok"], false);
+    assert!(body["checks"][2]["error"].is_string());
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn pool_status_reports_slots_and_health_degrades_when_saturated() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let pool = StoragePool::with_storage(patients_storage(), 2);
+    let config = api::ApiConfig {
+        pool_degraded_saturation: Some(0.5),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let get = |path: &'static str| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .get(format!("http://{addr}{path}"))
+                .send()
+                .await
+                .expect("http get");
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            res.json::<serde_json::Value>().await.expect("json")
+        }
+    };
+
+    assert_eq!(get("/health").await["status"], "ok");
+
+    let held = pool.acquire().await.expect("acquire");
+    let status = get("/v1/admin/pool").await;
+    assert_eq!(status["status"], "ok");
+    assert_eq!(status["pools"][0]["in_use"], 1);
+    assert_eq!(status["pools"][0]["size"], 2);
+
+    let _also_held = pool.acquire().await.expect("acquire");
+    assert_eq!(get("/health").await["status"], "degraded");
+    assert_eq!(get("/v1/admin/pool").await["pools"][0]["saturation"], 1.0);
+    drop(held);
+    assert_eq!(get("/health").await["status"], "ok");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn repeated_storage_failures_open_the_circuit() {
+    let pool = StoragePool::with_storage(patients_storage(), 4).with_circuit_breaker(
+        kadedb_services_ffi::BreakerConfig {
+            failures: 2,
+            window: std::time::Duration::from_secs(10),
+            cooldown: std::time::Duration::from_millis(200),
+        },
+    );
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let query = || {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+    let health = || async {
+        reqwest::get(format!("http://{addr}/health"))
+            .await
+            .expect("http get")
+            .json::<serde_json::Value>()
+            .await
+            .expect("json")
+    };
+
+    assert_eq!(health().await["circuit"], "closed");
+    for _ in 0..2 {
+        pool.acquire().await.expect("acquire").record_failure();
+    }
+    let res = query().await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let body = health().await;
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["circuit"], "open");
+
+    // After the cooldown a trial query goes through and closes the breaker.
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    assert_eq!(health().await["circuit"], "half_open");
+    let res = query().await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body = health().await;
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["circuit"], "closed");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn pause_refuses_queries_until_resumed() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+    let query = || {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+    let post = |path: &'static str| client.post(format!("http://{addr}{path}")).send();
+    let get = |path: &'static str| client.get(format!("http://{addr}{path}")).send();
+
+    let res = post("/v1/admin/pause").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = query().await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()["retry-after"], "30");
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert!(body["error"]
+        .as_str()
+        .is_some_and(|e| e.starts_with("maintenance")));
+
+    let health: serde_json::Value = get("/health")
+        .await
+        .expect("http get")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(health["status"], "maintenance");
+    let res = get("/ready").await.expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+    let res = post("/v1/admin/resume").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        query().await.expect("http post").status(),
+        reqwest::StatusCode::OK
+    );
+    let health: serde_json::Value = get("/health")
+        .await
+        .expect("http get")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(health["status"], "ok");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn dropping_an_ndjson_stream_frees_its_pool_slot() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 0..2_000 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let pool = StoragePool::with_storage(storage, 1);
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+    )
+    .await;
+
+    let mut res = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    res.chunk().await.expect("chunk").expect("first rows");
+    assert_eq!(pool.in_use(), 1);
+
+    // The client goes away mid-stream.
+    drop(res);
+    for _ in 0..200 {
+        if pool.in_use() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(pool.in_use(), 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn streams_and_exports_stop_reading_at_the_query_deadline() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    // Far more than the server and socket buffers hold.
+    let name = "x".repeat(500);
+    for id in 0..50_000 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::String(name.clone())])
+            .expect("insert");
+    }
+    let pool = StoragePool::with_storage(storage, 1);
+    let (addr, server) = spawn_with_tenancy(
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(pool.clone()),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    // The stream stalls on the unread body until its deadline has passed;
+    // the next row read then ends it.
+    let mut res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .header(api::QUERY_TIMEOUT_HEADER, "300")
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let mut text = String::from_utf8(res.chunk().await.expect("chunk").expect("rows").to_vec())
+        .expect("utf-8");
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    text.push_str(&res.text().await.expect("body"));
+    let last: serde_json::Value =
+        serde_json::from_str(text.lines().last().expect("lines")).expect("json line");
+    assert_eq!(last["$kadedb"]["code"], "timeout");
+    assert!(text.lines().count() < 50_000);
+
+    // An export past its deadline is a 504, and its slot is given back once
+    // the engine stops.
+    let res = client
+        .get(format!("http://{addr}/export"))
+        .query(&[("query", "SELECT * FROM patients"), ("limit", "50000")])
+        .header(api::QUERY_TIMEOUT_HEADER, "1")
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+    for _ in 0..200 {
+        if pool.in_use() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(pool.in_use(), 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn table_listing_filters_by_prefix_and_pages() {
+    let storage = patients_storage();
+    for table in ["patient_notes", "billing"] {
+        storage
+            .create_table(
+                table,
+                &[ColumnSpec {
+                    name: "id".to_string(),
+                    column_type: ColumnType::Integer,
+                    nullable: false,
+                }],
+            )
+            .expect("create table");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+
+    let client = reqwest::Client::new();
+    let list = |query: &'static str| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .get(format!("http://{addr}/v1/tables?{query}"))
+                .send()
+                .await
+                .expect("http get");
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            res.json::<serde_json::Value>().await.expect("json")
+        }
+    };
+
+    let body = list("").await;
+    assert_eq!(
+        body["tables"],
+        serde_json::json!(["billing", "patient_notes", "patients"])
+    );
+    assert_eq!(body["total"], 3);
+    assert!(body.get("next_offset").is_none());
+
+    let body = list("prefix=pat&limit=1").await;
+    assert_eq!(body["tables"], serde_json::json!(["patient_notes"]));
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["next_offset"], 1);
+
+    let body = list("prefix=pat&limit=1&offset=1").await;
+    assert_eq!(body["tables"], serde_json::json!(["patients"]));
+    assert!(body.get("next_offset").is_none());
+
+    let res = client
+        .get(format!("http://{addr}/v1/tables?limit=0"))
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn distinct_query_drops_repeated_rows() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for (id, name) in [(1, "alice"), (1, "alice"), (2, "bob"), (1, "alice")] {
+        insert
+            .execute(
+                &storage,
+                &[Value::Integer(id), Value::String(name.to_string())],
+            )
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?distinct=true"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.headers().get(api::DISTINCT_PARTIAL_HEADER).is_none());
+    let json: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(
+        json["rows"],
+        serde_json::json!([["1", "\"alice\""], ["2", "\"bob\""]])
+    );
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?distinct=true&shape=ndjson"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    for params in ["distinct=yes", "order_by=id&limit=ten", "envelope=0"] {
+        let res = client
+            .post(format!("http://{addr}/v1/query?{params}"))
+            .json(&body)
+            .send()
+            .await
+            .expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST, "{params}");
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn columns_param_projects_the_result() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for (id, name) in [(1, "alice"), (2, "bob"), (3, "alice")] {
+        insert
+            .execute(
+                &storage,
+                &[Value::Integer(id), Value::String(name.to_string())],
+            )
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+    let query = |params: &'static str| {
+        client
+            .post(format!("http://{addr}/v1/query?{params}"))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+
+    let res = query("columns=name").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let json: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(json["columns"], serde_json::json!(["name"]));
+    assert_eq!(
+        json["rows"],
+        serde_json::json!([["\"alice\""], ["\"bob\""], ["\"alice\""]])
+    );
+
+    // Projection comes before dedup, and keeps the requested order.
+    let json: serde_json::Value = query("columns=name,id&distinct=true")
+        .await
+        .expect("http post")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(json["columns"], serde_json::json!(["name", "id"]));
+    assert_eq!(json["rows"].as_array().map(Vec::len), Some(3));
+    let json: serde_json::Value = query("columns=name&distinct=true")
+        .await
+        .expect("http post")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(json["rows"].as_array().map(Vec::len), Some(2));
+
+    for params in ["columns=age", "columns=", "columns=name&shape=ndjson"] {
+        let res = query(params).await.expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST, "{params}");
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn sort_param_orders_bounded_results() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for (id, name) in [(10, "bob"), (2, "alice"), (7, "bob")] {
+        insert
+            .execute(
+                &storage,
+                &[Value::Integer(id), Value::String(name.to_string())],
+            )
+            .expect("insert");
+    }
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        sort_max_rows: Some(3),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(storage.clone(), 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let query = |params: &'static str| {
+        client
+            .post(format!("http://{addr}/v1/query?{params}"))
+            .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+            .send()
+    };
+    let ids = |json: &serde_json::Value| -> Vec<String> {
+        json["rows"]
+            .as_array()
+            .expect("rows")
+            .iter()
+            .map(|row| row[0].as_str().expect("id").to_string())
+            .collect()
+    };
+
+    // Integers compare as numbers, not text.
+    let res = query("sort=id").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.headers().get(api::SORT_SKIPPED_HEADER).is_none());
+    assert_eq!(ids(&res.json().await.expect("json")), ["2", "7", "10"]);
+    let json = query("sort=name:desc,id:desc")
+        .await
+        .expect("http post")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(ids(&json), ["10", "7", "2"]);
+
+    for params in ["sort=age", "sort=id:up", "sort=id&shape=ndjson"] {
+        let res = query(params).await.expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST, "{params}");
+    }
+
+    // Past the cap the rows come back as the query produced them.
+    insert
+        .execute(
+            &storage,
+            &[Value::Integer(1), Value::String("carol".to_string())],
+        )
+        .expect("insert");
+    let res = query("sort=id").await.expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()[api::SORT_SKIPPED_HEADER], "true");
+    assert_eq!(ids(&res.json().await.expect("json")), ["10", "2", "7", "1"]);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn token_cookie_authenticates_reads_and_double_submitted_writes() {
+    let spawn = |csrf_cookie: Option<&str>| {
+        let config = api::ApiConfig {
+            auth_cookie: Some(api::AuthCookie {
+                name: "kadedb_token".to_string(),
+                csrf_cookie: csrf_cookie.map(str::to_string),
+            }),
+            ..Default::default()
+        };
+        async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .expect("bind");
+            let addr = listener.local_addr().expect("local_addr");
+            let server = tokio::spawn(api::serve_with_config(
+                listener,
+                AuthConfig {
+                    enabled: true,
+                    jwt_secret: Some("secret".to_string()),
+                },
+                api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+                config,
+            ));
+            (addr, server)
+        }
+    };
+    let cookie = format!(
+        "theme=dark; kadedb_token={}",
+        token("secret", serde_json::json!({"role": "read"}))
+    );
+    let client = reqwest::Client::new();
+    let query = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let (addr, server) = spawn(None).await;
+    let res = client
+        .get(format!("http://{addr}/v1/tables"))
+        .header(reqwest::header::COOKIE, &cookie)
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // Without double-submit, mutating methods ignore the cookie.
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .header(reqwest::header::COOKIE, &cookie)
+        .json(&query)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    server.abort();
+
+    let (addr, server) = spawn(Some("kadedb_csrf")).await;
+    let cookie = format!("{cookie}; kadedb_csrf=abc123");
+    let post = |csrf: &'static str| {
+        client
+            .post(format!("http://{addr}/v1/query"))
+            .header(reqwest::header::COOKIE, &cookie)
+            .header(api::CSRF_HEADER, csrf)
+            .json(&query)
+            .send()
+    };
+    assert_eq!(
+        post("abc123").await.expect("http post").status(),
+        reqwest::StatusCode::OK
+    );
+    assert_eq!(
+        post("forged").await.expect("http post").status(),
+        reqwest::StatusCode::FORBIDDEN
+    );
+    server.abort();
+}
+
+#[tokio::test]
+async fn ndjson_stream_failing_midway_reports_rows_sent() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    // Far more than the server and socket buffers hold, so the stream is
+    // still running when it gets cancelled.
+    let name = "x".repeat(500);
+    for id in 0..50_000 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::String(name.clone())])
+            .expect("insert");
+    }
+    let (addr, server) = spawn_with_storage(storage).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let id = res.headers()[api::QUERY_ID_HEADER]
+        .to_str()
+        .expect("query id")
+        .to_string();
+
+    // Cancelling makes the next row read fail.
+    let cancelled = client
+        .delete(format!("http://{addr}/v1/queries/{id}"))
+        .send()
+        .await
+        .expect("http delete");
+    assert!(cancelled.status().is_success());
+
+    let text = res.text().await.expect("body");
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("json line"))
+        .collect();
+    let (last, rows) = lines.split_last().expect("lines");
+    assert!(rows.len() < 50_000);
+    assert_eq!(last["$kadedb"]["ok"], false);
+    assert_eq!(last["$kadedb"]["code"], "cancelled");
+    assert_eq!(last["$kadedb"]["rows_sent"], rows.len());
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn unbounded_select_is_auto_limited() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 1..=3 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        auto_limit: Some(2),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(storage, 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({"query": "SELECT * FROM patients"});
+
+    let res = client
+        .post(format!("http://{addr}/v1/query"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let json: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(json["rows"].as_array().map(Vec::len), Some(2));
+    assert_eq!(json["auto_limited"], true);
+
+    let res = client
+        .post(format!("http://{addr}/v1/query?shape=ndjson"))
+        .json(&body)
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.headers()[api::AUTO_LIMIT_HEADER], "2");
+    assert_eq!(res.text().await.expect("body").lines().count(), 2);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn admin_reload_applies_runtime_settings_from_the_config_file() {
+    let storage = patients_storage();
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 1..=3 {
+        insert
+            .execute(&storage, &[Value::Integer(id), Value::Null])
+            .expect("insert");
+    }
+    let path = std::env::temp_dir().join(format!("kadedb-reload-{}.env", std::process::id()));
+    std::fs::write(&path, "# no overrides yet\n").expect("write config");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    // As `from_env` would start it, so only the file's settings differ.
+    let config = api::ApiConfig {
+        config_file: Some(path.clone()),
+        pool_degraded_saturation: Some(api::DEFAULT_POOL_DEGRADED_SATURATION),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        api::Tenancy::single(StoragePool::with_storage(storage, 4)),
+        config,
+    ));
+    let client = reqwest::Client::new();
+    let reload = || {
+        let client = client.clone();
+        async move {
+            let res = client
+                .post(format!("http://{addr}/v1/admin/reload"))
+                .send()
+                .await
+                .expect("http post");
+            (
+                res.status(),
+                res.json::<serde_json::Value>().await.expect("json"),
+            )
+        }
+    };
+    let rows = || {
+        let client = client.clone();
+        async move {
+            let res = client
+                .post(format!("http://{addr}/v1/query"))
+                .json(&serde_json::json!({"query": "SELECT * FROM patients"}))
+                .send()
+                .await
+                .expect("http post");
+            let json: serde_json::Value = res.json().await.expect("json");
+            json["rows"].as_array().map_or(0, Vec::len)
+        }
+    };
+    assert_eq!(rows().await, 3);
+
+    std::fs::write(
+        &path,
+        "KADEDB_AUTO_LIMIT = 2\nKADEDB_API_ADDR=127.0.0.1:1\n",
+    )
+    .expect("write config");
+    let (status, body) = reload().await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(body["changed"], serde_json::json!(["KADEDB_AUTO_LIMIT"]));
+    assert_eq!(body["ignored"], serde_json::json!(["KADEDB_API_ADDR"]));
+    assert_eq!(rows().await, 2);
+
+    // Reloading the same file changes nothing.
+    let (_, body) = reload().await;
+    assert_eq!(body["changed"], serde_json::json!([]));
+
+    // A broken file is rejected and the running settings stay.
+    std::fs::write(&path, "KADEDB_AUTO_LIMIT\n").expect("write config");
+    let (status, body) = reload().await;
+    assert_eq!(status, reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body["error"].as_str().unwrap().contains("line 1"));
+    assert_eq!(rows().await, 2);
+
+    std::fs::remove_file(&path).ok();
+    server.abort();
+}
+
+#[tokio::test]
+async fn templates_run_by_name_and_only_admins_register_them() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let config = api::ApiConfig {
+        templates: api::QueryTemplates::default().with("all_patients", "SELECT * FROM patients"),
+        ..Default::default()
+    };
+    let server = tokio::spawn(api::serve_with_config(
+        listener,
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        api::Tenancy::single(StoragePool::with_storage(patients_storage(), 4)),
+        config,
+    ));
+
+    let client = reqwest::Client::new();
+    let reader = token("secret", serde_json::json!({"role": "read"}));
+    let admin = token("secret", serde_json::json!({"role": "admin"}));
+
+    let res = client
+        .post(format!("http://{addr}/v1/templates/all_patients/run"))
+        .bearer_auth(&reader)
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(body["columns"], serde_json::json!(["id", "name"]));
+
+    let res = client
+        .post(format!("http://{addr}/v1/templates/missing/run"))
+        .bearer_auth(&reader)
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let register = |token: String| {
+        client
+            .put(format!("http://{addr}/v1/templates/patients_again"))
+            .bearer_auth(token)
+            .json(&serde_json::json!({"sql": "SELECT * FROM patients"}))
+            .send()
+    };
+    let res = register(reader.clone()).await.expect("http put");
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let res = register(admin).await.expect("http put");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = client
+        .post(format!("http://{addr}/v1/templates/patients_again/run"))
+        .bearer_auth(&reader)
+        .json(&serde_json::json!({"params": [{"type": "integer", "value": 1}]}))
+        .send()
+        .await
+        .expect("http post");
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn table_schema_reports_columns_types_and_nullability() {
+    let (addr, server) = spawn_with_storage(patients_storage()).await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("http://{addr}/v1/tables/patients/schema"))
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.expect("json");
+    assert_eq!(
+        body["columns"],
+        serde_json::json!([
+            {"name": "id", "column_type": "integer", "nullable": false},
+            {"name": "name", "column_type": "string", "nullable": true},
+        ])
+    );
+
+    let res = client
+        .get(format!("http://{addr}/v1/tables/missing/schema"))
+        .send()
+        .await
+        .expect("http get");
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn admin_stats_reports_statement_cache_hits_misses_and_evictions() {
+    let storage = Storage::with_statement_cache(1).expect("storage");
+    for table in ["patients", "visits"] {
+        storage
+            .create_table(
+                table,
+                &[ColumnSpec {
+                    name: "id".to_string(),
+                    column_type: ColumnType::Integer,
+                    nullable: false,
+                }],
+            )
+            .expect("create table");
+    }
+    let (addr, server) = spawn_with_storage(Arc::new(storage)).await;
+    let client = reqwest::Client::new();
+
+    let stats = client
+        .get(format!("http://{addr}/v1/admin/stats"))
+        .send()
+        .await
+        .expect("http get")
+        .json::<serde_json::Value>()
+        .await
+        .expect("json");
+    assert_eq!(stats["pools"][0]["statement_cache"]["capacity"], 1);
+    assert!(stats["pools"][0]["statement_cache"]["hit_rate"].is_null());
+
+    for query in [
+        "SELECT * FROM patients",
+        "SELECT * FROM patients",
+        "SELECT * FROM visits",
+    ] {
+        let res = client
+            .post(format!("http://{addr}/v1/query/count"))
+            .json(&serde_json::json!({ "query": query }))
+            .send()
+            .await
+            .expect("http post");
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    let stats = client
+        .get(format!("http://{addr}/v1/admin/stats"))
+        .send()
+        .await
+        .expect("http get")
+        .json::<serde_json::Value>()
+        .await
+        .expect("json");
+    let cache = &stats["pools"][0]["statement_cache"];
+    assert_eq!(cache["len"], 1);
+    assert_eq!(cache["hits"], 1);
+    assert_eq!(cache["misses"], 2);
+    assert_eq!(cache["evictions"], 1);
+    assert!((cache["hit_rate"].as_f64().unwrap() - 1.0 / 3.0).abs() < 1e-9);
+
+    server.abort();
+}
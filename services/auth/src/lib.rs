@@ -1,5 +1,9 @@
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+mod timeouts;
+
+pub use timeouts::{RoleTimeout, RoleTimeouts};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
@@ -8,10 +12,23 @@ pub enum Role {
     Admin,
 }
 
+impl Role {
+    /// Parses a role name as tokens carry it: `read`, `write` or `admin`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "read" => Some(Role::Read),
+            "write" => Some(Role::Write),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Permission {
     Read,
     Write,
+    Admin,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -31,10 +48,55 @@ pub enum AuthError {
     #[error("unknown role")]
     UnknownRole,
 
+    #[error("missing tenant claim")]
+    MissingTenant,
+
     #[error("forbidden")]
     Forbidden,
+
+    #[error("missing required scope")]
+    MissingScope,
+
+    #[error("cookie credentials without a matching CSRF token")]
+    CsrfMismatch,
+
+    #[error("both a bearer token and an API key were sent")]
+    AmbiguousCredentials,
+}
+
+impl AuthError {
+    /// A stable, low-cardinality label for metrics and logs.
+    pub fn reason(&self) -> &'static str {
+        use jsonwebtoken::errors::ErrorKind;
+
+        match self {
+            AuthError::MissingAuthorization => "missing_authorization",
+            AuthError::InvalidAuthorizationScheme => "invalid_authorization_scheme",
+            AuthError::Jwt(err) => match err.kind() {
+                ErrorKind::ExpiredSignature => "expired",
+                ErrorKind::InvalidSignature => "invalid_signature",
+                _ => "malformed_token",
+            },
+            AuthError::MissingRole => "missing_role",
+            AuthError::UnknownRole => "unknown_role",
+            AuthError::MissingTenant => "missing_tenant",
+            AuthError::Forbidden => "forbidden",
+            AuthError::MissingScope => "missing_scope",
+            AuthError::CsrfMismatch => "csrf_mismatch",
+            AuthError::AmbiguousCredentials => "ambiguous_credentials",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthConfigError {
+    #[error("auth is enabled but KADEDB_JWT_SECRET is not set")]
+    MissingSecret,
 }
 
+/// Header (REST) / metadata key (gRPC) for API-key credentials.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
 #[derive(Debug, Clone)]
 pub struct AuthConfig {
     pub enabled: bool,
@@ -56,24 +118,34 @@ impl AuthConfig {
             jwt_secret,
         }
     }
+
+    /// Rejects settings under which every request would fail, so servers
+    /// refuse to start instead.
+    pub fn validate(&self) -> Result<(), AuthConfigError> {
+        let has_secret = self.jwt_secret.as_deref().is_some_and(|s| !s.is_empty());
+        if self.enabled && !has_secret {
+            return Err(AuthConfigError::MissingSecret);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Option<String>,
     pub role: Option<String>,
+    pub tenant: Option<String>,
+    pub scopes: Option<Vec<String>>,
     pub exp: Option<u64>,
     pub iat: Option<u64>,
+    /// Any other claims in the token, kept for [`Principal::claim`].
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 fn role_from_claims(claims: &Claims) -> Result<Role, AuthError> {
     let role = claims.role.as_deref().ok_or(AuthError::MissingRole)?;
-    match role {
-        "read" => Ok(Role::Read),
-        "write" => Ok(Role::Write),
-        "admin" => Ok(Role::Admin),
-        _ => Err(AuthError::UnknownRole),
-    }
+    Role::parse(role).ok_or(AuthError::UnknownRole)
 }
 
 fn role_allows(role: Role, permission: Permission) -> bool {
@@ -83,14 +155,83 @@ fn role_allows(role: Role, permission: Permission) -> bool {
         (Role::Write, Permission::Write) => true,
         (Role::Read, Permission::Read) => true,
         (Role::Read, Permission::Write) => false,
+        (Role::Write | Role::Read, Permission::Admin) => false,
     }
 }
 
+/// The authenticated caller, as established from a verified token.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: Option<String>,
+    pub role: Role,
+    pub tenant: Option<String>,
+    pub scopes: Vec<String>,
+    /// Claims not covered by the fields above.
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Principal {
+    /// Whether the caller's role grants `permission`.
+    pub fn allows(&self, permission: Permission) -> bool {
+        role_allows(self.role, permission)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// Fails with [`AuthError::MissingScope`] unless the token grants
+    /// `scope`. Checked in addition to the role, never instead of it.
+    pub fn require_scope(&self, scope: &str) -> Result<(), AuthError> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(AuthError::MissingScope)
+        }
+    }
+
+    /// A custom claim deserialized as `T`; `None` if absent or of another
+    /// shape.
+    pub fn claim<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.extra.get(name).and_then(|v| T::deserialize(v).ok())
+    }
+}
+
+/// Rejects a request that carries both an `Authorization` header and an
+/// [`API_KEY_HEADER`], so a caller never ends up authenticated as a principal
+/// other than the one it meant to use.
+///
+/// The bearer token is the only accepted credential for now. Once API keys
+/// are accepted, a key that resolves to the same principal as the token will
+/// be allowed alongside it; until keys can be resolved, any combination is
+/// treated as disagreeing. Always passes when auth is disabled.
+pub fn check_single_credential(
+    cfg: &AuthConfig,
+    authorization_header: Option<&str>,
+    api_key_header: Option<&str>,
+) -> Result<(), AuthError> {
+    if cfg.enabled && authorization_header.is_some() && api_key_header.is_some() {
+        return Err(AuthError::AmbiguousCredentials);
+    }
+    Ok(())
+}
+
 pub fn authorize_bearer_header(
     cfg: &AuthConfig,
     authorization_header: Option<&str>,
     required: Permission,
 ) -> Result<Option<Role>, AuthError> {
+    authenticate_bearer_header(cfg, authorization_header, required).map(|p| p.map(|p| p.role))
+}
+
+/// Like [`authorize_bearer_header`], but returns the full [`Principal`] so
+/// callers can act on claims beyond the role. Returns `None` when auth is
+/// disabled.
+pub fn authenticate_bearer_header(
+    cfg: &AuthConfig,
+    authorization_header: Option<&str>,
+    required: Permission,
+) -> Result<Option<Principal>, AuthError> {
     if !cfg.enabled {
         return Ok(None);
     }
@@ -119,5 +260,12 @@ pub fn authorize_bearer_header(
         return Err(AuthError::Forbidden);
     }
 
-    Ok(Some(role))
+    let claims = data.claims;
+    Ok(Some(Principal {
+        subject: claims.sub,
+        role,
+        tenant: claims.tenant,
+        scopes: claims.scopes.unwrap_or_default(),
+        extra: claims.extra,
+    }))
 }
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use crate::Role;
+
+/// One role's statement timeout: the deadline its calls get by default, and
+/// the longest one its callers may ask for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoleTimeout {
+    pub default: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+/// Statement timeouts per role, so interactive readers can be held to a
+/// short deadline while admins run long maintenance queries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoleTimeouts {
+    read: RoleTimeout,
+    write: RoleTimeout,
+    admin: RoleTimeout,
+}
+
+impl RoleTimeouts {
+    /// Parses comma-separated `role:default_ms[/max_ms]` entries, e.g.
+    /// `read:5000/30000,admin:600000`. Entries naming an unknown role or
+    /// holding anything but positive numbers are skipped.
+    pub fn parse(spec: &str) -> Self {
+        let ms = |v: &str| {
+            v.trim()
+                .parse()
+                .ok()
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis)
+        };
+        let mut timeouts = Self::default();
+        for entry in spec.split(',') {
+            let Some((role, limits)) = entry.split_once(':') else {
+                continue;
+            };
+            let Some(role) = Role::parse(role.trim()) else {
+                continue;
+            };
+            let timeout = match limits.split_once('/') {
+                Some((default, max)) => match (ms(default), ms(max)) {
+                    (Some(default), Some(max)) => RoleTimeout {
+                        default: Some(default.min(max)),
+                        max: Some(max),
+                    },
+                    _ => continue,
+                },
+                None => match ms(limits) {
+                    Some(default) => RoleTimeout {
+                        default: Some(default),
+                        max: None,
+                    },
+                    None => continue,
+                },
+            };
+            timeouts = timeouts.with(role, timeout);
+        }
+        timeouts
+    }
+
+    pub fn with(mut self, role: Role, timeout: RoleTimeout) -> Self {
+        *self.slot(role) = timeout;
+        self
+    }
+
+    pub fn get(&self, role: Role) -> RoleTimeout {
+        match role {
+            Role::Read => self.read,
+            Role::Write => self.write,
+            Role::Admin => self.admin,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn slot(&mut self, role: Role) -> &mut RoleTimeout {
+        match role {
+            Role::Read => &mut self.read,
+            Role::Write => &mut self.write,
+            Role::Admin => &mut self.admin,
+        }
+    }
+
+    /// How long a call may run. A deadline the client `requested` is
+    /// clamped to the role's max and `hard_max`; without one the call gets
+    /// the role's default, clamped the same way, or else `fallback` (the
+    /// server-wide timeout) clamped to the role's max. `role` is `None`
+    /// when auth is disabled.
+    pub fn limit(
+        &self,
+        role: Option<Role>,
+        requested: Option<Duration>,
+        fallback: Option<Duration>,
+        hard_max: Option<Duration>,
+    ) -> Option<Duration> {
+        let role = role.map(|r| self.get(r)).unwrap_or_default();
+        let clamp = |limit: Duration, max: Option<Duration>| max.map_or(limit, |m| limit.min(m));
+        let cap = match (role.max, hard_max) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        match (requested, role.default) {
+            (Some(limit), _) | (None, Some(limit)) => Some(clamp(limit, cap)),
+            (None, None) => fallback.map(|limit| clamp(limit, role.max)),
+        }
+    }
+}
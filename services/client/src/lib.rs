@@ -0,0 +1,368 @@
+//! Typed client for the KadeDB services over REST or gRPC.
+//!
+//! [`Client`] hides the transport: rows come back as JSON objects keyed by
+//! column name either way, bearer tokens are attached to every call, and
+//! overload responses are retried per [`RetryPolicy`]. Operations the chosen
+//! transport doesn't offer fail with [`ClientError::Unsupported`]. Server
+//! errors carry an [`ErrorCode`] either way; see [`ClientError::code`].
+
+use std::pin::Pin;
+
+use futures_util::{stream, Stream, StreamExt};
+use kadedb_services_ffi::{status_code, NDJSON_CONTROL_KEY};
+use kadedb_services_grpc::kadedb::{query_service_client::QueryServiceClient, QueryRequest};
+use serde::{Deserialize, Serialize};
+use tonic::transport::Channel;
+
+mod retry;
+
+pub use kadedb_services_ffi::ErrorCode;
+pub use retry::{parse_retry_after, RetryPolicy};
+
+/// One result row, keyed by column name.
+pub type Row = serde_json::Map<String, serde_json::Value>;
+
+/// Rows delivered as they arrive.
+pub type RowStream = Pin<Box<dyn Stream<Item = Result<Row, ClientError>> + Send>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("http: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("grpc connect: {0}")]
+    Connect(#[from] tonic::transport::Error),
+
+    #[error("grpc: {0}")]
+    Grpc(Box<tonic::Status>),
+
+    /// The REST server answered with an error status.
+    #[error("server returned {status}: {message}")]
+    Rejected {
+        status: u16,
+        code: ErrorCode,
+        message: String,
+    },
+
+    /// The server ended a streamed result with an error after `rows_sent`
+    /// rows.
+    #[error("stream failed after {rows_sent} rows: {message}")]
+    StreamFailed {
+        code: ErrorCode,
+        message: String,
+        rows_sent: u64,
+    },
+
+    #[error("malformed response: {0}")]
+    Decode(String),
+
+    #[error("{0} is not available over this transport")]
+    Unsupported(&'static str),
+}
+
+impl ClientError {
+    /// Why the server refused the call, the same over either transport.
+    /// `None` when the error didn't come from the server.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::Grpc(status) => Some(status_code(status)),
+            Self::Rejected { code, .. } | Self::StreamFailed { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::Status> for ClientError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Grpc(Box::new(status))
+    }
+}
+
+/// Column definition for [`Client::create_table`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnDef {
+    pub name: String,
+    pub column_type: String,
+    pub nullable: bool,
+}
+
+#[derive(Clone)]
+enum Transport {
+    Rest {
+        http: reqwest::Client,
+        base_url: String,
+    },
+    Grpc(QueryServiceClient<Channel>),
+}
+
+#[derive(Clone)]
+pub struct Client {
+    transport: Transport,
+    token: Option<String>,
+    retry: RetryPolicy,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    rows: Vec<Row>,
+}
+
+#[derive(Deserialize)]
+struct InsertResponse {
+    inserted: usize,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+impl Client {
+    /// A REST client for the server at `base_url` (e.g. `http://127.0.0.1:8080`).
+    pub fn rest(base_url: impl Into<String>) -> Self {
+        Self::with_transport(Transport::Rest {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Connects a gRPC client to `endpoint` (e.g. `http://127.0.0.1:50051`).
+    pub async fn grpc(endpoint: impl Into<String>) -> Result<Self, ClientError> {
+        let inner = QueryServiceClient::connect(endpoint.into()).await?;
+        Ok(Self::with_transport(Transport::Grpc(inner)))
+    }
+
+    fn with_transport(transport: Transport) -> Self {
+        Self {
+            transport,
+            token: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Sends `token` as a bearer token on every call.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Checks that the server is up. REST only.
+    pub async fn health(&self) -> Result<(), ClientError> {
+        let Transport::Rest { http, base_url } = &self.transport else {
+            return Err(ClientError::Unsupported("health"));
+        };
+        let url = format!("{base_url}/health");
+        let res = retry::send_with_retry(&self.retry, || http.get(&url)).await?;
+        check(res).await.map(drop)
+    }
+
+    /// Runs `sql` and returns all rows. Over REST the envelope is asked for,
+    /// so a server configured for bare results answers the same way.
+    pub async fn query(&self, sql: &str) -> Result<Vec<Row>, ClientError> {
+        match &self.transport {
+            Transport::Rest { .. } => {
+                let res = self
+                    .post("/v1/query?shape=objects&envelope=true", sql)
+                    .await?;
+                let body: QueryResponse = res
+                    .json()
+                    .await
+                    .map_err(|err| ClientError::Decode(err.to_string()))?;
+                Ok(body.rows)
+            }
+            Transport::Grpc(inner) => {
+                let result = retry::grpc_with_retry(&self.retry, || {
+                    let mut inner = inner.clone();
+                    let req = self.grpc_request(sql);
+                    async move { inner.query_unary(req).await }
+                })
+                .await?
+                .into_inner();
+                result.rows.iter().map(|r| parse_row(&r.json)).collect()
+            }
+        }
+    }
+
+    /// Runs `sql`, yielding rows as the server sends them.
+    pub async fn query_stream(&self, sql: &str) -> Result<RowStream, ClientError> {
+        match &self.transport {
+            Transport::Rest { .. } => {
+                let res = self.post("/v1/query?shape=ndjson", sql).await?;
+                Ok(Box::pin(ndjson_rows(res)))
+            }
+            Transport::Grpc(inner) => {
+                let rows = retry::grpc_with_retry(&self.retry, || {
+                    let mut inner = inner.clone();
+                    let req = self.grpc_request(sql);
+                    async move { inner.query(req).await }
+                })
+                .await?
+                .into_inner();
+                Ok(Box::pin(rows.map(|row| parse_row(&row?.json))))
+            }
+        }
+    }
+
+    /// Runs a statement whose rows, if any, aren't needed.
+    pub async fn execute(&self, sql: &str) -> Result<(), ClientError> {
+        match &self.transport {
+            Transport::Rest { .. } => self.post("/v1/query", sql).await.map(drop),
+            Transport::Grpc(_) => self.query(sql).await.map(drop),
+        }
+    }
+
+    /// Creates table `name`. REST only.
+    pub async fn create_table(&self, name: &str, columns: &[ColumnDef]) -> Result<(), ClientError> {
+        let Transport::Rest { http, base_url } = &self.transport else {
+            return Err(ClientError::Unsupported("create_table"));
+        };
+        let url = format!("{base_url}/v1/tables");
+        let body = serde_json::json!({"name": name, "columns": columns});
+        let res =
+            retry::send_with_retry(&self.retry, || self.authorize(http.post(&url).json(&body)))
+                .await?;
+        check(res).await.map(drop)
+    }
+
+    /// Inserts `rows` into `table`, returning how many were inserted. A row
+    /// that doesn't fit the table rejects the whole request. REST only.
+    pub async fn insert_rows(&self, table: &str, rows: &[Row]) -> Result<usize, ClientError> {
+        let Transport::Rest { http, base_url } = &self.transport else {
+            return Err(ClientError::Unsupported("insert_rows"));
+        };
+        let url = format!("{base_url}/v1/tables/{table}/rows");
+        let res =
+            retry::send_with_retry(&self.retry, || self.authorize(http.post(&url).json(rows)))
+                .await?;
+        let body: InsertResponse = check(res)
+            .await?
+            .json()
+            .await
+            .map_err(|err| ClientError::Decode(err.to_string()))?;
+        Ok(body.inserted)
+    }
+
+    /// POSTs `{"query": sql}` to `path` and checks the status.
+    async fn post(&self, path: &str, sql: &str) -> Result<reqwest::Response, ClientError> {
+        let Transport::Rest { http, base_url } = &self.transport else {
+            unreachable!("REST-only helper");
+        };
+        let url = format!("{base_url}{path}");
+        let body = serde_json::json!({"query": sql});
+        let res =
+            retry::send_with_retry(&self.retry, || self.authorize(http.post(&url).json(&body)))
+                .await?;
+        check(res).await
+    }
+
+    fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    fn grpc_request(&self, sql: &str) -> tonic::Request<QueryRequest> {
+        let mut req = tonic::Request::new(QueryRequest {
+            query: sql.to_string(),
+            ..Default::default()
+        });
+        if let Some(value) = self
+            .token
+            .as_ref()
+            .and_then(|t| format!("Bearer {t}").parse().ok())
+        {
+            req.metadata_mut().insert("authorization", value);
+        }
+        req
+    }
+}
+
+/// Turns an error status into [`ClientError::Rejected`], using the server's
+/// `{"error": ..., "code": ...}` when there is one.
+async fn check(res: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let text = res.text().await.unwrap_or_default();
+    let (message, code) = match serde_json::from_str::<ErrorResponse>(&text) {
+        Ok(e) => (e.error, e.code.as_deref().and_then(ErrorCode::parse)),
+        Err(_) => (text, None),
+    };
+    Err(ClientError::Rejected {
+        status: status.as_u16(),
+        code: code.unwrap_or_else(|| ErrorCode::from_http_status(status.as_u16())),
+        message,
+    })
+}
+
+fn parse_row(json: &str) -> Result<Row, ClientError> {
+    serde_json::from_str(json).map_err(|err| ClientError::Decode(err.to_string()))
+}
+
+/// Reads a control line's body: an error line becomes the stream's error,
+/// anything else (checksums, progress) is skipped.
+fn control_line(control: &serde_json::Value) -> Result<(), ClientError> {
+    let Some(message) = control.get("error") else {
+        return Ok(());
+    };
+    Err(ClientError::StreamFailed {
+        code: control
+            .get("code")
+            .and_then(|code| code.as_str())
+            .and_then(ErrorCode::parse)
+            .unwrap_or(ErrorCode::Internal),
+        message: message.as_str().unwrap_or_default().to_string(),
+        rows_sent: control
+            .get("rows_sent")
+            .and_then(|n| n.as_u64())
+            .unwrap_or_default(),
+    })
+}
+
+/// Splits an NDJSON body into rows as chunks arrive, ending with
+/// [`ClientError::StreamFailed`] if the server reports an error partway.
+fn ndjson_rows(res: reqwest::Response) -> impl Stream<Item = Result<Row, ClientError>> + Send {
+    stream::try_unfold(
+        (res, Vec::<u8>::new(), false),
+        |(mut res, mut buf, mut done)| async move {
+            loop {
+                if let Some(end) = buf.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=end).collect();
+                    let line = std::str::from_utf8(&line)
+                        .map_err(|err| ClientError::Decode(err.to_string()))?
+                        .trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    let row = parse_row(line)?;
+                    if let Some(control) = row.get(NDJSON_CONTROL_KEY) {
+                        control_line(control)?;
+                        continue;
+                    }
+                    return Ok(Some((row, (res, buf, done))));
+                }
+                if done {
+                    if buf.iter().all(u8::is_ascii_whitespace) {
+                        return Ok(None);
+                    }
+                    // A final line without a trailing newline.
+                    buf.push(b'\n');
+                    continue;
+                }
+                match res.chunk().await? {
+                    Some(chunk) => buf.extend_from_slice(&chunk),
+                    None => done = true,
+                }
+            }
+        },
+    )
+}
@@ -0,0 +1,114 @@
+//! Retry helpers shared by the REST and gRPC transports.
+//!
+//! Both transports signal overload the same way: REST answers `429 Too Many
+//! Requests` (or `503`) with an optional `Retry-After` header, gRPC answers
+//! `resource_exhausted` (or `unavailable`) with optional `retry-after`
+//! metadata. When the server names a delay we wait exactly that long;
+//! otherwise we fall back to capped exponential backoff.
+
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use reqwest::StatusCode;
+use tonic::{Code, Status};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (1-based), preferring the
+    /// server-provided hint when there is one.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        retry_after
+            .unwrap_or_else(|| {
+                self.base_delay
+                    .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            })
+            .min(self.max_delay)
+    }
+}
+
+/// Parses a `Retry-After` value: either delta-seconds or an HTTP-date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+fn is_retryable_code(code: Code) -> bool {
+    matches!(code, Code::ResourceExhausted | Code::Unavailable)
+}
+
+/// Sends the request built by `build`, retrying on `429`/`503` until the
+/// policy is exhausted. The final response is returned as-is, whatever its
+/// status.
+pub(crate) async fn send_with_retry(
+    policy: &RetryPolicy,
+    mut build: impl FnMut() -> reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 1;
+    loop {
+        let res = build().send().await?;
+
+        if !is_retryable_status(res.status()) || attempt >= policy.max_attempts {
+            return Ok(res);
+        }
+
+        let retry_after = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        tokio::time::sleep(policy.delay(attempt, retry_after)).await;
+        attempt += 1;
+    }
+}
+
+/// Runs a gRPC call, retrying on `resource_exhausted`/`unavailable`. `call`
+/// is invoked once per attempt so it can rebuild the (non-`Clone`) request.
+pub(crate) async fn grpc_with_retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut call: F,
+) -> Result<T, Status>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Status>>,
+{
+    let mut attempt = 1;
+    loop {
+        match call().await {
+            Err(status) if is_retryable_code(status.code()) && attempt < policy.max_attempts => {
+                let retry_after = status
+                    .metadata()
+                    .get("retry-after")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_retry_after);
+                tokio::time::sleep(policy.delay(attempt, retry_after)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
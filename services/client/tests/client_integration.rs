@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use kadedb_services_api as api;
+use kadedb_services_auth::AuthConfig;
+use kadedb_services_client::{Client, ClientError, ErrorCode};
+use kadedb_services_ffi::{ColumnSpec, ColumnType, Storage, StoragePool, Value};
+
+async fn spawn_rest() -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    spawn_rest_with(api::ApiConfig::default()).await
+}
+
+async fn spawn_rest_with(
+    config: api::ApiConfig,
+) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    let storage = Storage::new().expect("storage");
+    storage
+        .create_table(
+            "patients",
+            &[ColumnSpec {
+                name: "id".to_string(),
+                column_type: ColumnType::Integer,
+                nullable: false,
+            }],
+        )
+        .expect("create table");
+    let insert = storage.prepare_insert("patients").expect("prepare insert");
+    for id in 1..=3 {
+        insert
+            .execute(&storage, &[Value::Integer(id)])
+            .expect("insert");
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let tenancy = api::Tenancy::single(StoragePool::with_storage(Arc::new(storage), 4));
+    let server = tokio::spawn(async move {
+        api::serve_with_config(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            tenancy,
+            config,
+        )
+        .await;
+    });
+    (addr, server)
+}
+
+#[tokio::test]
+async fn rest_client_queries_and_streams_rows() {
+    let (addr, server) = spawn_rest().await;
+    let client = Client::rest(format!("http://{addr}"));
+
+    client.health().await.expect("health");
+
+    let rows = client.query("SELECT * FROM patients").await.expect("query");
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0]["id"], "1");
+
+    let streamed: Vec<_> = client
+        .query_stream("SELECT * FROM patients")
+        .await
+        .expect("stream")
+        .collect()
+        .await;
+    assert_eq!(streamed.len(), 3);
+    assert_eq!(streamed[2].as_ref().expect("row")["id"], "3");
+
+    let err = client
+        .query("SELECT * FROM missing")
+        .await
+        .expect_err("unknown table");
+    assert!(matches!(err, ClientError::Rejected { status: 400, .. }));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn rest_client_queries_a_server_configured_for_bare_results() {
+    let (addr, server) = spawn_rest_with(api::ApiConfig {
+        bare_results: true,
+        ..Default::default()
+    })
+    .await;
+    let client = Client::rest(format!("http://{addr}"));
+
+    let rows = client.query("SELECT * FROM patients").await.expect("query");
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[1]["id"], "2");
+
+    server.abort();
+}
+
+/// A REST server that answers one request with `body` as an NDJSON stream.
+async fn spawn_canned_ndjson(
+    body: &'static str,
+) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let server = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.expect("accept");
+        // Read the whole request before answering.
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = conn.read(&mut buf).await.expect("read");
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_ascii_lowercase();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length:"))
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+            assert!(n > 0, "request cut short");
+        }
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\n\
+             content-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        conn.write_all(response.as_bytes()).await.expect("write");
+    });
+    (addr, server)
+}
+
+#[tokio::test]
+async fn rest_stream_failing_partway_ends_with_its_error() {
+    let (addr, server) = spawn_canned_ndjson(concat!(
+        "{\"id\":\"1\"}\n",
+        "{\"$kadedb\":{\"progress\":{\"rows_sent\":1,\"elapsed_ms\":5}}}\n",
+        "{\"id\":\"2\"}\n",
+        "{\"$kadedb\":{\"ok\":false,\"error\":\"query cancelled\",\"code\":\"cancelled\",\"rows_sent\":2}}\n",
+    ))
+    .await;
+    let client = Client::rest(format!("http://{addr}"));
+
+    let items: Vec<_> = client
+        .query_stream("SELECT * FROM patients")
+        .await
+        .expect("stream")
+        .collect()
+        .await;
+    assert_eq!(items.len(), 3, "two rows, then the error");
+    assert_eq!(items[0].as_ref().expect("row")["id"], "1");
+    assert_eq!(items[1].as_ref().expect("row")["id"], "2");
+    let err = items[2].as_ref().expect_err("stream error");
+    assert!(
+        matches!(err, ClientError::StreamFailed { rows_sent: 2, message, .. } if message == "query cancelled"),
+        "{err}"
+    );
+    assert_eq!(err.code(), Some(ErrorCode::Cancelled));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn rest_client_inserts_rows() {
+    let (addr, server) = spawn_rest().await;
+    let client = Client::rest(format!("http://{addr}"));
+
+    let rows: Vec<_> = [4, 5]
+        .map(|id| serde_json::json!({"id": id}))
+        .into_iter()
+        .map(|row| row.as_object().expect("object").clone())
+        .collect();
+    assert_eq!(
+        client.insert_rows("patients", &rows).await.expect("insert"),
+        2
+    );
+    let read = client.query("SELECT * FROM patients").await.expect("query");
+    assert_eq!(read.len(), 5);
+
+    let bad = serde_json::json!({"unit": "mg"});
+    let err = client
+        .insert_rows("patients", &[bad.as_object().expect("object").clone()])
+        .await
+        .expect_err("unknown column");
+    assert!(matches!(err, ClientError::Rejected { status: 422, .. }));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn errors_carry_the_same_code_over_either_transport() {
+    let auth = AuthConfig {
+        enabled: true,
+        jwt_secret: Some("secret".to_string()),
+    };
+    let rest_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let rest_addr = rest_listener.local_addr().expect("local_addr");
+    let rest_server = tokio::spawn(api::serve(
+        rest_listener,
+        auth.clone(),
+        api::Tenancy::single(StoragePool::new(1).expect("pool")),
+    ));
+    let grpc_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let grpc_addr = grpc_listener.local_addr().expect("local_addr");
+    let grpc_server = tokio::spawn(kadedb_services_grpc::serve_with_listener(
+        grpc_listener,
+        auth,
+    ));
+
+    let rest = Client::rest(format!("http://{rest_addr}"));
+    let grpc = Client::grpc(format!("http://{grpc_addr}"))
+        .await
+        .expect("connect");
+    for client in [rest, grpc] {
+        let err = client
+            .query("SELECT * FROM patients")
+            .await
+            .expect_err("no token");
+        assert_eq!(err.code(), Some(ErrorCode::Unauthenticated), "{err}");
+    }
+
+    let (addr, server) = spawn_rest().await;
+    let err = Client::rest(format!("http://{addr}"))
+        .query("SELECT * FROM missing")
+        .await
+        .expect_err("unknown table");
+    assert_eq!(err.code(), Some(ErrorCode::InvalidSql));
+
+    server.abort();
+    rest_server.abort();
+    grpc_server.abort();
+}
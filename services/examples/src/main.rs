@@ -1,6 +1,8 @@
 use clap::{Parser, Subcommand};
-use kadedb_services_grpc::kadedb::query_service_client::QueryServiceClient;
-use kadedb_services_grpc::kadedb::QueryRequest;
+use futures_util::StreamExt;
+use kadedb_services_client::Client;
+
+mod selftest;
 
 #[derive(Parser)]
 #[command(name = "kadedb-services-examples")]
@@ -31,6 +33,17 @@ enum Command {
         #[arg(long, default_value = "SELECT 1")]
         query: String,
     },
+    /// Smoke-tests a deployment: health, a query, create table, insert and
+    /// read back. Exits non-zero if any step fails.
+    Selftest {
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        base_url: String,
+        /// Also checks a gRPC query against this endpoint.
+        #[arg(long)]
+        grpc_endpoint: Option<String>,
+        #[arg(long)]
+        token: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -39,49 +52,46 @@ async fn main() {
 
     match cli.cmd {
         Command::RestHealth { base_url } => {
-            let url = format!("{base_url}/health");
-            let res = reqwest::get(url).await.expect("http get");
-            let status = res.status();
-            let body = res.text().await.unwrap_or_default();
-            println!("{status}\n{body}");
+            Client::rest(base_url).health().await.expect("health");
+            println!("ok");
         }
         Command::RestQuery {
             base_url,
             token,
             query,
         } => {
-            let url = format!("{base_url}/query");
-            let client = reqwest::Client::new();
-            let mut req = client.post(url).json(&serde_json::json!({"query": query}));
-            if let Some(token) = token {
-                req = req.header("authorization", format!("Bearer {token}"));
+            let client = with_token(Client::rest(base_url), token);
+            for row in client.query(&query).await.expect("query") {
+                println!("{}", serde_json::Value::Object(row));
             }
-            let res = req.send().await.expect("http post");
-            let status = res.status();
-            let body = res.text().await.unwrap_or_default();
-            println!("{status}\n{body}");
         }
         Command::GrpcQuery {
             endpoint,
             token,
             query,
         } => {
-            let mut client = QueryServiceClient::connect(endpoint)
-                .await
-                .expect("connect");
-
-            let mut req = tonic::Request::new(QueryRequest { query });
-            if let Some(token) = token {
-                req.metadata_mut().insert(
-                    "authorization",
-                    format!("Bearer {token}").parse().expect("metadata"),
-                );
+            let client = Client::grpc(endpoint).await.expect("connect");
+            let client = with_token(client, token);
+            let mut rows = client.query_stream(&query).await.expect("query");
+            while let Some(row) = rows.next().await {
+                println!("{}", serde_json::Value::Object(row.expect("row")));
             }
-
-            let mut stream = client.query(req).await.expect("query").into_inner();
-            while let Some(row) = stream.message().await.expect("message") {
-                println!("{}", row.json);
+        }
+        Command::Selftest {
+            base_url,
+            grpc_endpoint,
+            token,
+        } => {
+            if !selftest::run(base_url, grpc_endpoint, token).await {
+                std::process::exit(1);
             }
         }
     }
 }
+
+fn with_token(client: Client, token: Option<String>) -> Client {
+    match token {
+        Some(token) => client.with_token(token),
+        None => client,
+    }
+}
@@ -0,0 +1,177 @@
+//! `selftest`: walks a deployment through the whole API flow (health,
+//! creating a table, inserting into it and reading the rows back over REST
+//! and gRPC) and reports each step with its timing. Meant as a post-deploy
+//! smoke test.
+
+use std::error::Error;
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use kadedb_services_client::{Client, ColumnDef, Row};
+
+use crate::with_token;
+
+type StepResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Runs every step, printing one line per step and a summary. Steps that
+/// need an earlier one (the insert needs the table) are skipped when it
+/// failed. Returns whether no step failed.
+pub async fn run(base_url: String, grpc_endpoint: Option<String>, token: Option<String>) -> bool {
+    let rest = with_token(Client::rest(base_url), token.clone());
+    // Tables can't be dropped through the API, so every run leaves its own.
+    let table = format!(
+        "kadedb_selftest_{}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    );
+    let rows = sample_rows();
+    let mut report = Report::default();
+
+    report
+        .step("health", async { Ok(rest.health().await?) })
+        .await;
+    let created = report
+        .step("create table", async {
+            Ok(rest.create_table(&table, &columns()).await?)
+        })
+        .await;
+    let inserted = created
+        && report
+            .step("insert", async {
+                let inserted = rest.insert_rows(&table, &rows).await?;
+                ensure(
+                    inserted == rows.len(),
+                    format!("inserted {inserted} of {} rows", rows.len()),
+                )
+            })
+            .await;
+    if inserted {
+        report
+            .step("read back", async {
+                let read = rest.query(&format!("SELECT * FROM {table}")).await?;
+                read_back(&read, &rows)
+            })
+            .await;
+        match grpc_endpoint {
+            Some(endpoint) => {
+                report
+                    .step("grpc read back", async {
+                        let client = with_token(Client::grpc(endpoint).await?, token);
+                        let read = client.query(&format!("SELECT * FROM {table}")).await?;
+                        read_back(&read, &rows)
+                    })
+                    .await;
+            }
+            None => report.skip("grpc read back", "no --grpc-endpoint"),
+        }
+    } else {
+        if !created {
+            report.skip("insert", "create table failed");
+        }
+        report.skip("read back", "insert failed");
+        report.skip("grpc read back", "insert failed");
+    }
+    report.summary()
+}
+
+fn columns() -> Vec<ColumnDef> {
+    vec![
+        ColumnDef {
+            name: "id".to_string(),
+            column_type: "integer".to_string(),
+            nullable: false,
+        },
+        ColumnDef {
+            name: "note".to_string(),
+            column_type: "string".to_string(),
+            nullable: true,
+        },
+    ]
+}
+
+fn sample_rows() -> Vec<Row> {
+    [(1, "first"), (2, "second")]
+        .into_iter()
+        .map(|(id, note)| {
+            let row = serde_json::json!({"id": id, "note": note});
+            row.as_object().cloned().unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Checks that `read` holds the `sent` rows, in order.
+fn read_back(read: &[Row], sent: &[Row]) -> StepResult {
+    ensure(
+        read.len() == sent.len(),
+        format!("read {} rows, expected {}", read.len(), sent.len()),
+    )?;
+    for (got, sent) in read.iter().zip(sent) {
+        ensure(
+            same_row(got, sent),
+            format!("read {got:?}, expected {sent:?}"),
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether a row read back holds what was inserted. REST results carry
+/// every value as a string, so values are compared as text.
+fn same_row(got: &Row, sent: &Row) -> bool {
+    let text = |v: &serde_json::Value| v.as_str().map_or_else(|| v.to_string(), str::to_string);
+    sent.iter()
+        .all(|(column, value)| got.get(column).map(text) == Some(text(value)))
+}
+
+fn ensure(ok: bool, message: impl Into<String>) -> StepResult {
+    if ok {
+        Ok(())
+    } else {
+        Err(message.into().into())
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    elapsed: Duration,
+}
+
+impl Report {
+    /// Runs `check` as step `name` and prints its outcome; returns whether
+    /// it passed.
+    async fn step(&mut self, name: &str, check: impl Future<Output = StepResult>) -> bool {
+        let started = Instant::now();
+        let result = check.await;
+        let took = started.elapsed();
+        self.elapsed += took;
+        match result {
+            Ok(()) => {
+                self.passed += 1;
+                println!("PASS  {name:<14} {took:>10.1?}");
+                true
+            }
+            Err(err) => {
+                self.failed += 1;
+                println!("FAIL  {name:<14} {took:>10.1?}  {err}");
+                false
+            }
+        }
+    }
+
+    fn skip(&mut self, name: &str, reason: &str) {
+        self.skipped += 1;
+        println!("SKIP  {name:<14} {:>10}  {reason}", "-");
+    }
+
+    fn summary(&self) -> bool {
+        println!(
+            "{} passed, {} failed, {} skipped in {:.1?}",
+            self.passed, self.failed, self.skipped, self.elapsed
+        );
+        self.failed == 0
+    }
+}
@@ -0,0 +1,67 @@
+//! Compares materializing a result with `all_rows_as_strings` against
+//! streaming it through `RowReader` on a wide table.
+//!
+//! Run with `cargo bench -p kadedb-services-ffi --bench row_reader`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kadedb_services_ffi::{ColumnSpec, ColumnType, Storage, Value};
+
+const ROWS: usize = 2_000;
+
+fn wide_storage(cols: usize) -> Storage {
+    let storage = Storage::new().expect("storage");
+    let columns: Vec<ColumnSpec> = (0..cols)
+        .map(|i| ColumnSpec {
+            name: format!("c{i}"),
+            column_type: ColumnType::String,
+            nullable: true,
+        })
+        .collect();
+    storage
+        .create_table("wide", &columns)
+        .expect("create table");
+
+    let insert = storage.prepare_insert("wide").expect("prepare");
+    for r in 0..ROWS {
+        let row: Vec<Value> = (0..cols)
+            .map(|c| Value::String(format!("row-{r}-col-{c}")))
+            .collect();
+        insert.execute(&storage, &row).expect("insert");
+    }
+    storage
+}
+
+fn bench_wide_results(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wide_result");
+    for cols in [8, 64] {
+        let storage = wide_storage(cols);
+
+        group.bench_with_input(BenchmarkId::new("materialize", cols), &cols, |b, _| {
+            b.iter(|| {
+                let mut rs = storage.execute_query("SELECT * FROM wide").expect("query");
+                let rows = rs.all_rows_as_strings().expect("rows");
+                black_box(
+                    rows.iter()
+                        .map(|r| r.iter().map(String::len).sum::<usize>())
+                        .sum::<usize>(),
+                )
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("row_reader", cols), &cols, |b, _| {
+            b.iter(|| {
+                let mut rs = storage.execute_query("SELECT * FROM wide").expect("query");
+                let mut reader = rs.row_reader();
+                let mut total = 0usize;
+                while let Some(row) = reader.next_row().expect("row") {
+                    total += row.iter().map(str::len).sum::<usize>();
+                }
+                black_box(total)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_wide_results);
+criterion_main!(benches);
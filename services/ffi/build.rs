@@ -5,6 +5,10 @@ fn main() {
     if std::env::var_os("CARGO_FEATURE_LINK_NATIVE").is_none() {
         return;
     }
+    // The in-memory fake replaces the native library entirely.
+    if std::env::var_os("CARGO_FEATURE_MOCK_STORAGE").is_some() {
+        return;
+    }
 
     // Repo layout: services/ffi -> repo_root/build/debug/lib
     let manifest_dir =
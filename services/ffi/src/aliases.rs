@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use crate::ColumnType;
+
+/// Type names other engines and engine versions use for the canonical
+/// [`ColumnType`]s, recognized unless overridden.
+const DEFAULT_ALIASES: &[(&str, ColumnType)] = &[
+    ("int", ColumnType::Integer),
+    ("int2", ColumnType::Integer),
+    ("int4", ColumnType::Integer),
+    ("int8", ColumnType::Integer),
+    ("smallint", ColumnType::Integer),
+    ("bigint", ColumnType::Integer),
+    ("real", ColumnType::Float),
+    ("double", ColumnType::Float),
+    ("float4", ColumnType::Float),
+    ("float8", ColumnType::Float),
+    ("text", ColumnType::String),
+    ("varchar", ColumnType::String),
+    ("char", ColumnType::String),
+    ("bool", ColumnType::Boolean),
+];
+
+/// Maps column type names to [`ColumnType`]s: the canonical names of
+/// [`ColumnType::parse`], plus aliases such as `INT4` or `TEXT`, so
+/// definitions written for another engine version's spelling still parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnTypeAliases(HashMap<String, ColumnType>);
+
+impl Default for ColumnTypeAliases {
+    /// The shipped aliases.
+    fn default() -> Self {
+        Self(
+            DEFAULT_ALIASES
+                .iter()
+                .map(|&(alias, ty)| (alias.to_string(), ty))
+                .collect(),
+        )
+    }
+}
+
+impl ColumnTypeAliases {
+    /// The shipped aliases plus those of a comma-separated `alias=type`
+    /// list, e.g. `serial=integer,numeric=float`; a listed alias replaces a
+    /// shipped one. Entries that aren't `alias=type` with a canonical type,
+    /// or that would rename a canonical type, are skipped.
+    pub fn parse(spec: &str) -> Self {
+        let mut aliases = Self::default();
+        for (alias, ty) in spec.split(',').filter_map(|entry| entry.split_once('=')) {
+            let alias = alias.trim().to_ascii_lowercase();
+            if alias.is_empty() || ColumnType::parse(&alias).is_some() {
+                continue;
+            }
+            if let Some(ty) = ColumnType::parse(ty) {
+                aliases.0.insert(alias, ty);
+            }
+        }
+        aliases
+    }
+
+    /// The type `name` names, ignoring case: a canonical name, or an alias.
+    pub fn resolve(&self, name: &str) -> Option<ColumnType> {
+        ColumnType::parse(name).or_else(|| {
+            self.0
+                .get(name.trim().to_ascii_lowercase().as_str())
+                .copied()
+        })
+    }
+}
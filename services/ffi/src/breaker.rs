@@ -0,0 +1,181 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::FfiError;
+
+/// Default consecutive storage failures that open a pool's breaker.
+pub const DEFAULT_BREAKER_FAILURES: u32 = 5;
+
+/// Default window the failures must fall within.
+pub const DEFAULT_BREAKER_WINDOW: Duration = Duration::from_secs(10);
+
+/// Default time an open breaker fails calls before letting a trial through.
+pub const DEFAULT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// When a pool's circuit breaker opens, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// Consecutive storage failures that open the breaker.
+    pub failures: u32,
+    /// A run of failures spread over longer than this starts over.
+    pub window: Duration,
+    /// How long the breaker stays open before a trial call is let through.
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failures: DEFAULT_BREAKER_FAILURES,
+            window: DEFAULT_BREAKER_WINDOW,
+            cooldown: DEFAULT_BREAKER_COOLDOWN,
+        }
+    }
+}
+
+/// Where a circuit breaker stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through.
+    Closed,
+    /// Calls fail with [`FfiError::CircuitOpen`] without touching storage.
+    Open,
+    /// The cooldown is over and one trial call decides whether to close.
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Inner {
+    Closed {
+        failures: u32,
+        since: Option<Instant>,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        trial: bool,
+    },
+}
+
+/// Trips after repeated storage failures so calls fail fast instead of
+/// piling up blocking tasks against a sick native layer.
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    config: BreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl Breaker {
+    pub(crate) fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner::Closed {
+                failures: 0,
+                since: None,
+            }),
+        }
+    }
+
+    pub(crate) fn state(&self) -> BreakerState {
+        match *self.lock() {
+            Inner::Closed { .. } => BreakerState::Closed,
+            Inner::Open { until } if Instant::now() < until => BreakerState::Open,
+            Inner::Open { .. } | Inner::HalfOpen { .. } => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Lets a call through, or fails with [`FfiError::CircuitOpen`]. Returns
+    /// whether the call is the half-open trial, which must be settled with
+    /// [`Breaker::record`] or [`Breaker::abandon`].
+    pub(crate) fn admit(&self) -> Result<bool, FfiError> {
+        let mut inner = self.lock();
+        match *inner {
+            Inner::Closed { .. } => Ok(false),
+            Inner::Open { until } if Instant::now() < until => Err(FfiError::CircuitOpen),
+            Inner::Open { .. } | Inner::HalfOpen { trial: false } => {
+                *inner = Inner::HalfOpen { trial: true };
+                transition(BreakerState::HalfOpen);
+                Ok(true)
+            }
+            Inner::HalfOpen { trial: true } => Err(FfiError::CircuitOpen),
+        }
+    }
+
+    /// Records how an admitted call went with storage. Once the breaker
+    /// has opened only the trial's outcome counts: calls admitted before
+    /// it opened neither close nor extend it.
+    pub(crate) fn record(&self, trial: bool, failed: bool) {
+        let mut inner = self.lock();
+        let now = Instant::now();
+        let next = match *inner {
+            Inner::Open { .. } | Inner::HalfOpen { .. } if !trial => return,
+            _ if !failed => Inner::Closed {
+                failures: 0,
+                since: None,
+            },
+            Inner::Closed { failures, since } => {
+                let (failures, since) = match since {
+                    Some(since) if now.duration_since(since) <= self.config.window => {
+                        (failures + 1, since)
+                    }
+                    _ => (1, now),
+                };
+                if failures >= self.config.failures {
+                    Inner::Open {
+                        until: now + self.config.cooldown,
+                    }
+                } else {
+                    Inner::Closed {
+                        failures,
+                        since: Some(since),
+                    }
+                }
+            }
+            Inner::Open { .. } | Inner::HalfOpen { .. } => Inner::Open {
+                until: now + self.config.cooldown,
+            },
+        };
+        let was_closed = matches!(*inner, Inner::Closed { .. });
+        match (&next, was_closed) {
+            (Inner::Open { .. }, true) => {
+                metrics::gauge!("circuit_breakers_open").increment(1.0);
+                transition(BreakerState::Open);
+            }
+            (Inner::Open { .. }, false) => transition(BreakerState::Open),
+            (Inner::Closed { .. }, false) => {
+                metrics::gauge!("circuit_breakers_open").decrement(1.0);
+                transition(BreakerState::Closed);
+            }
+            _ => {}
+        }
+        *inner = next;
+    }
+
+    /// Gives up the half-open trial without an outcome, e.g. when the call
+    /// never got a pool slot, so another call can take it.
+    pub(crate) fn abandon(&self) {
+        let mut inner = self.lock();
+        if let Inner::HalfOpen { trial: true } = *inner {
+            *inner = Inner::HalfOpen { trial: false };
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn transition(to: BreakerState) {
+    metrics::counter!("circuit_breaker_transitions_total", "state" => to.as_str()).increment(1);
+}
@@ -0,0 +1,178 @@
+use kadedb_services_auth::AuthError;
+
+use crate::FfiError;
+
+/// What went wrong with a request, the same over REST (the `code` field of
+/// error bodies) and gRPC (a `google.rpc.ErrorInfo` status detail). The
+/// HTTP status or gRPC code says how to react; this says why, at a grain
+/// clients can branch on.
+///
+/// The strings of [`ErrorCode::as_str`] are stable. Codes may be added, so
+/// clients should treat one they don't know like [`ErrorCode::Internal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// A malformed request: bad parameters, fields failing validation, rows
+    /// that don't fit the table.
+    InvalidRequest,
+    /// The engine couldn't run the SQL.
+    InvalidSql,
+    /// No credentials, or ones that aren't valid.
+    Unauthenticated,
+    /// Valid credentials without the permission, scope or statement
+    /// allowance the request needs.
+    PermissionDenied,
+    /// The route, cursor or other resource named doesn't exist.
+    NotFound,
+    /// The table named doesn't exist.
+    TableNotFound,
+    /// The request clashes with the current state, e.g. a rejected insert.
+    Conflict,
+    /// The request ran past its deadline.
+    Timeout,
+    /// The request was cancelled before it finished.
+    Cancelled,
+    /// Too many requests: a rate limit, or every storage connection busy.
+    Overloaded,
+    /// Storage is down or failing; see the circuit breaker.
+    StorageUnavailable,
+    /// The server isn't taking requests: paused or shutting down.
+    Unavailable,
+    /// A server-side failure the caller can't fix.
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidSql => "invalid_sql",
+            Self::Unauthenticated => "unauthenticated",
+            Self::PermissionDenied => "permission_denied",
+            Self::NotFound => "not_found",
+            Self::TableNotFound => "table_not_found",
+            Self::Conflict => "conflict",
+            Self::Timeout => "timeout",
+            Self::Cancelled => "cancelled",
+            Self::Overloaded => "overloaded",
+            Self::StorageUnavailable => "storage_unavailable",
+            Self::Unavailable => "unavailable",
+            Self::Internal => "internal",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "invalid_request" => Self::InvalidRequest,
+            "invalid_sql" => Self::InvalidSql,
+            "unauthenticated" => Self::Unauthenticated,
+            "permission_denied" => Self::PermissionDenied,
+            "not_found" => Self::NotFound,
+            "table_not_found" => Self::TableNotFound,
+            "conflict" => Self::Conflict,
+            "timeout" => Self::Timeout,
+            "cancelled" => Self::Cancelled,
+            "overloaded" => Self::Overloaded,
+            "storage_unavailable" => Self::StorageUnavailable,
+            "unavailable" => Self::Unavailable,
+            "internal" => Self::Internal,
+            _ => return None,
+        })
+    }
+
+    /// The code for an error known only by its HTTP status.
+    pub fn from_http_status(status: u16) -> Self {
+        match status {
+            401 => Self::Unauthenticated,
+            403 => Self::PermissionDenied,
+            404 | 410 => Self::NotFound,
+            408 | 504 => Self::Timeout,
+            409 => Self::Conflict,
+            429 => Self::Overloaded,
+            503 => Self::Unavailable,
+            400..=499 => Self::InvalidRequest,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&FfiError> for ErrorCode {
+    fn from(err: &FfiError) -> Self {
+        match err {
+            FfiError::ExecuteQueryFailed => Self::InvalidSql,
+            FfiError::ParamCountMismatch { .. }
+            | FfiError::InvalidParam { .. }
+            | FfiError::ParamTypeMismatch { .. }
+            | FfiError::ArityMismatch { .. }
+            | FfiError::TypeMismatch { .. }
+            | FfiError::QueryTooLong { .. } => Self::InvalidRequest,
+            FfiError::UnknownTable(_) => Self::TableNotFound,
+            FfiError::InsertFailed { .. }
+            | FfiError::DeleteFailed { .. }
+            | FfiError::UpdateFailed { .. } => Self::Conflict,
+            FfiError::StatementNotAllowed(_) => Self::PermissionDenied,
+            FfiError::Timeout => Self::Timeout,
+            FfiError::Cancelled => Self::Cancelled,
+            FfiError::PoolBusy => Self::Overloaded,
+            FfiError::CreateStorageFailed | FfiError::NativeUnavailable | FfiError::CircuitOpen => {
+                Self::StorageUnavailable
+            }
+            FfiError::CreateTableFailed(_)
+            | FfiError::ListTablesFailed
+            | FfiError::Utf8(_)
+            | FfiError::Nul(_) => Self::Internal,
+        }
+    }
+}
+
+impl From<&AuthError> for ErrorCode {
+    fn from(err: &AuthError) -> Self {
+        match err {
+            AuthError::Forbidden | AuthError::MissingScope | AuthError::CsrfMismatch => {
+                Self::PermissionDenied
+            }
+            AuthError::AmbiguousCredentials => Self::InvalidRequest,
+            AuthError::MissingAuthorization
+            | AuthError::InvalidAuthorizationScheme
+            | AuthError::Jwt(_)
+            | AuthError::MissingRole
+            | AuthError::UnknownRole
+            | AuthError::MissingTenant => Self::Unauthenticated,
+        }
+    }
+}
+
+/// How much of an error's cause responses reveal, over REST and gRPC alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorVerbosity {
+    /// Messages say what failed, storage errors included. For development.
+    #[default]
+    Detailed,
+    /// Errors whose message describes storage
+    /// ([`FfiError::exposes_internals`]) get a generic message and a
+    /// correlation id, the request id; the full message is logged under
+    /// that id. Errors in the request itself (a bad parameter, a field that
+    /// fails validation) keep their message. REST 401 challenges drop
+    /// `error_description`.
+    Minimal,
+}
+
+impl ErrorVerbosity {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "detailed" => Some(Self::Detailed),
+            "minimal" => Some(Self::Minimal),
+            _ => None,
+        }
+    }
+
+    /// Whether `err`'s message is withheld from clients.
+    pub fn hides(self, err: &FfiError) -> bool {
+        self == Self::Minimal && err.exposes_internals()
+    }
+}
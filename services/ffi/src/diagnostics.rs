@@ -0,0 +1,161 @@
+//! Postmortem context for blocking FFI work.
+//!
+//! Blocking tasks record the query they're running in a thread-local so the
+//! panic hook can say which query a panicking thread was serving. The guard
+//! clears it when the task finishes (or unwinds), so one query's text never
+//! shows up in another query's panic report.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+/// Default prefix for the names of threads running blocking FFI work,
+/// giving `kadedb-ffi-0`, `kadedb-ffi-1`, ...
+pub const DEFAULT_THREAD_PREFIX: &str = "kadedb-ffi";
+
+static THREAD_PREFIX: OnceLock<String> = OnceLock::new();
+
+/// The pool [`spawn_query`] runs work on. It is a runtime of its own, never
+/// driven, so that only its blocking threads carry the FFI thread names and
+/// the async workers of the caller's runtime keep theirs.
+static FFI_THREADS: OnceLock<Runtime> = OnceLock::new();
+
+/// Names the threads [`spawn_query`] runs work on `{prefix}-0`,
+/// `{prefix}-1`, ... in place of [`DEFAULT_THREAD_PREFIX`]. Only takes
+/// effect before the first [`spawn_query`].
+pub fn set_thread_prefix(prefix: impl Into<String>) {
+    let _ = THREAD_PREFIX.set(prefix.into());
+}
+
+fn ffi_threads() -> &'static Runtime {
+    FFI_THREADS.get_or_init(|| {
+        let prefix = THREAD_PREFIX.get_or_init(|| DEFAULT_THREAD_PREFIX.to_string());
+        tokio::runtime::Builder::new_current_thread()
+            .thread_name_fn(thread_namer(prefix.as_str()))
+            .build()
+            .expect("FFI thread pool")
+    })
+}
+
+thread_local! {
+    static CURRENT_QUERY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Marks the current thread as running `query` until dropped.
+pub struct QueryContext {
+    previous: Option<String>,
+}
+
+impl QueryContext {
+    pub fn enter(query: impl Into<String>) -> Self {
+        let previous = CURRENT_QUERY.with(|q| q.borrow_mut().replace(query.into()));
+        Self { previous }
+    }
+
+    /// The query the current thread is running, if any.
+    pub fn current() -> Option<String> {
+        CURRENT_QUERY.with(|q| q.try_borrow().ok().and_then(|q| q.clone()))
+    }
+}
+
+impl Drop for QueryContext {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_QUERY.with(|q| *q.borrow_mut() = previous);
+    }
+}
+
+static BLOCKING_QUEUED: AtomicUsize = AtomicUsize::new(0);
+static BLOCKING_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Blocking FFI tasks started through [`spawn_query`], process-wide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockingTasks {
+    /// Waiting for a blocking thread.
+    pub queued: usize,
+    /// On a blocking thread, in the native layer or reading its results.
+    pub running: usize,
+}
+
+impl BlockingTasks {
+    pub fn current() -> Self {
+        Self {
+            queued: BLOCKING_QUEUED.load(Ordering::Relaxed),
+            running: BLOCKING_RUNNING.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Moves `counter` by `delta` and mirrors it into the gauge `name`.
+fn adjust(counter: &AtomicUsize, name: &'static str, delta: isize) {
+    let value = if delta > 0 {
+        counter.fetch_add(delta as usize, Ordering::Relaxed) + delta as usize
+    } else {
+        counter.fetch_sub(delta.unsigned_abs(), Ordering::Relaxed) - delta.unsigned_abs()
+    };
+    metrics::gauge!(name).set(value as f64);
+}
+
+/// Counts a task as running until dropped, so a panicking task is counted
+/// out too.
+struct Running;
+
+impl Running {
+    fn start() -> Self {
+        adjust(&BLOCKING_QUEUED, "blocking_tasks_queued", -1);
+        adjust(&BLOCKING_RUNNING, "blocking_tasks_running", 1);
+        Self
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        adjust(&BLOCKING_RUNNING, "blocking_tasks_running", -1);
+    }
+}
+
+/// Runs blocking FFI work for `query` on the FFI thread pool with the query
+/// recorded as panic context. The task is counted in [`BlockingTasks`]
+/// and the `blocking_tasks_queued`/`blocking_tasks_running` gauges.
+pub fn spawn_query<F, R>(query: impl Into<String>, f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let query = query.into();
+    adjust(&BLOCKING_QUEUED, "blocking_tasks_queued", 1);
+    ffi_threads().spawn_blocking(move || {
+        let _running = Running::start();
+        let _context = QueryContext::enter(query);
+        f()
+    })
+}
+
+/// Installs a panic hook that logs the thread name and current query,
+/// passed through `redact` so literals don't reach the log, before deferring
+/// to the previously installed hook.
+pub fn install_panic_hook(redact: fn(&str) -> String) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(query) = QueryContext::current() {
+            let thread = std::thread::current();
+            eprintln!(
+                "thread '{}' panicked while running query: {}",
+                thread.name().unwrap_or("<unnamed>"),
+                redact(&query)
+            );
+        }
+        previous(info);
+    }));
+}
+
+/// A thread-name generator for `tokio::runtime::Builder::thread_name_fn`,
+/// producing `{prefix}-0`, `{prefix}-1`, ...
+fn thread_namer(prefix: impl Into<String>) -> impl Fn() -> String + Send + Sync + 'static {
+    let prefix = prefix.into();
+    let next = AtomicUsize::new(0);
+    move || format!("{prefix}-{}", next.fetch_add(1, Ordering::Relaxed))
+}
@@ -1,16 +1,408 @@
+//! Safe wrappers over the KadeDB C ABI (`libkadedb_c`).
+//!
+//! The `link-native` feature (on by default) links the native library. With
+//! it disabled the crate still builds and links without `libkadedb_c`, so
+//! crates that only need the shared types can depend on it:
+//!
+//! - available in both modes: [`Value`], [`ColumnType`], [`ColumnTypeAliases`], [`ColumnSpec`],
+//!   [`ColumnInfo`], [`TableSchema`], [`Statement`] (parsing and binding), [`FfiError`],
+//!   [`ErrorCode`], and
+//!   the query-context helpers ([`spawn_query`], [`install_panic_hook`]);
+//! - `link-native` only: everything that touches storage. Without it
+//!   [`Storage::new`] (and so [`StoragePool::new`]) returns
+//!   [`FfiError::NativeUnavailable`], and no other storage API can be reached.
+//!
+//! The `mock-storage` feature swaps the native library for an in-memory fake
+//! behind the same C entry points (CREATE TABLE, INSERT, `SELECT * FROM`),
+//! so code built on this crate can be tested without linking C++. It takes
+//! precedence over `link-native` when both are enabled.
+//!
+//! The `tonic` feature adds `From<FfiError> for tonic::Status`, and
+//! [`coded_status`] and [`status_code`] to carry an [`ErrorCode`] in status
+//! details.
+
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+
+mod aliases;
+mod breaker;
+mod cancel;
+mod code;
+mod diagnostics;
+#[cfg(feature = "mock-storage")]
+mod mock;
+mod pool;
+mod statement;
+mod statement_cache;
+#[cfg(feature = "tonic")]
+mod status;
+mod tables;
+
+pub use aliases::ColumnTypeAliases;
+pub use breaker::{
+    BreakerConfig, BreakerState, DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_FAILURES,
+    DEFAULT_BREAKER_WINDOW,
+};
+pub use cancel::CancelToken;
+pub use code::{ErrorCode, ErrorVerbosity};
+pub use diagnostics::{
+    install_panic_hook, set_thread_prefix, spawn_query, BlockingTasks, QueryContext,
+    DEFAULT_THREAD_PREFIX,
+};
+pub use pool::{
+    ConfiguredPools, PoolGuard, StoragePool, DEFAULT_POOL_QUEUE_DEPTH, DEFAULT_POOL_QUEUE_TIMEOUT,
+    DEFAULT_POOL_SIZE,
+};
+pub use statement::{AllowedStatements, Statement, StatementKind};
+use statement_cache::StatementCache;
+pub use statement_cache::{StatementCacheStats, DEFAULT_STATEMENT_CACHE_SIZE};
+#[cfg(feature = "tonic")]
+pub use status::{coded_status, minimal_status, status_code, RETRY_DELAY};
+pub use tables::{referenced_tables, RestrictedTables};
 
 #[derive(Debug, thiserror::Error)]
 pub enum FfiError {
     #[error("failed to create storage")]
     CreateStorageFailed,
 
+    #[error("native storage is unavailable: built without the `link-native` feature")]
+    NativeUnavailable,
+
     #[error("query returned null result set")]
     ExecuteQueryFailed,
 
     #[error("invalid utf8")]
     Utf8(#[from] std::str::Utf8Error),
+
+    #[error("failed to create table `{0}`")]
+    CreateTableFailed(String),
+
+    #[error("failed to list tables")]
+    ListTablesFailed,
+
+    #[error("unknown table `{0}`")]
+    UnknownTable(String),
+
+    #[error("insert into `{table}` failed")]
+    InsertFailed { table: String },
+
+    #[error("delete from `{table}` failed")]
+    DeleteFailed { table: String },
+
+    #[error("update of `{table}` failed")]
+    UpdateFailed { table: String },
+
+    #[error("row has {got} values but table has {expected} columns")]
+    ArityMismatch { expected: usize, got: usize },
+
+    #[error("column `{column}` expects {expected:?}")]
+    TypeMismatch {
+        column: String,
+        expected: ColumnType,
+    },
+
+    #[error("string contains NUL byte")]
+    Nul(#[from] std::ffi::NulError),
+
+    #[error("statement has {expected} placeholders but {got} parameters were bound")]
+    ParamCountMismatch { expected: usize, got: usize },
+
+    #[error("parameter {index}: {reason}")]
+    InvalidParam { index: usize, reason: String },
+
+    #[error("parameter {index} expects {expected:?}, got {got:?}")]
+    ParamTypeMismatch {
+        index: usize,
+        expected: ColumnType,
+        got: ColumnType,
+    },
+
+    #[error("query cancelled")]
+    Cancelled,
+
+    #[error("query timed out")]
+    Timeout,
+
+    #[error("storage is busy: no free connection")]
+    PoolBusy,
+
+    #[error("storage is unavailable after repeated failures")]
+    CircuitOpen,
+
+    #[error("query is {len} bytes, longer than the {max} allowed")]
+    QueryTooLong { len: usize, max: usize },
+
+    #[error("{} statements are not allowed", .0.as_str())]
+    StatementNotAllowed(StatementKind),
+}
+
+impl FfiError {
+    /// Whether the same request may succeed if retried later: storage was
+    /// momentarily out of connections or couldn't be opened.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            FfiError::PoolBusy | FfiError::CreateStorageFailed | FfiError::CircuitOpen
+        )
+    }
+
+    /// Whether storage itself failed, rather than rejecting the request:
+    /// what a pool's circuit breaker counts (see
+    /// [`PoolGuard::record_failure`]).
+    pub fn is_storage_failure(&self) -> bool {
+        matches!(
+            self,
+            FfiError::CreateStorageFailed
+                | FfiError::NativeUnavailable
+                | FfiError::CreateTableFailed(_)
+                | FfiError::ListTablesFailed
+                | FfiError::Utf8(_)
+        )
+    }
+
+    /// Whether the message describes the storage layer (its failures, or
+    /// which tables exist) rather than what was wrong with the request:
+    /// what [`ErrorVerbosity::Minimal`] hides.
+    pub fn exposes_internals(&self) -> bool {
+        match self {
+            FfiError::ParamCountMismatch { .. }
+            | FfiError::InvalidParam { .. }
+            | FfiError::ParamTypeMismatch { .. }
+            | FfiError::ArityMismatch { .. }
+            | FfiError::TypeMismatch { .. }
+            | FfiError::QueryTooLong { .. }
+            | FfiError::StatementNotAllowed(_)
+            | FfiError::PoolBusy
+            | FfiError::CircuitOpen
+            | FfiError::Cancelled
+            | FfiError::Timeout => false,
+            FfiError::ExecuteQueryFailed
+            | FfiError::UnknownTable(_)
+            | FfiError::CreateStorageFailed
+            | FfiError::NativeUnavailable
+            | FfiError::CreateTableFailed(_)
+            | FfiError::ListTablesFailed
+            | FfiError::InsertFailed { .. }
+            | FfiError::DeleteFailed { .. }
+            | FfiError::UpdateFailed { .. }
+            | FfiError::Utf8(_)
+            | FfiError::Nul(_) => true,
+        }
+    }
+}
+
+/// Column types understood by the native storage layer (mirrors `KDB_ColumnType`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Null,
+    Integer,
+    Float,
+    String,
+    Boolean,
+}
+
+impl ColumnType {
+    /// Parses a column type name (`integer`, `float`, `string`, `boolean`),
+    /// ignoring case. `null` is not a type a column can be declared with.
+    /// [`ColumnTypeAliases::resolve`] also takes other spellings.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "integer" => Some(Self::Integer),
+            "float" => Some(Self::Float),
+            "string" => Some(Self::String),
+            "boolean" => Some(Self::Boolean),
+            _ => None,
+        }
+    }
+
+    /// The lowercase name [`ColumnType::parse`] accepts; `null` for
+    /// [`ColumnType::Null`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::String => "string",
+            Self::Boolean => "boolean",
+        }
+    }
+
+    fn from_raw(raw: i32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Null),
+            1 => Some(Self::Integer),
+            2 => Some(Self::Float),
+            3 => Some(Self::String),
+            4 => Some(Self::Boolean),
+            _ => None,
+        }
+    }
+
+    fn to_raw(self) -> i32 {
+        match self {
+            Self::Null => 0,
+            Self::Integer => 1,
+            Self::Float => 2,
+            Self::String => 3,
+            Self::Boolean => 4,
+        }
+    }
+}
+
+/// A single cell value passed to or from the native layer (mirrors `KDB_Value`).
+///
+/// [`Value::Array`] is only a statement parameter: it binds as a list of
+/// literals for `IN (?)`, and is never a cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// The column type this value carries; [`ColumnType::Null`] for NULL.
+    /// For an array, the type of its first non-NULL element.
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            Value::Null => ColumnType::Null,
+            Value::Integer(_) => ColumnType::Integer,
+            Value::Float(_) => ColumnType::Float,
+            Value::String(_) => ColumnType::String,
+            Value::Boolean(_) => ColumnType::Boolean,
+            Value::Array(items) => items
+                .iter()
+                .map(Value::column_type)
+                .find(|&ty| ty != ColumnType::Null)
+                .unwrap_or(ColumnType::Null),
+        }
+    }
+
+    /// Whether the value fits a cell of type `ty`. Arrays fit no cell.
+    fn matches(&self, ty: ColumnType) -> bool {
+        matches!(
+            (self, ty),
+            (Value::Null, _)
+                | (Value::Integer(_), ColumnType::Integer)
+                | (Value::Float(_), ColumnType::Float)
+                | (Value::String(_), ColumnType::String)
+                | (Value::Boolean(_), ColumnType::Boolean)
+        )
+    }
+}
+
+/// How a [`Predicate`] compares a column with its value (mirrors
+/// `KDB_CompareOp`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn to_raw(self) -> i32 {
+        match self {
+            Self::Eq => 0,
+            Self::Ne => 1,
+            Self::Lt => 2,
+            Self::Le => 3,
+            Self::Gt => 4,
+            Self::Ge => 5,
+        }
+    }
+}
+
+/// `column op value`, the row filter of [`Storage::delete_rows`] and
+/// [`Storage::update_rows`] (mirrors `KDB_Predicate`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Predicate {
+    pub column: String,
+    pub op: CompareOp,
+    pub value: Value,
+}
+
+impl Predicate {
+    pub fn eq(column: impl Into<String>, value: Value) -> Self {
+        Self {
+            column: column.into(),
+            op: CompareOp::Eq,
+            value,
+        }
+    }
+}
+
+/// Column definition used when creating a table.
+#[derive(Debug, Clone)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+/// A table's columns, as returned by [`Storage::describe_table`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSchema {
+    pub name: String,
+    pub columns: Vec<ColumnSchema>,
+}
+
+/// One column of a [`TableSchema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: ColumnType,
+    /// `None` when the table wasn't created through this [`Storage`], since
+    /// the native layer doesn't report nullability.
+    pub nullable: Option<bool>,
+}
+
+/// Column metadata as reported by a result set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+/// How result sets report column names. Engines differ on how they fold
+/// identifier case, so `SELECT Name` may come back as `Name` or `name`; a
+/// fixed case keeps clients' lookups working either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColumnCase {
+    /// As the engine returns them.
+    #[default]
+    Preserve,
+    Lower,
+    Upper,
+}
+
+impl ColumnCase {
+    /// Parses `preserve`, `lower` or `upper`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "preserve" => Some(Self::Preserve),
+            "lower" => Some(Self::Lower),
+            "upper" => Some(Self::Upper),
+            _ => None,
+        }
+    }
+
+    /// `name` in this case.
+    pub fn apply(self, name: String) -> String {
+        match self {
+            Self::Preserve => name,
+            Self::Lower => name.to_lowercase(),
+            Self::Upper => name.to_uppercase(),
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -21,8 +413,35 @@ struct StorageRaw(usize);
 // - We do not transfer ownership; the owning Storage must outlive any use
 unsafe impl Send for StorageRaw {}
 
+/// Declares the native entry points. With `link-native` they are the real
+/// `extern "C"` symbols; with `mock-storage` the in-memory versions from
+/// [`mock`] are used instead. With neither they are stand-ins that are never
+/// reached, because [`Storage::new`] refuses to construct a storage.
+macro_rules! native_fns {
+    ($(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        #[cfg(all(feature = "link-native", not(feature = "mock-storage")))]
+        extern "C" {
+            $(pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+
+        $(
+            #[cfg(not(any(feature = "link-native", feature = "mock-storage")))]
+            #[allow(non_snake_case, unused_variables)]
+            pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                unreachable!(concat!(
+                    stringify!($name),
+                    " called in a build without the `link-native` feature"
+                ))
+            }
+        )*
+    };
+}
+
 #[allow(non_camel_case_types)]
 mod sys {
+    #[cfg(feature = "mock-storage")]
+    pub use crate::mock::*;
+
     #[repr(C)]
     pub struct KadeDB_Storage {
         _private: [u8; 0],
@@ -33,10 +452,105 @@ mod sys {
         _private: [u8; 0],
     }
 
-    extern "C" {
+    #[repr(C)]
+    pub struct KDB_TableSchema {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct KDB_ColumnConstraints {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct KDB_TableColumnEx {
+        pub name: *const i8,
+        pub column_type: i32,
+        pub nullable: i32,
+        pub unique: i32,
+        pub constraints: *const KDB_ColumnConstraints,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub union KDB_ValueData {
+        pub i64_: i64,
+        pub f64_: f64,
+        pub str_: *const i8,
+        pub boolean: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct KDB_Value {
+        pub value_type: i32,
+        pub data: KDB_ValueData,
+    }
+
+    #[repr(C)]
+    pub struct KDB_RowView {
+        pub values: *const KDB_Value,
+        pub count: u64,
+    }
+
+    #[repr(C)]
+    pub struct KDB_Predicate {
+        pub column: *const i8,
+        pub op: i32,
+        pub rhs: KDB_Value,
+    }
+
+    #[repr(C)]
+    pub struct KDB_Assignment {
+        pub column: *const i8,
+        pub is_column_ref: i32,
+        pub column_ref: *const i8,
+        pub constant: KDB_Value,
+    }
+
+    native_fns! {
         pub fn KadeDB_CreateStorage() -> *mut KadeDB_Storage;
         pub fn KadeDB_DestroyStorage(storage: *mut KadeDB_Storage);
 
+        pub fn KadeDB_TableSchema_Create() -> *mut KDB_TableSchema;
+        pub fn KadeDB_TableSchema_Destroy(schema: *mut KDB_TableSchema);
+        pub fn KadeDB_TableSchema_AddColumn(
+            schema: *mut KDB_TableSchema,
+            column: *const KDB_TableColumnEx,
+        ) -> i32;
+
+        pub fn KadeDB_CreateTable(
+            storage: *mut KadeDB_Storage,
+            table: *const i8,
+            schema: *const KDB_TableSchema,
+        ) -> i32;
+        pub fn KadeDB_InsertRow(
+            storage: *mut KadeDB_Storage,
+            table: *const i8,
+            row: *const KDB_RowView,
+        ) -> i32;
+        pub fn KadeDB_UpdateRows(
+            storage: *mut KadeDB_Storage,
+            table: *const i8,
+            assignments: *const KDB_Assignment,
+            assignment_count: u64,
+            predicate: *const KDB_Predicate,
+            out_updated: *mut u64,
+        ) -> i32;
+        pub fn KadeDB_DeleteRows(
+            storage: *mut KadeDB_Storage,
+            table: *const i8,
+            predicate: *const KDB_Predicate,
+            out_deleted: *mut u64,
+        ) -> i32;
+        pub fn KadeDB_ListTables_ToCSV(
+            storage: *mut KadeDB_Storage,
+            delimiter: i8,
+            out_buf: *mut i8,
+            out_buf_len: u64,
+            out_required_len: *mut u64,
+        ) -> i32;
+
         pub fn KadeDB_ExecuteQuery(
             storage: *mut KadeDB_Storage,
             query: *const i8,
@@ -45,48 +559,424 @@ mod sys {
         pub fn KadeDB_ResultSet_NextRow(rs: *mut KadeDB_ResultSet) -> i32;
         pub fn KadeDB_ResultSet_ColumnCount(rs: *mut KadeDB_ResultSet) -> i32;
         pub fn KadeDB_ResultSet_GetString(rs: *mut KadeDB_ResultSet, column: i32) -> *const i8;
+        pub fn KadeDB_ResultSet_GetColumnName(rs: *mut KadeDB_ResultSet, column: i32) -> *const i8;
+        pub fn KadeDB_ResultSet_GetColumnType(rs: *mut KadeDB_ResultSet, column: i32) -> i32;
 
         pub fn KadeDB_DestroyResultSet(rs: *mut KadeDB_ResultSet);
     }
 }
 
+/// Default longest query text passed to the native layer, in bytes.
+pub const DEFAULT_MAX_QUERY_LENGTH: usize = 1 << 20;
+
+/// The key NDJSON result streams wrap their control lines (errors,
+/// checksums, progress) in, as `{"$kadedb":{...}}`. It isn't an identifier,
+/// so no row has a column by that name.
+pub const NDJSON_CONTROL_KEY: &str = "$kadedb";
+
 pub struct Storage {
     raw: NonNull<sys::KadeDB_Storage>,
+    statements: StatementCache,
+    max_query_length: usize,
+    allowed_statements: AllowedStatements,
+    column_case: ColumnCase,
+    /// Schemas for [`Storage::describe_table`], by table.
+    schemas: Mutex<HashMap<String, Arc<TableSchema>>>,
+    /// Held across each [`Storage::upsert`], by table.
+    upserts: Mutex<HashMap<String, Arc<Mutex<()>>>>,
 }
 
 unsafe impl Send for Storage {}
 unsafe impl Sync for Storage {}
 
 impl Storage {
+    /// Creates an empty in-memory storage. Fails with
+    /// [`FfiError::NativeUnavailable`] when built without `link-native`.
     pub fn new() -> Result<Self, FfiError> {
+        Self::with_statement_cache(DEFAULT_STATEMENT_CACHE_SIZE)
+    }
+
+    /// Like [`Storage::new`], caching up to `capacity` parsed statements for
+    /// [`Storage::prepare`] (0 disables the cache).
+    pub fn with_statement_cache(capacity: usize) -> Result<Self, FfiError> {
+        if !cfg!(any(feature = "link-native", feature = "mock-storage")) {
+            return Err(FfiError::NativeUnavailable);
+        }
         let raw = unsafe { sys::KadeDB_CreateStorage() };
         let raw = NonNull::new(raw).ok_or(FfiError::CreateStorageFailed)?;
-        Ok(Self { raw })
+        Ok(Self {
+            raw,
+            statements: StatementCache::new(capacity),
+            max_query_length: DEFAULT_MAX_QUERY_LENGTH,
+            allowed_statements: AllowedStatements::default(),
+            column_case: ColumnCase::default(),
+            schemas: Mutex::default(),
+            upserts: Mutex::default(),
+        })
+    }
+
+    /// Rejects query text longer than `max` bytes with
+    /// [`FfiError::QueryTooLong`] before it reaches the native layer.
+    pub fn with_max_query_length(mut self, max: usize) -> Self {
+        self.max_query_length = max;
+        self
+    }
+
+    /// Refuses statements of kinds outside `allowed` with
+    /// [`FfiError::StatementNotAllowed`] before they reach the native layer.
+    pub fn with_allowed_statements(mut self, allowed: AllowedStatements) -> Self {
+        self.allowed_statements = allowed;
+        self
+    }
+
+    pub fn allowed_statements(&self) -> &AllowedStatements {
+        &self.allowed_statements
+    }
+
+    /// Reports the column names of query results in `case`. Table layouts
+    /// ([`Storage::describe_table`], inserts) keep the engine's names.
+    pub fn with_column_case(mut self, case: ColumnCase) -> Self {
+        self.column_case = case;
+        self
+    }
+
+    fn check_query(&self, query: &str) -> Result<(), FfiError> {
+        if query.len() > self.max_query_length {
+            return Err(FfiError::QueryTooLong {
+                len: query.len(),
+                max: self.max_query_length,
+            });
+        }
+        self.allowed_statements.check(query)
+    }
+
+    pub fn create_table(&self, table: &str, columns: &[ColumnSpec]) -> Result<(), FfiError> {
+        let c_table = CString::new(table)?;
+        let c_names = columns
+            .iter()
+            .map(|c| CString::new(c.name.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        unsafe {
+            let schema = sys::KadeDB_TableSchema_Create();
+            if schema.is_null() {
+                return Err(FfiError::CreateTableFailed(table.to_string()));
+            }
+
+            let mut ok = true;
+            for (col, name) in columns.iter().zip(&c_names) {
+                let raw = sys::KDB_TableColumnEx {
+                    name: name.as_ptr(),
+                    column_type: col.column_type.to_raw(),
+                    nullable: col.nullable as i32,
+                    unique: 0,
+                    constraints: std::ptr::null(),
+                };
+                if sys::KadeDB_TableSchema_AddColumn(schema, &raw) == 0 {
+                    ok = false;
+                    break;
+                }
+            }
+
+            if ok {
+                ok = sys::KadeDB_CreateTable(self.raw.as_ptr(), c_table.as_ptr(), schema) != 0;
+            }
+            sys::KadeDB_TableSchema_Destroy(schema);
+
+            if ok {
+                let schema = TableSchema {
+                    name: table.to_string(),
+                    columns: columns
+                        .iter()
+                        .map(|c| ColumnSchema {
+                            name: c.name.clone(),
+                            column_type: c.column_type,
+                            nullable: Some(c.nullable),
+                        })
+                        .collect(),
+                };
+                self.schemas
+                    .lock()
+                    .expect("schema cache lock")
+                    .insert(table.to_string(), Arc::new(schema));
+                Ok(())
+            } else {
+                Err(FfiError::CreateTableFailed(table.to_string()))
+            }
+        }
+    }
+
+    /// Sets each of `assignments`' columns to its value in the rows of
+    /// `table` that match `predicate`, or in all of them without one,
+    /// returning how many were updated. The native layer checks the updated
+    /// rows against the table's schema and applies all of them or none.
+    /// Neither the values nor the predicate's value may be arrays.
+    pub fn update_rows(
+        &self,
+        table: &str,
+        assignments: &[(String, Value)],
+        predicate: Option<&Predicate>,
+    ) -> Result<u64, FfiError> {
+        let c_table = CString::new(table)?;
+        // Keep names and string payloads alive for the duration of the call.
+        let names = assignments
+            .iter()
+            .map(|(column, _)| CString::new(column.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let strings = assignments
+            .iter()
+            .map(|(_, value)| match value {
+                Value::String(s) => CString::new(s.as_str()).map(Some),
+                _ => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let raw_assignments: Vec<sys::KDB_Assignment> = assignments
+            .iter()
+            .zip(names.iter().zip(&strings))
+            .map(|((_, value), (name, string))| sys::KDB_Assignment {
+                column: name.as_ptr(),
+                is_column_ref: 0,
+                column_ref: std::ptr::null(),
+                constant: raw_value(value, string.as_ref()),
+            })
+            .collect();
+        let filter = RawPredicate::new(predicate)?;
+        let mut updated = 0u64;
+        let ok = unsafe {
+            sys::KadeDB_UpdateRows(
+                self.raw.as_ptr(),
+                c_table.as_ptr(),
+                raw_assignments.as_ptr(),
+                raw_assignments.len() as u64,
+                filter.as_ptr(),
+                &mut updated,
+            )
+        };
+        if ok == 0 {
+            return Err(FfiError::UpdateFailed {
+                table: table.to_string(),
+            });
+        }
+        Ok(updated)
+    }
+
+    /// Sets `assignments` in the rows of `target`'s table that match `key`,
+    /// or, when none do, inserts `row`; returns whether it inserted.
+    ///
+    /// The engine has no `ON CONFLICT`, so this is an update and then an
+    /// insert. Upserts into the same table through this storage are
+    /// serialized, so two with the same key can't both insert; other writes
+    /// aren't held back.
+    pub fn upsert(
+        &self,
+        target: &PreparedInsert,
+        row: &[Value],
+        assignments: &[(String, Value)],
+        key: &Predicate,
+    ) -> Result<bool, FfiError> {
+        let lock = self
+            .upserts
+            .lock()
+            .expect("upsert locks")
+            .entry(target.table().to_string())
+            .or_default()
+            .clone();
+        let _serialized = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if self.update_rows(target.table(), assignments, Some(key))? > 0 {
+            return Ok(false);
+        }
+        target.execute(self, row)?;
+        Ok(true)
+    }
+
+    /// Deletes the rows of `table` that match `predicate`, or all of them
+    /// without one, returning how many were deleted. The predicate's value
+    /// must not be an array.
+    pub fn delete_rows(&self, table: &str, predicate: Option<&Predicate>) -> Result<u64, FfiError> {
+        let c_table = CString::new(table)?;
+        let filter = RawPredicate::new(predicate)?;
+        let mut deleted = 0u64;
+        let ok = unsafe {
+            sys::KadeDB_DeleteRows(
+                self.raw.as_ptr(),
+                c_table.as_ptr(),
+                filter.as_ptr(),
+                &mut deleted,
+            )
+        };
+        if ok == 0 {
+            return Err(FfiError::DeleteFailed {
+                table: table.to_string(),
+            });
+        }
+        Ok(deleted)
+    }
+
+    /// Names of all tables, sorted.
+    pub fn list_tables(&self) -> Result<Vec<String>, FfiError> {
+        // Table names are identifiers, so a comma never occurs inside one.
+        let delimiter = b',' as i8;
+        let mut needed = 0u64;
+        let ok = unsafe {
+            sys::KadeDB_ListTables_ToCSV(
+                self.raw.as_ptr(),
+                delimiter,
+                std::ptr::null_mut(),
+                0,
+                &mut needed,
+            )
+        };
+        if ok == 0 || needed == 0 {
+            return Err(FfiError::ListTablesFailed);
+        }
+        // A table created between the two calls is truncated away rather
+        // than overflowing: the second call never writes past `buf`.
+        let mut buf = vec![0u8; needed as usize];
+        let ok = unsafe {
+            sys::KadeDB_ListTables_ToCSV(
+                self.raw.as_ptr(),
+                delimiter,
+                buf.as_mut_ptr() as *mut i8,
+                buf.len() as u64,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(FfiError::ListTablesFailed);
+        }
+        let list = CStr::from_bytes_until_nul(&buf)
+            .map_err(|_| FfiError::ListTablesFailed)?
+            .to_str()?;
+        let mut tables: Vec<String> = list
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+        tables.sort();
+        Ok(tables)
+    }
+
+    /// Resolves a table's column layout once so rows can be inserted in bulk
+    /// without re-reading the schema per row.
+    pub fn prepare_insert(&self, table: &str) -> Result<PreparedInsert, FfiError> {
+        let rs = self.probe(table)?;
+        Ok(PreparedInsert {
+            table: CString::new(table)?,
+            columns: rs.columns(),
+        })
+    }
+
+    /// Like [`Storage::prepare_insert`], but the layout comes from the cached
+    /// [`Storage::describe_table`] instead of a query.
+    pub fn insert_target(&self, table: &str) -> Result<Arc<PreparedInsert>, FfiError> {
+        let schema = self.describe_table(table)?;
+        Ok(Arc::new(PreparedInsert {
+            table: CString::new(table)?,
+            columns: schema
+                .columns
+                .iter()
+                .map(|c| ColumnInfo {
+                    name: c.name.clone(),
+                    column_type: c.column_type,
+                })
+                .collect(),
+        }))
+    }
+
+    /// The columns of `table`, with the nullability it was created with.
+    ///
+    /// Schemas are recorded by [`Storage::create_table`] and cached, so this
+    /// doesn't touch the native layer for tables created here. Any other
+    /// table is probed once with an empty read. The cache is replaced on
+    /// every successful `create_table`; tables can't be altered or dropped
+    /// through this API, so an entry stays valid until then.
+    pub fn describe_table(&self, table: &str) -> Result<Arc<TableSchema>, FfiError> {
+        if let Some(schema) = self.schemas.lock().expect("schema cache lock").get(table) {
+            return Ok(schema.clone());
+        }
+        let rs = self.probe(table)?;
+        let schema = Arc::new(TableSchema {
+            name: table.to_string(),
+            columns: rs
+                .columns()
+                .into_iter()
+                .map(|c| ColumnSchema {
+                    name: c.name,
+                    column_type: c.column_type,
+                    nullable: None,
+                })
+                .collect(),
+        });
+        self.schemas
+            .lock()
+            .expect("schema cache lock")
+            .insert(table.to_string(), schema.clone());
+        Ok(schema)
+    }
+
+    /// An empty read of `table`, for its layout. Column names are the
+    /// engine's, whatever the [`ColumnCase`].
+    fn probe(&self, table: &str) -> Result<ResultSet, FfiError> {
+        let mut rs = self
+            .execute_query(&format!("SELECT * FROM {table}"))
+            .map_err(|_| FfiError::UnknownTable(table.to_string()))?;
+        rs.column_case = ColumnCase::Preserve;
+        Ok(rs)
+    }
+
+    /// Parses `sql`, reusing the cached statement for the same text. Text
+    /// over the length limit isn't cached; executing it fails.
+    pub fn prepare(&self, sql: &str) -> Arc<Statement> {
+        if sql.len() > self.max_query_length {
+            return Arc::new(Statement::new(sql));
+        }
+        self.statements.get_or_prepare(sql)
+    }
+
+    /// Counts of [`Storage::prepare`]'s statement cache.
+    pub fn statement_cache_stats(&self) -> StatementCacheStats {
+        self.statements.stats()
+    }
+
+    pub fn execute_prepared(
+        &self,
+        statement: &Statement,
+        params: &[Value],
+    ) -> Result<ResultSet, FfiError> {
+        self.execute_query(&statement.bind(params)?)
     }
 
     pub fn execute_query(&self, query: &str) -> Result<ResultSet, FfiError> {
-        let c_query = CString::new(query).expect("query contains NUL");
+        self.check_query(query)?;
+        let c_query = CString::new(query)?;
         let rs = unsafe { sys::KadeDB_ExecuteQuery(self.raw.as_ptr(), c_query.as_ptr()) };
         let rs = NonNull::new(rs).ok_or(FfiError::ExecuteQueryFailed)?;
-        Ok(ResultSet { raw: rs })
+        Ok(ResultSet {
+            raw: rs,
+            column_case: self.column_case,
+        })
     }
 
     pub async fn execute_query_rows_as_strings(
         &self,
         query: String,
     ) -> Result<Vec<Vec<String>>, FfiError> {
+        self.check_query(&query)?;
         // This is a blocking FFI call; run in a blocking task.
         // IMPORTANT: do not move `NonNull` across threads; move a raw pointer instead.
         // Also, do not drop/destroy the storage from the blocking thread.
         let storage = StorageRaw(self.raw.as_ptr() as usize);
 
-        tokio::task::spawn_blocking(move || unsafe {
-            let c_query = CString::new(query).expect("query contains NUL");
+        spawn_query(query.clone(), move || unsafe {
+            let c_query = CString::new(query)?;
 
             let storage_ptr = storage.0 as *mut sys::KadeDB_Storage;
             let rs = sys::KadeDB_ExecuteQuery(storage_ptr, c_query.as_ptr());
             let rs = NonNull::new(rs).ok_or(FfiError::ExecuteQueryFailed)?;
-            let mut rs = ResultSet { raw: rs };
+            let mut rs = ResultSet {
+                raw: rs,
+                column_case: ColumnCase::Preserve,
+            };
             rs.all_rows_as_strings()
         })
         .await
@@ -102,6 +992,7 @@ impl Drop for Storage {
 
 pub struct ResultSet {
     raw: NonNull<sys::KadeDB_ResultSet>,
+    column_case: ColumnCase,
 }
 
 unsafe impl Send for ResultSet {}
@@ -115,16 +1006,66 @@ impl ResultSet {
         unsafe { sys::KadeDB_ResultSet_NextRow(self.raw.as_ptr()) != 0 }
     }
 
-    pub fn get_string(&self, column: i32) -> Option<String> {
-        let ptr = unsafe { sys::KadeDB_ResultSet_GetString(self.raw.as_ptr(), column) };
+    /// The column's name, in the storage's [`ColumnCase`].
+    pub fn column_name(&self, column: i32) -> Option<String> {
+        let ptr = unsafe { sys::KadeDB_ResultSet_GetColumnName(self.raw.as_ptr(), column) };
         let ptr = NonNull::new(ptr as *mut i8)?;
         let s = unsafe { CStr::from_ptr(ptr.as_ptr()) };
+        Some(self.column_case.apply(s.to_str().ok()?.to_string()))
+    }
+
+    pub fn column_type(&self, column: i32) -> Option<ColumnType> {
+        ColumnType::from_raw(unsafe {
+            sys::KadeDB_ResultSet_GetColumnType(self.raw.as_ptr(), column)
+        })
+    }
+
+    /// Column names in result order. Available before (and without) reading
+    /// any rows.
+    pub fn column_names(&self) -> Vec<String> {
+        (0..self.column_count().max(0))
+            .map(|i| self.column_name(i).unwrap_or_default())
+            .collect()
+    }
+
+    pub fn columns(&self) -> Vec<ColumnInfo> {
+        (0..self.column_count().max(0))
+            .map(|i| ColumnInfo {
+                name: self.column_name(i).unwrap_or_default(),
+                column_type: self.column_type(i).unwrap_or(ColumnType::Null),
+            })
+            .collect()
+    }
+
+    pub fn get_string(&self, column: i32) -> Option<String> {
+        let s = self.get_cstr(column)?;
         Some(s.to_str().ok()?.to_string())
     }
 
+    // The native layer reuses one scratch buffer per result set, so the
+    // returned slice must be consumed before any other call on `self`.
+    fn get_cstr(&self, column: i32) -> Option<&CStr> {
+        let ptr = unsafe { sys::KadeDB_ResultSet_GetString(self.raw.as_ptr(), column) };
+        let ptr = NonNull::new(ptr as *mut i8)?;
+        Some(unsafe { CStr::from_ptr(ptr.as_ptr()) })
+    }
+
+    /// Iterates rows without allocating per cell; see [`RowReader`].
+    pub fn row_reader(&mut self) -> RowReader<'_> {
+        let cols = self.column_count().max(0) as usize;
+        RowReader {
+            rs: self,
+            cells: vec![String::new(); cols],
+            cancel: None,
+            deadline: None,
+        }
+    }
+
     pub fn all_rows_as_strings(&mut self) -> Result<Vec<Vec<String>>, FfiError> {
         let cols = self.column_count();
-        if cols < 0 {
+        // A statement without columns (e.g. DDL) yields no rows; don't step
+        // the cursor at all.
+        if cols <= 0 {
             return Ok(vec![]);
         }
 
@@ -140,8 +1081,238 @@ impl ResultSet {
     }
 }
 
+/// Reads rows into per-column buffers that are reused from row to row, so
+/// once the buffers have grown to fit, iterating allocates nothing per cell.
+///
+/// This is a lending iterator: each [`RowRef`] borrows the reader and is only
+/// valid until the next call to [`RowReader::next_row`].
+pub struct RowReader<'rs> {
+    rs: &'rs mut ResultSet,
+    cells: Vec<String>,
+    cancel: Option<CancelToken>,
+    deadline: Option<std::time::Instant>,
+}
+
+impl RowReader<'_> {
+    /// Makes [`RowReader::next_row`] fail with [`FfiError::Cancelled`] once
+    /// `token` is cancelled.
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Makes [`RowReader::next_row`] fail with [`FfiError::Timeout`] once
+    /// `deadline` has passed.
+    pub fn with_deadline(mut self, deadline: std::time::Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Advances to the next row. NULL or unreadable cells read as `""`, as in
+    /// [`ResultSet::all_rows_as_strings`]; non-UTF-8 cells are an error.
+    pub fn next_row(&mut self) -> Result<Option<RowRef<'_>>, FfiError> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(FfiError::Cancelled);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+        {
+            return Err(FfiError::Timeout);
+        }
+        // Zero-column results (DDL) have no rows to read.
+        if self.cells.is_empty() || !self.rs.next_row() {
+            return Ok(None);
+        }
+        for (i, cell) in self.cells.iter_mut().enumerate() {
+            cell.clear();
+            if let Some(s) = self.rs.get_cstr(i as i32) {
+                cell.push_str(s.to_str()?);
+            }
+        }
+        Ok(Some(RowRef { cells: &self.cells }))
+    }
+}
+
+/// The current row of a [`RowReader`].
+#[derive(Debug, Clone, Copy)]
+pub struct RowRef<'a> {
+    cells: &'a [String],
+}
+
+/// How the native layer renders a NULL cell.
+const NULL_CELL: &str = "null";
+
+impl<'a> RowRef<'a> {
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// The cell at `column`, or `""` when out of range.
+    pub fn get(&self, column: usize) -> &'a str {
+        self.cells.get(column).map_or("", String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> {
+        self.cells.iter().map(String::as_str)
+    }
+
+    /// Whether the cell at `column` is NULL. The native layer renders NULL as
+    /// a bare `null` and strings quoted, so the two can't be confused.
+    pub fn is_null(&self, column: usize) -> bool {
+        self.cells.get(column).is_some_and(|c| c == NULL_CELL)
+    }
+
+    pub fn to_vec(&self) -> Vec<String> {
+        self.cells.to_vec()
+    }
+}
+
 impl Drop for ResultSet {
     fn drop(&mut self) {
         unsafe { sys::KadeDB_DestroyResultSet(self.raw.as_ptr()) };
     }
 }
+
+/// A [`Predicate`] as a `KDB_Predicate`, with the C strings it points into.
+struct RawPredicate {
+    raw: Option<sys::KDB_Predicate>,
+    _column: Option<CString>,
+    _string: Option<CString>,
+}
+
+impl RawPredicate {
+    fn new(predicate: Option<&Predicate>) -> Result<Self, FfiError> {
+        let column = predicate
+            .map(|p| CString::new(p.column.as_str()))
+            .transpose()?;
+        let string = match predicate.map(|p| &p.value) {
+            Some(Value::String(s)) => Some(CString::new(s.as_str())?),
+            _ => None,
+        };
+        let raw = predicate
+            .zip(column.as_ref())
+            .map(|(p, column)| sys::KDB_Predicate {
+                column: column.as_ptr(),
+                op: p.op.to_raw(),
+                rhs: raw_value(&p.value, string.as_ref()),
+            });
+        Ok(Self {
+            raw,
+            _column: column,
+            _string: string,
+        })
+    }
+
+    /// The predicate, or null for none.
+    fn as_ptr(&self) -> *const sys::KDB_Predicate {
+        self.raw
+            .as_ref()
+            .map_or(std::ptr::null(), |p| p as *const _)
+    }
+}
+
+/// `value` as a `KDB_Value`; a string points into `string`, its C copy,
+/// which must outlive the result.
+fn raw_value(value: &Value, string: Option<&CString>) -> sys::KDB_Value {
+    match value {
+        Value::Null => sys::KDB_Value {
+            value_type: 0,
+            data: sys::KDB_ValueData { i64_: 0 },
+        },
+        Value::Integer(i) => sys::KDB_Value {
+            value_type: 1,
+            data: sys::KDB_ValueData { i64_: *i },
+        },
+        Value::Float(f) => sys::KDB_Value {
+            value_type: 2,
+            data: sys::KDB_ValueData { f64_: *f },
+        },
+        Value::String(_) => sys::KDB_Value {
+            value_type: 3,
+            data: sys::KDB_ValueData {
+                str_: string.map_or(std::ptr::null(), |s| s.as_ptr()),
+            },
+        },
+        Value::Boolean(b) => sys::KDB_Value {
+            value_type: 4,
+            data: sys::KDB_ValueData { boolean: *b as i32 },
+        },
+        Value::Array(_) => unreachable!("arrays are never cells"),
+    }
+}
+
+/// An INSERT bound to a table's column layout; see [`Storage::prepare_insert`].
+#[derive(Debug)]
+pub struct PreparedInsert {
+    table: CString,
+    columns: Vec<ColumnInfo>,
+}
+
+impl PreparedInsert {
+    pub fn table(&self) -> &str {
+        self.table.to_str().unwrap_or_default()
+    }
+
+    pub fn columns(&self) -> &[ColumnInfo] {
+        &self.columns
+    }
+
+    /// Checks a row against the column layout without touching storage.
+    pub fn check_row(&self, row: &[Value]) -> Result<(), FfiError> {
+        if row.len() != self.columns.len() {
+            return Err(FfiError::ArityMismatch {
+                expected: self.columns.len(),
+                got: row.len(),
+            });
+        }
+        for (value, col) in row.iter().zip(&self.columns) {
+            if !value.matches(col.column_type) {
+                return Err(FfiError::TypeMismatch {
+                    column: col.name.clone(),
+                    expected: col.column_type,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute(&self, storage: &Storage, row: &[Value]) -> Result<(), FfiError> {
+        self.check_row(row)?;
+
+        // Keep string payloads alive for the duration of the call.
+        let strings = row
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => CString::new(s.as_str()).map(Some),
+                _ => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let values: Vec<sys::KDB_Value> = row
+            .iter()
+            .zip(&strings)
+            .map(|(v, s)| raw_value(v, s.as_ref()))
+            .collect();
+
+        let view = sys::KDB_RowView {
+            values: values.as_ptr(),
+            count: values.len() as u64,
+        };
+        let ok = unsafe { sys::KadeDB_InsertRow(storage.raw.as_ptr(), self.table.as_ptr(), &view) };
+        if ok == 0 {
+            return Err(FfiError::InsertFailed {
+                table: self.table.to_string_lossy().into_owned(),
+            });
+        }
+        Ok(())
+    }
+}
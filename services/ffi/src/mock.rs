@@ -0,0 +1,437 @@
+//! In-memory stand-in for `libkadedb_c`, enabled by the `mock-storage` feature.
+//!
+//! It implements the same C entry points as the native library (the
+//! interface [`crate::Storage`] is written against), so every safe wrapper in
+//! this crate runs unchanged on top of it. It covers what the services
+//! exercise: CREATE TABLE, INSERT, UPDATE, DELETE and `SELECT * FROM <table>`, with the
+//! native layer's rendering (strings quoted, NULL as bare `null`).
+
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::Mutex;
+
+use crate::sys::{
+    KDB_Assignment, KDB_Predicate, KDB_RowView, KDB_TableColumnEx, KDB_TableSchema, KDB_Value,
+    KadeDB_ResultSet, KadeDB_Storage,
+};
+use crate::{ColumnType, Value};
+
+#[derive(Clone)]
+struct Column {
+    name: CString,
+    column_type: i32,
+    nullable: bool,
+}
+
+struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Default)]
+struct MockStorage {
+    tables: Mutex<HashMap<String, Table>>,
+}
+
+#[derive(Default)]
+struct MockSchema {
+    columns: Vec<Column>,
+}
+
+struct MockResultSet {
+    columns: Vec<Column>,
+    rows: Vec<Vec<Value>>,
+    /// Index of the current row; `None` before the first `NextRow`.
+    cursor: Option<usize>,
+    scratch: CString,
+}
+
+unsafe fn storage<'a>(raw: *mut KadeDB_Storage) -> Option<&'a MockStorage> {
+    (raw as *const MockStorage).as_ref()
+}
+
+unsafe fn result_set<'a>(raw: *mut KadeDB_ResultSet) -> Option<&'a mut MockResultSet> {
+    (raw as *mut MockResultSet).as_mut()
+}
+
+unsafe fn c_str(raw: *const i8) -> Option<String> {
+    if raw.is_null() {
+        return None;
+    }
+    CStr::from_ptr(raw).to_str().ok().map(str::to_string)
+}
+
+pub unsafe fn KadeDB_CreateStorage() -> *mut KadeDB_Storage {
+    Box::into_raw(Box::<MockStorage>::default()) as *mut KadeDB_Storage
+}
+
+pub unsafe fn KadeDB_DestroyStorage(storage: *mut KadeDB_Storage) {
+    if !storage.is_null() {
+        drop(Box::from_raw(storage as *mut MockStorage));
+    }
+}
+
+pub unsafe fn KadeDB_TableSchema_Create() -> *mut KDB_TableSchema {
+    Box::into_raw(Box::<MockSchema>::default()) as *mut KDB_TableSchema
+}
+
+pub unsafe fn KadeDB_TableSchema_Destroy(schema: *mut KDB_TableSchema) {
+    if !schema.is_null() {
+        drop(Box::from_raw(schema as *mut MockSchema));
+    }
+}
+
+pub unsafe fn KadeDB_TableSchema_AddColumn(
+    schema: *mut KDB_TableSchema,
+    column: *const KDB_TableColumnEx,
+) -> i32 {
+    let (Some(schema), Some(column)) = ((schema as *mut MockSchema).as_mut(), column.as_ref())
+    else {
+        return 0;
+    };
+    let Some(name) = c_str(column.name) else {
+        return 0;
+    };
+    if schema
+        .columns
+        .iter()
+        .any(|c| c.name.to_bytes() == name.as_bytes())
+    {
+        return 0;
+    }
+    schema.columns.push(Column {
+        name: CString::new(name).expect("name came from a C string"),
+        column_type: column.column_type,
+        nullable: column.nullable != 0,
+    });
+    1
+}
+
+pub unsafe fn KadeDB_CreateTable(
+    storage: *mut KadeDB_Storage,
+    table: *const i8,
+    schema: *const KDB_TableSchema,
+) -> i32 {
+    let (Some(storage), Some(table), Some(schema)) = (
+        self::storage(storage),
+        c_str(table),
+        (schema as *const MockSchema).as_ref(),
+    ) else {
+        return 0;
+    };
+    let mut tables = storage.tables.lock().expect("mock storage lock");
+    if tables.contains_key(&table) {
+        return 0;
+    }
+    tables.insert(
+        table,
+        Table {
+            columns: schema.columns.clone(),
+            rows: Vec::new(),
+        },
+    );
+    1
+}
+
+pub unsafe fn KadeDB_InsertRow(
+    storage: *mut KadeDB_Storage,
+    table: *const i8,
+    row: *const KDB_RowView,
+) -> i32 {
+    let (Some(storage), Some(table), Some(row)) =
+        (self::storage(storage), c_str(table), row.as_ref())
+    else {
+        return 0;
+    };
+    let raw = std::slice::from_raw_parts(row.values, row.count as usize);
+
+    let Some(values) = raw.iter().map(|v| value(v)).collect::<Option<Vec<_>>>() else {
+        return 0;
+    };
+
+    let mut tables = storage.tables.lock().expect("mock storage lock");
+    let Some(table) = tables.get_mut(&table) else {
+        return 0;
+    };
+    if values.len() != table.columns.len() {
+        return 0;
+    }
+    let fits = values.iter().zip(&table.columns).all(|(v, c)| match v {
+        Value::Null => c.nullable,
+        v => ColumnType::from_raw(c.column_type).is_some_and(|ty| v.matches(ty)),
+    });
+    if !fits {
+        return 0;
+    }
+    table.rows.push(values);
+    1
+}
+
+pub unsafe fn KadeDB_ListTables_ToCSV(
+    storage: *mut KadeDB_Storage,
+    delimiter: i8,
+    out_buf: *mut i8,
+    out_buf_len: u64,
+    out_required_len: *mut u64,
+) -> i32 {
+    let Some(storage) = self::storage(storage) else {
+        return 0;
+    };
+    let names: Vec<String> = storage
+        .tables
+        .lock()
+        .expect("mock storage lock")
+        .keys()
+        .cloned()
+        .collect();
+    let list = names.join(&(delimiter as u8 as char).to_string());
+    if let Some(required) = out_required_len.as_mut() {
+        *required = list.len() as u64 + 1;
+    }
+    if out_buf.is_null() || out_buf_len == 0 {
+        return 1;
+    }
+    let n = list.len().min(out_buf_len as usize - 1);
+    std::ptr::copy_nonoverlapping(list.as_ptr() as *const i8, out_buf, n);
+    *out_buf.add(n) = 0;
+    1
+}
+
+/// Mirrors the native parser: everything after `SELECT * FROM` is the table.
+fn parse_select_star_from(query: &str) -> Option<&str> {
+    let query = query.trim_start();
+    let prefix = "select * from ";
+    if query.len() < prefix.len() || !query[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let table = query[prefix.len()..].trim_end_matches([' ', '\t', '\n', '\r', ';']);
+    (!table.is_empty()).then_some(table)
+}
+
+pub unsafe fn KadeDB_ExecuteQuery(
+    storage: *mut KadeDB_Storage,
+    query: *const i8,
+) -> *mut KadeDB_ResultSet {
+    let (Some(storage), Some(query)) = (self::storage(storage), c_str(query)) else {
+        return std::ptr::null_mut();
+    };
+    let Some(table) = parse_select_star_from(&query) else {
+        return std::ptr::null_mut();
+    };
+    let tables = storage.tables.lock().expect("mock storage lock");
+    let Some(table) = tables.get(table) else {
+        return std::ptr::null_mut();
+    };
+    let rs = MockResultSet {
+        columns: table.columns.clone(),
+        rows: table.rows.clone(),
+        cursor: None,
+        scratch: CString::default(),
+    };
+    Box::into_raw(Box::new(rs)) as *mut KadeDB_ResultSet
+}
+
+pub unsafe fn KadeDB_ResultSet_NextRow(rs: *mut KadeDB_ResultSet) -> i32 {
+    let Some(rs) = result_set(rs) else {
+        return 0;
+    };
+    let next = rs.cursor.map_or(0, |c| c + 1);
+    if next < rs.rows.len() {
+        rs.cursor = Some(next);
+        1
+    } else {
+        0
+    }
+}
+
+pub unsafe fn KadeDB_ResultSet_ColumnCount(rs: *mut KadeDB_ResultSet) -> i32 {
+    result_set(rs).map_or(-1, |rs| rs.columns.len() as i32)
+}
+
+pub unsafe fn KadeDB_ResultSet_GetString(rs: *mut KadeDB_ResultSet, column: i32) -> *const i8 {
+    let Some(rs) = result_set(rs) else {
+        return std::ptr::null();
+    };
+    let cell = rs
+        .cursor
+        .and_then(|c| rs.rows.get(c))
+        .and_then(|row| row.get(usize::try_from(column).ok()?));
+    let Some(cell) = cell else {
+        return std::ptr::null();
+    };
+    let rendered = match cell {
+        Value::Null => "null".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::String(s) => format!("\"{s}\""),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(_) => unreachable!("cells are built from scalar KDB_Values"),
+    };
+    rs.scratch = CString::new(rendered).unwrap_or_default();
+    rs.scratch.as_ptr()
+}
+
+pub unsafe fn KadeDB_ResultSet_GetColumnName(rs: *mut KadeDB_ResultSet, column: i32) -> *const i8 {
+    result_set(rs)
+        .and_then(|rs| rs.columns.get(usize::try_from(column).ok()?))
+        .map_or(std::ptr::null(), |c| c.name.as_ptr())
+}
+
+pub unsafe fn KadeDB_ResultSet_GetColumnType(rs: *mut KadeDB_ResultSet, column: i32) -> i32 {
+    result_set(rs)
+        .and_then(|rs| rs.columns.get(usize::try_from(column).ok()?))
+        .map_or(-1, |c| c.column_type)
+}
+
+pub unsafe fn KadeDB_DestroyResultSet(rs: *mut KadeDB_ResultSet) {
+    if !rs.is_null() {
+        drop(Box::from_raw(rs as *mut MockResultSet));
+    }
+}
+
+/// A `KDB_Value` as a cell; `None` for an unknown type or a bad string.
+unsafe fn value(raw: &KDB_Value) -> Option<Value> {
+    Some(match raw.value_type {
+        0 => Value::Null,
+        1 => Value::Integer(raw.data.i64_),
+        2 => Value::Float(raw.data.f64_),
+        3 => Value::String(c_str(raw.data.str_)?),
+        4 => Value::Boolean(raw.data.boolean != 0),
+        _ => return None,
+    })
+}
+
+/// How `cell` compares with `rhs`; `None` when either is NULL or they are of
+/// different types, which no predicate matches.
+fn compare(cell: &Value, rhs: &Value) -> Option<Ordering> {
+    match (cell, rhs) {
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// The rows of `table` that `predicate` selects (all of them when it is
+/// null); `None` when it names no column of the table.
+unsafe fn selected(table: &Table, predicate: *const KDB_Predicate) -> Option<Vec<bool>> {
+    let Some(predicate) = predicate.as_ref() else {
+        return Some(vec![true; table.rows.len()]);
+    };
+    let column = c_str(predicate.column)?;
+    let index = table
+        .columns
+        .iter()
+        .position(|c| c.name.to_bytes() == column.as_bytes())?;
+    let rhs = value(&predicate.rhs)?;
+    Some(
+        table
+            .rows
+            .iter()
+            .map(|row| {
+                compare(&row[index], &rhs).is_some_and(|ord| match predicate.op {
+                    0 => ord.is_eq(),
+                    1 => ord.is_ne(),
+                    2 => ord.is_lt(),
+                    3 => ord.is_le(),
+                    4 => ord.is_gt(),
+                    5 => ord.is_ge(),
+                    _ => false,
+                })
+            })
+            .collect(),
+    )
+}
+
+pub unsafe fn KadeDB_UpdateRows(
+    storage: *mut KadeDB_Storage,
+    table: *const i8,
+    assignments: *const KDB_Assignment,
+    assignment_count: u64,
+    predicate: *const KDB_Predicate,
+    out_updated: *mut u64,
+) -> i32 {
+    let (Some(storage), Some(table)) = (self::storage(storage), c_str(table)) else {
+        return 0;
+    };
+    if assignments.is_null() || assignment_count == 0 {
+        return 0;
+    }
+    let mut tables = storage.tables.lock().expect("mock storage lock");
+    let Some(table) = tables.get_mut(&table) else {
+        return 0;
+    };
+    let index = |name: *const i8| {
+        let name = c_str(name)?;
+        table
+            .columns
+            .iter()
+            .position(|c| c.name.to_bytes() == name.as_bytes())
+    };
+    let mut sets = Vec::with_capacity(assignment_count as usize);
+    for assignment in std::slice::from_raw_parts(assignments, assignment_count as usize) {
+        let Some(target) = index(assignment.column) else {
+            return 0;
+        };
+        let source = if assignment.is_column_ref != 0 {
+            match index(assignment.column_ref) {
+                Some(source) => Err(source),
+                None => return 0,
+            }
+        } else {
+            match value(&assignment.constant) {
+                Some(value) => Ok(value),
+                None => return 0,
+            }
+        };
+        sets.push((target, source));
+    }
+    let Some(selected) = selected(table, predicate) else {
+        return 0;
+    };
+    let mut updated = 0;
+    for (row, _) in table.rows.iter_mut().zip(selected).filter(|(_, s)| *s) {
+        for (target, source) in &sets {
+            row[*target] = match source {
+                Ok(value) => value.clone(),
+                Err(source) => row[*source].clone(),
+            };
+        }
+        updated += 1;
+    }
+    if let Some(out) = out_updated.as_mut() {
+        *out = updated;
+    }
+    1
+}
+
+pub unsafe fn KadeDB_DeleteRows(
+    storage: *mut KadeDB_Storage,
+    table: *const i8,
+    predicate: *const KDB_Predicate,
+    out_deleted: *mut u64,
+) -> i32 {
+    let (Some(storage), Some(table)) = (self::storage(storage), c_str(table)) else {
+        return 0;
+    };
+    let mut tables = storage.tables.lock().expect("mock storage lock");
+    let Some(table) = tables.get_mut(&table) else {
+        return 0;
+    };
+    let Some(selected) = selected(table, predicate) else {
+        return 0;
+    };
+    let before = table.rows.len();
+    let mut selected = selected.into_iter();
+    table.rows.retain(|_| !selected.next().unwrap_or(false));
+    if let Some(deleted) = out_deleted.as_mut() {
+        *deleted = (before - table.rows.len()) as u64;
+    }
+    1
+}
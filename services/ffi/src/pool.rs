@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::breaker::{
+    Breaker, BreakerConfig, BreakerState, DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_FAILURES,
+    DEFAULT_BREAKER_WINDOW,
+};
+use crate::{
+    AllowedStatements, ColumnCase, FfiError, StatementCacheStats, StatementKind, Storage,
+    DEFAULT_MAX_QUERY_LENGTH, DEFAULT_STATEMENT_CACHE_SIZE,
+};
+
+/// Default number of concurrent handles a pool hands out.
+pub const DEFAULT_POOL_SIZE: usize = 16;
+
+/// Default number of callers that may wait for a slot at once.
+pub const DEFAULT_POOL_QUEUE_DEPTH: usize = 256;
+
+/// Default longest wait for a slot.
+pub const DEFAULT_POOL_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The storage a server opens at startup: a single pool, or one per tenant.
+pub enum ConfiguredPools {
+    Single(StoragePool),
+    Tenants(HashMap<String, StoragePool>),
+}
+
+impl ConfiguredPools {
+    /// Reads `KADEDB_TENANTS` (comma-separated tenant ids; unset means
+    /// single-tenant), `KADEDB_POOL_SIZE`, `KADEDB_POOL_QUEUE_DEPTH`,
+    /// `KADEDB_POOL_QUEUE_TIMEOUT_MS` (`0` lifts either queue bound),
+    /// `KADEDB_STATEMENT_CACHE_SIZE`, `KADEDB_MAX_QUERY_LENGTH` (bytes) and
+    /// `KADEDB_ALLOWED_STATEMENTS` (see [`AllowedStatements::from_env`]; an
+    /// invalid list allows nothing, and callers should fail startup on it
+    /// first) and
+    /// `KADEDB_COLUMN_CASE` (`preserve`, `lower` or `upper`), creating one
+    /// storage per tenant. Each pool gets its own circuit breaker from
+    /// `KADEDB_BREAKER_FAILURES` (`0` turns breakers off),
+    /// `KADEDB_BREAKER_WINDOW_MS` and `KADEDB_BREAKER_COOLDOWN_MS`.
+    pub fn from_env() -> Result<Self, FfiError> {
+        let pool_size = std::env::var("KADEDB_POOL_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_POOL_SIZE);
+        let cache_size = std::env::var("KADEDB_STATEMENT_CACHE_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_STATEMENT_CACHE_SIZE);
+        let queue_depth = std::env::var("KADEDB_POOL_QUEUE_DEPTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_POOL_QUEUE_DEPTH);
+        let queue_timeout = std::env::var("KADEDB_POOL_QUEUE_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_POOL_QUEUE_TIMEOUT);
+        let max_query_length = std::env::var("KADEDB_MAX_QUERY_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_MAX_QUERY_LENGTH);
+        let allowed_statements =
+            AllowedStatements::from_env().unwrap_or_else(|_| AllowedStatements::only([]));
+        let column_case = std::env::var("KADEDB_COLUMN_CASE")
+            .ok()
+            .and_then(|v| ColumnCase::parse(&v))
+            .unwrap_or_default();
+        let ms = |name: &str, default: Duration| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map_or(default, Duration::from_millis)
+        };
+        let breaker = std::env::var("KADEDB_BREAKER_FAILURES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(Some(DEFAULT_BREAKER_FAILURES), |n| (n > 0).then_some(n))
+            .map(|failures| BreakerConfig {
+                failures,
+                window: ms("KADEDB_BREAKER_WINDOW_MS", DEFAULT_BREAKER_WINDOW),
+                cooldown: ms("KADEDB_BREAKER_COOLDOWN_MS", DEFAULT_BREAKER_COOLDOWN),
+            });
+        let new_pool = || {
+            let pool = StoragePool::with_storage(
+                Arc::new(
+                    Storage::with_statement_cache(cache_size)?
+                        .with_max_query_length(max_query_length)
+                        .with_allowed_statements(allowed_statements.clone())
+                        .with_column_case(column_case),
+                ),
+                pool_size,
+            )
+            .with_queue(
+                Some(queue_depth).filter(|&d| d > 0),
+                Some(queue_timeout).filter(|t| !t.is_zero()),
+            );
+            Ok::<_, FfiError>(match breaker {
+                Some(config) => pool.with_circuit_breaker(config),
+                None => pool,
+            })
+        };
+
+        let tenants: Vec<String> = std::env::var("KADEDB_TENANTS")
+            .ok()
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if tenants.is_empty() {
+            return Ok(Self::Single(new_pool()?));
+        }
+
+        let pools = tenants
+            .into_iter()
+            .map(|t| Ok((t, new_pool()?)))
+            .collect::<Result<HashMap<_, _>, FfiError>>()?;
+        Ok(Self::Tenants(pools))
+    }
+}
+
+/// A shared storage instance with a bounded number of concurrent users.
+///
+/// The native storage is internally synchronized, so a "connection" here is a
+/// permit to use it rather than a separate handle; the bound keeps blocking
+/// FFI work from piling up on the runtime's blocking pool.
+///
+/// Callers that find every slot taken wait in a FIFO queue, so a burst is
+/// served in arrival order and no caller starves. [`StoragePool::with_queue`]
+/// bounds the queue's depth and each caller's wait; past either bound
+/// [`StoragePool::acquire`] fails with [`FfiError::PoolBusy`]. The depth is
+/// reported as the `pool_queue_depth` gauge and waits in the
+/// `pool_queue_wait_seconds` histogram; slots in use, summed over all pools,
+/// as `pool_connections_in_use`.
+///
+/// [`StoragePool::with_circuit_breaker`] adds a breaker that opens after
+/// repeated storage failures; while it is open `acquire` fails at once with
+/// [`FfiError::CircuitOpen`].
+#[derive(Clone)]
+pub struct StoragePool {
+    storage: Arc<Storage>,
+    permits: Arc<Semaphore>,
+    size: usize,
+    queued: Arc<AtomicUsize>,
+    max_queued: Option<usize>,
+    max_wait: Option<Duration>,
+    replica: Option<Arc<StoragePool>>,
+    breaker: Option<Arc<Breaker>>,
+}
+
+impl StoragePool {
+    pub fn new(size: usize) -> Result<Self, FfiError> {
+        Ok(Self::with_storage(Arc::new(Storage::new()?), size))
+    }
+
+    pub fn with_storage(storage: Arc<Storage>, size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            storage,
+            permits: Arc::new(Semaphore::new(size)),
+            size,
+            queued: Arc::new(AtomicUsize::new(0)),
+            max_queued: None,
+            max_wait: None,
+            replica: None,
+            breaker: None,
+        }
+    }
+
+    /// Bounds the wait for a slot: at most `max_queued` callers queue, each
+    /// for at most `max_wait`. `None` leaves that bound off (the default for
+    /// both).
+    pub fn with_queue(mut self, max_queued: Option<usize>, max_wait: Option<Duration>) -> Self {
+        self.max_queued = max_queued;
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Opens a circuit breaker after `config.failures` consecutive storage
+    /// failures ([`PoolGuard::record_failure`]) within `config.window`.
+    /// While open, [`StoragePool::acquire`] fails with
+    /// [`FfiError::CircuitOpen`]; after `config.cooldown` one trial call is
+    /// let through, and its outcome closes or reopens the breaker. Open
+    /// breakers are counted in the `circuit_breakers_open` gauge.
+    pub fn with_circuit_breaker(mut self, config: BreakerConfig) -> Self {
+        self.breaker = Some(Arc::new(Breaker::new(config)));
+        self
+    }
+
+    /// The breaker's state; [`BreakerState::Closed`] without one.
+    pub fn circuit(&self) -> BreakerState {
+        self.breaker
+            .as_ref()
+            .map_or(BreakerState::Closed, |breaker| breaker.state())
+    }
+
+    /// Sends reads routed with [`StoragePool::route`] to `replica`, a pool
+    /// over a read replica of this pool's storage. The replica may lag, so
+    /// callers that must see their own writes route with `strong`.
+    pub fn with_read_replica(mut self, replica: StoragePool) -> Self {
+        self.replica = Some(Arc::new(replica));
+        self
+    }
+
+    pub fn read_replica(&self) -> Option<&StoragePool> {
+        self.replica.as_deref()
+    }
+
+    /// The pool to run `sql` on: the read replica for a SELECT (including
+    /// `WITH` queries) unless `strong` is set, this pool for everything
+    /// else and when there is no replica.
+    pub fn route(&self, sql: &str, strong: bool) -> &StoragePool {
+        match &self.replica {
+            Some(replica) if !strong && StatementKind::of(sql) == StatementKind::Select => replica,
+            _ => self,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Slots currently handed out.
+    pub fn in_use(&self) -> usize {
+        self.size - self.permits.available_permits()
+    }
+
+    /// Callers currently waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// The statement cache of the pool's storage, shared by all its slots.
+    pub fn statement_cache_stats(&self) -> StatementCacheStats {
+        self.storage.statement_cache_stats()
+    }
+
+    /// Waits for a free slot and returns a guard giving access to storage.
+    ///
+    /// Fails with [`FfiError::PoolBusy`] when the queue is full or the wait
+    /// runs past the pool's limit, and with [`FfiError::CircuitOpen`] while
+    /// the breaker is open.
+    pub async fn acquire(&self) -> Result<PoolGuard, FfiError> {
+        let trial = match &self.breaker {
+            Some(breaker) => breaker.admit()?,
+            None => false,
+        };
+        let guard = self.acquire_slot().await;
+        match (&guard, &self.breaker) {
+            (Ok(guard), _) => guard.trial.store(trial, Ordering::Relaxed),
+            (Err(_), Some(breaker)) if trial => breaker.abandon(),
+            _ => {}
+        }
+        guard
+    }
+
+    async fn acquire_slot(&self) -> Result<PoolGuard, FfiError> {
+        // The semaphore hands released permits to waiters in order, so this
+        // only succeeds when nobody is queued.
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(self.guard(permit));
+        }
+
+        let queued = Queued::enter(&self.queued);
+        if self.max_queued.is_some_and(|max| queued.depth > max) {
+            metrics::counter!("pool_queue_rejections_total", "reason" => "full").increment(1);
+            return Err(FfiError::PoolBusy);
+        }
+
+        let started = Instant::now();
+        let permit = self.permits.clone().acquire_owned();
+        let permit = match self.max_wait {
+            Some(max_wait) => tokio::time::timeout(max_wait, permit).await.ok(),
+            None => Some(permit.await),
+        };
+        drop(queued);
+        metrics::histogram!("pool_queue_wait_seconds").record(started.elapsed().as_secs_f64());
+
+        match permit {
+            Some(permit) => Ok(self.guard(permit.expect("pool semaphore closed"))),
+            None => {
+                metrics::counter!("pool_queue_rejections_total", "reason" => "timeout")
+                    .increment(1);
+                Err(FfiError::PoolBusy)
+            }
+        }
+    }
+
+    fn guard(&self, permit: OwnedSemaphorePermit) -> PoolGuard {
+        metrics::gauge!("pool_connections_in_use").increment(1.0);
+        PoolGuard {
+            storage: self.storage.clone(),
+            _permit: permit,
+            breaker: self.breaker.clone(),
+            trial: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+        }
+    }
+}
+
+/// One caller in the wait queue; leaves it on drop, including when the
+/// request is abandoned mid-wait.
+struct Queued<'a> {
+    queued: &'a AtomicUsize,
+    /// Queue depth counting this caller.
+    depth: usize,
+}
+
+impl<'a> Queued<'a> {
+    fn enter(queued: &'a AtomicUsize) -> Self {
+        let depth = queued.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics::gauge!("pool_queue_depth").set(depth as f64);
+        Self { queued, depth }
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let depth = self.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+        metrics::gauge!("pool_queue_depth").set(depth as f64);
+    }
+}
+
+/// Access to pooled storage; the slot is released on drop.
+///
+/// With a circuit breaker, dropping the guard records the call as a success
+/// unless [`PoolGuard::record_failure`] was called or the thread is
+/// panicking.
+pub struct PoolGuard {
+    storage: Arc<Storage>,
+    _permit: OwnedSemaphorePermit,
+    breaker: Option<Arc<Breaker>>,
+    trial: AtomicBool,
+    failed: AtomicBool,
+}
+
+impl PoolGuard {
+    /// An owned handle for moving into blocking tasks. Keep the guard alive
+    /// until the task completes so the slot stays accounted for.
+    pub fn storage(&self) -> Arc<Storage> {
+        self.storage.clone()
+    }
+
+    /// Counts the call towards the pool's circuit breaker as a storage
+    /// failure. Only failures of storage itself belong here (see
+    /// [`FfiError::is_storage_failure`]), not rejected queries or rows.
+    pub fn record_failure(&self) {
+        self.failed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for PoolGuard {
+    fn drop(&mut self) {
+        if let Some(breaker) = &self.breaker {
+            let failed = self.failed.load(Ordering::Relaxed) || std::thread::panicking();
+            breaker.record(self.trial.load(Ordering::Relaxed), failed);
+        }
+        metrics::gauge!("pool_connections_in_use").decrement(1.0);
+    }
+}
+
+impl Deref for PoolGuard {
+    type Target = Storage;
+
+    fn deref(&self) -> &Storage {
+        &self.storage
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{ColumnType, FfiError, Value};
+
+/// A query with `?` placeholders, ready to be bound and executed.
+///
+/// The native layer has no prepare/bind entry points, so binding renders each
+/// parameter as a properly escaped SQL literal. Placeholders inside string
+/// literals, quoted identifiers and comments are not counted.
+///
+/// A [`Value::Array`] parameter expands to its elements as a comma-separated
+/// list, so `id IN (?)` takes any number of ids through one placeholder.
+/// The array must be non-empty, hold no arrays, and its non-NULL elements
+/// must share one type.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    sql: String,
+    /// Byte offsets of each `?` in `sql`.
+    placeholders: Vec<usize>,
+    /// Expected type of each placeholder, when known.
+    param_types: Option<Vec<ColumnType>>,
+    /// A SELECT without a LIMIT clause.
+    unbounded_select: bool,
+    kind: StatementKind,
+}
+
+/// The kind of a statement, from its leading keyword. A small fixed set, so
+/// it can label metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementKind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// `CREATE`, `ALTER`, `DROP` and `TRUNCATE`.
+    Ddl,
+    Other,
+}
+
+impl StatementKind {
+    /// The kind of `sql`, ignoring leading comments.
+    pub fn of(sql: &str) -> Self {
+        let mut words = Vec::new();
+        scan(sql, |i, b| words_push(&mut words, sql, i, b));
+        Self::from_keyword(words.first().copied())
+    }
+
+    fn from_keyword(word: Option<&str>) -> Self {
+        let Some(word) = word else {
+            return Self::Other;
+        };
+        match word.to_ascii_lowercase().as_str() {
+            "select" | "with" => Self::Select,
+            "insert" => Self::Insert,
+            "update" => Self::Update,
+            "delete" => Self::Delete,
+            "create" | "alter" | "drop" | "truncate" => Self::Ddl,
+            _ => Self::Other,
+        }
+    }
+
+    /// The metric label: `select`, `insert`, `update`, `delete`, `ddl` or
+    /// `other`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Select => "select",
+            Self::Insert => "insert",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::Ddl => "ddl",
+            Self::Other => "other",
+        }
+    }
+
+    /// The kind named by [`StatementKind::as_str`], ignoring case.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "select" => Some(Self::Select),
+            "insert" => Some(Self::Insert),
+            "update" => Some(Self::Update),
+            "delete" => Some(Self::Delete),
+            "ddl" => Some(Self::Ddl),
+            "other" => Some(Self::Other),
+            _ => None,
+        }
+    }
+}
+
+/// Which [`StatementKind`]s a storage executes. The default allows every
+/// kind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AllowedStatements(Option<Vec<StatementKind>>);
+
+impl AllowedStatements {
+    /// Allows only `kinds`.
+    pub fn only(kinds: impl IntoIterator<Item = StatementKind>) -> Self {
+        Self(Some(kinds.into_iter().collect()))
+    }
+
+    /// Parses a comma-separated list of kind names, e.g. `select,insert`;
+    /// a blank list allows all. Fails on the first unknown name.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let names: Vec<&str> = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .collect();
+        if names.is_empty() {
+            return Ok(Self::default());
+        }
+        names
+            .into_iter()
+            .map(|name| {
+                StatementKind::parse(name).ok_or_else(|| format!("unknown statement kind `{name}`"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self::only)
+    }
+
+    /// Reads `KADEDB_ALLOWED_STATEMENTS` (see [`AllowedStatements::parse`]);
+    /// unset allows all.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("KADEDB_ALLOWED_STATEMENTS") {
+            Ok(v) => Self::parse(&v).map_err(|err| format!("KADEDB_ALLOWED_STATEMENTS: {err}")),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn permits(&self, kind: StatementKind) -> bool {
+        self.0.as_ref().is_none_or(|kinds| kinds.contains(&kind))
+    }
+
+    /// Fails with [`FfiError::StatementNotAllowed`] unless `sql`'s kind is
+    /// allowed.
+    pub fn check(&self, sql: &str) -> Result<(), FfiError> {
+        self.check_kind(StatementKind::of(sql))
+    }
+
+    /// Like [`AllowedStatements::check`], for a write that doesn't go
+    /// through SQL, such as a row insert or a table created from JSON.
+    pub fn check_kind(&self, kind: StatementKind) -> Result<(), FfiError> {
+        if self.permits(kind) {
+            Ok(())
+        } else {
+            Err(FfiError::StatementNotAllowed(kind))
+        }
+    }
+}
+
+impl Statement {
+    pub fn new(sql: &str) -> Self {
+        let mut placeholders = Vec::new();
+        let mut words = Vec::new();
+        scan(sql, |i, b| {
+            if b == b'?' {
+                placeholders.push(i);
+            }
+            words_push(&mut words, sql, i, b);
+        });
+        let unbounded_select = words
+            .first()
+            .is_some_and(|w| w.eq_ignore_ascii_case("select"))
+            && !words.iter().any(|w| w.eq_ignore_ascii_case("limit"));
+        Self {
+            sql: sql.to_string(),
+            placeholders,
+            param_types: None,
+            unbounded_select,
+            kind: StatementKind::from_keyword(words.first().copied()),
+        }
+    }
+
+    /// Declares the expected type of each placeholder, in order, so `bind`
+    /// rejects mismatched values up front instead of leaving the engine to
+    /// fail on them. NULL binds to any type.
+    ///
+    /// The native layer has no prepare step that reports parameter types, so
+    /// they must come from the caller (e.g. from a table's column layout).
+    /// Without them only the parameter count is checked.
+    pub fn with_param_types(mut self, types: Vec<ColumnType>) -> Self {
+        self.param_types = Some(types);
+        self
+    }
+
+    pub fn param_types(&self) -> Option<&[ColumnType]> {
+        self.param_types.as_deref()
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    pub fn param_count(&self) -> usize {
+        self.placeholders.len()
+    }
+
+    /// Whether this is a SELECT with no `LIMIT` clause. Keywords inside
+    /// literals, quoted identifiers and comments don't count.
+    pub fn is_unbounded_select(&self) -> bool {
+        self.unbounded_select
+    }
+
+    pub fn kind(&self) -> StatementKind {
+        self.kind
+    }
+
+    /// Renders the statement with `params` substituted for its placeholders.
+    pub fn bind(&self, params: &[Value]) -> Result<String, FfiError> {
+        if params.len() != self.placeholders.len() {
+            return Err(FfiError::ParamCountMismatch {
+                expected: self.placeholders.len(),
+                got: params.len(),
+            });
+        }
+        if let Some(types) = &self.param_types {
+            for (index, (param, &expected)) in params.iter().zip(types).enumerate() {
+                let fits = match param {
+                    Value::Array(items) => items.iter().all(|item| item.matches(expected)),
+                    param => param.matches(expected),
+                };
+                if !fits {
+                    return Err(FfiError::ParamTypeMismatch {
+                        index,
+                        expected,
+                        got: param.column_type(),
+                    });
+                }
+            }
+        }
+
+        let mut out = String::with_capacity(self.sql.len() + params.len() * 8);
+        let mut last = 0;
+        for (index, (&pos, param)) in self.placeholders.iter().zip(params).enumerate() {
+            out.push_str(&self.sql[last..pos]);
+            push_literal(&mut out, index, param)?;
+            last = pos + 1;
+        }
+        out.push_str(&self.sql[last..]);
+        Ok(out)
+    }
+}
+
+fn push_literal(out: &mut String, index: usize, value: &Value) -> Result<(), FfiError> {
+    match value {
+        Value::Null => out.push_str("NULL"),
+        Value::Integer(i) => out.push_str(&i.to_string()),
+        Value::Float(f) if f.is_finite() => out.push_str(&format!("{f:?}")),
+        Value::Float(_) => {
+            return Err(FfiError::InvalidParam {
+                index,
+                reason: "non-finite float".to_string(),
+            })
+        }
+        Value::String(s) => {
+            if s.contains('\0') {
+                return Err(FfiError::InvalidParam {
+                    index,
+                    reason: "string contains NUL byte".to_string(),
+                });
+            }
+            out.push('\'');
+            out.push_str(&s.replace('\'', "''"));
+            out.push('\'');
+        }
+        Value::Boolean(b) => out.push_str(if *b { "TRUE" } else { "FALSE" }),
+        Value::Array(items) => {
+            check_array(index, items)?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                push_literal(out, index, item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rejects arrays that can't bind as an `IN` list: empty ones (`IN ()` is
+/// not SQL), nested ones, and ones mixing element types.
+fn check_array(index: usize, items: &[Value]) -> Result<(), FfiError> {
+    let invalid = |reason: &str| {
+        Err(FfiError::InvalidParam {
+            index,
+            reason: reason.to_string(),
+        })
+    };
+    if items.is_empty() {
+        return invalid("array is empty");
+    }
+    if items.iter().any(|item| matches!(item, Value::Array(_))) {
+        return invalid("arrays can't nest");
+    }
+    let mut types = items
+        .iter()
+        .map(Value::column_type)
+        .filter(|&ty| ty != ColumnType::Null);
+    let first = types.next().unwrap_or(ColumnType::Null);
+    if let Some(other) = types.find(|&ty| ty != first) {
+        return invalid(&format!(
+            "array mixes {} and {} elements",
+            first.name(),
+            other.name()
+        ));
+    }
+    Ok(())
+}
+
+/// Appends the word (`[A-Za-z0-9_]+`) ending at byte `i` of `sql` to `words`,
+/// when `b` is the first byte after it. Fed every code byte by [`scan`].
+fn words_push<'a>(words: &mut Vec<&'a str>, sql: &'a str, i: usize, b: u8) {
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    if is_word(b) && (i + 1 == sql.len() || !is_word(sql.as_bytes()[i + 1])) {
+        let start = sql.as_bytes()[..i]
+            .iter()
+            .rposition(|&b| !is_word(b))
+            .map_or(0, |p| p + 1);
+        words.push(&sql[start..=i]);
+    }
+}
+
+/// Calls `code` with each byte of `sql` outside string literals, quoted
+/// identifiers and comments, and its offset.
+fn scan(sql: &str, mut code: impl FnMut(usize, u8)) {
+    let bytes = sql.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'\'' | b'"') => {
+                // Skip to the closing quote; a doubled quote is an escape.
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == quote {
+                        if bytes.get(i + 1) == Some(&quote) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            b => code(i, b),
+        }
+        i += 1;
+    }
+}
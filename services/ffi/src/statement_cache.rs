@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::Statement;
+
+/// Default number of parsed statements kept per storage.
+pub const DEFAULT_STATEMENT_CACHE_SIZE: usize = 256;
+
+/// Parsed statements by SQL text, least-recently-used first out.
+///
+/// The native layer has no prepared statements, so there is no engine-side
+/// handle to finalize on eviction; evicting drops the parsed statement once
+/// the last in-flight query using it finishes. Lookups are counted in
+/// `statement_cache_hits_total` and `statement_cache_misses_total`,
+/// evictions in `statement_cache_evictions_total`, and per cache in
+/// [`StatementCacheStats`].
+pub(crate) struct StatementCache {
+    capacity: usize,
+    inner: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A statement cache's size and counts since the storage was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatementCacheStats {
+    /// Statements the cache holds at most; 0 when caching is off.
+    pub capacity: usize,
+    /// Statements held now.
+    pub len: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl StatementCacheStats {
+    /// `hits / (hits + misses)`, or `None` before the first lookup. A low
+    /// rate means few statements repeat; such workloads are better served
+    /// by query templates.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    statements: HashMap<String, (Arc<Statement>, u64)>,
+    /// Bumped on every access; an entry's stamp is its last use.
+    clock: u64,
+}
+
+impl StatementCache {
+    /// A cache holding up to `capacity` statements; 0 disables caching.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> StatementCacheStats {
+        StatementCacheStats {
+            capacity: self.capacity,
+            len: self
+                .inner
+                .lock()
+                .expect("statement cache lock")
+                .statements
+                .len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn get_or_prepare(&self, sql: &str) -> Arc<Statement> {
+        if self.capacity == 0 {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            metrics::counter!("statement_cache_misses_total").increment(1);
+            return Arc::new(Statement::new(sql));
+        }
+        let mut inner = self.inner.lock().expect("statement cache lock");
+        inner.clock += 1;
+        let now = inner.clock;
+        if let Some((statement, used)) = inner.statements.get_mut(sql) {
+            *used = now;
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            metrics::counter!("statement_cache_hits_total").increment(1);
+            return statement.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        metrics::counter!("statement_cache_misses_total").increment(1);
+
+        if inner.statements.len() >= self.capacity {
+            let oldest = inner
+                .statements
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(sql, _)| sql.clone());
+            if let Some(oldest) = oldest {
+                inner.statements.remove(&oldest);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+                metrics::counter!("statement_cache_evictions_total").increment(1);
+            }
+        }
+        let statement = Arc::new(Statement::new(sql));
+        inner
+            .statements
+            .insert(sql.to_string(), (statement.clone(), now));
+        statement
+    }
+}
@@ -0,0 +1,212 @@
+//! gRPC status mapping, enabled by the `tonic` feature.
+
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use prost::Message;
+use tonic::{metadata::MetadataValue, Code, Status};
+
+use crate::{ErrorCode, FfiError};
+
+/// How long clients are asked to wait before retrying a transient failure.
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Type URL of `google.rpc.RetryInfo` in status details.
+const RETRY_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+/// Type URL of `google.rpc.ErrorInfo` in status details.
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+
+/// `ErrorInfo.domain` of the details this crate writes.
+const ERROR_DOMAIN: &str = "kadedb";
+
+/// Sorts errors by whether a client may retry them:
+///
+/// - transient conditions ([`FfiError::is_transient`]) are `unavailable`,
+///   carrying a `retry-after` (seconds) trailer and a `google.rpc.RetryInfo`
+///   detail;
+/// - malformed queries and parameters are `invalid_argument`, and rows that
+///   break the table's constraints, or a server built without storage, are
+///   `failed_precondition`, neither worth retrying unchanged;
+/// - statements of a kind the server doesn't allow are `permission_denied`;
+/// - failures the caller can't fix are `internal`.
+///
+/// Every status also carries the error's [`ErrorCode`] as a
+/// `google.rpc.ErrorInfo` detail; see [`status_code`]. The error itself is
+/// kept as the status's source, for [`minimal_status`].
+impl From<FfiError> for Status {
+    fn from(err: FfiError) -> Self {
+        let message = err.to_string();
+        let error = ErrorCode::from(&err);
+        if err.is_transient() {
+            let mut status = with_details(Code::Unavailable, error, message, Some(RETRY_DELAY));
+            status.set_source(Arc::new(err));
+            return status;
+        }
+        let code = match err {
+            FfiError::ExecuteQueryFailed
+            | FfiError::ParamCountMismatch { .. }
+            | FfiError::InvalidParam { .. }
+            | FfiError::ParamTypeMismatch { .. }
+            | FfiError::QueryTooLong { .. } => Code::InvalidArgument,
+            FfiError::ArityMismatch { .. }
+            | FfiError::TypeMismatch { .. }
+            | FfiError::InsertFailed { .. }
+            | FfiError::DeleteFailed { .. }
+            | FfiError::UpdateFailed { .. }
+            | FfiError::NativeUnavailable => Code::FailedPrecondition,
+            FfiError::UnknownTable(_) => Code::NotFound,
+            FfiError::StatementNotAllowed(_) => Code::PermissionDenied,
+            FfiError::Timeout => Code::DeadlineExceeded,
+            FfiError::Cancelled => Code::Cancelled,
+            FfiError::CreateStorageFailed | FfiError::PoolBusy | FfiError::CircuitOpen => {
+                Code::Unavailable
+            }
+            FfiError::CreateTableFailed(_)
+            | FfiError::ListTablesFailed
+            | FfiError::Utf8(_)
+            | FfiError::Nul(_) => Code::Internal,
+        };
+        let mut status = with_details(code, error, message, None);
+        status.set_source(Arc::new(err));
+        status
+    }
+}
+
+/// `status` as [`ErrorVerbosity::Minimal`](crate::ErrorVerbosity::Minimal)
+/// sends it, when it was made from an [`FfiError`] whose message describes
+/// storage: the same code and details, with a generic message naming
+/// `correlation_id`. `None` when the status can go out as it is.
+pub fn minimal_status(status: &Status, correlation_id: &str) -> Option<Status> {
+    let err = status.source()?.downcast_ref::<FfiError>()?;
+    if !err.exposes_internals() {
+        return None;
+    }
+    let message = format!(
+        "{} (correlation id {correlation_id})",
+        status.code().description().to_lowercase()
+    );
+    let retry = err.is_transient().then_some(RETRY_DELAY);
+    Some(with_details(
+        status.code(),
+        ErrorCode::from(err),
+        message,
+        retry,
+    ))
+}
+
+/// A status whose details carry `error`, for failures that don't come from
+/// an [`FfiError`] (auth, shutdown).
+pub fn coded_status(code: Code, error: ErrorCode, message: impl Into<String>) -> Status {
+    with_details(code, error, message.into(), None)
+}
+
+/// The [`ErrorCode`] a status carries in its details, or failing that the
+/// nearest one to its gRPC code.
+pub fn status_code(status: &Status) -> ErrorCode {
+    RpcStatus::decode(status.details())
+        .ok()
+        .into_iter()
+        .flat_map(|details| details.details)
+        .filter(|any| any.type_url == ERROR_INFO_TYPE_URL)
+        .filter_map(|any| ErrorInfo::decode(any.value.as_slice()).ok())
+        .find(|info| info.domain == ERROR_DOMAIN)
+        .and_then(|info| ErrorCode::parse(&info.reason))
+        .unwrap_or(match status.code() {
+            Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+                ErrorCode::InvalidRequest
+            }
+            Code::Unauthenticated => ErrorCode::Unauthenticated,
+            Code::PermissionDenied => ErrorCode::PermissionDenied,
+            Code::NotFound => ErrorCode::NotFound,
+            Code::AlreadyExists | Code::Aborted => ErrorCode::Conflict,
+            Code::DeadlineExceeded => ErrorCode::Timeout,
+            Code::Cancelled => ErrorCode::Cancelled,
+            Code::ResourceExhausted => ErrorCode::Overloaded,
+            Code::Unavailable => ErrorCode::Unavailable,
+            _ => ErrorCode::Internal,
+        })
+}
+
+/// A status carrying `error`, and when `retry` is set telling the client
+/// to retry after that long.
+fn with_details(code: Code, error: ErrorCode, message: String, retry: Option<Duration>) -> Status {
+    let mut details = vec![Any {
+        type_url: ERROR_INFO_TYPE_URL.to_string(),
+        value: ErrorInfo {
+            reason: error.as_str().to_string(),
+            domain: ERROR_DOMAIN.to_string(),
+        }
+        .encode_to_vec(),
+    }];
+    if let Some(delay) = retry {
+        details.push(Any {
+            type_url: RETRY_INFO_TYPE_URL.to_string(),
+            value: RetryInfo {
+                retry_delay: Some(ProtoDuration {
+                    seconds: delay.as_secs() as i64,
+                    nanos: delay.subsec_nanos() as i32,
+                }),
+            }
+            .encode_to_vec(),
+        });
+    }
+    let details = RpcStatus {
+        code: code as i32,
+        message: message.clone(),
+        details,
+    };
+    let mut status = Status::with_details(code, message, details.encode_to_vec().into());
+    if let Some(delay) = retry {
+        status
+            .metadata_mut()
+            .insert("retry-after", MetadataValue::from(delay.as_secs().max(1)));
+    }
+    status
+}
+
+/// `google.rpc.Status`, the payload of `grpc-status-details-bin`.
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<Any>,
+}
+
+/// `google.protobuf.Any`
+#[derive(Clone, PartialEq, Message)]
+struct Any {
+    #[prost(string, tag = "1")]
+    type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    value: Vec<u8>,
+}
+
+/// `google.rpc.ErrorInfo`, without its `metadata` map.
+#[derive(Clone, PartialEq, Message)]
+struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    reason: String,
+    #[prost(string, tag = "2")]
+    domain: String,
+}
+
+/// `google.rpc.RetryInfo`
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<ProtoDuration>,
+}
+
+/// `google.protobuf.Duration`
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
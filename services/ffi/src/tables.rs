@@ -0,0 +1,218 @@
+/// Words that can follow a table name but are never its alias.
+const RESERVED: &[&str] = &[
+    "as",
+    "cross",
+    "except",
+    "fetch",
+    "for",
+    "from",
+    "full",
+    "group",
+    "having",
+    "inner",
+    "intersect",
+    "join",
+    "left",
+    "limit",
+    "natural",
+    "of",
+    "offset",
+    "on",
+    "order",
+    "outer",
+    "returning",
+    "right",
+    "select",
+    "set",
+    "union",
+    "using",
+    "values",
+    "where",
+    "window",
+];
+
+/// Words that introduce a table name.
+const INTRODUCERS: &[&str] = &["from", "join", "into", "update", "table"];
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Quoted(String),
+    Dot,
+    Comma,
+    Open,
+    Other,
+}
+
+impl Token<'_> {
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self, Token::Word(w) if w.eq_ignore_ascii_case(word))
+    }
+
+    /// An identifier: a quoted one, or a word that isn't reserved.
+    fn ident(&self) -> Option<String> {
+        match self {
+            Token::Word(w) if !RESERVED.iter().any(|r| w.eq_ignore_ascii_case(r)) => {
+                Some(w.to_ascii_lowercase())
+            }
+            Token::Quoted(name) => Some(name.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The tables `sql` names: each name following `FROM` (including the rest of
+/// a comma-separated list), `JOIN`, `INTO`, `UPDATE` and `TABLE`, in order.
+/// Schema-qualified names keep their qualifier (`audit.log`); aliases,
+/// subqueries and table functions are skipped. Unquoted names are
+/// lowercased, quoted ones kept as written.
+///
+/// This is a scan, not a parse, so it errs towards finding too much: a
+/// column named after a table in `EXTRACT(YEAR FROM col)` is listed too.
+pub fn referenced_tables(sql: &str) -> Vec<String> {
+    let tokens = tokenize(sql);
+    let mut tables = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let Some(introducer) = INTRODUCERS.iter().find(|w| tokens[i].is_word(w)) else {
+            i += 1;
+            continue;
+        };
+        i += 1;
+        while ["if", "not", "exists", "only"]
+            .iter()
+            .any(|w| tokens.get(i).is_some_and(|t| t.is_word(w)))
+        {
+            i += 1;
+        }
+        while let Some(mut name) = tokens.get(i).and_then(Token::ident) {
+            i += 1;
+            while tokens.get(i) == Some(&Token::Dot) {
+                let Some(part) = tokens.get(i + 1).and_then(Token::ident) else {
+                    break;
+                };
+                name = format!("{name}.{part}");
+                i += 2;
+            }
+            let function = tokens.get(i) == Some(&Token::Open);
+            if !(function && matches!(*introducer, "from" | "join")) {
+                tables.push(name);
+            }
+            if *introducer != "from" {
+                break;
+            }
+            if tokens.get(i).is_some_and(|t| t.is_word("as")) {
+                i += 1;
+            }
+            if tokens.get(i).and_then(Token::ident).is_some() {
+                i += 1;
+            }
+            if tokens.get(i) != Some(&Token::Comma) {
+                break;
+            }
+            i += 1;
+        }
+    }
+    tables
+}
+
+/// Splits `sql` into words, quoted identifiers and the punctuation
+/// [`referenced_tables`] needs, dropping string literals and comments.
+fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'$';
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b if b.is_ascii_whitespace() => i += 1,
+            quote @ (b'\'' | b'"' | b'`') => {
+                // A doubled quote is an escape.
+                let mut text = Vec::new();
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == quote {
+                        if bytes.get(i + 1) == Some(&quote) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    text.push(bytes[i]);
+                    i += 1;
+                }
+                i += 1;
+                tokens.push(match quote {
+                    b'\'' => Token::Other,
+                    _ => Token::Quoted(String::from_utf8_lossy(&text).into_owned()),
+                });
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            b if is_word(b) => {
+                let start = i;
+                while i < bytes.len() && is_word(bytes[i]) {
+                    i += 1;
+                }
+                tokens.push(Token::Word(&sql[start..i]));
+            }
+            b => {
+                tokens.push(match b {
+                    b'.' => Token::Dot,
+                    b',' => Token::Comma,
+                    b'(' => Token::Open,
+                    _ => Token::Other,
+                });
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Tables that only admins may query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestrictedTables(Vec<String>);
+
+impl RestrictedTables {
+    /// Parses a comma-separated list of table names, e.g.
+    /// `audit_log,system.catalog`. Names are matched ignoring case.
+    pub fn parse(spec: &str) -> Self {
+        Self(
+            spec.split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The first restricted table `sql` references, if any. An unqualified
+    /// entry matches the table in any schema; a qualified one only in its
+    /// own.
+    pub fn find_in(&self, sql: &str) -> Option<String> {
+        if self.0.is_empty() {
+            return None;
+        }
+        referenced_tables(sql).into_iter().find(|table| {
+            let table = table.to_ascii_lowercase();
+            let unqualified = table.rsplit('.').next().unwrap_or(&table);
+            self.0
+                .iter()
+                .any(|entry| *entry == table || (!entry.contains('.') && entry == unqualified))
+        })
+    }
+}
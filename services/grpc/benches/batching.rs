@@ -0,0 +1,167 @@
+//! Throughput of a narrow result streamed one row per message (`Query`)
+//! against `RowBatch`es of increasing size (`QueryBatched`), over a loopback
+//! connection.
+//!
+//! The service here replays precomputed rows, so what is measured is the
+//! per-message cost of tonic and HTTP/2 (encoding, framing, wakeups) rather
+//! than the storage engine. Wire sizes are printed once per batch size before
+//! timing.
+//!
+//! Run with `cargo bench -p kadedb-services-grpc --bench batching`.
+
+use std::pin::Pin;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kadedb_services_grpc::kadedb::{
+    query_service_client::QueryServiceClient,
+    query_service_server::{QueryService, QueryServiceServer},
+    InsertRequest, InsertSummary, QueryRequest, QueryResult, QueryRow, QuerySchema, RowBatch,
+};
+use prost::Message;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{transport::Channel, Request, Response, Status, Streaming};
+
+const ROWS: usize = 10_000;
+
+const BATCH_SIZES: [usize; 5] = [1, 16, 64, 256, 1024];
+
+fn narrow_rows() -> Vec<QueryRow> {
+    (0..ROWS)
+        .map(|i| QueryRow {
+            json: serde_json::json!({"id": i, "ok": i % 2 == 0}).to_string(),
+            progress: None,
+        })
+        .collect()
+}
+
+/// Streams [`narrow_rows`] for every call.
+struct Replay {
+    rows: Vec<QueryRow>,
+}
+
+type Rows<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl QueryService for Replay {
+    type QueryStream = Rows<QueryRow>;
+    type QueryBatchedStream = Rows<RowBatch>;
+
+    async fn query(&self, _: Request<QueryRequest>) -> Result<Response<Self::QueryStream>, Status> {
+        let rows = self.rows.clone();
+        Ok(Response::new(Box::pin(tokio_stream::iter(rows).map(Ok))))
+    }
+
+    async fn query_batched(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<Self::QueryBatchedStream>, Status> {
+        let size = request.get_ref().batch_size as usize;
+        let batches: Vec<RowBatch> = self
+            .rows
+            .chunks(size)
+            .map(|chunk| RowBatch {
+                rows: chunk.to_vec(),
+            })
+            .collect();
+        Ok(Response::new(Box::pin(tokio_stream::iter(batches).map(Ok))))
+    }
+
+    async fn query_unary(&self, _: Request<QueryRequest>) -> Result<Response<QueryResult>, Status> {
+        Err(Status::unimplemented("not benchmarked"))
+    }
+
+    async fn describe_query(
+        &self,
+        _: Request<QueryRequest>,
+    ) -> Result<Response<QuerySchema>, Status> {
+        Err(Status::unimplemented("not benchmarked"))
+    }
+
+    async fn insert_batch(
+        &self,
+        _: Request<Streaming<InsertRequest>>,
+    ) -> Result<Response<InsertSummary>, Status> {
+        Err(Status::unimplemented("not benchmarked"))
+    }
+}
+
+/// Reads a whole result, returning the rows received.
+async fn fetch(client: &mut QueryServiceClient<Channel>, batch: usize) -> usize {
+    let request = QueryRequest {
+        query: "SELECT * FROM bench".to_string(),
+        batch_size: batch as u32,
+        ..Default::default()
+    };
+    let mut rows = 0;
+    if batch == 1 {
+        let mut stream = client.query(request).await.expect("query").into_inner();
+        while let Some(row) = stream.next().await {
+            black_box(row.expect("row"));
+            rows += 1;
+        }
+    } else {
+        let mut stream = client
+            .query_batched(request)
+            .await
+            .expect("query")
+            .into_inner();
+        while let Some(batch) = stream.next().await {
+            rows += black_box(batch.expect("batch")).rows.len();
+        }
+    }
+    rows
+}
+
+fn bench_batching(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().expect("runtime");
+    let rows = narrow_rows();
+    for batch in BATCH_SIZES {
+        let wire: usize = if batch == 1 {
+            rows.iter().map(|r| 5 + r.encoded_len()).sum()
+        } else {
+            rows.chunks(batch)
+                .map(|chunk| {
+                    let batch = RowBatch {
+                        rows: chunk.to_vec(),
+                    };
+                    5 + batch.encoded_len()
+                })
+                .sum()
+        };
+        eprintln!(
+            "batch {batch}: {} messages, {wire} bytes",
+            ROWS.div_ceil(batch)
+        );
+    }
+
+    let mut client = runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("local_addr");
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+            .expect("incoming");
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(QueryServiceServer::new(Replay { rows }))
+                .serve_with_incoming(incoming),
+        );
+        QueryServiceClient::connect(format!("http://{addr}"))
+            .await
+            .expect("connect")
+    });
+
+    let mut group = c.benchmark_group("grpc_batching");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    for batch in BATCH_SIZES {
+        group.bench_with_input(
+            BenchmarkId::new("rows_per_message", batch),
+            &batch,
+            |b, &batch| b.iter(|| assert_eq!(runtime.block_on(fetch(&mut client, batch)), ROWS)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_batching);
+criterion_main!(benches);
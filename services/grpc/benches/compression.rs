@@ -0,0 +1,76 @@
+//! CPU cost against wire size for gRPC response compression, on a unary
+//! result shaped like an export page (JSON rows of mixed text and numbers).
+//!
+//! tonic compresses gzip at level 6 and zstd at level 3; the neighbouring
+//! levels show what a configurable level would buy. Compressed sizes are
+//! printed once per level before timing.
+//!
+//! Run with `cargo bench -p kadedb-services-grpc --bench compression`.
+
+use std::io::Write;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kadedb_services_grpc::kadedb::{QueryResult, QueryRow};
+use prost::Message;
+
+const ROWS: usize = 2_000;
+
+const WARDS: [&str; 3] = ["cardiology", "oncology", "pediatrics"];
+
+fn export_page() -> Vec<u8> {
+    let rows = (0..ROWS)
+        .map(|i| QueryRow {
+            json: serde_json::json!({
+                "id": i,
+                "name": format!("patient-{i}"),
+                "ward": WARDS[i % WARDS.len()],
+                "heart_rate": 60.0 + (i % 40) as f64 * 0.5,
+                "notes": format!("follow-up visit {} scheduled", i % 12),
+            })
+            .to_string(),
+            progress: None,
+        })
+        .collect();
+    QueryResult { rows }.encode_to_vec()
+}
+
+fn gzip(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder.write_all(data).expect("gzip");
+    encoder.finish().expect("gzip")
+}
+
+fn zstd(data: &[u8], level: i32) -> Vec<u8> {
+    zstd::encode_all(data, level).expect("zstd")
+}
+
+fn bench_compression(c: &mut Criterion) {
+    let page = export_page();
+    let mut group = c.benchmark_group("grpc_compression");
+    group.throughput(Throughput::Bytes(page.len() as u64));
+
+    for level in [1, 6, 9] {
+        eprintln!(
+            "gzip level {level}: {} -> {} bytes",
+            page.len(),
+            gzip(&page, level).len()
+        );
+        group.bench_with_input(BenchmarkId::new("gzip", level), &level, |b, &level| {
+            b.iter(|| black_box(gzip(&page, level)))
+        });
+    }
+    for level in [1, 3, 9] {
+        eprintln!(
+            "zstd level {level}: {} -> {} bytes",
+            page.len(),
+            zstd(&page, level).len()
+        );
+        group.bench_with_input(BenchmarkId::new("zstd", level), &level, |b, &level| {
+            b.iter(|| black_box(zstd(&page, level)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compression);
+criterion_main!(benches);
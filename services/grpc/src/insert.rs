@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use kadedb_services_ffi::{spawn_query, ColumnType, PoolGuard, PreparedInsert, Value};
+use serde_json::{Map, Value as JsonValue};
+use tonic::{Status, Streaming};
+
+use crate::kadedb::{InsertRequest, InsertSummary, RowError};
+use crate::ErrorReport;
+
+/// Default rows applied per `InsertBatch` batch.
+pub const DEFAULT_INSERT_BATCH_ROWS: usize = 500;
+
+/// Upper bound on the row errors an `InsertBatch` summary lists.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// Reads `rows` into `guard`'s storage, applying them `batch_rows` at a
+/// time. The next message isn't read while a batch is being applied, so a
+/// client sending faster than storage keeps up is held back by the stream's
+/// flow control.
+///
+/// The engine has no transactions, so rows applied before a failure of the
+/// stream itself (the client going away, a malformed message) stay applied.
+pub(crate) async fn insert_batch(
+    guard: PoolGuard,
+    mut rows: Streaming<InsertRequest>,
+    batch_rows: usize,
+    errors: ErrorReport,
+) -> Result<InsertSummary, Status> {
+    let Some(first) = rows.message().await? else {
+        return Ok(InsertSummary::default());
+    };
+    let table = first.table.clone();
+    if !is_identifier(&table) {
+        return Err(Status::invalid_argument(
+            "the first message must name the `table`, as a plain identifier",
+        ));
+    }
+    let storage = guard.storage();
+    let target = {
+        let table = table.clone();
+        spawn_query(format!("InsertBatch into {table}"), move || {
+            storage.insert_target(&table)
+        })
+        .await
+        .expect("spawn_blocking")?
+    };
+
+    let mut insert = Batches {
+        guard,
+        target,
+        pending: Vec::with_capacity(batch_rows),
+        summary: InsertSummary::default(),
+        errors,
+    };
+    let mut next = Some(first);
+    let mut index = 0;
+    loop {
+        let message = match next.take() {
+            Some(message) => message,
+            None => match rows.message().await? {
+                Some(message) => message,
+                None => break,
+            },
+        };
+        let row = if !message.table.is_empty() && message.table != table {
+            Err(format!(
+                "table `{}` differs from the stream's `{table}`",
+                message.table
+            ))
+        } else {
+            row_values(&insert.target, &message.json)
+        };
+        match row {
+            Ok(row) => insert.pending.push((index, row)),
+            Err(message) => insert.fail(index, message),
+        }
+        index += 1;
+        if insert.pending.len() >= batch_rows {
+            insert.flush().await;
+        }
+    }
+    insert.flush().await;
+    Ok(insert.summary)
+}
+
+struct Batches {
+    guard: PoolGuard,
+    target: Arc<PreparedInsert>,
+    /// Rows waiting to be applied, with their position in the stream.
+    pending: Vec<(u64, Vec<Value>)>,
+    summary: InsertSummary,
+    /// Words the storage failures of rows.
+    errors: ErrorReport,
+}
+
+impl Batches {
+    fn fail(&mut self, index: u64, message: String) {
+        self.summary.failed += 1;
+        if self.summary.errors.len() < MAX_REPORTED_ERRORS {
+            self.summary.errors.push(RowError { index, message });
+        }
+    }
+
+    /// Applies the pending rows, row by row.
+    async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let batch = std::mem::take(&mut self.pending);
+        let attempted = batch.len() as u64;
+        let storage = self.guard.storage();
+        let target = self.target.clone();
+        let context = format!("InsertBatch of {attempted} rows into {}", target.table());
+        let failures = spawn_query(context, move || {
+            batch
+                .into_iter()
+                .filter_map(|(index, row)| {
+                    target.execute(&storage, &row).err().map(|err| (index, err))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .expect("spawn_blocking");
+        self.summary.inserted += attempted - failures.len() as u64;
+        for (index, err) in failures {
+            let message = self.errors.message(err);
+            self.fail(index, message);
+        }
+    }
+}
+
+/// A row from its JSON object, in `target`'s column order; missing columns
+/// are NULL.
+fn row_values(target: &PreparedInsert, json: &str) -> Result<Vec<Value>, String> {
+    let object: Map<String, JsonValue> =
+        serde_json::from_str(json).map_err(|err| format!("invalid JSON object: {err}"))?;
+    let columns = target.columns();
+    let mut row = vec![Value::Null; columns.len()];
+    for (name, value) in &object {
+        let idx = columns
+            .iter()
+            .position(|c| c.name == *name)
+            .ok_or_else(|| format!("unknown column `{name}`"))?;
+        row[idx] = json_value(columns[idx].column_type, value)
+            .ok_or_else(|| format!("{name}: unsupported value {value}"))?;
+    }
+    target.check_row(&row).map_err(|err| err.to_string())?;
+    Ok(row)
+}
+
+/// A JSON scalar as a storage value, as the REST row insert reads it.
+fn json_value(ty: ColumnType, value: &JsonValue) -> Option<Value> {
+    Some(match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(b) => Value::Boolean(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) if ty == ColumnType::Float => Value::Float(i as f64),
+            Some(i) => Value::Integer(i),
+            None if n.is_f64() => Value::Float(n.as_f64()?),
+            None => return None,
+        },
+        JsonValue::String(s) => Value::String(s.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => return None,
+    })
+}
+
+/// `[A-Za-z_][A-Za-z0-9_]*`: table names are spliced into the SQL that reads
+/// the table's layout.
+fn is_identifier(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    bytes
+        .next()
+        .is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
@@ -1,85 +1,1293 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use kadedb_services_auth::{authorize_bearer_header, AuthConfig, AuthError, Permission};
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_stream::wrappers::TcpListenerStream;
-use tonic::{transport::Server, Request, Response, Status};
+use futures_util::{StreamExt as _, TryStreamExt as _};
+use kadedb_services_auth::{
+    authenticate_bearer_header, check_single_credential, AuthConfig, AuthError, Permission,
+    Principal, Role, RoleTimeouts, API_KEY_HEADER,
+};
+use kadedb_services_ffi::{
+    coded_status, minimal_status, spawn_query, AllowedStatements, ColumnCase, ConfiguredPools,
+    ErrorCode, ErrorVerbosity, FfiError, RestrictedTables, StatementKind, StoragePool,
+    DEFAULT_MAX_QUERY_LENGTH,
+};
+use kadedb_services_telemetry::{
+    record_query, AccessEntry, AccessLog, Draining, ListenerConfig, QueryTags, RequestId,
+    SlowQueryLog, QUERY_TAG_HEADER, SHUTTING_DOWN,
+};
+use sha2::{Digest, Sha256};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt as _};
+use tonic::{
+    codec::CompressionEncoding,
+    metadata::MetadataValue,
+    service::interceptor::InterceptedService,
+    transport::{server::TcpIncoming, Server},
+    Request, Response, Status,
+};
+use tracing::Instrument;
+
+mod insert;
+mod proxied;
+mod request_id;
+mod rows;
+
+pub use insert::DEFAULT_INSERT_BATCH_ROWS;
+pub use tonic::transport::Error as TransportError;
 
 pub mod kadedb {
     tonic::include_proto!("kadedb");
 }
 
+/// Maps an auth failure to a gRPC status, counting it in
+/// `auth_failures_total{reason}`.
 fn map_auth_error(err: AuthError) -> Status {
+    metrics::counter!("auth_failures_total", "reason" => err.reason()).increment(1);
+    let error = ErrorCode::from(&err);
     match err {
-        AuthError::Forbidden => Status::permission_denied("forbidden"),
-        _ => Status::unauthenticated("unauthenticated"),
+        AuthError::Forbidden => coded_status(tonic::Code::PermissionDenied, error, "forbidden"),
+        AuthError::MissingScope => coded_status(tonic::Code::PermissionDenied, error, err.reason()),
+        AuthError::AmbiguousCredentials => {
+            coded_status(tonic::Code::InvalidArgument, error, err.reason())
+        }
+        _ => coded_status(tonic::Code::Unauthenticated, error, "unauthenticated"),
     }
 }
 
 use kadedb::query_service_server::{QueryService, QueryServiceServer};
-use kadedb::{QueryRequest, QueryRow};
+use kadedb::{
+    ColumnSchema, InsertRequest, InsertSummary, QueryProgress, QueryRequest, QueryResult, QueryRow,
+    QuerySchema, RowBatch,
+};
 
-#[derive(Default)]
-pub struct QueryServiceImpl;
+/// Metadata naming the tenant when auth is disabled; with auth enabled only
+/// the token's `tenant` claim is trusted.
+pub const TENANT_HEADER: &str = "x-tenant-id";
 
-#[tonic::async_trait]
-impl QueryService for QueryServiceImpl {
-    type QueryStream = Pin<Box<dyn tokio_stream::Stream<Item = Result<QueryRow, Status>> + Send>>;
+/// The storage calls are served from.
+#[derive(Clone, Default)]
+enum Pools {
+    #[default]
+    None,
+    Single(StoragePool),
+    /// One pool per tenant; calls that don't name a known tenant are refused.
+    Tenants(Arc<HashMap<String, StoragePool>>),
+}
 
-    async fn query(
+/// Default row cap for `QueryUnary`.
+pub const DEFAULT_UNARY_ROW_CAP: usize = 100;
+
+/// Default server-side maximum for `QueryRequest.max_rows`.
+pub const DEFAULT_MAX_ROWS: u64 = 100_000;
+
+/// Default rows per `QueryBatched` message.
+pub const DEFAULT_BATCH_ROWS: usize = 256;
+
+/// Most rows a `QueryBatched` call may ask for per message.
+pub const MAX_BATCH_ROWS: usize = 10_000;
+
+/// Default wait before a partial `QueryBatched` batch is sent anyway.
+pub const DEFAULT_BATCH_FLUSH: Duration = Duration::from_millis(10);
+
+/// Default time between progress events on a stream that asked for them.
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default cap on concurrent HTTP/2 streams (calls) per connection.
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 200;
+
+/// Trailer set on a `Query` stream that was cut off at its row limit.
+pub const TRUNCATED_TRAILER: &str = "x-kadedb-truncated";
+
+/// Trailer on a `Query` stream that failed partway: how many rows were sent
+/// before the error, so clients can tell whether to retry or resume.
+pub const ROWS_SENT_TRAILER: &str = "x-kadedb-rows-sent";
+
+/// Request metadata `sha256` asks `Query` for a checksum; the trailer of the
+/// same name on a completed stream carries `sha256:<hex>`, the SHA-256 of
+/// every row's `json` followed by `\n`.
+pub const CHECKSUM_METADATA: &str = "x-kadedb-checksum";
+
+/// Request metadata asking for an uncompressed response whatever the server's
+/// [`Compression`], e.g. for rows that are already compressed blobs.
+pub const NO_COMPRESSION_HEADER: &str = "x-kadedb-no-compression";
+
+/// Algorithm the server compresses responses with, for clients that accept
+/// it. Requests compressed with either algorithm are always accepted.
+///
+/// tonic compresses at the algorithm's default level; it has no setting for
+/// the level yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Parses `none`, `gzip` or `zstd`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "" => Some(Self::None),
+            "gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    fn encoding(self) -> Option<CompressionEncoding> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some(CompressionEncoding::Gzip),
+            Self::Zstd => Some(CompressionEncoding::Zstd),
+        }
+    }
+}
+
+pub struct QueryServiceImpl {
+    unary_row_cap: usize,
+    max_rows: u64,
+    query_tags: QueryTags,
+    slow_queries: SlowQueryLog,
+    request_timeout: Option<Duration>,
+    max_query_timeout: Option<Duration>,
+    role_timeouts: RoleTimeouts,
+    access_log: AccessLog,
+    compression: Compression,
+    max_concurrent_streams: Option<u32>,
+    max_query_length: usize,
+    allowed_statements: AllowedStatements,
+    restricted_tables: RestrictedTables,
+    batch_rows: usize,
+    batch_flush: Duration,
+    progress_interval: Option<Duration>,
+    storage: Pools,
+    insert_batch_rows: usize,
+    column_case: ColumnCase,
+    error_verbosity: ErrorVerbosity,
+    /// A setting [`QueryServiceImpl::from_env`] couldn't read, reported by
+    /// [`QueryServiceImpl::validate`].
+    config_error: Option<String>,
+}
+
+impl Default for QueryServiceImpl {
+    fn default() -> Self {
+        Self {
+            unary_row_cap: DEFAULT_UNARY_ROW_CAP,
+            max_rows: DEFAULT_MAX_ROWS,
+            query_tags: QueryTags::default(),
+            slow_queries: SlowQueryLog::default(),
+            request_timeout: None,
+            max_query_timeout: None,
+            role_timeouts: RoleTimeouts::default(),
+            access_log: AccessLog::default(),
+            compression: Compression::None,
+            max_concurrent_streams: Some(DEFAULT_MAX_CONCURRENT_STREAMS),
+            max_query_length: DEFAULT_MAX_QUERY_LENGTH,
+            allowed_statements: AllowedStatements::default(),
+            restricted_tables: RestrictedTables::default(),
+            batch_rows: DEFAULT_BATCH_ROWS,
+            batch_flush: DEFAULT_BATCH_FLUSH,
+            progress_interval: Some(DEFAULT_PROGRESS_INTERVAL),
+            storage: Pools::None,
+            insert_batch_rows: DEFAULT_INSERT_BATCH_ROWS,
+            column_case: ColumnCase::Preserve,
+            error_verbosity: ErrorVerbosity::Detailed,
+            config_error: None,
+        }
+    }
+}
+
+impl QueryServiceImpl {
+    /// Reads `KADEDB_UNARY_ROW_CAP`, `KADEDB_MAX_ROWS`, `KADEDB_QUERY_TAGS`,
+    /// the `KADEDB_SLOW_QUERY_*` log settings, `KADEDB_REQUEST_TIMEOUT_MS`,
+    /// `KADEDB_MAX_QUERY_TIMEOUT_MS`, `KADEDB_ROLE_TIMEOUTS`,
+    /// the `KADEDB_ACCESS_LOG*` settings, `KADEDB_GRPC_COMPRESSION`,
+    /// `KADEDB_GRPC_MAX_CONCURRENT_STREAMS` (0 lifts the cap),
+    /// `KADEDB_MAX_QUERY_LENGTH`, `KADEDB_ALLOWED_STATEMENTS`,
+    /// `KADEDB_RESTRICTED_TABLES`,
+    /// `KADEDB_GRPC_BATCH_ROWS`, `KADEDB_GRPC_BATCH_FLUSH_MS`,
+    /// `KADEDB_PROGRESS_INTERVAL_MS` (0 disables progress events),
+    /// `KADEDB_GRPC_INSERT_BATCH_ROWS`, `KADEDB_COLUMN_CASE` and
+    /// `KADEDB_ERROR_VERBOSITY` (`detailed` or `minimal`), falling
+    /// back to the defaults; an invalid `KADEDB_GRPC_COMPRESSION` fails
+    /// [`QueryServiceImpl::validate`] instead. No
+    /// storage is attached; see [`QueryServiceImpl::with_storage_from_env`].
+    pub fn from_env() -> Self {
+        let unary_row_cap = std::env::var("KADEDB_UNARY_ROW_CAP")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_UNARY_ROW_CAP);
+        let max_rows = std::env::var("KADEDB_MAX_ROWS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_MAX_ROWS);
+        let request_timeout = std::env::var("KADEDB_REQUEST_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+        let max_query_timeout = std::env::var("KADEDB_MAX_QUERY_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+        let role_timeouts = std::env::var("KADEDB_ROLE_TIMEOUTS")
+            .map(|v| RoleTimeouts::parse(&v))
+            .unwrap_or_default();
+        let (compression, compression_error) = match std::env::var("KADEDB_GRPC_COMPRESSION") {
+            Ok(v) => match Compression::parse(&v) {
+                Some(compression) => (compression, None),
+                None => (
+                    Compression::default(),
+                    Some(format!(
+                        "KADEDB_GRPC_COMPRESSION: `{v}` is not none, gzip or zstd"
+                    )),
+                ),
+            },
+            Err(_) => (Compression::default(), None),
+        };
+        let max_concurrent_streams = std::env::var("KADEDB_GRPC_MAX_CONCURRENT_STREAMS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(Some(DEFAULT_MAX_CONCURRENT_STREAMS), |n: u32| {
+                (n > 0).then_some(n)
+            });
+        let max_query_length = std::env::var("KADEDB_MAX_QUERY_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_MAX_QUERY_LENGTH);
+        let (allowed_statements, allowed_statements_error) = match AllowedStatements::from_env() {
+            Ok(allowed) => (allowed, None),
+            Err(err) => (AllowedStatements::only([]), Some(err)),
+        };
+        let config_error = compression_error.or(allowed_statements_error);
+        let restricted_tables = std::env::var("KADEDB_RESTRICTED_TABLES")
+            .map(|v| RestrictedTables::parse(&v))
+            .unwrap_or_default();
+        let batch_rows = std::env::var("KADEDB_GRPC_BATCH_ROWS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .map_or(DEFAULT_BATCH_ROWS, |n: usize| n.min(MAX_BATCH_ROWS));
+        let batch_flush = std::env::var("KADEDB_GRPC_BATCH_FLUSH_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .map_or(DEFAULT_BATCH_FLUSH, Duration::from_millis);
+        let progress_interval = std::env::var("KADEDB_PROGRESS_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(Some(DEFAULT_PROGRESS_INTERVAL), |ms: u64| {
+                (ms > 0).then(|| Duration::from_millis(ms))
+            });
+        let insert_batch_rows = std::env::var("KADEDB_GRPC_INSERT_BATCH_ROWS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_INSERT_BATCH_ROWS);
+        let column_case = std::env::var("KADEDB_COLUMN_CASE")
+            .ok()
+            .and_then(|v| ColumnCase::parse(&v))
+            .unwrap_or_default();
+        let error_verbosity = std::env::var("KADEDB_ERROR_VERBOSITY")
+            .ok()
+            .and_then(|v| ErrorVerbosity::parse(&v))
+            .unwrap_or_default();
+        Self {
+            unary_row_cap,
+            max_rows,
+            query_tags: QueryTags::from_env(),
+            slow_queries: SlowQueryLog::from_env(),
+            request_timeout,
+            max_query_timeout,
+            role_timeouts,
+            access_log: AccessLog::from_env(),
+            compression,
+            max_concurrent_streams,
+            max_query_length,
+            allowed_statements,
+            restricted_tables,
+            batch_rows,
+            batch_flush,
+            progress_interval,
+            storage: Pools::None,
+            insert_batch_rows,
+            column_case,
+            error_verbosity,
+            config_error,
+        }
+    }
+
+    /// Fails if a setting read by [`QueryServiceImpl::from_env`] was
+    /// invalid, e.g. an unknown `KADEDB_GRPC_COMPRESSION` or
+    /// `KADEDB_ALLOWED_STATEMENTS` kind, rather than
+    /// serving with the default in its place.
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.config_error {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn with_unary_row_cap(mut self, cap: usize) -> Self {
+        self.unary_row_cap = cap;
+        self
+    }
+
+    /// Sets the hard maximum rows a `Query` stream sends, whatever the
+    /// request asks for.
+    pub fn with_max_rows(mut self, max_rows: u64) -> Self {
+        self.max_rows = max_rows.max(1);
+        self
+    }
+
+    pub fn with_query_tags(mut self, tags: QueryTags) -> Self {
+        self.query_tags = tags;
+        self
+    }
+
+    pub fn with_slow_queries(mut self, log: SlowQueryLog) -> Self {
+        self.slow_queries = log;
+        self
+    }
+
+    /// Fails calls with `DEADLINE_EXCEEDED` once they run longer than
+    /// `timeout`, unless the client set its own deadline. For `Query` this
+    /// covers the whole stream.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Caps the deadline a client sets on a call: a longer one is shortened
+    /// to `timeout`.
+    pub fn with_max_query_timeout(mut self, timeout: Duration) -> Self {
+        self.max_query_timeout = Some(timeout);
+        self
+    }
+
+    /// Gives callers a default deadline, and a longest one, by role.
+    pub fn with_role_timeouts(mut self, timeouts: RoleTimeouts) -> Self {
+        self.role_timeouts = timeouts;
+        self
+    }
+
+    pub fn with_access_log(mut self, log: AccessLog) -> Self {
+        self.access_log = log;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Caps the calls one connection may have in flight; `None` lifts the
+    /// cap. Streams opened past it are refused (`REFUSED_STREAM`), which
+    /// clients retry once a slot frees up.
+    pub fn with_max_concurrent_streams(mut self, max: Option<u32>) -> Self {
+        self.max_concurrent_streams = max;
+        self
+    }
+
+    /// Rejects query text longer than `max` bytes with `INVALID_ARGUMENT`.
+    pub fn with_max_query_length(mut self, max: usize) -> Self {
+        self.max_query_length = max;
+        self
+    }
+
+    /// Rejects statements of kinds outside `allowed` with
+    /// `PERMISSION_DENIED`.
+    pub fn with_allowed_statements(mut self, allowed: AllowedStatements) -> Self {
+        self.allowed_statements = allowed;
+        self
+    }
+
+    /// The storage queries read and `InsertBatch` writes to. Without one
+    /// every call fails with `FAILED_PRECONDITION`.
+    pub fn with_storage(mut self, pool: StoragePool) -> Self {
+        self.storage = Pools::Single(pool);
+        self
+    }
+
+    /// Attaches the storage of [`ConfiguredPools::from_env`], per tenant
+    /// when `KADEDB_TENANTS` lists any, as the REST API opens it.
+    pub fn with_storage_from_env(self) -> Result<Self, FfiError> {
+        Ok(match ConfiguredPools::from_env()? {
+            ConfiguredPools::Single(pool) => self.with_storage(pool),
+            ConfiguredPools::Tenants(pools) => self.with_tenant_storage(pools),
+        })
+    }
+
+    /// Per-tenant storage in place of [`QueryServiceImpl::with_storage`]:
+    /// each call uses the pool of its caller's `tenant` claim (or, with
+    /// auth disabled, its [`TENANT_HEADER`]).
+    pub fn with_tenant_storage(mut self, tenants: HashMap<String, StoragePool>) -> Self {
+        self.storage = Pools::Tenants(Arc::new(tenants));
+        self
+    }
+
+    /// Rows `InsertBatch` applies at a time.
+    pub fn with_insert_batch_rows(mut self, rows: usize) -> Self {
+        self.insert_batch_rows = rows.max(1);
+        self
+    }
+
+    /// Reports result column names (row keys, `DescribeQuery`) in `case`.
+    pub fn with_column_case(mut self, case: ColumnCase) -> Self {
+        self.column_case = case;
+        self
+    }
+
+    /// How much of a storage error's cause statuses reveal; see
+    /// [`ErrorVerbosity`].
+    pub fn with_error_verbosity(mut self, verbosity: ErrorVerbosity) -> Self {
+        self.error_verbosity = verbosity;
+        self
+    }
+
+    /// Refuses queries referencing `tables` with `PERMISSION_DENIED` unless
+    /// the caller is an admin.
+    pub fn with_restricted_tables(mut self, tables: RestrictedTables) -> Self {
+        self.restricted_tables = tables;
+        self
+    }
+
+    /// Rows per `QueryBatched` message when the request doesn't say.
+    pub fn with_batch_rows(mut self, rows: usize) -> Self {
+        self.batch_rows = rows.clamp(1, MAX_BATCH_ROWS);
+        self
+    }
+
+    /// How long a partial `QueryBatched` batch waits for more rows before it
+    /// is sent.
+    pub fn with_batch_flush(mut self, flush: Duration) -> Self {
+        self.batch_flush = flush;
+        self
+    }
+
+    /// How often a stream whose request set `progress` gets a progress
+    /// event; `None` sends none.
+    pub fn with_progress_interval(mut self, interval: Option<Duration>) -> Self {
+        self.progress_interval = interval;
+        self
+    }
+
+    /// Refuses a query that is too long, of a kind that isn't allowed, or
+    /// that references a restricted table when the caller isn't an admin.
+    #[allow(clippy::result_large_err)]
+    fn check_query(&self, request: &Request<QueryRequest>) -> Result<(), Status> {
+        let query = &request.get_ref().query;
+        if query.len() > self.max_query_length {
+            return Err(FfiError::QueryTooLong {
+                len: query.len(),
+                max: self.max_query_length,
+            }
+            .into());
+        }
+        self.allowed_statements.check(query)?;
+        let restricted = request
+            .extensions()
+            .get::<Principal>()
+            .is_some_and(|p| p.role != Role::Admin);
+        if let Some(table) = self.restricted_tables.find_in(query).filter(|_| restricted) {
+            return Err(Status::permission_denied(format!(
+                "table `{table}` is restricted to admins"
+            )));
+        }
+        Ok(())
+    }
+
+    /// How long a call may run: the client's deadline, clamped to
+    /// `max_query_timeout` and the caller's role max, or else the role's
+    /// default or the request timeout. See [`RoleTimeouts::limit`].
+    fn timeout<T>(&self, request: &Request<T>) -> Option<Duration> {
+        let client = request
+            .metadata()
+            .get(GRPC_TIMEOUT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_grpc_timeout);
+        let role = request.extensions().get::<Principal>().map(|p| p.role);
+        self.role_timeouts
+            .limit(role, client, self.request_timeout, self.max_query_timeout)
+    }
+
+    /// Awaits `fut` within `limit`, if there is one.
+    #[allow(clippy::result_large_err)]
+    async fn within_deadline<T>(
+        limit: Option<Duration>,
+        fut: impl Future<Output = T>,
+    ) -> Result<T, Status> {
+        match limit {
+            Some(limit) => tokio::time::timeout(limit, fut)
+                .await
+                .map_err(|_| request_timed_out()),
+            None => Ok(fut.await),
+        }
+    }
+
+    /// The storage pool of the calling tenant; `method` names the call in
+    /// the error when no storage is attached.
+    #[allow(clippy::result_large_err)]
+    fn pool<T>(&self, request: &Request<T>, method: &str) -> Result<StoragePool, Status> {
+        match &self.storage {
+            Pools::None => Err(Status::failed_precondition(format!(
+                "{method} needs storage, and none is attached to this server"
+            ))),
+            Pools::Single(pool) => Ok(pool.clone()),
+            Pools::Tenants(pools) => {
+                let tenant = match request.extensions().get::<Principal>() {
+                    Some(principal) => principal.tenant.as_deref(),
+                    None => request
+                        .metadata()
+                        .get(TENANT_HEADER)
+                        .and_then(|v| v.to_str().ok()),
+                };
+                let tenant = tenant.ok_or_else(|| map_auth_error(AuthError::MissingTenant))?;
+                pools
+                    .get(tenant)
+                    .cloned()
+                    .ok_or_else(|| map_auth_error(AuthError::Forbidden))
+            }
+        }
+    }
+
+    /// The allowlisted `x-query-tag` of a call.
+    fn tag<T>(&self, request: &Request<T>) -> String {
+        let tag = request
+            .metadata()
+            .get(QUERY_TAG_HEADER)
+            .and_then(|v| v.to_str().ok());
+        self.query_tags.resolve(tag)
+    }
+
+    /// How the statuses of a call are worded.
+    fn errors<T>(&self, request: &Request<T>) -> ErrorReport {
+        ErrorReport {
+            verbosity: self.error_verbosity,
+            request_id: request.extensions().get::<RequestId>().cloned(),
+        }
+    }
+}
+
+/// Applies the service's [`ErrorVerbosity`] to the statuses of one call.
+#[derive(Clone)]
+struct ErrorReport {
+    verbosity: ErrorVerbosity,
+    request_id: Option<RequestId>,
+}
+
+impl ErrorReport {
+    /// `status` as the caller sees it: under [`ErrorVerbosity::Minimal`], a
+    /// storage error's message is logged under the call's request id and
+    /// replaced (see [`minimal_status`]).
+    fn apply(&self, status: Status) -> Status {
+        if self.verbosity != ErrorVerbosity::Minimal {
+            return status;
+        }
+        let correlation_id = self
+            .request_id
+            .clone()
+            .unwrap_or_else(RequestId::generate)
+            .to_string();
+        match minimal_status(&status, &correlation_id) {
+            Some(mut hidden) => {
+                tracing::warn!(%correlation_id, code = ?status.code(), error = %status.message(), "call failed");
+                if let Some(rows_sent) = status.metadata().get(ROWS_SENT_TRAILER) {
+                    hidden
+                        .metadata_mut()
+                        .insert(ROWS_SENT_TRAILER, rows_sent.clone());
+                }
+                hidden
+            }
+            None => status,
+        }
+    }
+
+    /// `err`'s message as the caller sees it, for failures reported outside
+    /// a status (a row of an `InsertBatch`).
+    fn message(&self, err: FfiError) -> String {
+        self.apply(err.into()).message().to_string()
+    }
+}
+
+impl From<kadedb_services_ffi::ColumnType> for kadedb::ColumnType {
+    fn from(ty: kadedb_services_ffi::ColumnType) -> Self {
+        use kadedb_services_ffi::ColumnType as Ffi;
+        match ty {
+            Ffi::Null => Self::Null,
+            Ffi::Integer => Self::Integer,
+            Ffi::Float => Self::Float,
+            Ffi::String => Self::String,
+            Ffi::Boolean => Self::Boolean,
+        }
+    }
+}
+
+/// The `sub` claim of the caller, when auth is on.
+fn subject<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<Principal>()
+        .and_then(|p| p.subject.clone())
+}
+
+/// Writes the access log line for a finished call to `method` (e.g.
+/// `QueryUnary`).
+fn log_call(
+    log: &AccessLog,
+    method: &str,
+    code: tonic::Code,
+    started: Instant,
+    subject: Option<&str>,
+    client: Option<std::net::SocketAddr>,
+) {
+    log.record(&AccessEntry {
+        method: "grpc",
+        path: &format!("/kadedb.QueryService/{method}"),
+        status: code as u16,
+        latency: started.elapsed(),
+        subject,
+        bytes: None,
+        client,
+    });
+}
+
+/// `response`, left uncompressed when the call carried
+/// [`NO_COMPRESSION_HEADER`].
+fn maybe_uncompressed<T>(opt_out: bool, mut response: Response<T>) -> Response<T> {
+    if opt_out {
+        response.disable_compression();
+    }
+    response
+}
+
+/// The deadline a gRPC client sets on a call.
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Parses a `grpc-timeout` value: up to 8 digits and a unit (`H`, `M`, `S`,
+/// `m`, `u` or `n`).
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let n: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(n * 3600),
+        "M" => Duration::from_secs(n * 60),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    })
+}
+
+fn request_timed_out() -> Status {
+    Status::deadline_exceeded("request timed out")
+}
+
+impl QueryServiceImpl {
+    /// `InsertBatch`, without the access log and metrics.
+    async fn insert(
+        &self,
+        request: Request<tonic::Streaming<InsertRequest>>,
+    ) -> Result<InsertSummary, Status> {
+        // The interceptor only checks for read permission.
+        if let Some(principal) = request.extensions().get::<Principal>() {
+            if !principal.allows(Permission::Write) {
+                return Err(map_auth_error(AuthError::Forbidden));
+            }
+        }
+        self.allowed_statements.check_kind(StatementKind::Insert)?;
+        let pool = self.pool(&request, "InsertBatch")?;
+        let errors = self.errors(&request);
+        let guard = pool.acquire().await?;
+        insert::insert_batch(guard, request.into_inner(), self.insert_batch_rows, errors).await
+    }
+
+    /// `DescribeQuery`, without the access log: the result columns of a
+    /// `SELECT`, as the engine reports them. The engine can't prepare a
+    /// statement without running it, so the statement runs but no row is
+    /// read; anything other than a `SELECT` is refused rather than run.
+    async fn describe(&self, request: Request<QueryRequest>) -> Result<QuerySchema, Status> {
+        self.check_query(&request)?;
+        let query = &request.get_ref().query;
+        if query.trim().is_empty() {
+            return Err(Status::invalid_argument("query is empty"));
+        }
+        if StatementKind::of(query) != StatementKind::Select {
+            return Err(Status::invalid_argument(
+                "DescribeQuery takes a SELECT statement",
+            ));
+        }
+        let guard = self.pool(&request, "DescribeQuery")?.acquire().await?;
+        let query = request.into_inner().query;
+        let storage = guard.storage();
+        let columns = spawn_query(query.clone(), move || {
+            let statement = storage.prepare(&query);
+            let result = storage
+                .execute_prepared(&statement, &[])
+                .map(|rs| rs.columns());
+            if result.as_ref().is_err_and(FfiError::is_storage_failure) {
+                guard.record_failure();
+            }
+            result
+        })
+        .await
+        .expect("spawn_blocking")?;
+        let columns = columns
+            .into_iter()
+            .map(|c| ColumnSchema {
+                name: self.column_case.apply(c.name),
+                r#type: kadedb::ColumnType::from(c.column_type).into(),
+            })
+            .collect();
+        Ok(QuerySchema { columns })
+    }
+
+    /// `QueryUnary`, without the access log. Rows are read from the caller's
+    /// storage as for `Query`, and collected up to the row cap.
+    async fn unary(
         &self,
         request: Request<QueryRequest>,
-    ) -> Result<Response<Self::QueryStream>, Status> {
+        subject: Option<&str>,
+    ) -> Result<Response<QueryResult>, Status> {
+        let tag = self.tag(&request);
+        let timeout = self.timeout(&request);
+        let opt_out = request.metadata().contains_key(NO_COMPRESSION_HEADER);
+        self.check_query(&request)?;
+        let guard = self.pool(&request, "QueryUnary")?.acquire().await?;
         let query = request.into_inner().query;
+        let cap = self.unary_row_cap;
 
+        let kind = StatementKind::of(&query).as_str();
+        let span = tracing::info_span!("query.execute", tag = %tag, unary = true);
+        let started = Instant::now();
+        let mut produced = rows::produce(
+            guard,
+            query.clone(),
+            self.column_case,
+            timeout.map(|limit| started + limit),
+        );
+        // Stops reading, and so frees the pool slot, one row past the cap.
+        let collect = async move {
+            let mut rows = Vec::new();
+            while let Some(row) = produced.recv().await {
+                rows.push(row?);
+                if rows.len() > cap {
+                    break;
+                }
+            }
+            Ok::<_, FfiError>(rows)
+        };
+        let rows = match Self::within_deadline(timeout, collect.instrument(span)).await {
+            Ok(Ok(rows)) => rows,
+            Ok(Err(err)) => {
+                record_query(&tag, kind, false, started.elapsed());
+                return Err(err.into());
+            }
+            Err(status) => {
+                record_query(&tag, kind, false, started.elapsed());
+                return Err(status);
+            }
+        };
+        record_query(&tag, kind, rows.len() <= cap, started.elapsed());
+        self.slow_queries
+            .record(&query, started.elapsed(), rows.len(), subject);
+        if rows.len() > cap {
+            return Err(Status::out_of_range(format!(
+                "result exceeds the unary row cap of {cap}; use the streaming Query RPC"
+            )));
+        }
+
+        Ok(maybe_uncompressed(
+            opt_out,
+            Response::new(QueryResult { rows }),
+        ))
+    }
+}
+
+impl QueryServiceImpl {
+    /// Starts the query of a streaming call (`method` names it in the access
+    /// log) on the caller's storage and returns its rows as they are read,
+    /// ending with an error (worded by `errors`) or trailers status when
+    /// there is one, and whether the call opted out of compression. Progress
+    /// events are interleaved when the request asks for them.
+    #[allow(clippy::result_large_err)]
+    async fn stream_rows(
+        &self,
+        request: Request<QueryRequest>,
+        method: &'static str,
+        errors: ErrorReport,
+    ) -> Result<(RowStream, bool), Status> {
+        let tag = self.tag(&request);
+        let subject = subject(&request);
+        let client = request.remote_addr();
+        let timeout = self.timeout(&request);
+        let opt_out = request.metadata().contains_key(NO_COMPRESSION_HEADER);
+        let mut digest = match request.metadata().get(CHECKSUM_METADATA) {
+            None => None,
+            Some(v) if v == "sha256" => Some(Sha256::new()),
+            Some(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "unsupported {CHECKSUM_METADATA}; expected `sha256`"
+                )))
+            }
+        };
+        self.check_query(&request)?;
+        let guard = self.pool(&request, method)?.acquire().await?;
+        let QueryRequest {
+            query,
+            max_rows,
+            progress,
+            ..
+        } = request.into_inner();
+        let limit = match max_rows {
+            0 => self.max_rows,
+            n => n.min(self.max_rows),
+        };
+
+        let kind = StatementKind::of(&query).as_str();
+        let deadline = timeout.map(|limit| tokio::time::Instant::now() + limit);
+        let mut produced = rows::produce(
+            guard,
+            query.clone(),
+            self.column_case,
+            deadline.map(tokio::time::Instant::into_std),
+        );
         let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let slow = self.slow_queries.clone();
+        let access = self.access_log.clone();
+        let started = Instant::now();
+        let rows_sent = Arc::new(AtomicU64::new(0));
+        let counter = rows_sent.clone();
+
+        let span = tracing::info_span!("query.execute", tag = %tag);
+        tokio::spawn(
+            async move {
+                let mut code = tonic::Code::Ok;
+                let mut rows = 0;
+                // A successful status carrying trailers: the client sees a
+                // normal end of stream.
+                let mut trailers = Status::new(tonic::Code::Ok, "");
+                loop {
+                    // `None` once the deadline has passed.
+                    let row = match deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline, produced.recv())
+                            .await
+                            .ok(),
+                        None => Some(produced.recv().await),
+                    };
+                    let row = match row {
+                        Some(Some(Ok(row))) => row,
+                        Some(Some(Err(err))) => {
+                            let mut status = Status::from(err);
+                            status
+                                .metadata_mut()
+                                .insert(ROWS_SENT_TRAILER, MetadataValue::from(rows));
+                            code = status.code();
+                            let _ = tx.send(Err(status)).await;
+                            break;
+                        }
+                        Some(None) => break,
+                        None => {
+                            end_past_deadline(&tx, rows);
+                            code = tonic::Code::DeadlineExceeded;
+                            break;
+                        }
+                    };
+                    if rows == limit {
+                        trailers
+                            .metadata_mut()
+                            .insert(TRUNCATED_TRAILER, MetadataValue::from_static("true"));
+                        break;
+                    }
+                    if let Some(digest) = &mut digest {
+                        digest.update(row.json.as_bytes());
+                        digest.update(b"\n");
+                    }
+                    // `None` once the deadline has passed.
+                    let sent = match deadline {
+                        Some(deadline) if tokio::time::Instant::now() >= deadline => None,
+                        Some(deadline) => tokio::time::timeout_at(deadline, tx.send(Ok(row)))
+                            .await
+                            .ok(),
+                        None => Some(tx.send(Ok(row)).await),
+                    };
+                    match sent {
+                        Some(Ok(())) => {
+                            rows += 1;
+                            counter.store(rows, Ordering::Relaxed);
+                        }
+                        Some(Err(_)) => {
+                            // The client went away.
+                            code = tonic::Code::Cancelled;
+                            break;
+                        }
+                        None => {
+                            end_past_deadline(&tx, rows);
+                            code = tonic::Code::DeadlineExceeded;
+                            break;
+                        }
+                    }
+                }
+                if code == tonic::Code::Ok {
+                    if let Some(digest) = digest {
+                        let checksum = format!("sha256:{:x}", digest.finalize());
+                        if let Ok(value) = checksum.parse() {
+                            trailers.metadata_mut().insert(CHECKSUM_METADATA, value);
+                        }
+                    }
+                    if !trailers.metadata().is_empty() {
+                        let _ = tx.send(Err(trailers)).await;
+                    }
+                }
+                record_query(&tag, kind, code == tonic::Code::Ok, started.elapsed());
+                slow.record(&query, started.elapsed(), rows as usize, subject.as_deref());
+                log_call(&access, method, code, started, subject.as_deref(), client);
+            }
+            .instrument(span),
+        );
 
-        tokio::spawn(async move {
-            let rows = [
-                serde_json::json!({"echo": query, "row": 1}).to_string(),
-                serde_json::json!({"echo": query, "row": 2}).to_string(),
-                serde_json::json!({"echo": query, "row": 3}).to_string(),
-            ];
+        let stream: RowStream = match self.progress_interval.filter(|_| progress) {
+            Some(every) => Box::pin(with_progress(rx, every, rows_sent, started)),
+            None => Box::pin(ReceiverStream::new(rx)),
+        };
+        let stream = stream.map_err(move |status| errors.apply(status));
+        Ok((Box::pin(stream), opt_out))
+    }
+}
 
-            for json in rows {
-                if tx.send(Ok(QueryRow { json })).await.is_err() {
-                    break;
+/// Ends a stream that ran past its deadline with `DEADLINE_EXCEEDED`,
+/// carrying the rows sent. The client may not be reading, so this doesn't
+/// wait on it.
+fn end_past_deadline(tx: &tokio::sync::mpsc::Sender<Result<QueryRow, Status>>, rows_sent: u64) {
+    let mut status = request_timed_out();
+    status
+        .metadata_mut()
+        .insert(ROWS_SENT_TRAILER, MetadataValue::from(rows_sent));
+    let _ = tx.try_send(Err(status));
+}
+
+type RowStream = Pin<Box<dyn tokio_stream::Stream<Item = Result<QueryRow, Status>> + Send>>;
+
+/// The items of `rows`, with a progress event every `every` until it ends: how many rows
+/// have been sent (`rows_sent`) and how long since `started`. The engine
+/// can't report how far it has scanned, so this is only a heartbeat.
+fn with_progress(
+    rows: tokio::sync::mpsc::Receiver<Result<QueryRow, Status>>,
+    every: Duration,
+    rows_sent: Arc<AtomicU64>,
+    started: Instant,
+) -> impl tokio_stream::Stream<Item = Result<QueryRow, Status>> {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    futures_util::stream::unfold((rows, ticks), move |(mut rows, mut ticks)| {
+        let rows_sent = rows_sent.clone();
+        async move {
+            let item = tokio::select! {
+                biased;
+                _ = ticks.tick() => Ok(QueryRow {
+                    json: String::new(),
+                    progress: Some(QueryProgress {
+                        rows_sent: rows_sent.load(Ordering::Relaxed),
+                        elapsed_ms: started.elapsed().as_millis() as u64,
+                    }),
+                }),
+                item = rows.recv() => item?,
+            };
+            Some((item, (rows, ticks)))
+        }
+    })
+}
+
+/// Packs a chunk of `stream_rows` items into batches: the rows, then the
+/// closing status if the chunk holds it.
+fn into_batches(chunk: Vec<Result<QueryRow, Status>>) -> Vec<Result<RowBatch, Status>> {
+    let mut batches = Vec::with_capacity(2);
+    let mut rows = Vec::with_capacity(chunk.len());
+    for item in chunk {
+        match item {
+            Ok(row) => rows.push(row),
+            Err(status) => {
+                if !rows.is_empty() {
+                    batches.push(Ok(RowBatch {
+                        rows: std::mem::take(&mut rows),
+                    }));
                 }
+                batches.push(Err(status));
             }
-        });
+        }
+    }
+    if !rows.is_empty() {
+        batches.push(Ok(RowBatch { rows }));
+    }
+    batches
+}
 
-        Ok(Response::new(
-            Box::pin(ReceiverStream::new(rx)) as Self::QueryStream
+#[tonic::async_trait]
+impl QueryService for QueryServiceImpl {
+    type QueryStream = RowStream;
+    type QueryBatchedStream =
+        Pin<Box<dyn tokio_stream::Stream<Item = Result<RowBatch, Status>> + Send>>;
+
+    async fn query(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<Self::QueryStream>, Status> {
+        let errors = self.errors(&request);
+        let (rows, opt_out) = self
+            .stream_rows(request, "Query", errors.clone())
+            .await
+            .map_err(|status| errors.apply(status))?;
+        Ok(maybe_uncompressed(opt_out, Response::new(rows)))
+    }
+
+    /// [`QueryService::query`]'s rows, sent `batch_size` at a time. A
+    /// partial batch goes out once no row has arrived for `batch_flush`, and
+    /// at the end of the stream.
+    async fn query_batched(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<Self::QueryBatchedStream>, Status> {
+        let batch_size = match request.get_ref().batch_size {
+            0 => self.batch_rows,
+            n => (n as usize).min(MAX_BATCH_ROWS),
+        };
+        let errors = self.errors(&request);
+        let (rows, opt_out) = self
+            .stream_rows(request, "QueryBatched", errors.clone())
+            .await
+            .map_err(|status| errors.apply(status))?;
+        let batches = rows
+            .chunks_timeout(batch_size, self.batch_flush)
+            .flat_map(|chunk| futures_util::stream::iter(into_batches(chunk)));
+        Ok(maybe_uncompressed(
+            opt_out,
+            Response::new(Box::pin(batches) as Self::QueryBatchedStream),
         ))
     }
+
+    async fn query_unary(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<QueryResult>, Status> {
+        let started = Instant::now();
+        let subject = subject(&request);
+        let client = request.remote_addr();
+        let errors = self.errors(&request);
+        let result = self
+            .unary(request, subject.as_deref())
+            .await
+            .map_err(|status| errors.apply(status));
+        let code = result
+            .as_ref()
+            .map_or_else(Status::code, |_| tonic::Code::Ok);
+        log_call(
+            &self.access_log,
+            "QueryUnary",
+            code,
+            started,
+            subject.as_deref(),
+            client,
+        );
+        result
+    }
+
+    async fn insert_batch(
+        &self,
+        request: Request<tonic::Streaming<InsertRequest>>,
+    ) -> Result<Response<InsertSummary>, Status> {
+        let started = Instant::now();
+        let subject = subject(&request);
+        let client = request.remote_addr();
+        let tag = self.tag(&request);
+        let errors = self.errors(&request);
+        let result = self
+            .insert(request)
+            .await
+            .map_err(|status| errors.apply(status));
+        let code = result
+            .as_ref()
+            .map_or_else(Status::code, |_| tonic::Code::Ok);
+        record_query(&tag, "insert", result.is_ok(), started.elapsed());
+        log_call(
+            &self.access_log,
+            "InsertBatch",
+            code,
+            started,
+            subject.as_deref(),
+            client,
+        );
+        result.map(Response::new)
+    }
+
+    async fn describe_query(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<QuerySchema>, Status> {
+        let started = Instant::now();
+        let subject = subject(&request);
+        let client = request.remote_addr();
+        let errors = self.errors(&request);
+        let result = self
+            .describe(request)
+            .await
+            .map(Response::new)
+            .map_err(|status| errors.apply(status));
+        let code = result
+            .as_ref()
+            .map_or_else(Status::code, |_| tonic::Code::Ok);
+        log_call(
+            &self.access_log,
+            "DescribeQuery",
+            code,
+            started,
+            subject.as_deref(),
+            client,
+        );
+        result
+    }
+}
+
+/// Checks the `authorization` metadata of an incoming call against `cfg`,
+/// requiring read permission, and stores the caller's
+/// [`Principal`](kadedb_services_auth::Principal) in the request extensions.
+/// Passes the request through untouched when auth is disabled.
+#[allow(clippy::result_large_err)]
+pub fn auth_interceptor(cfg: &AuthConfig, mut req: Request<()>) -> Result<Request<()>, Status> {
+    if !cfg.enabled {
+        return Ok(req);
+    }
+
+    let header = req
+        .metadata()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok());
+    let api_key = req
+        .metadata()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    let span =
+        tracing::info_span!("auth", permission = ?Permission::Read, reason = tracing::field::Empty);
+    let principal = span
+        .in_scope(|| {
+            check_single_credential(cfg, header, api_key)?;
+            authenticate_bearer_header(cfg, header, Permission::Read)
+        })
+        .map_err(|err| {
+            span.record("reason", err.reason());
+            map_auth_error(err)
+        })?;
+    if let Some(principal) = principal {
+        req.extensions_mut().insert(principal);
+    }
+    Ok(req)
 }
 
 pub async fn serve(addr: std::net::SocketAddr, auth_cfg: AuthConfig) {
-    let listener = tokio::net::TcpListener::bind(addr).await.expect("bind");
-    serve_with_listener(listener, auth_cfg).await;
+    let listener_cfg = ListenerConfig::from_env();
+    let listener = listener_cfg.bind(addr).expect("bind");
+    serve_with_config(
+        listener,
+        auth_cfg,
+        QueryServiceImpl::from_env(),
+        &listener_cfg,
+    )
+    .await;
 }
 
 pub async fn serve_with_listener(listener: tokio::net::TcpListener, auth_cfg: AuthConfig) {
-    let interceptor = move |req: Request<()>| -> Result<Request<()>, Status> {
-        if !auth_cfg.enabled {
-            return Ok(req);
-        }
+    serve_service(listener, auth_cfg, QueryServiceImpl::from_env()).await;
+}
 
-        let header = req
-            .metadata()
-            .get("authorization")
-            .and_then(|v| v.to_str().ok());
+/// Serves a preconfigured `service` on `listener` with default TCP settings.
+pub async fn serve_service(
+    listener: tokio::net::TcpListener,
+    auth_cfg: AuthConfig,
+    service: QueryServiceImpl,
+) {
+    serve_with_config(listener, auth_cfg, service, &ListenerConfig::default()).await;
+}
+
+/// Serves `service` on `listener`, applying the per-connection settings of
+/// `listener_cfg` (the listener itself is expected to be bound with it).
+pub async fn serve_with_config(
+    listener: tokio::net::TcpListener,
+    auth_cfg: AuthConfig,
+    service: QueryServiceImpl,
+    listener_cfg: &ListenerConfig,
+) {
+    serve_with_shutdown(
+        listener,
+        auth_cfg,
+        service,
+        listener_cfg,
+        std::future::pending(),
+    )
+    .await
+    .expect("serve");
+}
 
-        authorize_bearer_header(&auth_cfg, header, Permission::Read)
-            .map(|_| req)
-            .map_err(map_auth_error)
+/// Like [`serve_with_config`], but stops accepting calls once `shutdown`
+/// resolves and returns after in-flight calls finish. With
+/// `listener_cfg.proxy_protocol`, calls see the client named by their
+/// connection's PROXY protocol header as [`Request::remote_addr`]. Calls
+/// arriving after `shutdown` fires, including during
+/// `listener_cfg.shutdown_grace`, fail with `unavailable`.
+pub async fn serve_with_shutdown(
+    listener: tokio::net::TcpListener,
+    auth_cfg: AuthConfig,
+    service: QueryServiceImpl,
+    listener_cfg: &ListenerConfig,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    let draining = Draining::default();
+    let shutdown = draining.after(shutdown, listener_cfg.shutdown_grace);
+    #[allow(clippy::result_large_err)]
+    let interceptor = move |req: Request<()>| {
+        if draining.is_draining() {
+            return Err(coded_status(
+                tonic::Code::Unavailable,
+                ErrorCode::Unavailable,
+                SHUTTING_DOWN,
+            ));
+        }
+        auth_interceptor(&auth_cfg, req)
     };
 
-    let svc = QueryServiceServer::with_interceptor(QueryServiceImpl, interceptor);
+    let compression = service.compression.encoding();
+    let max_concurrent_streams = service.max_concurrent_streams;
+    let mut server = QueryServiceServer::new(service)
+        .accept_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Zstd);
+    if let Some(encoding) = compression {
+        server = server.send_compressed(encoding);
+    }
+    let svc = InterceptedService::new(server, interceptor);
 
-    Server::builder()
-        .add_service(svc)
-        .serve_with_incoming(TcpListenerStream::new(listener))
+    let router = Server::builder()
+        .max_concurrent_streams(max_concurrent_streams)
+        .layer(request_id::RequestIdLayer)
+        .add_service(svc);
+    if listener_cfg.proxy_protocol {
+        let incoming = proxied::incoming(listener, listener_cfg.clone());
+        return router
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .await;
+    }
+    let incoming =
+        TcpIncoming::from_listener(listener, listener_cfg.nodelay, None).expect("incoming");
+    router
+        .serve_with_incoming_shutdown(incoming, shutdown)
         .await
-        .expect("serve");
 }
@@ -1,17 +1,58 @@
+use std::process::ExitCode;
+
 use kadedb_services_auth::AuthConfig;
+use kadedb_services_grpc::QueryServiceImpl;
+use kadedb_services_telemetry::{ConfigFile, ListenerConfig, StartupError};
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
-        )
-        .init();
+fn main() -> ExitCode {
+    match start() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => err.report("kadedb-grpc"),
+    }
+}
+
+fn start() -> Result<(), StartupError> {
+    ConfigFile::load_into_env().map_err(|err| StartupError::Config(err.to_string()))?;
+    kadedb_services_ffi::install_panic_hook(kadedb_services_telemetry::redact_sql);
+    if let Ok(prefix) = std::env::var("KADEDB_FFI_THREAD_PREFIX") {
+        kadedb_services_ffi::set_thread_prefix(prefix);
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(StartupError::Runtime)?
+        .block_on(run())
+}
+
+async fn run() -> Result<(), StartupError> {
+    let _telemetry = kadedb_services_telemetry::init("kadedb-grpc");
 
-    let addr = "0.0.0.0:50051".parse().expect("valid addr");
     let auth_cfg = AuthConfig::from_env();
+    auth_cfg
+        .validate()
+        .map_err(|err| StartupError::Auth(err.to_string()))?;
 
-    tracing::info!("gRPC listening on {addr}");
+    let service = QueryServiceImpl::from_env();
+    service.validate().map_err(StartupError::Config)?;
+    let service = service
+        .with_storage_from_env()
+        .map_err(|err| StartupError::Storage(err.to_string()))?;
 
-    kadedb_services_grpc::serve(addr, auth_cfg).await;
+    let addr = "0.0.0.0:50051".parse().expect("valid addr");
+    let listener_cfg = ListenerConfig::from_env();
+    let listener = listener_cfg
+        .bind(addr)
+        .map_err(|source| StartupError::Bind { addr, source })?;
+
+    tracing::info!("gRPC listening on {addr}");
+    kadedb_services_grpc::serve_with_shutdown(
+        listener,
+        auth_cfg,
+        service,
+        &listener_cfg,
+        std::future::pending(),
+    )
+    .await
+    .map_err(|err| StartupError::Serve(err.to_string()))
 }
@@ -0,0 +1,98 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use kadedb_services_telemetry::ListenerConfig;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+/// A connection attributed to the client its PROXY protocol header named,
+/// which calls then see as [`tonic::Request::remote_addr`].
+pub(crate) struct ProxiedStream {
+    stream: TcpStream,
+    client: SocketAddr,
+}
+
+impl Connected for ProxiedStream {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> TcpConnectInfo {
+        TcpConnectInfo {
+            local_addr: self.stream.local_addr().ok(),
+            remote_addr: Some(self.client),
+        }
+    }
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Accepts connections from `listener`, reading each one's PROXY protocol
+/// header on its own task so a slow client doesn't hold up the others.
+/// Connections whose header is missing or malformed are dropped. Accepting
+/// stops once the returned stream is dropped.
+pub(crate) fn incoming(
+    listener: TcpListener,
+    config: ListenerConfig,
+) -> ReceiverStream<io::Result<ProxiedStream>> {
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            let mut stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        tracing::error!(error = %err, "accept failed");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                },
+                () = tx.closed() => break,
+            };
+            if let Err(err) = stream.set_nodelay(config.nodelay) {
+                tracing::trace!(error = %err, "failed to set TCP_NODELAY");
+            }
+            let tx = tx.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                match config.client_addr(&mut stream).await {
+                    Ok(client) => {
+                        let _ = tx.send(Ok(ProxiedStream { stream, client })).await;
+                    }
+                    Err(err) => tracing::debug!(error = %err, "dropped connection"),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
@@ -0,0 +1,68 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::HeaderValue;
+use kadedb_services_telemetry::{request_span, RequestId, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::Instrument;
+
+/// Gives every call a [`RequestId`], taken from incoming `x-request-id`
+/// metadata when it is usable. Log lines emitted while handling the call
+/// carry it as `request_id`, and the response (or error) returns it as
+/// `x-request-id` metadata.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<http::Request<B>> for RequestIdService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let id = RequestId::from_header(
+            req.headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok()),
+        );
+        req.extensions_mut().insert(id.clone());
+        let traceparent = req
+            .headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|v| v.to_str().ok());
+        let span = request_span(&id, traceparent);
+        let call = span.in_scope(|| self.inner.call(req));
+        Box::pin(
+            async move {
+                let mut res = call.await?;
+                if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::Instant;
+
+use kadedb_services_ffi::{
+    spawn_query, ColumnCase, ColumnInfo, ColumnType, FfiError, PoolGuard, RowRef,
+};
+use tokio::sync::mpsc;
+
+use crate::kadedb::QueryRow;
+
+/// One result row, keyed by column name.
+pub(crate) type Row = serde_json::Map<String, serde_json::Value>;
+
+/// Rows pulled ahead of the stream sending them.
+pub(crate) const ROW_BUFFER: usize = 8;
+
+/// `row` as a message, its column names in `case`.
+pub(crate) fn query_row(row: Row, case: ColumnCase) -> QueryRow {
+    let row: Row = match case {
+        ColumnCase::Preserve => row,
+        case => row.into_iter().map(|(k, v)| (case.apply(k), v)).collect(),
+    };
+    QueryRow {
+        json: serde_json::Value::Object(row).to_string(),
+        progress: None,
+    }
+}
+
+/// Runs `query` on `guard`'s storage and reads its rows on the FFI thread
+/// pool, one at a time as the receiver takes them: at most [`ROW_BUFFER`]
+/// wait between the result set and the stream, plus what HTTP/2 flow
+/// control lets through to the client, so a result far larger than memory
+/// is never collected.
+///
+/// The task owns `guard`. The pool slot is returned once reading stops: at
+/// the end of the result, on an error (the last item), at `deadline`, or as
+/// soon as the receiver is dropped because the client went away.
+pub(crate) fn produce(
+    guard: PoolGuard,
+    query: String,
+    case: ColumnCase,
+    deadline: Option<Instant>,
+) -> mpsc::Receiver<Result<QueryRow, FfiError>> {
+    let (tx, rx) = mpsc::channel(ROW_BUFFER);
+    let storage = guard.storage();
+    spawn_query(query.clone(), move || {
+        let run = || {
+            let statement = storage.prepare(&query);
+            let mut rs = storage.execute_prepared(&statement, &[])?;
+            let columns = rs.columns();
+            let mut reader = rs.row_reader();
+            if let Some(deadline) = deadline {
+                reader = reader.with_deadline(deadline);
+            }
+            while let Some(row) = reader.next_row()? {
+                let row = query_row(to_row(&columns, row), case);
+                if tx.blocking_send(Ok(row)).is_err() {
+                    break;
+                }
+            }
+            Ok::<_, FfiError>(())
+        };
+        if let Err(err) = run() {
+            if err.is_storage_failure() {
+                guard.record_failure();
+            }
+            let _ = tx.blocking_send(Err(err));
+        }
+    });
+    rx
+}
+
+/// `row` keyed by column name, each cell as the JSON value of its column's
+/// type: strings without the quotes the native layer renders them with,
+/// NULL as `null`. A cell that doesn't parse as its type is kept as text.
+fn to_row(columns: &[ColumnInfo], row: RowRef<'_>) -> Row {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let cell = row.get(i);
+            let value = if row.is_null(i) {
+                serde_json::Value::Null
+            } else {
+                cell_value(column.column_type, cell)
+            };
+            (column.name.clone(), value)
+        })
+        .collect()
+}
+
+fn cell_value(ty: ColumnType, cell: &str) -> serde_json::Value {
+    let text = || serde_json::Value::String(cell.to_string());
+    match ty {
+        ColumnType::Integer => cell
+            .trim()
+            .parse::<i64>()
+            .map_or_else(|_| text(), Into::into),
+        ColumnType::Float => cell
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or_else(text, serde_json::Value::Number),
+        ColumnType::Boolean => cell
+            .trim()
+            .parse::<bool>()
+            .map_or_else(|_| text(), Into::into),
+        ColumnType::String => {
+            let unquoted = cell
+                .strip_prefix('"')
+                .and_then(|c| c.strip_suffix('"'))
+                .unwrap_or(cell);
+            serde_json::Value::String(unquoted.to_string())
+        }
+        ColumnType::Null => serde_json::Value::Null,
+    }
+}
@@ -0,0 +1,114 @@
+use kadedb_services_auth::AuthConfig;
+use kadedb_services_grpc::auth_interceptor;
+use tonic::{Code, Request};
+
+const SECRET: &str = "test-secret";
+
+fn enabled() -> AuthConfig {
+    AuthConfig {
+        enabled: true,
+        jwt_secret: Some(SECRET.to_string()),
+    }
+}
+
+fn request(authorization: Option<&str>) -> Request<()> {
+    let mut req = Request::new(());
+    if let Some(value) = authorization {
+        req.metadata_mut()
+            .insert("authorization", value.parse().expect("metadata value"));
+    }
+    req
+}
+
+fn token(role: &str) -> String {
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &serde_json::json!({"sub": "tester", "role": role, "exp": u32::MAX}),
+        &jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .expect("encode token")
+}
+
+#[test]
+fn disabled_auth_passes_requests_through() {
+    let cfg = AuthConfig {
+        enabled: false,
+        jwt_secret: None,
+    };
+    assert!(auth_interceptor(&cfg, request(None)).is_ok());
+}
+
+#[test]
+fn missing_header_is_unauthenticated() {
+    let status = auth_interceptor(&enabled(), request(None)).expect_err("rejected");
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[test]
+fn wrong_scheme_is_unauthenticated() {
+    let header = format!("Basic {}", token("read"));
+    let status = auth_interceptor(&enabled(), request(Some(&header))).expect_err("rejected");
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[test]
+fn malformed_token_is_unauthenticated() {
+    let status =
+        auth_interceptor(&enabled(), request(Some("Bearer not.a.jwt"))).expect_err("rejected");
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[test]
+fn unknown_role_is_rejected() {
+    let header = format!("Bearer {}", token("superuser"));
+    let status = auth_interceptor(&enabled(), request(Some(&header))).expect_err("rejected");
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[test]
+fn valid_token_is_accepted() {
+    let header = format!("Bearer {}", token("read"));
+    assert!(auth_interceptor(&enabled(), request(Some(&header))).is_ok());
+}
+
+#[test]
+fn token_with_api_key_is_ambiguous() {
+    let mut req = request(Some(&format!("Bearer {}", token("read"))));
+    req.metadata_mut()
+        .insert("x-api-key", "some-key".parse().expect("metadata value"));
+    let status = auth_interceptor(&enabled(), req).expect_err("rejected");
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "ambiguous_credentials");
+}
+
+#[test]
+fn accepted_call_carries_scopes_and_custom_claims() {
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &serde_json::json!({
+            "sub": "tester",
+            "role": "read",
+            "tenant": "acme",
+            "scopes": ["reports:read"],
+            "region": "eu-west",
+            "exp": u32::MAX,
+        }),
+        &jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .expect("encode token");
+    let req =
+        auth_interceptor(&enabled(), request(Some(&format!("Bearer {token}")))).expect("accepted");
+
+    let principal = req
+        .extensions()
+        .get::<kadedb_services_auth::Principal>()
+        .expect("principal");
+    assert_eq!(principal.tenant.as_deref(), Some("acme"));
+    assert!(principal.has_scope("reports:read"));
+    assert!(!principal.has_scope("reports:write"));
+    assert_eq!(
+        principal.claim::<String>("region").as_deref(),
+        Some("eu-west")
+    );
+    assert_eq!(principal.claim::<u64>("region"), None);
+}
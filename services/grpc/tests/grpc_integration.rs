@@ -1,7 +1,17 @@
-use kadedb_services_auth::AuthConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use kadedb_services_auth::{AuthConfig, Role, RoleTimeout, RoleTimeouts};
+use kadedb_services_ffi::{
+    status_code, AllowedStatements, ColumnCase, ColumnSpec, ColumnType, ErrorCode, ErrorVerbosity,
+    StatementKind, Storage, StoragePool, Value,
+};
 use kadedb_services_grpc::{
-    kadedb::query_service_client::QueryServiceClient, kadedb::QueryRequest,
+    kadedb::query_service_client::QueryServiceClient, kadedb::ColumnType as ProtoColumnType,
+    kadedb::InsertRequest, kadedb::QueryRequest, Compression, QueryServiceImpl,
+    NO_COMPRESSION_HEADER, TENANT_HEADER,
 };
+use tonic::codec::CompressionEncoding;
 
 #[tokio::test]
 async fn grpc_query_streams_rows() {
@@ -10,6 +20,46 @@ async fn grpc_query_streams_rows() {
         .expect("bind");
     let addr = listener.local_addr().expect("local_addr");
 
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default().with_storage(StoragePool::with_storage(readings_with(3), 1)),
+    ));
+
+    let endpoint = format!("http://{addr}");
+    let mut client = QueryServiceClient::connect(endpoint)
+        .await
+        .expect("connect");
+
+    let mut stream = client
+        .query(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("query")
+        .into_inner();
+
+    let mut count = 0usize;
+    while let Some(_row) = stream.message().await.expect("message") {
+        count += 1;
+    }
+
+    assert_eq!(count, 3);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_queries_without_storage_fail_precondition() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
     let server = tokio::spawn(async move {
         kadedb_services_grpc::serve_with_listener(
             listener,
@@ -21,25 +71,1487 @@ async fn grpc_query_streams_rows() {
         .await;
     });
 
-    let endpoint = format!("http://{addr}");
-    let mut client = QueryServiceClient::connect(endpoint)
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let request = || QueryRequest {
+        query: "SELECT * FROM readings".to_string(),
+        ..Default::default()
+    };
+
+    let status = client.query(request()).await.expect_err("query");
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(
+        status.message(),
+        "Query needs storage, and none is attached to this server"
+    );
+    let status = client
+        .query_unary(request())
+        .await
+        .expect_err("query_unary");
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_service_from_env_opens_storage_as_the_binary_does() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    // What the kadedb-grpc binary serves.
+    let service = QueryServiceImpl::from_env()
+        .with_storage_from_env()
+        .expect("storage from env");
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        service,
+    ));
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    // Storage answers, so the table is unknown rather than the call
+    // failing its precondition.
+    let status = client
+        .insert_batch(tokio_stream::iter(vec![InsertRequest {
+            table: "missing".to_string(),
+            json: r#"{"id": 1}"#.to_string(),
+        }]))
+        .await
+        .expect_err("unknown table");
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let status = client
+        .query_unary(QueryRequest {
+            query: "SELECT * FROM missing".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect_err("unknown table");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_query_unary_enforces_row_cap() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let storage = readings_with(3);
+    let pool = StoragePool::with_storage(storage.clone(), 1);
+
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default()
+            .with_unary_row_cap(3)
+            .with_storage(pool.clone()),
+    ));
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let unary = |query: &str| QueryRequest {
+        query: query.to_string(),
+        ..Default::default()
+    };
+
+    let result = client
+        .query_unary(unary("SELECT * FROM readings"))
+        .await
+        .expect("query unary")
+        .into_inner();
+    let rows: Vec<&str> = result.rows.iter().map(|r| r.json.as_str()).collect();
+    assert_eq!(
+        rows,
+        [
+            r#"{"id":0,"value":0.0}"#,
+            r#"{"id":1,"value":0.5}"#,
+            r#"{"id":2,"value":1.0}"#,
+        ]
+    );
+
+    let insert = storage.prepare_insert("readings").expect("prepare insert");
+    insert
+        .execute(&storage, &[Value::Integer(3), Value::Null])
+        .expect("insert");
+    let status = client
+        .query_unary(unary("SELECT * FROM readings"))
+        .await
+        .expect_err("over cap");
+    assert_eq!(status.code(), tonic::Code::OutOfRange);
+    assert_eq!(idle(&pool).await, 0);
+
+    let status = client
+        .query_unary(unary("SELECT * FROM missing"))
+        .await
+        .expect_err("unknown table");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(idle(&pool).await, 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_responses_carry_the_request_id() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default().with_storage(StoragePool::with_storage(readings_with(1), 1)),
+    ));
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let request_id = |metadata: &tonic::metadata::MetadataMap| {
+        metadata
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let res = client
+        .query_unary(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("query_unary");
+    let generated = request_id(res.metadata()).expect("request id");
+    assert_eq!(generated.len(), 16);
+
+    // Errors carry it too, and a caller's own id is kept.
+    let mut request = tonic::Request::new(QueryRequest {
+        query: "x".repeat(kadedb_services_ffi::DEFAULT_MAX_QUERY_LENGTH + 1),
+        ..Default::default()
+    });
+    request
+        .metadata_mut()
+        .insert("x-request-id", "ticket-42".parse().unwrap());
+    let status = client.query_unary(request).await.expect_err("too long");
+    assert_eq!(request_id(status.metadata()).as_deref(), Some("ticket-42"));
+
+    server.abort();
+}
+
+#[test]
+fn ffi_errors_map_to_grpc_codes() {
+    use kadedb_services_ffi::FfiError;
+    use tonic::{Code, Status};
+
+    let cases = [
+        (FfiError::ExecuteQueryFailed, Code::InvalidArgument),
+        (
+            FfiError::ParamCountMismatch {
+                expected: 1,
+                got: 0,
+            },
+            Code::InvalidArgument,
+        ),
+        (FfiError::UnknownTable("t".into()), Code::NotFound),
+        (FfiError::CreateStorageFailed, Code::Unavailable),
+        (FfiError::Timeout, Code::DeadlineExceeded),
+        (FfiError::Cancelled, Code::Cancelled),
+    ];
+    for (err, code) in cases {
+        assert_eq!(Status::from(err).code(), code);
+    }
+}
+
+#[tokio::test]
+async fn grpc_describe_query_returns_columns() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let storage = readings_with(2);
+
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default().with_storage(StoragePool::with_storage(storage.clone(), 1)),
+    ));
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let describe = |query: &str| QueryRequest {
+        query: query.to_string(),
+        ..Default::default()
+    };
+
+    let schema = client
+        .describe_query(describe("SELECT * FROM readings"))
+        .await
+        .expect("describe")
+        .into_inner();
+    let columns: Vec<(&str, i32)> = schema
+        .columns
+        .iter()
+        .map(|c| (c.name.as_str(), c.r#type))
+        .collect();
+    assert_eq!(
+        columns,
+        [
+            ("id", ProtoColumnType::Integer as i32),
+            ("value", ProtoColumnType::Float as i32),
+        ]
+    );
+
+    for (query, message) in [
+        (" ", "query is empty"),
+        ("SELECT * FROM missing", "query returned null result set"),
+        (
+            "DELETE FROM readings",
+            "DescribeQuery takes a SELECT statement",
+        ),
+    ] {
+        let status = client
+            .describe_query(describe(query))
+            .await
+            .expect_err(query);
+        assert_eq!(status.code(), tonic::Code::InvalidArgument, "{query}");
+        assert_eq!(status.message(), message, "{query}");
+    }
+    // Describing never runs anything but a SELECT.
+    let rows = storage
+        .execute_query("SELECT * FROM readings")
+        .expect("select")
+        .all_rows_as_strings()
+        .expect("rows");
+    assert_eq!(rows.len(), 2);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_query_truncates_at_max_rows() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_storage(StoragePool::with_storage(readings_with(5), 1)),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
         .await
         .expect("connect");
 
     let mut stream = client
         .query(QueryRequest {
-            query: "SELECT 1".to_string(),
+            query: "SELECT * FROM readings".to_string(),
+            max_rows: 2,
+            ..Default::default()
         })
         .await
         .expect("query")
         .into_inner();
 
-    let mut count = 0usize;
-    while let Some(_row) = stream.message().await.expect("message") {
-        count += 1;
+    let mut ids = Vec::new();
+    while let Some(row) = stream.message().await.expect("message") {
+        let row: serde_json::Value = serde_json::from_str(&row.json).expect("json");
+        ids.push(row["id"].as_i64().expect("id"));
     }
+    assert_eq!(ids, [0, 1]);
+    let trailers = stream.trailers().await.expect("trailers").expect("present");
+    assert_eq!(
+        trailers
+            .get(kadedb_services_grpc::TRUNCATED_TRAILER)
+            .and_then(|v| v.to_str().ok()),
+        Some("true")
+    );
 
-    assert_eq!(count, 3);
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_query_sends_a_checksum_trailer_on_request() {
+    use sha2::{Digest, Sha256};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_storage(StoragePool::with_storage(readings_with(3), 1)),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+
+    let request = |query: &str| {
+        let mut request = tonic::Request::new(QueryRequest {
+            query: query.to_string(),
+            ..Default::default()
+        });
+        request.metadata_mut().insert(
+            kadedb_services_grpc::CHECKSUM_METADATA,
+            "sha256".parse().expect("metadata"),
+        );
+        request
+    };
+    let mut stream = client
+        .query(request("SELECT * FROM readings"))
+        .await
+        .expect("query")
+        .into_inner();
+
+    let mut digest = Sha256::new();
+    let mut rows = Vec::new();
+    while let Some(row) = stream.message().await.expect("message") {
+        digest.update(row.json.as_bytes());
+        digest.update(b"\n");
+        rows.push(row.json);
+    }
+    assert_eq!(
+        rows,
+        [
+            r#"{"id":0,"value":0.0}"#,
+            r#"{"id":1,"value":0.5}"#,
+            r#"{"id":2,"value":1.0}"#,
+        ]
+    );
+    let trailers = stream.trailers().await.expect("trailers").expect("present");
+    assert_eq!(
+        trailers
+            .get(kadedb_services_grpc::CHECKSUM_METADATA)
+            .and_then(|v| v.to_str().ok()),
+        Some(format!("sha256:{:x}", digest.finalize()).as_str())
+    );
+
+    // A stream that fails carries no checksum.
+    let status = match client.query(request("SELECT * FROM missing")).await {
+        Ok(response) => response
+            .into_inner()
+            .message()
+            .await
+            .expect_err("unknown table"),
+        Err(status) => status,
+    };
+    assert!(!status
+        .metadata()
+        .contains_key(kadedb_services_grpc::CHECKSUM_METADATA));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_query_batched_packs_rows_into_batches() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_storage(StoragePool::with_storage(readings_with(3), 1)),
+        )
+        .await;
+    });
+
+    let client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let batch_sizes = |batch_size: u32, max_rows: u64| {
+        let mut client = client.clone();
+        async move {
+            let mut stream = client
+                .query_batched(QueryRequest {
+                    query: "SELECT * FROM readings".to_string(),
+                    max_rows,
+                    batch_size,
+                    ..Default::default()
+                })
+                .await
+                .expect("query")
+                .into_inner();
+            let mut sizes = Vec::new();
+            let mut ids = Vec::new();
+            while let Some(batch) = stream.message().await.expect("message") {
+                sizes.push(batch.rows.len());
+                for row in batch.rows {
+                    let row: serde_json::Value = serde_json::from_str(&row.json).expect("json");
+                    ids.push(row["id"].as_i64().expect("id"));
+                }
+            }
+            // Batches keep the rows in result order.
+            assert_eq!(ids, (0..ids.len() as i64).collect::<Vec<_>>());
+            let truncated = stream
+                .trailers()
+                .await
+                .expect("trailers")
+                .is_some_and(|t| t.contains_key(kadedb_services_grpc::TRUNCATED_TRAILER));
+            (sizes, truncated)
+        }
+    };
+
+    // The table has three rows: a full batch, then the rest at the end.
+    assert_eq!(batch_sizes(2, 0).await, (vec![2, 1], false));
+    assert_eq!(batch_sizes(0, 0).await, (vec![3], false));
+    // Trailers still close the stream after the last batch.
+    assert_eq!(batch_sizes(2, 2).await, (vec![2], true));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_rejects_overlong_queries() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default().with_max_query_length(8),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+
+    let status = client
+        .query_unary(QueryRequest {
+            query: "SELECT 1 FROM t".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect_err("too long");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "query is 15 bytes, longer than the 8 allowed"
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_refuses_statement_kinds_outside_the_allowlist() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_allowed_statements(
+                    AllowedStatements::parse("select").expect("valid allowlist"),
+                )
+                .with_storage(StoragePool::with_storage(readings_with(1), 1)),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+
+    client
+        .query_unary(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("select allowed");
+
+    let status = client
+        .query_unary(QueryRequest {
+            query: "DELETE FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect_err("delete refused");
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    assert_eq!(status.message(), "delete statements are not allowed");
+
+    // InsertBatch takes no SQL but is held to the list too.
+    let status = client
+        .insert_batch(tokio_stream::iter(vec![InsertRequest {
+            table: "readings".to_string(),
+            json: r#"{"id": 2}"#.to_string(),
+        }]))
+        .await
+        .expect_err("insert refused");
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    assert_eq!(status.message(), "insert statements are not allowed");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_reports_column_names_in_the_configured_case() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_column_case(ColumnCase::Upper)
+                .with_storage(StoragePool::with_storage(readings_with(1), 1)),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let request = QueryRequest {
+        query: "SELECT * FROM readings".to_string(),
+        ..Default::default()
+    };
+
+    let result = client
+        .query_unary(request.clone())
+        .await
+        .expect("query")
+        .into_inner();
+    let row: serde_json::Value = serde_json::from_str(&result.rows[0].json).expect("json");
+    assert_eq!(row, serde_json::json!({"ID": 0, "VALUE": 0.0}));
+
+    let schema = client
+        .describe_query(request)
+        .await
+        .expect("describe")
+        .into_inner();
+    let names: Vec<&str> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["ID", "VALUE"]);
+
+    server.abort();
+}
+
+#[test]
+fn storage_errors_map_to_retryable_or_final_statuses() {
+    use kadedb_services_ffi::{ColumnType, FfiError};
+    use tonic::{Code, Status};
+
+    let busy = Status::from(FfiError::PoolBusy);
+    assert_eq!(busy.code(), Code::Unavailable);
+    assert_eq!(busy.metadata().get("retry-after").unwrap(), "1");
+    let details = busy.details();
+    assert!(details
+        .windows(b"google.rpc.RetryInfo".len())
+        .any(|w| w == b"google.rpc.RetryInfo"));
+
+    let constraint = Status::from(FfiError::TypeMismatch {
+        column: "id".into(),
+        expected: ColumnType::Integer,
+    });
+    assert_eq!(constraint.code(), Code::FailedPrecondition);
+    assert!(constraint.metadata().get("retry-after").is_none());
+    assert!(!constraint
+        .details()
+        .windows(b"google.rpc.RetryInfo".len())
+        .any(|w| w == b"google.rpc.RetryInfo"));
+
+    assert_eq!(
+        Status::from(FfiError::ExecuteQueryFailed).code(),
+        Code::InvalidArgument
+    );
+}
+
+#[test]
+fn trace_sampling_follows_the_callers_traceparent() {
+    use kadedb_services_telemetry::TraceSampling;
+
+    let sampled = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let unsampled = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00";
+    let none = TraceSampling::ratio(0.0);
+    let all = TraceSampling::ratio(1.0);
+
+    assert!(none.sample(Some(sampled)));
+    assert!(!all.sample(Some(unsampled)));
+    // Without a usable traceparent, the ratio decides.
+    assert!(!none.sample(None));
+    assert!(all.sample(None));
+    assert!(!none.sample(Some("00-not-a-trace-01")));
+    let half = TraceSampling::ratio(0.5);
+    let kept = (0..1000).filter(|_| half.sample(None)).count();
+    assert!((350..650).contains(&kept), "{kept}");
+}
+
+/// `readings` holding rows `0..rows`.
+fn readings_with(rows: i64) -> Arc<Storage> {
+    let storage = readings_storage();
+    let insert = storage.prepare_insert("readings").expect("prepare insert");
+    for id in 0..rows {
+        insert
+            .execute(
+                &storage,
+                &[Value::Integer(id), Value::Float(id as f64 / 2.0)],
+            )
+            .expect("insert");
+    }
+    storage
+}
+
+/// `pool`'s in-use count once it drops to 0, or after a second.
+async fn idle(pool: &StoragePool) -> usize {
+    for _ in 0..100 {
+        if pool.in_use() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    pool.in_use()
+}
+
+#[tokio::test]
+async fn grpc_streams_results_without_collecting_them() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let pool = StoragePool::with_storage(readings_with(50_000), 1);
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default()
+            .with_max_rows(u64::MAX)
+            .with_storage(pool.clone()),
+    ));
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+
+    let mut stream = client
+        .query(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("query")
+        .into_inner();
+    let first = stream.message().await.expect("row").expect("a row");
+    let first: serde_json::Value = serde_json::from_str(&first.json).expect("json");
+    assert_eq!(first, serde_json::json!({"id": 0, "value": 0.0}));
+    for _ in 0..10 {
+        stream.message().await.expect("row").expect("more rows");
+    }
+
+    // A client that stops reading holds the reader back: it has only read
+    // what flow control and the buffers let through, so it still holds its
+    // pool slot rather than having read the whole result.
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(pool.in_use(), 1);
+
+    // Once the call is dropped the reader stops and gives the slot back.
+    drop(stream);
+    assert_eq!(idle(&pool).await, 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_cancelled_streams_give_back_their_pool_slot() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let pool = StoragePool::with_storage(readings_with(50_000), 1);
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default()
+            .with_max_rows(u64::MAX)
+            .with_storage(pool.clone()),
+    ));
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let request = || QueryRequest {
+        query: "SELECT * FROM readings".to_string(),
+        ..Default::default()
+    };
+
+    // The only slot is taken by each stream in turn, so every call after
+    // the first only runs once the one before it was cancelled.
+    for _ in 0..3 {
+        let mut stream = client.query(request()).await.expect("query").into_inner();
+        stream.message().await.expect("row").expect("a row");
+        assert_eq!(pool.in_use(), 1);
+        drop(stream);
+
+        assert_eq!(idle(&pool).await, 0, "the cancelled stream kept its slot");
+    }
+
+    let mut batches = client
+        .query_batched(request())
+        .await
+        .expect("query batched")
+        .into_inner();
+    batches.message().await.expect("batch").expect("a batch");
+    drop(batches);
+    assert_eq!(idle(&pool).await, 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_progress_events_interleave_with_rows_from_storage() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default()
+            .with_max_rows(u64::MAX)
+            .with_progress_interval(Some(std::time::Duration::from_millis(20)))
+            .with_storage(StoragePool::with_storage(readings_with(50_000), 1)),
+    ));
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+
+    let mut stream = client
+        .query(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            progress: true,
+            ..Default::default()
+        })
+        .await
+        .expect("query")
+        .into_inner();
+    // A client that pauses holds the stream open past the interval, so an
+    // event is due before the rows run out.
+    stream.message().await.expect("message").expect("a row");
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let mut rows = 1;
+    let progress = loop {
+        let message = stream.message().await.expect("message").expect("more");
+        if let Some(event) = message.progress {
+            assert!(message.json.is_empty());
+            break event;
+        }
+        rows += 1;
+    };
+    assert!(
+        progress.rows_sent >= rows,
+        "{} < {rows}",
+        progress.rows_sent
+    );
+    assert!(progress.elapsed_ms >= 20);
+
+    // Rows keep coming after the event, in order.
+    let next = stream.message().await.expect("message").expect("more");
+    let next: serde_json::Value = serde_json::from_str(&next.json).expect("json");
+    assert_eq!(next["id"], rows);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_query_past_request_timeout_is_deadline_exceeded() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_request_timeout(std::time::Duration::ZERO)
+                .with_storage(StoragePool::with_storage(readings_with(3), 1)),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+
+    let mut stream = client
+        .query(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("query")
+        .into_inner();
+
+    let status = loop {
+        match stream.message().await {
+            Ok(Some(_)) => continue,
+            Ok(None) => panic!("stream finished within a zero timeout"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+    assert_eq!(
+        status
+            .metadata()
+            .get(kadedb_services_grpc::ROWS_SENT_TRAILER)
+            .and_then(|v| v.to_str().ok()),
+        Some("0")
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_client_deadline_overrides_the_request_timeout_up_to_the_max() {
+    let spawn = |service: QueryServiceImpl| async move {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("local_addr");
+        let server = tokio::spawn(kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            service.with_storage(StoragePool::with_storage(readings_with(3), 1)),
+        ));
+        let client = QueryServiceClient::connect(format!("http://{addr}"))
+            .await
+            .expect("connect");
+        (client, server)
+    };
+    let call = || {
+        let mut request = tonic::Request::new(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        });
+        request.set_timeout(std::time::Duration::from_secs(30));
+        request
+    };
+
+    // The client's 30s replaces a zero server default.
+    let (mut client, server) =
+        spawn(QueryServiceImpl::default().with_request_timeout(std::time::Duration::ZERO)).await;
+    let mut stream = client.query(call()).await.expect("query").into_inner();
+    let mut rows = 0;
+    while stream.message().await.expect("row").is_some() {
+        rows += 1;
+    }
+    assert_eq!(rows, 3);
+    server.abort();
+
+    // ...but not past the server's maximum.
+    let (mut client, server) =
+        spawn(QueryServiceImpl::default().with_max_query_timeout(std::time::Duration::ZERO)).await;
+    let mut stream = client.query(call()).await.expect("query").into_inner();
+    let status = loop {
+        match stream.message().await {
+            Ok(Some(_)) => continue,
+            Ok(None) => panic!("stream finished past a zero maximum"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_role_timeouts_apply_by_the_callers_role() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let zero = RoleTimeout {
+        default: Some(std::time::Duration::ZERO),
+        max: Some(std::time::Duration::ZERO),
+    };
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: true,
+            jwt_secret: Some("secret".to_string()),
+        },
+        QueryServiceImpl::default()
+            .with_role_timeouts(RoleTimeouts::default().with(Role::Read, zero))
+            .with_storage(StoragePool::with_storage(readings_with(3), 1)),
+    ));
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let call = |role: &str| {
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &serde_json::json!({"role": role, "exp": u32::MAX}),
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .expect("encode token");
+        let mut request = tonic::Request::new(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        });
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {token}").parse().expect("metadata"),
+        );
+        // Past the reader's maximum, so it is cut to zero.
+        request.set_timeout(std::time::Duration::from_secs(30));
+        request
+    };
+
+    let mut stream = client
+        .query(call("read"))
+        .await
+        .expect("query")
+        .into_inner();
+    let status = loop {
+        match stream.message().await {
+            Ok(Some(_)) => continue,
+            Ok(None) => panic!("reader's stream finished past a zero maximum"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+
+    let mut stream = client
+        .query(call("admin"))
+        .await
+        .expect("query")
+        .into_inner();
+    let mut rows = 0;
+    while stream.message().await.expect("row").is_some() {
+        rows += 1;
+    }
+    assert_eq!(rows, 3);
+
+    server.abort();
+}
+
+#[test]
+fn unknown_compression_fails_validation() {
+    // An invalid value leaves the default in place, so setting it can't
+    // change what other tests' services do.
+    std::env::set_var("KADEDB_GRPC_COMPRESSION", "brotli");
+    let service = QueryServiceImpl::from_env();
+    std::env::remove_var("KADEDB_GRPC_COMPRESSION");
+
+    let err = service.validate().expect_err("invalid compression");
+    assert!(err.contains("`brotli`"), "{err}");
+    assert_eq!(QueryServiceImpl::default().validate(), Ok(()));
+}
+
+#[test]
+fn unknown_statement_kinds_fail_to_parse() {
+    // Parsed directly: an invalid KADEDB_ALLOWED_STATEMENTS allows nothing,
+    // which would leak into other tests' services.
+    let err = AllowedStatements::parse("select, selct").expect_err("unknown kind");
+    assert!(err.contains("`selct`"), "{err}");
+    assert_eq!(
+        AllowedStatements::parse(" , "),
+        Ok(AllowedStatements::default())
+    );
+    assert_eq!(
+        AllowedStatements::parse("SELECT,insert"),
+        Ok(AllowedStatements::only([
+            StatementKind::Select,
+            StatementKind::Insert
+        ]))
+    );
+}
+
+#[tokio::test]
+async fn grpc_responses_are_compressed_unless_the_call_opts_out() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_compression(Compression::Zstd)
+                .with_storage(StoragePool::with_storage(readings_with(3), 1)),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect")
+        .accept_compressed(CompressionEncoding::Zstd);
+
+    let res = client
+        .query_unary(QueryRequest {
+            query: "SELECT * FROM readings".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("query");
+    assert_eq!(
+        res.metadata()
+            .get("grpc-encoding")
+            .and_then(|v| v.to_str().ok()),
+        Some("zstd")
+    );
+    assert_eq!(res.into_inner().rows.len(), 3);
+
+    let mut request = tonic::Request::new(QueryRequest {
+        query: "SELECT * FROM readings".to_string(),
+        ..Default::default()
+    });
+    request
+        .metadata_mut()
+        .insert(NO_COMPRESSION_HEADER, "1".parse().expect("metadata"));
+    // The response still names the encoding; its message is sent with the
+    // compressed flag unset.
+    let res = client.query_unary(request).await.expect("query");
+    assert_eq!(res.into_inner().rows.len(), 3);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_server_advertises_max_concurrent_streams() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default().with_max_concurrent_streams(Some(2)),
+        )
+        .await;
+    });
+
+    let tcp = tokio::net::TcpStream::connect(addr).await.expect("connect");
+    let (client, connection) = h2::client::handshake(tcp).await.expect("handshake");
+    tokio::spawn(connection);
+    let mut client = client.ready().await.expect("ready");
+
+    // Any request round trip guarantees the server's SETTINGS have arrived.
+    let request = http::Request::post(format!("http://{addr}/kadedb.QueryService/DescribeQuery"))
+        .header("content-type", "application/grpc")
+        .body(())
+        .expect("request");
+    let (response, _) = client.send_request(request, true).expect("send");
+    response.await.expect("response");
+
+    assert_eq!(client.current_max_send_streams(), 2);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_proxy_protocol_connections_need_a_header() {
+    use kadedb_services_telemetry::ListenerConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_with_config(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default(),
+            &ListenerConfig {
+                proxy_protocol: true,
+                ..Default::default()
+            },
+        )
+        .await;
+    });
+
+    let mut tcp = tokio::net::TcpStream::connect(addr).await.expect("connect");
+    tcp.write_all(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 443\r\n")
+        .await
+        .expect("write");
+    let (client, connection) = h2::client::handshake(tcp).await.expect("handshake");
+    tokio::spawn(connection);
+    let mut client = client.ready().await.expect("ready");
+    let request = http::Request::post(format!("http://{addr}/kadedb.QueryService/DescribeQuery"))
+        .header("content-type", "application/grpc")
+        .body(())
+        .expect("request");
+    let (response, _) = client.send_request(request, true).expect("send");
+    let response = response.await.expect("response");
+    assert_eq!(response.status(), 200);
+
+    // Without a header the connection is dropped before HTTP/2 starts.
+    let mut tcp = tokio::net::TcpStream::connect(addr).await.expect("connect");
+    tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+        .await
+        .expect("write");
+    let mut buf = Vec::new();
+    let _ = tcp.read_to_end(&mut buf).await;
+    assert!(buf.is_empty());
+
+    server.abort();
+}
+
+/// Storage holding an empty `readings(id INTEGER NOT NULL, value FLOAT)`.
+fn readings_storage() -> Arc<Storage> {
+    let storage = Storage::new().expect("storage");
+    storage
+        .create_table(
+            "readings",
+            &[
+                ColumnSpec {
+                    name: "id".to_string(),
+                    column_type: ColumnType::Integer,
+                    nullable: false,
+                },
+                ColumnSpec {
+                    name: "value".to_string(),
+                    column_type: ColumnType::Float,
+                    nullable: true,
+                },
+            ],
+        )
+        .expect("create table");
+    Arc::new(storage)
+}
+
+#[tokio::test]
+async fn grpc_insert_batch_applies_rows_and_reports_bad_ones() {
+    let storage = readings_storage();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let pool = StoragePool::with_storage(storage.clone(), 2);
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default()
+                .with_storage(pool)
+                .with_insert_batch_rows(2),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let rows = [
+        r#"{"id": 1, "value": 1.5}"#,
+        r#"{"id": 2}"#,
+        r#"{"id": 3, "unit": "C"}"#,
+        r#"{"id": 4, "value": 2}"#,
+        r#"{"id": "five"}"#,
+    ];
+    let requests: Vec<InsertRequest> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, json)| InsertRequest {
+            table: if i == 0 {
+                "readings".to_string()
+            } else {
+                String::new()
+            },
+            json: json.to_string(),
+        })
+        .collect();
+    let summary = client
+        .insert_batch(tokio_stream::iter(requests))
+        .await
+        .expect("insert batch")
+        .into_inner();
+
+    assert_eq!(summary.inserted, 3);
+    assert_eq!(summary.failed, 2);
+    let errors: Vec<(u64, &str)> = summary
+        .errors
+        .iter()
+        .map(|e| (e.index, e.message.as_str()))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (2, "unknown column `unit`"),
+            (4, "column `id` expects Integer")
+        ]
+    );
+    let stored = storage
+        .execute_query("SELECT * FROM readings")
+        .and_then(|mut rs| rs.all_rows_as_strings())
+        .expect("read back");
+    assert_eq!(stored.len(), 3);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_insert_batch_needs_storage() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default(),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let status = client
+        .insert_batch(tokio_stream::iter(vec![InsertRequest {
+            table: "readings".to_string(),
+            json: r#"{"id": 1}"#.to_string(),
+        }]))
+        .await
+        .expect_err("no storage");
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_minimal_error_verbosity_hides_storage_messages() {
+    let storage = readings_storage();
+    storage
+        .create_table(
+            "notes",
+            &[ColumnSpec {
+                name: "body".to_string(),
+                column_type: ColumnType::String,
+                nullable: false,
+            }],
+        )
+        .expect("create table");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+    let server = tokio::spawn(kadedb_services_grpc::serve_service(
+        listener,
+        AuthConfig {
+            enabled: false,
+            jwt_secret: None,
+        },
+        QueryServiceImpl::default()
+            .with_storage(StoragePool::with_storage(storage, 1))
+            .with_error_verbosity(ErrorVerbosity::Minimal),
+    ));
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let insert = |table: &str, json: &str| {
+        let mut request = tonic::Request::new(tokio_stream::iter(vec![InsertRequest {
+            table: table.to_string(),
+            json: json.to_string(),
+        }]));
+        request
+            .metadata_mut()
+            .insert("x-request-id", "ticket-7".parse().expect("metadata"));
+        request
+    };
+
+    let status = client
+        .insert_batch(insert("secret_table", r#"{"id": 1}"#))
+        .await
+        .expect_err("unknown table");
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    assert_eq!(status_code(&status), ErrorCode::TableNotFound);
+    assert!(!status.message().contains("secret_table"));
+    assert!(status.message().contains("ticket-7"));
+
+    // Rows failing in storage are reported the same way.
+    let summary = client
+        .insert_batch(insert("notes", r#"{"body": "a\u0000b"}"#))
+        .await
+        .expect("insert batch")
+        .into_inner();
+    assert_eq!(summary.failed, 1);
+    assert!(!summary.errors[0].message.contains("NUL"));
+    assert!(summary.errors[0].message.contains("ticket-7"));
+
+    // Mistakes in the request itself are still explained.
+    let status = client
+        .query_unary(QueryRequest {
+            query: "x".repeat(kadedb_services_ffi::DEFAULT_MAX_QUERY_LENGTH + 1),
+            ..Default::default()
+        })
+        .await
+        .expect_err("too long");
+    assert!(status.message().contains("longer than"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn grpc_insert_batch_writes_to_the_callers_tenant() {
+    let acme = readings_storage();
+    let globex = readings_storage();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local_addr");
+
+    let tenants = HashMap::from([
+        (
+            "acme".to_string(),
+            StoragePool::with_storage(acme.clone(), 1),
+        ),
+        (
+            "globex".to_string(),
+            StoragePool::with_storage(globex.clone(), 1),
+        ),
+    ]);
+    let server = tokio::spawn(async move {
+        kadedb_services_grpc::serve_service(
+            listener,
+            AuthConfig {
+                enabled: false,
+                jwt_secret: None,
+            },
+            QueryServiceImpl::default().with_tenant_storage(tenants),
+        )
+        .await;
+    });
+
+    let mut client = QueryServiceClient::connect(format!("http://{addr}"))
+        .await
+        .expect("connect");
+    let insert = |tenant: Option<&str>| {
+        let mut request = tonic::Request::new(tokio_stream::iter(vec![InsertRequest {
+            table: "readings".to_string(),
+            json: r#"{"id": 1}"#.to_string(),
+        }]));
+        if let Some(tenant) = tenant {
+            request
+                .metadata_mut()
+                .insert(TENANT_HEADER, tenant.parse().expect("metadata"));
+        }
+        request
+    };
+
+    let summary = client
+        .insert_batch(insert(Some("globex")))
+        .await
+        .expect("insert batch")
+        .into_inner();
+    assert_eq!(summary.inserted, 1);
+    let count = |storage: &Storage| {
+        storage
+            .execute_query("SELECT * FROM readings")
+            .and_then(|mut rs| rs.all_rows_as_strings())
+            .expect("read back")
+            .len()
+    };
+    assert_eq!((count(&acme), count(&globex)), (0, 1));
+
+    let status = client
+        .insert_batch(insert(Some("initech")))
+        .await
+        .expect_err("unknown tenant");
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    let status = client
+        .insert_batch(insert(None))
+        .await
+        .expect_err("no tenant");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
 
     server.abort();
 }
@@ -0,0 +1,182 @@
+//! REST and gRPC in one process.
+//!
+//! [`serve_all`] runs both servers side by side with shared auth settings and
+//! one shutdown path: a signal, or either server exiting, stops both.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use kadedb_services_api::{ApiConfig, Tenancy};
+use kadedb_services_auth::AuthConfig;
+use kadedb_services_ffi::FfiError;
+use kadedb_services_grpc::QueryServiceImpl;
+use tokio::sync::watch;
+
+/// Default REST address, matching the standalone API binary.
+pub const DEFAULT_API_ADDR: &str = "0.0.0.0:8080";
+/// Default gRPC address, matching the standalone gRPC binary.
+pub const DEFAULT_GRPC_ADDR: &str = "0.0.0.0:50051";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServeError {
+    #[error("failed to bind {addr}: {source}")]
+    Bind {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+
+    #[error("REST server failed: {0}")]
+    Api(std::io::Error),
+
+    #[error("gRPC server failed: {0}")]
+    Grpc(#[from] kadedb_services_grpc::TransportError),
+}
+
+pub struct ServerConfig {
+    pub api_addr: SocketAddr,
+    pub grpc_addr: SocketAddr,
+    pub auth: AuthConfig,
+    pub tenancy: Tenancy,
+    /// REST settings; its `listener` settings apply to both listeners.
+    pub api: ApiConfig,
+    pub grpc: QueryServiceImpl,
+}
+
+impl ServerConfig {
+    /// Reads `KADEDB_API_ADDR` and `KADEDB_GRPC_ADDR` plus everything the
+    /// standalone binaries read. Unset or unparsable addresses fall back to
+    /// the defaults; storage creation failures are returned.
+    pub fn from_env() -> Result<Self, FfiError> {
+        let addr = |name: &str, default: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| default.parse().expect("valid default addr"))
+        };
+        Ok(Self {
+            api_addr: addr("KADEDB_API_ADDR", DEFAULT_API_ADDR),
+            grpc_addr: addr("KADEDB_GRPC_ADDR", DEFAULT_GRPC_ADDR),
+            auth: AuthConfig::from_env(),
+            tenancy: Tenancy::from_env()?,
+            api: ApiConfig::from_env(),
+            grpc: QueryServiceImpl::from_env(),
+        })
+    }
+}
+
+/// Serves REST and gRPC until SIGINT/SIGTERM, or until either server exits.
+/// Both then drain in-flight requests before this returns; the first server
+/// error, if any, is returned.
+///
+/// Both servers share `config.auth` and the storage pools in
+/// `config.tenancy`: gRPC queries and `InsertBatch` use the same pool as
+/// REST, or with several tenants the pool named by the tenant header.
+pub async fn serve_all(config: ServerConfig) -> Result<(), ServeError> {
+    serve_all_with_shutdown(config, shutdown_signal()).await
+}
+
+/// [`serve_all`] with a caller-provided shutdown trigger instead of signals.
+pub async fn serve_all_with_shutdown(
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), ServeError> {
+    let listener_cfg = config.api.listener.clone();
+    let bind = |addr| {
+        listener_cfg
+            .bind(addr)
+            .map_err(|source| ServeError::Bind { addr, source })
+    };
+    let api_listener = bind(config.api_addr)?;
+    let grpc_listener = bind(config.grpc_addr)?;
+    tracing::info!(
+        "listening on {} (REST) and {} (gRPC)",
+        config.api_addr,
+        config.grpc_addr
+    );
+
+    let stop = Arc::new(watch::channel(false).0);
+    let stopped = |stop: &watch::Sender<bool>| {
+        let mut rx = stop.subscribe();
+        async move {
+            let _ = rx.wait_for(|stopped| *stopped).await;
+        }
+    };
+
+    let trigger = {
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            shutdown.await;
+            tracing::info!("shutting down");
+            stop.send_replace(true);
+        })
+    };
+
+    let grpc_service = match &config.tenancy {
+        Tenancy::Single(pool) => config.grpc.with_storage(pool.clone()),
+        Tenancy::Multi(pools) => config.grpc.with_tenant_storage((**pools).clone()),
+    };
+    let api = {
+        let stop = stop.clone();
+        let serve = kadedb_services_api::serve_with_shutdown(
+            api_listener,
+            config.auth.clone(),
+            config.tenancy,
+            config.api,
+            stopped(&stop),
+        );
+        tokio::spawn(async move {
+            let result = serve.await.map_err(ServeError::Api);
+            stop.send_replace(true);
+            result
+        })
+    };
+
+    let grpc = {
+        let stop = stop.clone();
+        let until = stopped(&stop);
+        tokio::spawn(async move {
+            let result = kadedb_services_grpc::serve_with_shutdown(
+                grpc_listener,
+                config.auth,
+                grpc_service,
+                &listener_cfg,
+                until,
+            )
+            .await
+            .map_err(ServeError::from);
+            stop.send_replace(true);
+            result
+        })
+    };
+
+    let (api, grpc) = tokio::join!(api, grpc);
+    trigger.abort();
+    api.expect("REST server task")?;
+    grpc.expect("gRPC server task")?;
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,44 @@
+use std::process::ExitCode;
+
+use kadedb_services_server::{ServeError, ServerConfig};
+use kadedb_services_telemetry::{ConfigFile, StartupError};
+
+fn main() -> ExitCode {
+    match start() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => err.report("kadedb-server"),
+    }
+}
+
+fn start() -> Result<(), StartupError> {
+    ConfigFile::load_into_env().map_err(|err| StartupError::Config(err.to_string()))?;
+    kadedb_services_ffi::install_panic_hook(kadedb_services_telemetry::redact_sql);
+    if let Ok(prefix) = std::env::var("KADEDB_FFI_THREAD_PREFIX") {
+        kadedb_services_ffi::set_thread_prefix(prefix);
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(StartupError::Runtime)?
+        .block_on(run())
+}
+
+async fn run() -> Result<(), StartupError> {
+    let _telemetry = kadedb_services_telemetry::init("kadedb-server");
+
+    let config = ServerConfig::from_env().map_err(|err| StartupError::Storage(err.to_string()))?;
+    config
+        .auth
+        .validate()
+        .map_err(|err| StartupError::Auth(err.to_string()))?;
+    config.api.validate().map_err(StartupError::Config)?;
+    config.grpc.validate().map_err(StartupError::Config)?;
+
+    kadedb_services_server::serve_all(config)
+        .await
+        .map_err(|err| match err {
+            ServeError::Bind { addr, source } => StartupError::Bind { addr, source },
+            err => StartupError::Serve(err.to_string()),
+        })
+}
@@ -0,0 +1,136 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Fields an access log line may carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessFields {
+    pub method: bool,
+    pub path: bool,
+    pub status: bool,
+    pub latency: bool,
+    pub subject: bool,
+    pub bytes: bool,
+    pub client: bool,
+}
+
+impl Default for AccessFields {
+    fn default() -> Self {
+        Self {
+            method: true,
+            path: true,
+            status: true,
+            latency: true,
+            subject: true,
+            bytes: true,
+            client: true,
+        }
+    }
+}
+
+impl AccessFields {
+    /// Parses a comma-separated field list such as `method,status,latency`.
+    /// Unknown names are ignored.
+    pub fn parse(value: &str) -> Self {
+        let mut fields = Self {
+            method: false,
+            path: false,
+            status: false,
+            latency: false,
+            subject: false,
+            bytes: false,
+            client: false,
+        };
+        for name in value.split(',').map(str::trim) {
+            match name {
+                "method" => fields.method = true,
+                "path" => fields.path = true,
+                "status" => fields.status = true,
+                "latency" => fields.latency = true,
+                "subject" => fields.subject = true,
+                "bytes" => fields.bytes = true,
+                "client" => fields.client = true,
+                _ => {}
+            }
+        }
+        fields
+    }
+}
+
+/// One handled request or call.
+#[derive(Debug, Clone)]
+pub struct AccessEntry<'a> {
+    /// HTTP method, or `grpc` for gRPC calls.
+    pub method: &'a str,
+    /// Request path; for gRPC the full method name.
+    pub path: &'a str,
+    /// HTTP status, or the numeric gRPC status code.
+    pub status: u16,
+    pub latency: Duration,
+    pub subject: Option<&'a str>,
+    /// Response body size, when known up front.
+    pub bytes: Option<u64>,
+    /// The client's address, as the connection or its PROXY protocol header
+    /// names it; unknown for requests served without a connection.
+    pub client: Option<SocketAddr>,
+}
+
+/// One-line-per-request access log at INFO (target `kadedb::access`).
+///
+/// Unlike the debug spans this is the audit trail ops ingests, so it is off
+/// unless enabled and carries only the configured fields.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLog {
+    enabled: bool,
+    fields: AccessFields,
+}
+
+impl AccessLog {
+    /// An enabled log with every field.
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            fields: AccessFields::default(),
+        }
+    }
+
+    pub fn with_fields(mut self, fields: AccessFields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Reads `KADEDB_ACCESS_LOG` (`true`/`false`, default off) and
+    /// `KADEDB_ACCESS_LOG_FIELDS` (default: all fields).
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("KADEDB_ACCESS_LOG")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        let fields = std::env::var("KADEDB_ACCESS_LOG_FIELDS")
+            .map(|v| AccessFields::parse(&v))
+            .unwrap_or_default();
+        Self { enabled, fields }
+    }
+
+    pub fn record(&self, entry: &AccessEntry<'_>) {
+        if !self.enabled {
+            return;
+        }
+        let f = self.fields;
+        // `None` fields are left out of the line.
+        tracing::info!(
+            target: "kadedb::access",
+            method = f.method.then_some(entry.method),
+            path = f.path.then_some(entry.path),
+            status = f.status.then_some(entry.status),
+            latency_ms = f.latency.then_some(entry.latency.as_secs_f64() * 1000.0),
+            subject = f.subject.then_some(entry.subject.unwrap_or("-")),
+            bytes = entry.bytes.filter(|_| f.bytes),
+            client = f.client.then(|| entry.client.map_or_else(|| "-".to_string(), |a| a.to_string())),
+            "access"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Names a file of settings that override the environment.
+pub const CONFIG_FILE_ENV: &str = "KADEDB_CONFIG_FILE";
+
+/// Settings read from `KADEDB_CONFIG_FILE`: one `NAME=value` per line, with
+/// the same names as the environment variables. Blank lines and lines
+/// starting with `#` are skipped; whitespace around names and values is
+/// trimmed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    vars: BTreeMap<String, String>,
+}
+
+impl ConfigFile {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut vars = BTreeMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected NAME=value", n + 1));
+            };
+            vars.insert(name.trim().to_string(), value.trim().to_string());
+        }
+        Ok(Self { vars })
+    }
+
+    /// The file's value for `name`, else the environment's.
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.vars
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Reads the file named by `KADEDB_CONFIG_FILE`, if set, and copies its
+    /// settings into the process environment so every `from_env` sees them.
+    ///
+    /// Setting the environment isn't thread-safe: call this first thing in
+    /// `main`, before the runtime starts.
+    pub fn load_into_env() -> io::Result<()> {
+        let Some(path) = std::env::var_os(CONFIG_FILE_ENV) else {
+            return Ok(());
+        };
+        let file = Self::read(Path::new(&path)).map_err(|err| {
+            io::Error::new(err.kind(), format!("{}: {err}", path.to_string_lossy()))
+        })?;
+        for (name, value) in file.iter() {
+            std::env::set_var(name, value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The error servers answer requests with while draining: a 503 over REST,
+/// `unavailable` over gRPC.
+pub const SHUTTING_DOWN: &str = "shutting_down: the server is shutting down";
+
+/// Set once a server's shutdown signal fires, so requests arriving while
+/// in-flight ones drain are turned away with a retryable error instead of
+/// hanging or finding the connection reset.
+#[derive(Debug, Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Waits for `shutdown`, marks the server draining, then waits out
+    /// `grace` (see [`ListenerConfig::shutdown_grace`]). The server's own
+    /// shutdown (closing the listener and waiting for connections) should
+    /// wait on this rather than on `shutdown`.
+    ///
+    /// [`ListenerConfig::shutdown_grace`]: crate::ListenerConfig::shutdown_grace
+    pub fn after(
+        &self,
+        shutdown: impl Future<Output = ()>,
+        grace: Option<Duration>,
+    ) -> impl Future<Output = ()> {
+        let draining = self.0.clone();
+        async move {
+            shutdown.await;
+            draining.store(true, Ordering::Relaxed);
+            if let Some(grace) = grace {
+                tracing::info!(grace_ms = grace.as_millis() as u64, "draining");
+                tokio::time::sleep(grace).await;
+            }
+        }
+    }
+}
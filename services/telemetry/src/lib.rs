@@ -0,0 +1,258 @@
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+mod access;
+mod config_file;
+mod drain;
+mod listener;
+#[cfg(feature = "proxy-protocol")]
+mod proxy;
+mod request_id;
+mod sampling;
+mod slow;
+mod startup;
+mod tags;
+
+pub use access::{AccessEntry, AccessFields, AccessLog};
+pub use config_file::{ConfigFile, CONFIG_FILE_ENV};
+pub use drain::{Draining, SHUTTING_DOWN};
+pub use listener::{ListenerConfig, DEFAULT_BACKLOG};
+pub use request_id::{RequestId, REQUEST_ID_HEADER};
+pub use sampling::{
+    request_span, SampledSpans, TraceSampling, TRACEPARENT_HEADER, TRACE_SAMPLE_RATIO_ENV,
+};
+pub use slow::{redact_sql, SlowQueryLog};
+pub use startup::StartupError;
+pub use tags::{record_query, QueryTags, QUERY_TAG_HEADER, UNTAGGED};
+
+/// Address for a standalone Prometheus scrape listener. The REST API also
+/// serves the same registry at `/metrics`.
+pub const METRICS_ADDR_ENV: &str = "KADEDB_METRICS_ADDR";
+
+/// Log filter used when `RUST_LOG` is unset or invalid.
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+static METRICS: OnceLock<PrometheusHandle> = OnceLock::new();
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Keeps exporters alive for the life of the process; flushes on drop.
+#[must_use = "dropping the guard shuts telemetry export down"]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    otel: Option<otel::Providers>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(providers) = self.otel.take() {
+            providers.shutdown();
+        }
+    }
+}
+
+/// Installs the global tracing subscriber (`RUST_LOG`-filtered fmt output)
+/// and the Prometheus metrics recorder, plus OTLP trace/metric export when
+/// built with the `otel` feature and `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+/// Exported traces are sampled per [`TraceSampling::from_env`]; log output
+/// isn't.
+///
+/// Must be called from within a Tokio runtime.
+pub fn init(service_name: &'static str) -> TelemetryGuard {
+    install_metrics();
+    sampling::install(TraceSampling::from_env());
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = LOG_FILTER.set(handle);
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer());
+
+    #[cfg(feature = "otel")]
+    {
+        use tracing_subscriber::Layer as _;
+
+        let providers = otel::Providers::from_env(service_name);
+        let (trace_layer, metrics_layer) = match &providers {
+            Some(p) => (
+                Some(
+                    p.trace_layer(service_name)
+                        .with_filter(sampling::SampledSpans),
+                ),
+                Some(p.metrics_layer()),
+            ),
+            None => (None, None),
+        };
+        registry.with(trace_layer).with(metrics_layer).init();
+        TelemetryGuard { otel: providers }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = service_name;
+        registry.init();
+        TelemetryGuard {}
+    }
+}
+
+/// The log filter in effect, or `None` if [`init`] hasn't installed the
+/// subscriber.
+pub fn log_filter() -> Option<String> {
+    LOG_FILTER.get()?.with_current(|f| f.to_string()).ok()
+}
+
+/// Parses `directives` (`RUST_LOG` syntax) and returns them normalized the
+/// way [`log_filter`] reports them.
+pub fn parse_log_filter(directives: &str) -> Result<String, String> {
+    EnvFilter::try_new(directives)
+        .map(|f| f.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// Swaps the log filter without restarting. Returns `Ok(false)` if [`init`]
+/// hasn't installed the subscriber.
+pub fn set_log_filter(directives: &str) -> Result<bool, String> {
+    let filter = EnvFilter::try_new(directives).map_err(|err| err.to_string())?;
+    let Some(handle) = LOG_FILTER.get() else {
+        return Ok(false);
+    };
+    handle.reload(filter).map_err(|err| err.to_string())?;
+    Ok(true)
+}
+
+/// Renders the Prometheus exposition for everything recorded through the
+/// `metrics` facade, or `None` if [`init`] hasn't installed a recorder.
+pub fn render_metrics() -> Option<String> {
+    METRICS.get().map(PrometheusHandle::render)
+}
+
+fn install_metrics() {
+    let listen: Option<SocketAddr> = match std::env::var(METRICS_ADDR_ENV) {
+        Ok(addr) => match addr.parse() {
+            Ok(addr) => Some(addr),
+            Err(err) => {
+                eprintln!("ignoring {METRICS_ADDR_ENV}={addr}: {err}");
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let result = match listen {
+        Some(addr) => PrometheusBuilder::new()
+            .with_http_listener(addr)
+            .build()
+            .map(|(recorder, exporter)| {
+                tokio::spawn(exporter);
+                recorder
+            }),
+        None => Ok(PrometheusBuilder::new().build_recorder()),
+    };
+
+    match result {
+        Ok(recorder) => {
+            let handle = recorder.handle();
+            if metrics::set_global_recorder(recorder).is_ok() {
+                let _ = METRICS.set(handle);
+            }
+        }
+        Err(err) => eprintln!("metrics disabled: {err}"),
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::{trace::TracerProvider as _, KeyValue};
+    use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{
+        metrics::{PeriodicReader, SdkMeterProvider},
+        runtime,
+        trace::TracerProvider,
+        Resource,
+    };
+    use tracing::Subscriber;
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+    pub struct Providers {
+        tracer: TracerProvider,
+        meter: SdkMeterProvider,
+    }
+
+    impl Providers {
+        pub fn from_env(service_name: &'static str) -> Option<Self> {
+            let endpoint = std::env::var(ENDPOINT_ENV).ok()?;
+            match Self::new(service_name, &endpoint) {
+                Ok(providers) => Some(providers),
+                Err(err) => {
+                    // The subscriber isn't installed yet, so report directly.
+                    eprintln!("OTLP export disabled: {err}");
+                    None
+                }
+            }
+        }
+
+        fn new(
+            service_name: &'static str,
+            endpoint: &str,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let resource = Resource::new([KeyValue::new("service.name", service_name)]);
+
+            let spans = SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()?;
+            let tracer = TracerProvider::builder()
+                .with_batch_exporter(spans, runtime::Tokio)
+                .with_resource(resource.clone())
+                .build();
+
+            let metrics = MetricExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()?;
+            let meter = SdkMeterProvider::builder()
+                .with_reader(PeriodicReader::builder(metrics, runtime::Tokio).build())
+                .with_resource(resource)
+                .build();
+
+            opentelemetry::global::set_tracer_provider(tracer.clone());
+            opentelemetry::global::set_meter_provider(meter.clone());
+
+            Ok(Self { tracer, meter })
+        }
+
+        pub fn trace_layer<S>(
+            &self,
+            service_name: &'static str,
+        ) -> tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            tracing_opentelemetry::layer().with_tracer(self.tracer.tracer(service_name))
+        }
+
+        pub fn metrics_layer<S>(&self) -> tracing_opentelemetry::MetricsLayer<S>
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            tracing_opentelemetry::MetricsLayer::new(self.meter.clone())
+        }
+
+        pub fn shutdown(self) {
+            if let Err(err) = self.tracer.shutdown() {
+                eprintln!("OTLP trace shutdown failed: {err}");
+            }
+            if let Err(err) = self.meter.shutdown() {
+                eprintln!("OTLP metrics shutdown failed: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Default accept backlog; the kernel caps it at `net.core.somaxconn`.
+pub const DEFAULT_BACKLOG: u32 = 1024;
+
+/// TCP settings for the service listeners.
+///
+/// - `nodelay` disables Nagle's algorithm on accepted connections. Responses
+///   here are mostly small and latency-bound, and with Nagle on they can wait
+///   for the peer's delayed ACK (~40ms on Linux) before going out. Turning it
+///   off costs a few more small packets on bulk transfers.
+/// - `backlog` is how many completed connections may queue before `accept`.
+///   A larger queue absorbs connection bursts instead of dropping SYNs (and
+///   making clients wait out a retransmit), at the price of queued clients
+///   waiting longer when the server is genuinely saturated.
+/// - `reuse_address` lets a restarted server rebind while old connections
+///   are still in `TIME_WAIT`.
+/// - `proxy_protocol` expects every connection to open with a PROXY protocol
+///   (v1 or v2) header, as sent by a load balancer, and attributes the
+///   connection to the client it names rather than to the balancer. Only
+///   enable it behind a balancer that sends one: anyone else could claim any
+///   address. Needs the `proxy-protocol` feature.
+/// - `shutdown_grace` keeps the listener open this long after the shutdown
+///   signal, answering new requests with a retryable `shutting_down` error
+///   while in-flight ones drain. Clients that haven't yet seen the instance
+///   leave rotation then get an answer to retry elsewhere rather than a
+///   refused connection. `None` stops accepting at once.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    pub reuse_address: bool,
+    pub nodelay: bool,
+    pub backlog: u32,
+    pub proxy_protocol: bool,
+    pub shutdown_grace: Option<Duration>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            reuse_address: true,
+            nodelay: true,
+            backlog: DEFAULT_BACKLOG,
+            proxy_protocol: false,
+            shutdown_grace: None,
+        }
+    }
+}
+
+impl ListenerConfig {
+    /// Reads `KADEDB_TCP_REUSEADDR`, `KADEDB_TCP_NODELAY`,
+    /// `KADEDB_PROXY_PROTOCOL` (`true`/`false`), `KADEDB_TCP_BACKLOG` and
+    /// `KADEDB_SHUTDOWN_GRACE_MS` (0 disables); unset or invalid values keep
+    /// the defaults.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let flag = |name: &str, fallback: bool| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(fallback)
+        };
+        Self {
+            reuse_address: flag("KADEDB_TCP_REUSEADDR", default.reuse_address),
+            nodelay: flag("KADEDB_TCP_NODELAY", default.nodelay),
+            backlog: std::env::var("KADEDB_TCP_BACKLOG")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(default.backlog),
+            proxy_protocol: flag("KADEDB_PROXY_PROTOCOL", default.proxy_protocol),
+            shutdown_grace: std::env::var("KADEDB_SHUTDOWN_GRACE_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+        }
+    }
+
+    /// Binds a listener on `addr` with these settings. `nodelay` is applied
+    /// per connection by the server, not here. Must be called from within a
+    /// Tokio runtime. Fails when `proxy_protocol` is set but the feature
+    /// isn't built in.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<tokio::net::TcpListener> {
+        if self.proxy_protocol && !cfg!(feature = "proxy-protocol") {
+            return Err(proxy_protocol_unsupported());
+        }
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(self.reuse_address)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog.min(i32::MAX as u32) as i32)?;
+        tokio::net::TcpListener::from_std(socket.into())
+    }
+
+    /// The client `stream` was accepted from: its peer, or with
+    /// `proxy_protocol` the address in the header the stream opens with,
+    /// which this reads. The proxy's own health checks carry no address and
+    /// get the peer. Fails when the header is missing, malformed or takes
+    /// longer than five seconds; the connection should then be dropped.
+    pub async fn client_addr(&self, stream: &mut tokio::net::TcpStream) -> io::Result<SocketAddr> {
+        let peer = stream.peer_addr()?;
+        if !self.proxy_protocol {
+            return Ok(peer);
+        }
+        #[cfg(feature = "proxy-protocol")]
+        {
+            let header = crate::proxy::read_header(stream);
+            match tokio::time::timeout(crate::proxy::HEADER_TIMEOUT, header).await {
+                Ok(client) => Ok(client?.unwrap_or(peer)),
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no PROXY protocol header in time",
+                )),
+            }
+        }
+        #[cfg(not(feature = "proxy-protocol"))]
+        Err(proxy_protocol_unsupported())
+    }
+}
+
+fn proxy_protocol_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "KADEDB_PROXY_PROTOCOL is set, but built without the `proxy-protocol` feature",
+    )
+}
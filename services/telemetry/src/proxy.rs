@@ -0,0 +1,116 @@
+//! PROXY protocol (v1 and v2) headers, which a load balancer in front of the
+//! services sends ahead of each connection to name the client it accepted.
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long a new connection may take to send its header.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest v1 header, `\r\n` included.
+const V1_MAX_LEN: usize = 107;
+
+/// Reads the header at the start of `stream`, consuming exactly its bytes.
+/// Returns the client's address, or `None` when the proxy sent none: a v1
+/// `UNKNOWN` or v2 `LOCAL` header (the proxy's own health checks), or an
+/// address family other than TCP over IPv4/IPv6. A connection starting with
+/// anything else is an error.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    // Both versions are at least this long: v1's shortest is `PROXY UNKNOWN\r\n`.
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(malformed("missing PROXY protocol header"))
+    }
+}
+
+/// `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n`, of which `start` is
+/// already read. The rest is read a byte at a time so nothing past the
+/// header is consumed.
+async fn read_v1<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    start: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(malformed("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| malformed("PROXY v1 header isn't ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _, port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| malformed("bad PROXY v1 source address"))?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                return Err(malformed("PROXY v1 address doesn't match its family"));
+            }
+            let port = port
+                .parse()
+                .map_err(|_| malformed("bad PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(malformed("malformed PROXY v1 header")),
+    }
+}
+
+/// The binary header after its signature: version and command, address
+/// family, length, then the addresses and any TLVs, which are skipped.
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    let [version_command, family, len @ ..] = head;
+    if version_command >> 4 != 2 {
+        return Err(malformed("unsupported PROXY protocol version"));
+    }
+    let mut body = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).await?;
+    match version_command & 0x0f {
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err(malformed("unknown PROXY v2 command")),
+    }
+    let short = || malformed("PROXY v2 addresses truncated");
+    match family {
+        // TCP over IPv4: source, destination, source port, destination port.
+        0x11 => {
+            let a = body.get(..12).ok_or_else(short)?;
+            let ip = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+            Ok(Some(SocketAddr::new(
+                ip.into(),
+                u16::from_be_bytes([a[8], a[9]]),
+            )))
+        }
+        // TCP over IPv6, laid out the same.
+        0x21 => {
+            let a = body.get(..36).ok_or_else(short)?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&a[..16]);
+            let ip = Ipv6Addr::from(octets);
+            Ok(Some(SocketAddr::new(
+                ip.into(),
+                u16::from_be_bytes([a[32], a[33]]),
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn malformed(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
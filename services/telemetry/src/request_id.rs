@@ -0,0 +1,49 @@
+use std::fmt;
+
+/// Header (REST) / metadata key (gRPC) carrying a request's id.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied id that is kept.
+const MAX_LEN: usize = 128;
+
+/// Identifies one request in responses and logs, so a client can quote it
+/// and operators can find the matching log lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// 16 random hex digits.
+    pub fn generate() -> Self {
+        let mut id = [0u8; 8];
+        getrandom::getrandom(&mut id).expect("generate request id");
+        Self(id.iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    /// The id a proxy or client already assigned in [`REQUEST_ID_HEADER`],
+    /// if it is at most 128 letters, digits, `-`, `_`, `.` or `:`; otherwise
+    /// a new one.
+    pub fn from_header(value: Option<&str>) -> Self {
+        match value {
+            Some(id)
+                if !id.is_empty()
+                    && id.len() <= MAX_LEN
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b)) =>
+            {
+                Self(id.to_string())
+            }
+            _ => Self::generate(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use tracing::{
+    field::{Field, Visit},
+    span, Metadata, Span, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, Filter},
+    registry::LookupSpan,
+};
+
+use crate::RequestId;
+
+/// Fraction of requests whose traces are exported in full, `0` to `1`.
+pub const TRACE_SAMPLE_RATIO_ENV: &str = "KADEDB_TRACE_SAMPLE_RATIO";
+
+/// W3C Trace Context header (REST) / metadata key (gRPC) naming the caller's
+/// trace and whether the caller sampled it.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+static SAMPLING: OnceLock<TraceSampling> = OnceLock::new();
+
+/// Which requests export their traces in full. The decision is made once,
+/// when the request arrives: a `traceparent` from the caller decides by its
+/// sampled flag, so a distributed trace is kept or dropped as a whole;
+/// otherwise a random [`TraceSampling::ratio`] of requests is sampled.
+///
+/// An unsampled request still exports its `request` span, and the events
+/// logged under it, so errors are always captured; only the spans beneath
+/// it (queries, storage calls) are dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceSampling {
+    ratio: f64,
+}
+
+impl Default for TraceSampling {
+    /// Every request.
+    fn default() -> Self {
+        Self { ratio: 1.0 }
+    }
+}
+
+impl TraceSampling {
+    /// Samples `ratio` of requests, clamped to `0.0..=1.0`.
+    pub fn ratio(ratio: f64) -> Self {
+        Self {
+            ratio: if ratio.is_nan() {
+                1.0
+            } else {
+                ratio.clamp(0.0, 1.0)
+            },
+        }
+    }
+
+    /// Reads [`TRACE_SAMPLE_RATIO_ENV`]; unset or invalid samples every
+    /// request.
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var(TRACE_SAMPLE_RATIO_ENV) else {
+            return Self::default();
+        };
+        match value.parse::<f64>() {
+            Ok(ratio) if (0.0..=1.0).contains(&ratio) => Self { ratio },
+            _ => {
+                // The subscriber isn't installed yet, so report directly.
+                eprintln!("ignoring {TRACE_SAMPLE_RATIO_ENV}={value}: expected 0 to 1");
+                Self::default()
+            }
+        }
+    }
+
+    /// Whether a request arriving with `traceparent` is sampled.
+    pub fn sample(&self, traceparent: Option<&str>) -> bool {
+        if let Some(sampled) = traceparent.and_then(parent_sampled) {
+            return sampled;
+        }
+        if self.ratio >= 1.0 {
+            return true;
+        }
+        let mut bytes = [0u8; 8];
+        getrandom::getrandom(&mut bytes).expect("sample trace");
+        // 53 random bits, uniform in [0, 1).
+        let draw = (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64;
+        draw < self.ratio
+    }
+}
+
+/// The sampled flag of a `traceparent` (`00-<trace id>-<parent id>-<flags>`),
+/// or `None` when the header is malformed.
+fn parent_sampled(traceparent: &str) -> Option<bool> {
+    let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    match traceparent.trim().split('-').collect::<Vec<_>>().as_slice() {
+        [version, trace, parent, flags]
+            if hex(version, 2) && *version != "ff" && hex(trace, 32) && hex(parent, 16) =>
+        {
+            let flags = u8::from_str_radix(flags, 16)
+                .ok()
+                .filter(|_| flags.len() == 2)?;
+            Some(flags & 1 == 1)
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn install(sampling: TraceSampling) {
+    let _ = SAMPLING.set(sampling);
+}
+
+/// The span a request is served in, carrying its `request_id` and whether
+/// it is sampled under the [`TraceSampling`] installed by [`crate::init`]
+/// (every request when it hasn't run). With the `otel` feature, a valid
+/// `traceparent` becomes the span's remote parent.
+pub fn request_span(id: &RequestId, traceparent: Option<&str>) -> Span {
+    let sampled = SAMPLING
+        .get()
+        .copied()
+        .unwrap_or_default()
+        .sample(traceparent);
+    let span = tracing::info_span!("request", request_id = %id, sampled);
+    #[cfg(feature = "otel")]
+    if let Some(traceparent) = traceparent {
+        use opentelemetry::propagation::TextMapPropagator;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let carrier =
+            std::collections::HashMap::from([(TRACEPARENT_HEADER.to_string(), traceparent.into())]);
+        let parent =
+            opentelemetry_sdk::propagation::TraceContextPropagator::new().extract(&carrier);
+        span.set_parent(parent);
+    }
+    span
+}
+
+/// A per-layer filter dropping the spans beneath an unsampled
+/// [`request_span`]; events pass, attaching to the request span. [`crate::init`]
+/// puts it on the OTLP trace layer, and other exporting layers can take it
+/// with `.with_filter(SampledSpans)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SampledSpans;
+
+/// Marks a request span that wasn't sampled.
+struct Unsampled;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Filter<S> for SampledSpans {
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        !meta.is_span()
+            || cx
+                .lookup_current()
+                .is_none_or(|span| span.extensions().get::<Unsampled>().is_none())
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, cx: Context<'_, S>) {
+        let mut sampled = SampledField(None);
+        attrs.record(&mut sampled);
+        if sampled.0 == Some(false) {
+            if let Some(span) = cx.span(id) {
+                span.extensions_mut().insert(Unsampled);
+            }
+        }
+    }
+}
+
+/// Reads a span's `sampled` field.
+struct SampledField(Option<bool>);
+
+impl Visit for SampledField {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "sampled" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+/// Logs queries slower than a threshold at WARN (target `kadedb::slow_query`).
+///
+/// Disabled unless a threshold is set. With `redact` on, literals are
+/// replaced by `?` before logging so slow-query logs don't carry data.
+#[derive(Debug, Clone, Default)]
+pub struct SlowQueryLog {
+    threshold: Option<Duration>,
+    redact: bool,
+}
+
+impl SlowQueryLog {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold: Some(threshold),
+            redact: false,
+        }
+    }
+
+    pub fn with_redaction(mut self, redact: bool) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    /// Reads `KADEDB_SLOW_QUERY_THRESHOLD_MS` and `KADEDB_SLOW_QUERY_REDACT`
+    /// (`true`/`false`). Unset or invalid thresholds leave the log disabled.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// [`SlowQueryLog::from_env`] with the settings looked up through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let threshold = var("KADEDB_SLOW_QUERY_THRESHOLD_MS")
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_millis);
+        let redact = var("KADEDB_SLOW_QUERY_REDACT")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        Self { threshold, redact }
+    }
+
+    pub fn threshold(&self) -> Option<Duration> {
+        self.threshold
+    }
+
+    pub fn redacts(&self) -> bool {
+        self.redact
+    }
+
+    /// Logs `sql` if `elapsed` exceeds the threshold.
+    pub fn record(&self, sql: &str, elapsed: Duration, rows: usize, subject: Option<&str>) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        if elapsed <= threshold {
+            return;
+        }
+        let sql = if self.redact {
+            redact_sql(sql)
+        } else {
+            sql.to_string()
+        };
+        tracing::warn!(
+            target: "kadedb::slow_query",
+            sql = %sql,
+            duration_ms = elapsed.as_millis() as u64,
+            rows,
+            subject = subject.unwrap_or("-"),
+            "slow query"
+        );
+    }
+}
+
+/// Replaces string and numeric literals in `sql` with `?`. Identifiers,
+/// keywords and quoted identifiers are kept.
+pub fn redact_sql(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut prev_ident = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // '' inside a literal is an escaped quote.
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                out.push('?');
+                prev_ident = false;
+            }
+            '"' => {
+                out.push(c);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                prev_ident = false;
+            }
+            c if c.is_ascii_digit() && !prev_ident => {
+                while chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.')
+                    .is_some()
+                {}
+                out.push('?');
+                prev_ident = false;
+            }
+            c => {
+                out.push(c);
+                prev_ident = c.is_alphanumeric() || c == '_';
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,50 @@
+use std::io;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+
+/// Why a server binary failed to come up (or stopped), with a distinct exit
+/// code per cause so supervisors can tell a bad deploy from a busy port.
+///
+/// Codes follow `sysexits.h`: configuration 78 (`EX_CONFIG`), auth
+/// configuration 77 (`EX_NOPERM`), storage 74 (`EX_IOERR`), runtime 71
+/// (`EX_OSERR`), bind 69 (`EX_UNAVAILABLE`). A server that fails after
+/// starting exits with 1.
+#[derive(Debug, thiserror::Error)]
+pub enum StartupError {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    #[error("invalid auth configuration: {0}")]
+    Auth(String),
+
+    #[error("failed to bind {addr}: {source}")]
+    Bind { addr: SocketAddr, source: io::Error },
+
+    #[error("storage preflight failed: {0}")]
+    Storage(String),
+
+    #[error("failed to start the async runtime: {0}")]
+    Runtime(io::Error),
+
+    #[error("server failed: {0}")]
+    Serve(String),
+}
+
+impl StartupError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            StartupError::Config(_) => 78,
+            StartupError::Auth(_) => 77,
+            StartupError::Storage(_) => 74,
+            StartupError::Runtime(_) => 71,
+            StartupError::Bind { .. } => 69,
+            StartupError::Serve(_) => 1,
+        }
+    }
+
+    /// Prints `<binary>: <error>` to stderr and returns the exit code.
+    pub fn report(&self, binary: &str) -> ExitCode {
+        eprintln!("{binary}: {self}");
+        ExitCode::from(self.exit_code())
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header (REST) / metadata key (gRPC) naming the feature a query serves.
+pub const QUERY_TAG_HEADER: &str = "x-query-tag";
+
+/// Label used for queries without an allowed tag.
+pub const UNTAGGED: &str = "untagged";
+
+/// Query tags accepted as span fields and metric labels. Anything else is
+/// recorded as [`UNTAGGED`], which keeps label cardinality bounded by the
+/// allowlist no matter what clients send.
+#[derive(Debug, Clone, Default)]
+pub struct QueryTags(Arc<HashSet<String>>);
+
+impl QueryTags {
+    pub fn new<I, S>(tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(Arc::new(tags.into_iter().map(Into::into).collect()))
+    }
+
+    /// Reads the comma-separated allowlist from `KADEDB_QUERY_TAGS`.
+    pub fn from_env() -> Self {
+        let tags = std::env::var("KADEDB_QUERY_TAGS").unwrap_or_default();
+        Self::new(
+            tags.split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string),
+        )
+    }
+
+    /// The label to record for a request that sent `tag`.
+    pub fn resolve(&self, tag: Option<&str>) -> String {
+        match tag.map(str::trim) {
+            Some(tag) if self.0.contains(tag) => tag.to_string(),
+            _ => UNTAGGED.to_string(),
+        }
+    }
+}
+
+/// Records one finished query in `queries_total{tag,kind,outcome}` and
+/// `query_duration_seconds{tag,kind}`. `kind` is the statement kind, from a
+/// fixed set.
+pub fn record_query(tag: &str, kind: &'static str, ok: bool, elapsed: Duration) {
+    let outcome = if ok { "ok" } else { "error" };
+    metrics::counter!(
+        "queries_total",
+        "tag" => tag.to_string(),
+        "kind" => kind,
+        "outcome" => outcome
+    )
+    .increment(1);
+    metrics::histogram!("query_duration_seconds", "tag" => tag.to_string(), "kind" => kind)
+        .record(elapsed.as_secs_f64());
+}